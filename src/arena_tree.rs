@@ -0,0 +1,777 @@
+/*
+ * Parse XML text input into a document whose elements are all kept in one
+ * arena and referred to by NodeIds. A NodeId is just an index, so it can be
+ * copied freely, and each node records its parent, first and last children
+ * and siblings, so moving around the tree takes constant time.
+ *
+ * The nodes are kept in a Vec rather than a typed_arena::Arena because
+ * nodes refer to each other by index, which a typed_arena can't look up,
+ * and because a Vec can be shared by reference counting, which lets an
+ * ArenaElement own a handle on the document without borrowing it.
+ *
+ * ArenaElement implements Element on top of the arena, so anything that
+ * reads an Element tree (writing, formatting, XPath, TreeIndex) also
+ * works on an ArenaDocument. Cloning an ArenaElement, as XsdLevelInfo does,
+ * copies a reference count and a NodeId, not a subtree. ArenaElements are
+ * read-only. The document is changed with ArenaDocument::node_mut(), or
+ * copied with ArenaDocument::to_tree() for the edits in edit_tree.
+ */
+
+use std::cell::{OnceCell, RefCell};
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::ops::Range;
+use std::rc::Rc;
+use xml::reader::XmlEvent;
+
+use crate::document::DocumentInfo;
+use crate::element::{Element, ElementInfo};
+use crate::misc::nl_indent;
+use crate::parse_doc::{Accumulator, LevelInfo, ParseDoc};
+use crate::parse_item::LineNumber;
+use crate::parse_tree::TreeElement;
+use crate::qname::QName;
+use crate::write_tree::write_document;
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * Handle on a node of an ArenaDocument. NodeIds are given out in document
+ * order, so the root is always NodeId 0 and the descendants of a node have
+ * the NodeIds that follow it.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /**
+     * Position of the node in document order
+     */
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/**
+ * An element stored in the arena
+ * element_info:        Name, attributes and namespace of the element
+ * before_element:      Items between the previous sibling (or the start of
+ *                      the parent) and the element. For the root, the prolog.
+ * content:             Items after the last subelement
+ * after_element:       For the root, the epilog. Otherwise empty.
+ * parent:              Parent of the element, None for the root
+ * first_child:         First subelement, if any
+ * last_child:          Last subelement, if any
+ * previous_sibling:    Subelement of the parent just before this one
+ * next_sibling:        Subelement of the parent just after this one
+ * end:                 NodeId index after that of the last descendant
+ * depth:               Number of ancestors
+ */
+#[derive(Clone, Debug)]
+pub struct ArenaNode {
+    pub element_info:       ElementInfo,
+    pub before_element:     Vec<XmlEvent>,
+    pub content:            Vec<XmlEvent>,
+    pub after_element:      Vec<XmlEvent>,
+    parent:                 Option<NodeId>,
+    first_child:            Option<NodeId>,
+    last_child:             Option<NodeId>,
+    previous_sibling:       Option<NodeId>,
+    next_sibling:           Option<NodeId>,
+    end:                    usize,
+    depth:                  usize,
+}
+
+impl ArenaNode {
+    fn new(element_info: ElementInfo, depth: usize) -> ArenaNode {
+        ArenaNode {
+            element_info,
+            before_element:     vec![],
+            content:            vec![],
+            after_element:      vec![],
+            parent:             None,
+            first_child:        None,
+            last_child:         None,
+            previous_sibling:   None,
+            next_sibling:       None,
+            end:                0,
+            depth,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.element_info.owned_name.local_name
+    }
+}
+
+/**
+ * Parsed document with its elements in an arena
+ * document_info:   Information from the XML declaration
+ * nodes:           Every element, in document order
+ */
+#[derive(Clone)]
+pub struct ArenaDocument {
+    pub document_info:  DocumentInfo,
+    nodes:              Rc<Vec<ArenaNode>>,
+}
+
+impl ArenaDocument {
+    pub fn parse<R: Read>(buf_reader: BufReader<R>) -> Result<ArenaDocument, XmlDocumentError> {
+        let level_info = ArenaLevelInfo::new();
+        let (document_info, _) = ParseArena::parse_base(buf_reader, &level_info)?;
+        Ok(level_info.document(document_info))
+    }
+
+    pub fn parse_path(path: &str) -> Result<ArenaDocument, XmlDocumentError> {
+        let level_info = ArenaLevelInfo::new();
+        let (document_info, _) = ParseArena::parse_path_base(path, &level_info)?;
+        Ok(level_info.document(document_info))
+    }
+
+    /*
+     * Write the document out as XML
+     */
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_document(writer, &self.document_info, &self.root_element())
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /**
+     * Number of elements in the document
+     */
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, id: NodeId) -> &ArenaNode {
+        &self.nodes[id.0]
+    }
+
+    /**
+     * Change the name, attributes, text, comments or processing
+     * instructions of a node. ArenaElements made before the change keep
+     * seeing the document as it was, so if there are any, the nodes are
+     * copied the first time.
+     */
+    pub fn node_mut(&mut self, id: NodeId) -> &mut ArenaNode {
+        &mut Rc::make_mut(&mut self.nodes)[id.0]
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).last_child
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).previous_sibling
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).next_sibling
+    }
+
+    /**
+     * Number of ancestors of the node
+     */
+    pub fn depth(&self, id: NodeId) -> usize {
+        self.node(id).depth
+    }
+
+    /**
+     * Subelements of the node, in document order
+     */
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children { document: self, next: self.first_child(id) }
+    }
+
+    /**
+     * Ancestors of the node, starting with its parent and ending with the
+     * root
+     */
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors { document: self, next: self.parent(id) }
+    }
+
+    /**
+     * Descendants of the node, in document order
+     */
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> {
+        self.subtree(id).skip(1).map(NodeId)
+    }
+
+    /*
+     * Indices of a node and its descendants
+     */
+    fn subtree(&self, id: NodeId) -> Range<usize> {
+        id.0..self.node(id).end
+    }
+
+    /**
+     * View of a node as an Element
+     */
+    pub fn element(&self, id: NodeId) -> ArenaElement {
+        ArenaElement::new(self.nodes.clone(), id)
+    }
+
+    pub fn root_element(&self) -> ArenaElement {
+        self.element(self.root())
+    }
+
+    /**
+     * Copy a node and its descendants into a tree of TreeElements, which
+     * can be edited
+     */
+    pub fn to_tree(&self, id: NodeId) -> Box<dyn Element> {
+        let node = self.node(id);
+        let subelements = self.children(id).map(|child| self.to_tree(child)).collect();
+        Box::new(TreeElement::new(node.element_info.clone(), node.before_element.clone(),
+            node.content.clone(), node.after_element.clone(), subelements))
+    }
+}
+
+impl fmt::Debug for ArenaDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ArenaDocument({} nodes)", self.nodes.len())
+    }
+}
+
+/**
+ * Iterator over the subelements of a node
+ */
+pub struct Children<'a> {
+    document:   &'a ArenaDocument,
+    next:       Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document.next_sibling(current);
+        Some(current)
+    }
+}
+
+/**
+ * Iterator over the ancestors of a node
+ */
+pub struct Ancestors<'a> {
+    document:   &'a ArenaDocument,
+    next:       Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document.parent(current);
+        Some(current)
+    }
+}
+
+/**
+ * An element of an ArenaDocument, seen through the Element trait
+ * nodes:       The nodes of the document
+ * id:          The element
+ * subelements: Views of the subelements, made the first time they are
+ *              wanted
+ *
+ * An ArenaElement is a view of the document, so changing it through the
+ * Element trait couldn't change the document. Rather than quietly making
+ * a copy, it is read-only: the edits in edit_tree return an error and the
+ * *_mut() methods panic.
+ */
+pub struct ArenaElement {
+    nodes:          Rc<Vec<ArenaNode>>,
+    id:             NodeId,
+    subelements:    OnceCell<Vec<Box<dyn Element>>>,
+}
+
+impl ArenaElement {
+    fn new(nodes: Rc<Vec<ArenaNode>>, id: NodeId) -> ArenaElement {
+        ArenaElement { nodes, id, subelements: OnceCell::new() }
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    fn node(&self) -> &ArenaNode {
+        &self.nodes[self.id.0]
+    }
+
+    fn read_only(&self) -> ! {
+        panic!("ArenaElement {} is read-only, use ArenaDocument::node_mut() or \
+            ArenaDocument::to_tree() to change it", self.id)
+    }
+
+    fn make_subelements(&self) -> Vec<Box<dyn Element>> {
+        let mut subelements: Vec<Box<dyn Element>> = vec![];
+        let mut next = self.node().first_child;
+        while let Some(id) = next {
+            subelements.push(Box::new(ArenaElement::new(self.nodes.clone(), id)));
+            next = self.nodes[id.0].next_sibling;
+        }
+        subelements
+    }
+}
+
+/*
+ * Only the handle is copied. The subelement views will be made again if
+ * they are wanted.
+ */
+impl Clone for ArenaElement {
+    fn clone(&self) -> Self {
+        ArenaElement::new(self.nodes.clone(), self.id)
+    }
+}
+
+impl fmt::Debug for ArenaElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.debug(f, 0)
+    }
+}
+
+impl Element for ArenaElement {
+    fn display(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        write!(f, "{}{} {}", nl_indent(depth), self.id, self.qname())?;
+        for subelement in self.subelements() {
+            subelement.display(f, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn debug(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        self.display(f, depth)
+    }
+
+    /**
+     * Find a subelement (one level deeper) with the given name
+     */
+    fn get(&self, name: &str) -> Option<&dyn Element> {
+        self.subelements()
+            .iter()
+            .find(|&x| x.name() == name)
+            .map(|v| &**v)
+    }
+
+    fn name(&self) -> &str {
+        self.node().name()
+    }
+
+    fn lineno(&self) -> LineNumber {
+        self.node().element_info.lineno()
+    }
+
+    fn element_info(&self) -> &ElementInfo {
+        &self.node().element_info
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn element_info_mut(&mut self) -> &mut ElementInfo {
+        self.read_only()
+    }
+
+    fn subelements(&self) -> &Vec<Box<dyn Element>> {
+        self.subelements.get_or_init(|| self.make_subelements())
+    }
+
+    fn subelements_mut(&mut self) -> &mut Vec<Box<dyn Element>> {
+        self.read_only()
+    }
+
+    fn before_element(&self) -> &Vec<XmlEvent> {
+        &self.node().before_element
+    }
+
+    fn before_element_mut(&mut self) -> &mut Vec<XmlEvent> {
+        self.read_only()
+    }
+
+    fn content(&self) -> &Vec<XmlEvent> {
+        &self.node().content
+    }
+
+    fn content_mut(&mut self) -> &mut Vec<XmlEvent> {
+        self.read_only()
+    }
+
+    fn after_element(&self) -> &Vec<XmlEvent> {
+        &self.node().after_element
+    }
+
+    fn after_element_mut(&mut self) -> &mut Vec<XmlEvent> {
+        self.read_only()
+    }
+}
+
+pub struct ParseArena;
+
+impl ParseDoc for ParseArena {
+    type LI = ArenaLevelInfo;
+    type AC = ArenaAccumulator;
+}
+
+/**
+ * LevelInfo that adds each element to the arena as it starts
+ * depth:   Depth of the elements at this level
+ * nodes:   The arena, shared by all levels
+ */
+pub struct ArenaLevelInfo {
+    depth:  usize,
+    nodes:  Rc<RefCell<Vec<ArenaNode>>>,
+}
+
+impl ArenaLevelInfo {
+    pub fn new() -> Self {
+        ArenaLevelInfo {
+            depth:  0,
+            nodes:  Rc::new(RefCell::new(vec![])),
+        }
+    }
+
+    /*
+     * Take the nodes built by a parse to make the document
+     */
+    fn document(&self, document_info: DocumentInfo) -> ArenaDocument {
+        ArenaDocument {
+            document_info,
+            nodes: Rc::new(self.nodes.take()),
+        }
+    }
+}
+
+impl Default for ArenaLevelInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LevelInfo for ArenaLevelInfo {
+    type AccumulatorType = ArenaAccumulator;
+
+    fn next_level(&self) -> Self {
+        ArenaLevelInfo {
+            depth:  self.depth + 1,
+            nodes:  self.nodes.clone(),
+        }
+    }
+
+    fn create_accumulator(&self, element_info: ElementInfo) ->
+        Result<ArenaAccumulator, XmlDocumentError>
+    {
+        Ok(ArenaAccumulator::new(element_info, self.depth, self.nodes.clone()))
+    }
+}
+
+/**
+ * Accumulator that links an element into the arena. The node is added
+ * when the element starts, so NodeIds are in document order, and the
+ * parent is filled in when the subelement is added to its parent.
+ * id:                      The element being accumulated
+ * element_name:            Name of the element
+ * element_lineno:          Line number of the start of the element
+ * current_subelement_name: Name of the subelement being parsed, if any
+ * pending:                 Text, comments and processing instructions seen
+ *                          since the last subelement
+ * nodes:                   The arena
+ */
+pub struct ArenaAccumulator {
+    id:                         NodeId,
+    element_name:               QName,
+    element_lineno:             LineNumber,
+    current_subelement_name:    Option<QName>,
+    pending:                    Vec<XmlEvent>,
+    nodes:                      Rc<RefCell<Vec<ArenaNode>>>,
+}
+
+impl ArenaAccumulator {
+    fn new(element_info: ElementInfo, depth: usize, nodes: Rc<RefCell<Vec<ArenaNode>>>) -> Self {
+        let element_name = element_info.qname();
+        let element_lineno = element_info.lineno();
+        let id = {
+            let mut nodes = nodes.borrow_mut();
+            nodes.push(ArenaNode::new(element_info, depth));
+            NodeId(nodes.len() - 1)
+        };
+        ArenaAccumulator {
+            id,
+            element_name,
+            element_lineno,
+            current_subelement_name: None,
+            pending: vec![],
+            nodes,
+        }
+    }
+}
+
+impl Accumulator for ArenaAccumulator {
+    type Value = NodeId;
+
+    fn start_subelement(&mut self, element_info: &ElementInfo) {
+        self.current_subelement_name = Some(element_info.qname());
+    }
+
+    fn add_subelement(&mut self, subelement: NodeId) {
+        let mut nodes = self.nodes.borrow_mut();
+        let previous = nodes[self.id.0].last_child;
+
+        let child = &mut nodes[subelement.0];
+        self.current_subelement_name = Some(child.element_info.qname());
+        child.before_element.append(&mut self.pending);
+        child.parent = Some(self.id);
+        child.previous_sibling = previous;
+
+        if let Some(previous) = previous {
+            nodes[previous.0].next_sibling = Some(subelement);
+        }
+        let node = &mut nodes[self.id.0];
+        node.first_child.get_or_insert(subelement);
+        node.last_child = Some(subelement);
+    }
+
+    fn end_subelement(&mut self) {
+        self.current_subelement_name = None;
+    }
+
+    fn has_open_subelement(&self) -> bool {
+        self.current_subelement_name.is_some()
+    }
+
+    fn current_subelement_name(&self) -> Option<&QName> {
+        self.current_subelement_name.as_ref()
+    }
+
+    fn finish(mut self) -> NodeId {
+        let mut nodes = self.nodes.borrow_mut();
+        let end = nodes.len();
+        let node = &mut nodes[self.id.0];
+        node.content.append(&mut self.pending);
+        node.end = end;
+        self.id
+    }
+
+    fn element_name(&self) -> &QName {
+        &self.element_name
+    }
+
+    fn element_lineno(&self) -> LineNumber {
+        self.element_lineno
+    }
+
+    fn characters(&mut self, characters: &str) {
+        self.pending.push(XmlEvent::Characters(characters.to_string()));
+    }
+
+    fn whitespace(&mut self, whitespace: &str) {
+        self.pending.push(XmlEvent::Whitespace(whitespace.to_string()));
+    }
+
+    fn cdata(&mut self, cdata: &str) {
+        self.pending.push(XmlEvent::CData(cdata.to_string()));
+    }
+
+    fn comment(&mut self, comment: &str) {
+        self.pending.push(XmlEvent::Comment(comment.to_string()));
+    }
+
+    fn processing_instruction(&mut self, name: &str, data: Option<&str>) {
+        self.pending.push(XmlEvent::ProcessingInstruction {
+            name: name.to_string(),
+            data: data.map(|d| d.to_string()),
+        });
+    }
+
+    fn prolog(&mut self, event: &XmlEvent) {
+        self.nodes.borrow_mut()[self.id.0].before_element.push(event.clone());
+    }
+
+    fn epilog(&mut self, event: &XmlEvent) {
+        self.nodes.borrow_mut()[self.id.0].after_element.push(event.clone());
+    }
+}
+
+/*
+ * Parse a string into an arena document, for tests. Trees come from
+ * crate::parse_tree::parse
+ */
+#[cfg(test)]
+pub(crate) fn parse_arena(input: &str) -> ArenaDocument {
+    ArenaDocument::parse(BufReader::new(io::Cursor::new(input.as_bytes())))
+        .expect("parse failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use std::fs::File;
+    use std::io::BufReader;
+    use xml::reader::XmlEvent;
+
+    use crate::element::Element;
+    use crate::navigate::TreeIndex;
+    use crate::parse_tree::{ParseTree, TreeLevelInfo};
+    use crate::xml_document_error::XmlDocumentError;
+    use crate::xpath::XPath;
+
+    use super::{parse_arena, ArenaDocument, NodeId};
+
+    fn names(document: &ArenaDocument, ids: impl Iterator<Item = NodeId>) -> Vec<&str> {
+        ids.map(|id| document.node(id).name()).collect()
+    }
+
+    #[test]
+    fn test_navigate() {
+        println!("Running test {}", function_name!());
+        let document = parse_arena("<a><b><c/><d/></b>text<e><f><g/></f></e><h/></a>");
+        assert_eq!(document.len(), 8);
+
+        let root = document.root();
+        assert_eq!(names(&document, document.children(root)), vec!["b", "e", "h"]);
+        assert_eq!(names(&document, document.descendants(root)),
+            vec!["b", "c", "d", "e", "f", "g", "h"]);
+
+        let e = document.children(root).nth(1).unwrap();
+        assert_eq!(e.index(), 4);
+        assert_eq!(document.previous_sibling(e).map(|id| document.node(id).name()), Some("b"));
+        assert_eq!(document.next_sibling(e), document.last_child(root));
+        assert_eq!(document.node(e).before_element, vec![XmlEvent::Characters("text".to_string())]);
+        assert_eq!(names(&document, document.descendants(e)), vec!["f", "g"]);
+
+        let g = document.descendants(e).last().unwrap();
+        assert_eq!(document.depth(g), 3);
+        assert_eq!(names(&document, document.ancestors(g)), vec!["f", "e", "a"]);
+        assert!(document.first_child(g).is_none());
+        assert!(document.parent(root).is_none());
+        assert!(document.previous_sibling(document.first_child(root).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_element() {
+        println!("Running test {}", function_name!());
+        let document = parse_arena("<!-- before --><a><b x=\"1\">one</b><b x=\"2\"/>\n</a>");
+        let root = document.root_element();
+        assert_eq!(root.name(), "a");
+        assert_eq!(root.before_element(), &vec![XmlEvent::Comment(" before ".to_string())]);
+        assert_eq!(root.subelements().len(), 2);
+        assert_eq!(root.get("b").unwrap().text(), "one");
+
+        // Existing Element code works on the arena
+        let nodes = XPath::new("//b[@x = '2']").unwrap().select(&root).unwrap();
+        assert_eq!(nodes.len(), 1);
+        let index = TreeIndex::new(&root);
+        assert_eq!(index.len(), 3);
+
+    }
+
+    #[test]
+    fn test_edit() {
+        println!("Running test {}", function_name!());
+        let mut document = parse_arena("<a><b x=\"1\">one</b><b x=\"2\"/>\n</a>");
+        let before = document.root_element();
+
+        // Changes to the document are seen by views made afterwards
+        let b = document.first_child(document.root()).unwrap();
+        document.node_mut(b).element_info.attributes[0].value = "3".to_string();
+        document.node_mut(b).content = vec![XmlEvent::Characters("three".to_string())];
+        let after = document.root_element();
+        assert_eq!(after.subelements()[0].attribute("x"), Some("3"));
+        assert_eq!(after.subelements()[0].text(), "three");
+        assert_eq!(before.subelements()[0].attribute("x"), Some("1"));
+        let mut output = Vec::new();
+        document.write(&mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("<b x=\"3\">three</b>"));
+
+        // A copy as a tree can be edited without changing the document
+        let mut tree = document.to_tree(document.root());
+        tree.remove_child(0).unwrap();
+        tree.set_attribute("y", "4").unwrap();
+        assert_eq!(tree.subelements().len(), 1);
+        assert_eq!(tree.attribute("y"), Some("4"));
+        assert_eq!(document.root_element().subelements().len(), 2);
+    }
+
+    #[test]
+    fn test_read_only() {
+        println!("Running test {}", function_name!());
+        let document = parse_arena("<a xmlns:p=\"urn:p\"><b/></a>");
+        let mut root: Box<dyn Element> = Box::new(document.root_element());
+        assert!(root.is_read_only());
+
+        // Edits give an error rather than panicking
+        let tree = document.to_tree(document.root());
+        let results = [
+            root.set_attribute("x", "1"),
+            root.remove_attribute("x").map(|_| ()),
+            root.rename("c"),
+            root.declare_namespace("q", "urn:q"),
+            root.remove_child(0).map(|_| ()),
+            root.move_child(0, 0),
+            root.insert_child(0, tree.clone()),
+            root.replace_child(0, tree.clone()).map(|_| ()),
+        ];
+        for result in results {
+            match result {
+                Err(XmlDocumentError::ReadOnly(name)) => assert_eq!(name, "a"),
+                other => panic!("Expected ReadOnly, got {:?}", other),
+            }
+        }
+
+        // So does adding an arena element to a tree
+        let mut tree = tree;
+        let b = document.root_element().subelements()[0].clone();
+        assert!(matches!(tree.insert_child(0, b), Err(XmlDocumentError::ReadOnly(_))));
+        assert_eq!(document.root_element().attribute("x"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "read-only")]
+    fn test_read_only_mut() {
+        println!("Running test {}", function_name!());
+        let document = parse_arena("<a><b/></a>");
+        let mut root: Box<dyn Element> = Box::new(document.root_element());
+        root.set_text("x");
+    }
+
+    #[test]
+    fn test_schema() {
+        println!("Running test {}", function_name!());
+        let path = "schema/SpaceSystem-patched.xsd";
+        let document = ArenaDocument::parse_path(path).expect("parse failed");
+        let mut arena_output = Vec::new();
+        document.write(&mut arena_output).unwrap();
+
+        let file = File::open(path).unwrap();
+        let tree = ParseTree::parse(BufReader::new(file), &TreeLevelInfo::new()).unwrap();
+        let mut tree_output = Vec::new();
+        ParseTree::new(tree.0, tree.1).write(&mut tree_output).unwrap();
+
+        assert_eq!(String::from_utf8(arena_output).unwrap(),
+            String::from_utf8(tree_output).unwrap());
+        assert_eq!(document.len(), TreeIndex::new(&document.root_element()).len());
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ * Write Graphviz DOT for an XML file to standard output, e.g.
+ *
+ *     xml-dot --depth 3 --attributes db.xml | dot -Tsvg > db.svg
+ *     xml-dot --derivation --root NameDescriptionType SpaceSystem.xsd
+ *
+ * The exit status is 0 on success and 2 if the file can't be read or
+ * drawn.
+ */
+
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+
+use xml_tree::{write_container_dot, write_schema_dot, write_tree_dot, DotOptions, ParseTree,
+    SchemaGraph, SchemaView, TreeLevelInfo, XmlDocumentError};
+
+const USAGE: &str = "\
+usage: xml-dot [--depth <n>] [--attributes] [--root <root>]
+               [--derivation | --containment | --containers] <file>
+
+Draw the element tree of <file>, or with --derivation or --containment,
+the type derivation or containment graph of the schema <file>, or with
+--containers, the inheritance of the XTCE SequenceContainers in <file>.
+--depth limits how far below the root to go and --attributes labels
+elements with their attributes. --root starts from a path of element
+names for trees, a component for schemas and a container for containers.";
+
+/*
+ * What to draw
+ */
+#[derive(Clone, Copy)]
+enum Drawing {
+    Tree,
+    Schema(SchemaView),
+    Containers,
+}
+
+fn main() -> ExitCode {
+    let mut options = DotOptions::default();
+    let mut drawing = Drawing::Tree;
+    let mut files = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" => match args.next().and_then(|depth| depth.parse().ok()) {
+                Some(depth) => options.max_depth = Some(depth),
+                None => return usage(),
+            },
+            "--attributes" => options.attributes = true,
+            "--root" => match args.next() {
+                Some(root) => options.root = Some(root),
+                None => return usage(),
+            },
+            "--derivation" => drawing = Drawing::Schema(SchemaView::Derivation),
+            "--containment" => drawing = Drawing::Schema(SchemaView::Containment),
+            "--containers" => drawing = Drawing::Containers,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            },
+            arg if arg.starts_with('-') && arg.len() > 1 => return usage(),
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 1 {
+        return usage();
+    }
+
+    match draw(&files[0], drawing, &options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("xml-dot: {}: {}", files[0], e);
+            ExitCode::from(2)
+        },
+    }
+}
+
+fn draw(path: &str, drawing: Drawing, options: &DotOptions) -> Result<(), XmlDocumentError> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    match drawing {
+        Drawing::Tree => {
+            let (_, root) = ParseTree::parse_path(path, &TreeLevelInfo::new())?;
+            write_tree_dot(&mut writer, root.as_ref(), options)?;
+        },
+        Drawing::Schema(view) => {
+            let schema_graph = SchemaGraph::from_path(path)?;
+            write_schema_dot(&mut writer, &schema_graph, view, options)?;
+        },
+        Drawing::Containers => {
+            let (_, root) = ParseTree::parse_path(path, &TreeLevelInfo::new())?;
+            write_container_dot(&mut writer, root.as_ref(), options)?;
+        },
+    }
+    writer.flush()
+        .map_err(|e| XmlDocumentError::IoError("standard output".to_string(), e.into()))
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
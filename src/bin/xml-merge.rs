@@ -0,0 +1,149 @@
+/*
+ * Three-way merge of XML files, for use as a git merge driver. Set it up
+ * with:
+ *
+ *     git config merge.xml.name "XML tree merge"
+ *     git config merge.xml.driver "xml-merge %O %A %B %L %P"
+ *
+ * and, in .gitattributes:
+ *
+ *     *.xsd merge=xml
+ *     *.xml merge=xml
+ *
+ * As git expects, the result is written over the file for ours. The exit
+ * status is 0 for a clean merge, 1 if there were conflicts, which are
+ * marked in the output by comments starting with "CONFLICT:", and 2 if the
+ * files couldn't be merged at all, in which case ours is left alone.
+ */
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::ExitCode;
+
+use xml_tree::{merge_paths, write_document, DiffOptions};
+
+const USAGE: &str = "\
+usage: xml-merge [--key <attribute>]... [--strict-whitespace] <base> <ours> <theirs>
+                 [<marker-size> [<path>]]
+
+Merge the changes from <base> to <theirs> into <ours>, writing the result to
+<ours>. Subelements are matched by the first --key attribute they have,
+\"name\" if no --key is given. <marker-size> is accepted for git and ignored;
+<path> is the name used in messages.";
+
+fn main() -> ExitCode {
+    merge_files(std::env::args().skip(1))
+}
+
+/*
+ * Merge the files given on the command line, returning the exit status
+ */
+fn merge_files(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let mut options = DiffOptions::default();
+    let mut keys = vec![];
+    let mut files = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key" => match args.next() {
+                Some(key) => keys.push(key),
+                None => return usage(),
+            },
+            "--strict-whitespace" => options.ignore_whitespace = false,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            },
+            arg if arg.starts_with('-') && arg.len() > 1 => return usage(),
+            _ => files.push(arg),
+        }
+    }
+    if !keys.is_empty() {
+        options.keys = keys;
+    }
+    if !(3..=5).contains(&files.len()) {
+        return usage();
+    }
+
+    let (base, ours, theirs) = (&files[0], &files[1], &files[2]);
+    let name = files.get(4).unwrap_or(ours);
+
+    let (document_info, result) = match merge_paths(base, ours, theirs, &options) {
+        Ok(merged) => merged,
+        Err(e) => {
+            eprintln!("xml-merge: {}: {}", name, e);
+            return ExitCode::from(2);
+        },
+    };
+
+    let written = File::create(ours).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_document(&mut writer, &document_info, result.root.as_ref())?;
+        writer.flush()
+    });
+    if let Err(e) = written {
+        eprintln!("xml-merge: {}: {}", name, e);
+        return ExitCode::from(2);
+    }
+
+    for conflict in &result.conflicts {
+        eprintln!("xml-merge: {}: CONFLICT {}", name, conflict);
+    }
+    match result.is_clean() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(1),
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use std::fs;
+    use std::process::ExitCode;
+
+    use super::merge_files;
+
+    #[test]
+    fn test_merge_files() {
+        println!("Running test {}", function_name!());
+        let dir = std::env::temp_dir().join(format!("xml-merge-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, text: &str| {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let run = |args: &[&str]| merge_files(args.iter().map(|arg| arg.to_string()));
+
+        // A clean merge is written over ours
+        let base = write("base.xml", "<a>\n  <b id=\"1\"/>\n  <b id=\"2\"/>\n</a>\n");
+        let ours = write("ours.xml", "<a>\n  <b id=\"1\" x=\"1\"/>\n  <b id=\"2\"/>\n</a>\n");
+        let theirs = write("theirs.xml", "<a>\n  <b id=\"2\"/>\n  <b id=\"1\"/>\n</a>\n");
+        assert_eq!(run(&["--key", "id", &base, &ours, &theirs, "7", "a.xml"]), ExitCode::SUCCESS);
+        assert_eq!(fs::read_to_string(&ours).unwrap(),
+            "<a>\n  <b id=\"2\"/>\n  <b id=\"1\" x=\"1\"/>\n</a>\n");
+
+        // Conflicts are marked in ours
+        let ours = write("ours.xml", "<a><b id=\"1\" x=\"1\"/></a>");
+        let theirs = write("theirs.xml", "<a><b id=\"1\" x=\"2\"/></a>");
+        assert_eq!(run(&["--key", "id", &base, &ours, &theirs]), ExitCode::from(1));
+        assert!(fs::read_to_string(&ours).unwrap().contains("<!-- CONFLICT: attribute \"x\""));
+
+        // Files that can't be parsed leave ours alone
+        let broken = write("theirs.xml", "<a>");
+        assert_eq!(run(&[&base, &ours, &broken]), ExitCode::from(2));
+        assert!(fs::read_to_string(&ours).unwrap().contains("CONFLICT"));
+
+        // Bad usage
+        assert_eq!(run(&[&base, &ours]), ExitCode::from(2));
+        assert_eq!(run(&["--key"]), ExitCode::from(2));
+        assert_eq!(run(&["--unknown", &base, &ours, &theirs]), ExitCode::from(2));
+        assert_eq!(run(&["--help"]), ExitCode::SUCCESS);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
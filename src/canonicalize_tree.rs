@@ -0,0 +1,483 @@
+/*
+ * Write an XML tree in canonical form, as defined by Canonical XML 1.0
+ * (https://www.w3.org/TR/xml-c14n) and Exclusive XML Canonicalization 1.0
+ * (https://www.w3.org/TR/xml-exc-c14n/). Documents that differ only in how
+ * they were written have the same canonical form, byte for byte, so it can
+ * be checksummed or signed. The canonical form:
+ *
+ * - is UTF-8, with no XML declaration
+ * - has no whitespace outside the root element. Each comment and
+ *   processing instruction outside it goes on a line of its own.
+ * - writes each element as a start tag and an end tag, never as <name/>
+ * - has namespace declarations first, sorted by prefix, then attributes,
+ *   sorted by namespace URI and then local name
+ * - has attribute values in double quotes, and CDATA sections written as
+ *   text, with only the characters that need it escaped and always the
+ *   same way
+ * - has no comments, unless they are asked for
+ *
+ * Line ends and attribute values are normalized by the parser, which keeps
+ * carriage returns, newlines and tabs written as character references, so
+ * those are written as references here. xml-rs doesn't read document type
+ * declarations, so attribute defaults and entities declared in one aren't
+ * applied and all attribute values are treated as CDATA. An element
+ * canonicalized on its own doesn't know the xml:lang, xml:space and
+ * xml:base attributes of its ancestors, so inclusive canonicalization
+ * can't copy them to it.
+ */
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use xml::namespace::{Namespace, NS_EMPTY_URI, NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XML_PREFIX};
+use xml::reader::XmlEvent;
+
+use crate::element::{Element, ElementInfo};
+use crate::write_tree::qualified_name;
+
+/**
+ * How to canonicalize
+ * exclusive:           Use Exclusive XML Canonicalization, which declares
+ *                      a namespace only on elements whose name or attributes
+ *                      use it, rather than on the outermost element it is in
+ *                      scope for
+ * with_comments:       Keep comments
+ * inclusive_prefixes:  With exclusive canonicalization, prefixes to treat as
+ *                      inclusive canonicalization would (the
+ *                      InclusiveNamespaces PrefixList). "#default" stands for
+ *                      the default namespace.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct CanonicalOptions {
+    pub exclusive:          bool,
+    pub with_comments:      bool,
+    pub inclusive_prefixes: Vec<String>,
+}
+
+impl CanonicalOptions {
+    /*
+     * Whether the declaration of a prefix in scope for an element is
+     * written for it, if not already in effect
+     */
+    fn declares(&self, element_info: &ElementInfo, prefix: &str) -> bool {
+        if !self.exclusive {
+            return true;
+        }
+        let listed = match prefix {
+            NS_NO_PREFIX => "#default",
+            _ => prefix,
+        };
+        self.inclusive_prefixes.iter().any(|inclusive| inclusive == listed) ||
+            element_info.owned_name.prefix.as_deref().unwrap_or(NS_NO_PREFIX) == prefix ||
+            element_info.attributes.iter()
+                .any(|attribute| attribute.name.prefix.as_deref() == Some(prefix))
+    }
+}
+
+/**
+ * Write a document in canonical form
+ * writer:  Where to write the document
+ * root:    Root element of the document
+ * options: How to canonicalize
+ */
+pub fn canonicalize_document<W: Write>(writer: &mut W, root: &dyn Element,
+    options: &CanonicalOptions) -> io::Result<()> {
+    for event in root.before_element() {
+        if is_outside_item(event, options) {
+            write_event(writer, event, options)?;
+            writeln!(writer)?;
+        }
+    }
+
+    write_tag(writer, root, &Namespace::empty(), options)?;
+
+    for event in root.after_element() {
+        if is_outside_item(event, options) {
+            writeln!(writer)?;
+            write_event(writer, event, options)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Write an element and everything in it in canonical form, as the
+ * document subset holding just that element. With inclusive
+ * canonicalization, all the namespaces in scope are declared on it.
+ */
+pub fn canonicalize_element<W: Write>(writer: &mut W, element: &dyn Element,
+    options: &CanonicalOptions) -> io::Result<()> {
+    write_tag(writer, element, &Namespace::empty(), options)
+}
+
+/*
+ * Write an element, given the namespace declarations in effect in the
+ * output for its parent
+ */
+fn write_tag<W: Write>(writer: &mut W, element: &dyn Element, rendered: &Namespace,
+    options: &CanonicalOptions) -> io::Result<()> {
+    let element_info = element.element_info();
+    let (start_tag, rendered) = start_tag(element_info, rendered, options);
+    write!(writer, "{}>", start_tag)?;
+
+    for subelement in element.subelements() {
+        for event in subelement.before_element() {
+            write_event(writer, event, options)?;
+        }
+        write_tag(writer, subelement.as_ref(), &rendered, options)?;
+    }
+    for event in element.content() {
+        write_event(writer, event, options)?;
+    }
+
+    write!(writer, "</{}>", qualified_name(&element_info.owned_name))
+}
+
+/*
+ * Start tag for an element, without the closing '>', and the namespace
+ * declarations in effect in the output for its subelements
+ * element_info:    Start of the element
+ * rendered:        Namespace declarations in effect in the output for the
+ *                  parent
+ * options:         How to canonicalize
+ */
+pub(crate) fn start_tag(element_info: &ElementInfo, rendered: &Namespace,
+    options: &CanonicalOptions) -> (String, Namespace) {
+    let mut start_tag = format!("<{}", qualified_name(&element_info.owned_name));
+    let mut in_effect = rendered.clone();
+
+    // Namespace's map is ordered by prefix, with the default namespace first
+    for (prefix, uri) in &element_info.namespace {
+        if prefix == NS_XML_PREFIX || prefix == NS_XMLNS_PREFIX ||
+            !options.declares(element_info, prefix) {
+            continue;
+        }
+        if prefix == NS_NO_PREFIX {
+            // An empty default namespace is only declared to undo another
+            if rendered.get(NS_NO_PREFIX).unwrap_or(NS_EMPTY_URI) == uri {
+                continue;
+            }
+            start_tag += &format!(" xmlns=\"{}\"", escape_attribute(uri));
+        } else {
+            if rendered.get(prefix) == Some(uri) {
+                continue;
+            }
+            start_tag += &format!(" xmlns:{}=\"{}\"", prefix, escape_attribute(uri));
+        }
+        in_effect.force_put(prefix, uri);
+    }
+
+    let mut attributes: Vec<_> = element_info.attributes.iter().collect();
+    attributes.sort_by_key(|attribute| (attribute.name.namespace.as_deref().unwrap_or(""),
+        attribute.name.local_name.as_str()));
+    for attribute in attributes {
+        start_tag += &format!(" {}=\"{}\"", qualified_name(&attribute.name),
+            escape_attribute(&attribute.value));
+    }
+
+    (start_tag, in_effect)
+}
+
+/*
+ * Whether an item outside the root element is written. Whitespace never
+ * is, and comments only when asked for.
+ */
+pub(crate) fn is_outside_item(event: &XmlEvent, options: &CanonicalOptions) -> bool {
+    match event {
+        XmlEvent::Comment(_) => options.with_comments,
+        XmlEvent::ProcessingInstruction { .. } => true,
+        _ => false,
+    }
+}
+
+/*
+ * Write text, a CDATA section, a comment or a processing instruction in
+ * canonical form
+ */
+pub(crate) fn write_event<W: Write>(writer: &mut W, event: &XmlEvent,
+    options: &CanonicalOptions) -> io::Result<()> {
+    match event {
+        XmlEvent::Characters(text) | XmlEvent::Whitespace(text) | XmlEvent::CData(text) =>
+            write!(writer, "{}", escape_text(text)),
+        XmlEvent::Comment(comment) if options.with_comments =>
+            write!(writer, "<!--{}-->", comment),
+        // xml-rs keeps the whitespace between the target and the data
+        XmlEvent::ProcessingInstruction { name, data } =>
+            match data.as_deref().map(str::trim_start) {
+                None | Some("") => write!(writer, "<?{}?>", name),
+                Some(data) => write!(writer, "<?{} {}?>", name, data),
+            },
+        _ => Ok(()),
+    }
+}
+
+/*
+ * Escape text, replacing '&', '<', '>' and carriage returns, which are
+ * only left after line end normalization if written as references
+ */
+fn escape_text(text: &str) -> Cow<'_, str> {
+    escape(text, text_replacement)
+}
+
+fn text_replacement(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '\r' => Some("&#xD;"),
+        _ => None,
+    }
+}
+
+/*
+ * Escape an attribute value, replacing '&', '<', '"' and the tabs,
+ * newlines and carriage returns left after normalization, which were
+ * written as references
+ */
+fn escape_attribute(value: &str) -> Cow<'_, str> {
+    escape(value, attribute_replacement)
+}
+
+fn attribute_replacement(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '"' => Some("&quot;"),
+        '\t' => Some("&#x9;"),
+        '\n' => Some("&#xA;"),
+        '\r' => Some("&#xD;"),
+        _ => None,
+    }
+}
+
+fn escape(s: &str, replacement: fn(char) -> Option<&'static str>) -> Cow<'_, str> {
+    if !s.chars().any(|c| replacement(c).is_some()) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut escaped = String::with_capacity(s.len() + 16);
+    for c in s.chars() {
+        match replacement(c) {
+            Some(replaced) => escaped.push_str(replaced),
+            None => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use crate::parse_tree::parse;
+
+    use super::{canonicalize_document, canonicalize_element, CanonicalOptions};
+
+    fn canonicalize(input: &str, options: &CanonicalOptions) -> String {
+        let mut output = Vec::new();
+        canonicalize_document(&mut output, parse(input).as_ref(), options)
+            .expect("canonicalize failed");
+        String::from_utf8(output).expect("output not UTF-8")
+    }
+
+    /*
+     * Examples from section 3 of the Canonical XML 1.0 specification. The
+     * ones that depend on a document type declaration have those parts
+     * left out.
+     */
+    const SPEC_3_1: &str = concat!(
+        "<?xml version=\"1.0\"?>\n",
+        "\n",
+        "<?xml-stylesheet   href=\"doc.xsl\"\n",
+        "   type=\"text/xsl\"   ?>\n",
+        "\n",
+        "<!DOCTYPE doc SYSTEM \"doc.dtd\">\n",
+        "\n",
+        "<doc>Hello, world!<!-- Comment 1 --></doc>\n",
+        "\n",
+        "<?pi-without-data     ?>\n",
+        "\n",
+        "<!-- Comment 2 -->\n",
+        "\n",
+        "<!-- Comment 3 -->\n");
+
+    const SPEC_3_3: &str = concat!(
+        "<doc>\n",
+        "   <e1   />\n",
+        "   <e2   ></e2>\n",
+        "   <e3   name = \"elem3\"   id=\"elem3\"   />\n",
+        "   <e4   name=\"elem4\"   id=\"elem4\"   ></e4>\n",
+        "   <e5 a:attr=\"out\" b:attr=\"sorted\" attr2=\"all\" attr=\"I'm\"\n",
+        "      xmlns:b=\"http://www.ietf.org\"\n",
+        "      xmlns:a=\"http://www.w3.org\"\n",
+        "      xmlns=\"http://example.org\"/>\n",
+        "   <e6 xmlns=\"\" xmlns:a=\"http://www.w3.org\">\n",
+        "      <e7 xmlns=\"http://www.ietf.org\">\n",
+        "         <e8 xmlns=\"\" xmlns:a=\"http://www.w3.org\">\n",
+        "            <e9 xmlns=\"\" xmlns:a=\"http://www.ietf.org\"/>\n",
+        "         </e8>\n",
+        "      </e7>\n",
+        "   </e6>\n",
+        "</doc>\n");
+
+    const SPEC_3_3_CANONICAL: &str = concat!(
+        "<doc>\n",
+        "   <e1></e1>\n",
+        "   <e2></e2>\n",
+        "   <e3 id=\"elem3\" name=\"elem3\"></e3>\n",
+        "   <e4 id=\"elem4\" name=\"elem4\"></e4>\n",
+        "   <e5 xmlns=\"http://example.org\" xmlns:a=\"http://www.w3.org\" ",
+        "xmlns:b=\"http://www.ietf.org\" attr=\"I'm\" attr2=\"all\" b:attr=\"sorted\" ",
+        "a:attr=\"out\"></e5>\n",
+        "   <e6 xmlns:a=\"http://www.w3.org\">\n",
+        "      <e7 xmlns=\"http://www.ietf.org\">\n",
+        "         <e8 xmlns=\"\">\n",
+        "            <e9 xmlns:a=\"http://www.ietf.org\"></e9>\n",
+        "         </e8>\n",
+        "      </e7>\n",
+        "   </e6>\n",
+        "</doc>");
+
+    #[test]
+    fn test_outside_root() {
+        println!("Running test {}", function_name!());
+        let without = CanonicalOptions::default();
+        assert_eq!(canonicalize(SPEC_3_1, &without), concat!(
+            "<?xml-stylesheet href=\"doc.xsl\"\n",
+            "   type=\"text/xsl\"   ?>\n",
+            "<doc>Hello, world!</doc>\n",
+            "<?pi-without-data?>"));
+
+        let with = CanonicalOptions { with_comments: true, ..Default::default() };
+        assert_eq!(canonicalize(SPEC_3_1, &with), concat!(
+            "<?xml-stylesheet href=\"doc.xsl\"\n",
+            "   type=\"text/xsl\"   ?>\n",
+            "<doc>Hello, world!<!-- Comment 1 --></doc>\n",
+            "<?pi-without-data?>\n",
+            "<!-- Comment 2 -->\n",
+            "<!-- Comment 3 -->"));
+    }
+
+    #[test]
+    fn test_whitespace() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<doc>\n",
+            "   <clean>   </clean>\n",
+            "   <dirty>   A   B   </dirty>\n",
+            "   <mixed>\n",
+            "      A\n",
+            "      <clean>   </clean>\n",
+            "      B\n",
+            "      <dirty>   A   B   </dirty>\n",
+            "      C\n",
+            "   </mixed>\n",
+            "</doc>\n");
+        assert_eq!(canonicalize(input, &CanonicalOptions::default()), input.trim_end());
+    }
+
+    #[test]
+    fn test_tags() {
+        println!("Running test {}", function_name!());
+        assert_eq!(canonicalize(SPEC_3_3, &CanonicalOptions::default()), SPEC_3_3_CANONICAL);
+    }
+
+    #[test]
+    fn test_characters() {
+        println!("Running test {}", function_name!());
+        // Section 3.4, without the elements whose attributes are declared
+        // in the document type declaration
+        let input = concat!(
+            "<doc>\n",
+            "   <value>&#x32;</value>\n",
+            "   <compute><![CDATA[value>\"0\" && value<\"10\" ?\"valid\":\"error\"]]></compute>\n",
+            "   <compute expr='value>\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"'>",
+            "valid</compute>\n",
+            "</doc>\n");
+        assert_eq!(canonicalize(input, &CanonicalOptions::default()), concat!(
+            "<doc>\n",
+            "   <value>2</value>\n",
+            "   <compute>value&gt;\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"",
+            "</compute>\n",
+            "   <compute expr=\"value>&quot;0&quot; &amp;&amp; value&lt;&quot;10&quot; ",
+            "?&quot;valid&quot;:&quot;error&quot;\">valid</compute>\n",
+            "</doc>"));
+
+        // Section 3.6: the output is always UTF-8
+        let input = "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><doc>&#169;</doc>";
+        assert_eq!(canonicalize(input, &CanonicalOptions::default()), "<doc>\u{a9}</doc>");
+
+        // Line ends are normalized, and whitespace in attribute values
+        // becomes spaces
+        let expected = "<a b=\"1 2 3\">x\ny\nz</a>";
+        assert_eq!(canonicalize("<a b='1\r\n2\t3'>x\r\ny\rz</a>", &CanonicalOptions::default()),
+            expected);
+        assert_eq!(canonicalize("<a b='1\n2\t3'>x\ny\nz</a>", &CanonicalOptions::default()),
+            expected);
+
+        // Whitespace written as character references is kept
+        let input = concat!(
+            "<doc><text>First line&#x0d;&#10;Second line</text>",
+            "<norm attr=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/></doc>");
+        assert_eq!(canonicalize(input, &CanonicalOptions::default()), concat!(
+            "<doc><text>First line&#xD;\nSecond line</text>",
+            "<norm attr=\" '    &#xD;&#xA;&#x9;   ' \"></norm></doc>"));
+    }
+
+    #[test]
+    fn test_exclusive() {
+        println!("Running test {}", function_name!());
+        // The example from section 2.2 of the Exclusive XML
+        // Canonicalization specification
+        let root = parse(concat!(
+            "<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\">",
+            "<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">",
+            "<n3:stuff xmlns:n3=\"ftp://example.org\"/>",
+            "</n1:elem2>",
+            "</n0:local>"));
+        let elem2 = root.subelements()[0].as_ref();
+        let subset = |options: &CanonicalOptions| {
+            let mut output = Vec::new();
+            canonicalize_element(&mut output, elem2, options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(subset(&CanonicalOptions::default()), concat!(
+            "<n1:elem2 xmlns:n0=\"foo:bar\" xmlns:n1=\"http://example.net\" ",
+            "xmlns:n3=\"ftp://example.org\" xml:lang=\"en\">",
+            "<n3:stuff></n3:stuff>",
+            "</n1:elem2>"));
+
+        let exclusive = CanonicalOptions { exclusive: true, ..Default::default() };
+        assert_eq!(subset(&exclusive), concat!(
+            "<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">",
+            "<n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff>",
+            "</n1:elem2>"));
+
+        // Prefixes in the InclusiveNamespaces PrefixList are declared as
+        // for inclusive canonicalization
+        let listed = CanonicalOptions {
+            exclusive: true,
+            inclusive_prefixes: vec!["n0".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(subset(&listed), concat!(
+            "<n1:elem2 xmlns:n0=\"foo:bar\" xmlns:n1=\"http://example.net\" xml:lang=\"en\">",
+            "<n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff>",
+            "</n1:elem2>"));
+
+        // A default namespace is only declared where an unprefixed element
+        // uses it, and only undone where an unprefixed element needs that
+        let input = concat!(
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\">",
+            "<p:b p:x=\"1\"><c xmlns=\"\"><p:d/></c></p:b>",
+            "</a>");
+        assert_eq!(canonicalize(input, &exclusive), concat!(
+            "<a xmlns=\"urn:a\">",
+            "<p:b xmlns:p=\"urn:p\" p:x=\"1\"><c xmlns=\"\"><p:d></p:d></c></p:b>",
+            "</a>"));
+        assert_eq!(canonicalize(input, &CanonicalOptions::default()), concat!(
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\">",
+            "<p:b p:x=\"1\"><c xmlns=\"\"><p:d></p:d></c></p:b>",
+            "</a>"));
+    }
+}
@@ -0,0 +1,1346 @@
+/*
+ * serde Deserializer that reads XML straight from the parse_item::Parser
+ * event stream, without building a tree. The mapping is:
+ *
+ * o    The root element is the value deserialized. Its name isn't checked.
+ * o    A struct is an element. A field named "@name" is the attribute
+ *      "name", a field named "$text" is the text directly within the
+ *      element, and any other field is the subelement with that name.
+ *      Namespaces are ignored; attributes and subelements are matched by
+ *      their local names, and a prefix in a field name, e.g. "@xsi:type",
+ *      isn't part of the match. Attributes and subelements with no
+ *      matching field are skipped.
+ * o    A field named "$value" takes the subelements that don't match any
+ *      other field. It is usually an enum, or a Vec of one, where the
+ *      name of each element picks the variant.
+ * o    A Vec field takes a run of adjacent subelements with the same name.
+ *      If subelements with that name appear again after something else,
+ *      it is reported as a duplicate field. A Vec of numbers, booleans or
+ *      strings as an attribute or "$text" is a whitespace-separated list,
+ *      as for xs:list.
+ * o    An Option field is None if the attribute or subelement is missing,
+ *      or for "$text", if the element has no text. Any other "$text"
+ *      field takes empty text, or the whitespace in an element with
+ *      nothing else in it. In an element with xml:space="preserve",
+ *      whitespace between subelements is text, too.
+ * o    Numbers, booleans (true, false, 1 or 0) and strings are parsed from
+ *      the text of an element or the value of an attribute. Leading and
+ *      trailing whitespace is ignored for everything but strings.
+ * o    An enum is either the name of a unit variant, as text or as an
+ *      attribute value, or an element holding one subelement whose name
+ *      is the variant and whose content is the variant's data.
+ * o    A map takes attributes as "@name", subelements by name and text as
+ *      "$text", like a struct with every field.
+ * o    Text within an element that also has subelements has to be in one
+ *      place; text in more than one place is an error. Comments and
+ *      processing instructions are ignored everywhere.
+ *
+ * Errors from within an element are reported with the position of the
+ * element's start tag.
+ */
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use serde::de::value::{SeqDeserializer, StringDeserializer};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::vec;
+use xml::attribute::OwnedAttribute;
+use xml::namespace::NS_XML_URI;
+use xml::reader::XmlEvent;
+
+use crate::element::ElementInfo;
+use crate::parse_item::{Parser, SourcePosition, TreeElement};
+use crate::write_tree::qualified_name;
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * Deserialize a value from XML in a string
+ */
+pub fn from_str<T: DeserializeOwned>(s: &str) -> Result<T, XmlDocumentError> {
+    from_reader(BufReader::new(Cursor::new(s.as_bytes())))
+}
+
+/**
+ * Deserialize a value from XML read from a reader
+ */
+pub fn from_reader<R: Read, T: DeserializeOwned>(reader: R) -> Result<T, XmlDocumentError> {
+    let mut deserializer = Deserializer::new(reader);
+    T::deserialize(&mut deserializer)
+}
+
+/**
+ * Deserialize a value from an XML file
+ */
+pub fn from_path<T: DeserializeOwned>(path: &str) -> Result<T, XmlDocumentError> {
+    match File::open(path) {
+        Err(e) => Err(XmlDocumentError::IoError(path.to_string(), Arc::new(e))),
+        Ok(file) => from_reader(BufReader::new(file)),
+    }
+}
+
+/**
+ * Deserializer for a document. serde::Deserializer is implemented for
+ * &mut Deserializer.
+ * parser:  Source of the XML events
+ * skipped: Whitespace passed over looking for the next subelement of a
+ *          sequence, which is text if the sequence ended there
+ */
+pub struct Deserializer<R: Read> {
+    parser:     Parser<R>,
+    skipped:    String,
+}
+
+impl<R: Read> Deserializer<R> {
+    pub fn new(reader: R) -> Self {
+        Deserializer {
+            parser:     Parser::new(reader),
+            skipped:    String::new(),
+        }
+    }
+
+    /*
+     * Read up to and including the start of the root element
+     */
+    fn root(&mut self) -> Result<ElementInfo, XmlDocumentError> {
+        loop {
+            let item = self.parser.next()?;
+            match item.event {
+                XmlEvent::StartDocument { .. } |
+                    XmlEvent::Whitespace(_) |
+                    XmlEvent::Comment(_) |
+                    XmlEvent::ProcessingInstruction { .. } => {},
+                XmlEvent::StartElement { name, attributes, namespace } =>
+                    return Ok(ElementInfo::new(name, item.span, attributes, namespace)),
+                event => return Err(XmlDocumentError::UnexpectedXml(item.span.start,
+                    Box::new(event))),
+            }
+        }
+    }
+
+    /*
+     * Read what follows the root element, which can only be comments,
+     * processing instructions and whitespace
+     */
+    fn end(&mut self) -> Result<(), XmlDocumentError> {
+        loop {
+            let item = self.parser.next()?;
+            match item.event {
+                XmlEvent::EndDocument => return Ok(()),
+                XmlEvent::Whitespace(_) |
+                    XmlEvent::Comment(_) |
+                    XmlEvent::ProcessingInstruction { .. } => {},
+                event => return Err(XmlDocumentError::UnexpectedXml(item.span.start,
+                    Box::new(event))),
+            }
+        }
+    }
+
+    /*
+     * Read the start of a subelement, which is the lookahead item
+     */
+    fn start_subelement(&mut self) -> Result<ElementInfo, XmlDocumentError> {
+        let item = self.parser.next()?;
+        match item.event {
+            XmlEvent::StartElement { name, attributes, namespace } =>
+                Ok(ElementInfo::new(name, item.span, attributes, namespace)),
+            event => Err(XmlDocumentError::UnexpectedXml(item.span.start, Box::new(event))),
+        }
+    }
+
+    /*
+     * Skip whitespace, comments and processing instructions, returning the
+     * next item, which is left as the lookahead item. The whitespace is
+     * kept in skipped.
+     */
+    fn skip_ignorable(&mut self) -> Result<TreeElement, XmlDocumentError> {
+        loop {
+            let item = self.parser.lookahead()?;
+            match item.event {
+                XmlEvent::Whitespace(s) => {
+                    self.skipped.push_str(&s);
+                    self.parser.skip();
+                },
+                XmlEvent::Comment(_) |
+                    XmlEvent::ProcessingInstruction { .. } => self.parser.skip(),
+                _ => return Ok(item),
+            }
+        }
+    }
+
+    /*
+     * Read text up to the next element start or end, skipping comments
+     * and processing instructions. The start or end is left as the
+     * lookahead item.
+     */
+    fn text_run(&mut self) -> Result<String, XmlDocumentError> {
+        let mut text = std::mem::take(&mut self.skipped);
+        loop {
+            match self.parser.lookahead()?.event {
+                XmlEvent::Characters(s) | XmlEvent::CData(s) | XmlEvent::Whitespace(s) =>
+                    text.push_str(&s),
+                XmlEvent::Comment(_) | XmlEvent::ProcessingInstruction { .. } => {},
+                _ => return Ok(text),
+            }
+            self.parser.skip();
+        }
+    }
+
+    /*
+     * Read the rest of an element that should hold only text, and return
+     * the text
+     */
+    fn element_text(&mut self, element_info: &ElementInfo) -> Result<String, XmlDocumentError> {
+        let text = self.text_run()?;
+        let item = self.parser.next()?;
+        match item.event {
+            XmlEvent::EndElement { .. } => Ok(text),
+            XmlEvent::StartElement { name, .. } => Err(XmlDocumentError::Deserialize(
+                item.span.start, qualified_name(&element_info.owned_name).to_string(),
+                format!("expected text, found <{}>", qualified_name(&name)))),
+            event => Err(XmlDocumentError::UnexpectedXml(item.span.start, Box::new(event))),
+        }
+    }
+
+    /*
+     * Skip the rest of an element, including its end
+     */
+    fn skip_element(&mut self) -> Result<(), XmlDocumentError> {
+        self.parser.skip_content()?;
+        self.parser.next()?;
+        Ok(())
+    }
+}
+
+/*
+ * Deserialize the root element with a serde::Deserializer method and then
+ * check the end of the document
+ */
+macro_rules! deserialize_root {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+            let element_info = self.root()?;
+            let value = ElementDeserializer::new(self, element_info).$method(visitor)?;
+            self.end()?;
+            Ok(value)
+        }
+    )*};
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
+    type Error = XmlDocumentError;
+
+    deserialize_root! {
+        deserialize_any deserialize_bool
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_option deserialize_unit
+        deserialize_seq deserialize_map deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        let element_info = self.root()?;
+        let value = ElementDeserializer::new(self, element_info)
+            .deserialize_unit_struct(name, visitor)?;
+        self.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        let element_info = self.root()?;
+        let value = ElementDeserializer::new(self, element_info)
+            .deserialize_newtype_struct(name, visitor)?;
+        self.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        let element_info = self.root()?;
+        let value = ElementDeserializer::new(self, element_info).deserialize_tuple(len, visitor)?;
+        self.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, name: &'static str, len: usize,
+        visitor: V) -> Result<V::Value, XmlDocumentError> {
+        let element_info = self.root()?;
+        let value = ElementDeserializer::new(self, element_info)
+            .deserialize_tuple_struct(name, len, visitor)?;
+        self.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        let element_info = self.root()?;
+        let value = ElementDeserializer::new(self, element_info)
+            .deserialize_struct(name, fields, visitor)?;
+        self.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str,
+        variants: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        let element_info = self.root()?;
+        let value = ElementDeserializer::new(self, element_info)
+            .deserialize_enum(name, variants, visitor)?;
+        self.end()?;
+        Ok(value)
+    }
+}
+
+/*
+ * Give a serde message the position and name of the element it is about.
+ * Errors that already have a position are left alone.
+ */
+fn locate(err: XmlDocumentError, position: SourcePosition, name: &str) -> XmlDocumentError {
+    match err {
+        XmlDocumentError::Custom(message) =>
+            XmlDocumentError::Deserialize(position, name.to_string(), message),
+        err => err,
+    }
+}
+
+/*
+ * Deserializer for an element whose start has been read. Deserializing
+ * it reads up to and including its end.
+ * de:              Where the element is read from
+ * element_info:    Start of the element
+ * variant:         True if the element's name is the variant when it is
+ *                  deserialized as an enum, as it is for "$value" fields
+ */
+struct ElementDeserializer<'a, R: Read> {
+    de:             &'a mut Deserializer<R>,
+    element_info:   ElementInfo,
+    variant:        bool,
+}
+
+impl<'a, R: Read> ElementDeserializer<'a, R> {
+    fn new(de: &'a mut Deserializer<R>, element_info: ElementInfo) -> Self {
+        ElementDeserializer {
+            de,
+            element_info,
+            variant: false,
+        }
+    }
+
+    /*
+     * Run a function with this deserializer, giving any serde message
+     * from it the position of the element
+     */
+    fn located<T, F>(self, f: F) -> Result<T, XmlDocumentError>
+    where
+        F: FnOnce(Self) -> Result<T, XmlDocumentError>,
+    {
+        let position = self.element_info.span.start;
+        let name = qualified_name(&self.element_info.owned_name).to_string();
+        f(self).map_err(|e| locate(e, position, &name))
+    }
+
+    /*
+     * Read the rest of the element, skipping anything left in it
+     */
+    fn finish(self) -> Result<(), XmlDocumentError> {
+        self.de.skip_element()
+    }
+}
+
+/*
+ * Deserialize the text of an element
+ */
+macro_rules! deserialize_element_text {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+            self.located(|this| {
+                let text = this.de.element_text(&this.element_info)?;
+                TextDeserializer(text).$method(visitor)
+            })
+        }
+    )*};
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for ElementDeserializer<'_, R> {
+    type Error = XmlDocumentError;
+
+    deserialize_element_text! {
+        deserialize_bool
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_identifier
+    }
+
+    // An element with attributes or subelements is a map, anything else
+    // is a string
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.located(|this| {
+            let attributes = this.element_info.attributes.clone();
+            if !attributes.is_empty() {
+                return visitor.visit_map(ElementMap::new(this.de, attributes, &[], None));
+            }
+
+            let text = this.de.text_run()?;
+            match this.de.parser.lookahead()?.event {
+                XmlEvent::StartElement { .. } =>
+                    visitor.visit_map(ElementMap::new(this.de, vec![], &[], Some(text))),
+                _ => {
+                    this.de.parser.next()?;
+                    visitor.visit_string(text)
+                },
+            }
+        })
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        self.finish()?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.finish()?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // The subelements, whatever their names, or if there are none, a
+    // whitespace-separated list
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.located(|this| {
+            let text = this.de.text_run()?;
+            match this.de.parser.lookahead()?.event {
+                XmlEvent::StartElement { .. } => {
+                    let value = visitor.visit_seq(ChildSeq {
+                        de:         &mut *this.de,
+                        matcher:    Matcher::Any,
+                    })?;
+                    this.finish()?;
+                    Ok(value)
+                },
+                _ => {
+                    this.de.parser.next()?;
+                    TextDeserializer(text).deserialize_seq(visitor)
+                },
+            }
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize,
+        visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.deserialize_struct("", &[], visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.located(|this| {
+            visitor.visit_map(ElementMap::new(this.de, this.element_info.attributes, fields, None))
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+        _variants: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        if self.variant {
+            return self.located(|this| visitor.visit_enum(ElementEnum {
+                de:             this.de,
+                element_info:   this.element_info,
+            }));
+        }
+
+        self.located(|this| {
+            let text = this.de.text_run()?;
+            match this.de.parser.lookahead()?.event {
+                XmlEvent::StartElement { .. } if text.trim().is_empty() => {
+                    let element_info = this.de.start_subelement()?;
+                    let value = visitor.visit_enum(ElementEnum {
+                        de: &mut *this.de,
+                        element_info,
+                    })?;
+                    this.finish()?;
+                    Ok(value)
+                },
+                _ => {
+                    this.finish()?;
+                    TextDeserializer(text).deserialize_enum("", &[], visitor)
+                },
+            }
+        })
+    }
+}
+
+/*
+ * Enum given by an element, whose name is the variant
+ */
+struct ElementEnum<'a, R: Read> {
+    de:             &'a mut Deserializer<R>,
+    element_info:   ElementInfo,
+}
+
+impl<'de, 'a, R: Read> de::EnumAccess<'de> for ElementEnum<'a, R> {
+    type Error = XmlDocumentError;
+    type Variant = ElementDeserializer<'a, R>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) ->
+        Result<(S::Value, Self::Variant), XmlDocumentError> {
+        let name: StringDeserializer<XmlDocumentError> =
+            self.element_info.owned_name.local_name.clone().into_deserializer();
+        let variant = seed.deserialize(name)?;
+        Ok((variant, ElementDeserializer::new(self.de, self.element_info)))
+    }
+}
+
+impl<'de, R: Read> de::VariantAccess<'de> for ElementDeserializer<'_, R> {
+    type Error = XmlDocumentError;
+
+    fn unit_variant(self) -> Result<(), XmlDocumentError> {
+        self.finish()
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) ->
+        Result<S::Value, XmlDocumentError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        de::Deserializer::deserialize_struct(self, "", fields, visitor)
+    }
+}
+
+/*
+ * Value of the key last returned by an ElementMap
+ */
+enum Pending {
+    Text(String),
+    NoText(String),
+    Subelements(Matcher),
+}
+
+/*
+ * Which subelements make up a value
+ */
+enum Matcher {
+    Any,
+    Name(String),
+    Others(&'static [&'static str]),
+}
+
+impl Matcher {
+    fn matches(&self, local_name: &str) -> bool {
+        match self {
+            Matcher::Any => true,
+            Matcher::Name(name) => name == local_name,
+            Matcher::Others(fields) => field_for(fields, false, local_name).is_none(),
+        }
+    }
+}
+
+/*
+ * The field for an attribute or subelement with the given local name. A
+ * prefix on the field name, e.g. "xtce:Parameter" or "@xsi:type", isn't
+ * part of the match.
+ */
+fn field_for(fields: &'static [&'static str], attribute: bool, local_name: &str) ->
+    Option<&'static str> {
+    let local_part = |name: &str| name.split_once(':').map_or(name, |(_, local)| local)
+        == local_name;
+    fields.iter().copied().find(|field| match field.strip_prefix('@') {
+        Some(name) => attribute && local_part(name),
+        None => !attribute && !field.starts_with('$') && local_part(field),
+    })
+}
+
+/*
+ * Attributes, text and subelements of an element, as a map. Reading the
+ * last entry reads the end of the element.
+ * de:          Where the element is read from
+ * attributes:  Attributes not yet returned
+ * fields:      Field names of the struct being deserialized, or empty for
+ *              a map
+ * text:        Text already read from the start of the element, if any
+ * text_seen:   True once the text of the element has been returned
+ * preserve:    True if the element has xml:space="preserve", so that
+ *              whitespace is text even if there are subelements
+ * nested:      True once a subelement has been found
+ * pending:     Value of the last key returned
+ */
+struct ElementMap<'a, R: Read> {
+    de:         &'a mut Deserializer<R>,
+    attributes: vec::IntoIter<OwnedAttribute>,
+    fields:     &'static [&'static str],
+    text:       Option<String>,
+    text_seen:  bool,
+    preserve:   bool,
+    nested:     bool,
+    pending:    Option<Pending>,
+}
+
+impl<'a, R: Read> ElementMap<'a, R> {
+    fn new(de: &'a mut Deserializer<R>, mut attributes: Vec<OwnedAttribute>,
+        fields: &'static [&'static str], text: Option<String>) -> Self {
+        // xml:space is only a field if the struct asks for it
+        let is_space = |attribute: &OwnedAttribute| attribute.name.local_name == "space" &&
+            attribute.name.namespace.as_deref() == Some(NS_XML_URI);
+        let preserve = attributes.iter()
+            .any(|attribute| is_space(attribute) && attribute.value == "preserve");
+        attributes.retain(|attribute| !is_space(attribute) ||
+            field_for(fields, true, "space").is_some());
+
+        ElementMap {
+            de,
+            attributes: attributes.into_iter(),
+            fields,
+            text,
+            text_seen:  false,
+            preserve,
+            nested:     false,
+            pending:    None,
+        }
+    }
+
+    fn key<'de, S: DeserializeSeed<'de>>(&mut self, seed: S, key: String, pending: Pending) ->
+        Result<Option<S::Value>, XmlDocumentError> {
+        self.pending = Some(pending);
+        seed.deserialize(IntoDeserializer::<XmlDocumentError>::into_deserializer(key)).map(Some)
+    }
+}
+
+impl<'de, R: Read> de::MapAccess<'de> for ElementMap<'_, R> {
+    type Error = XmlDocumentError;
+
+    fn next_key_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) ->
+        Result<Option<S::Value>, XmlDocumentError> {
+        if let Some(attribute) = self.attributes.next() {
+            let key = match field_for(self.fields, true, &attribute.name.local_name) {
+                Some(field) => field.to_string(),
+                None => format!("@{}", attribute.name.local_name),
+            };
+            return self.key(seed, key, Pending::Text(attribute.value));
+        }
+
+        let text = match self.text.take() {
+            Some(text) => text,
+            None => self.de.text_run()?,
+        };
+        let preserved = self.preserve && !text.is_empty() && !self.text_seen &&
+            self.fields.contains(&"$text");
+        if preserved || !text.trim().is_empty() {
+            if self.text_seen {
+                return Err(XmlDocumentError::Custom(format!(
+                    "text \"{}\" in more than one place", text.trim())));
+            }
+            self.text_seen = true;
+            return self.key(seed, "$text".to_string(), Pending::Text(text));
+        }
+
+        let item = self.de.parser.lookahead()?;
+        match item.event {
+            XmlEvent::StartElement { name, .. } => {
+                self.nested = true;
+                let local_name = name.local_name;
+                match field_for(self.fields, false, &local_name) {
+                    Some(field) => self.key(seed, field.to_string(),
+                        Pending::Subelements(Matcher::Name(local_name))),
+                    None if !self.fields.contains(&"$value") => self.key(seed,
+                        local_name.clone(), Pending::Subelements(Matcher::Name(local_name))),
+                    None => self.key(seed, "$value".to_string(),
+                        Pending::Subelements(Matcher::Others(self.fields))),
+                }
+            },
+            XmlEvent::EndElement { .. } if self.fields.contains(&"$text") && !self.text_seen => {
+                // Give "$text" the whitespace, if that's all there is, so
+                // that a struct with a text field can have empty text. The
+                // end of the element is read with the next key.
+                self.text_seen = true;
+                let text = match self.nested {
+                    false => text,
+                    true => String::new(),
+                };
+                self.key(seed, "$text".to_string(), Pending::NoText(text))
+            },
+            XmlEvent::EndElement { .. } => {
+                self.de.parser.skip();
+                Ok(None)
+            },
+            event => Err(XmlDocumentError::UnexpectedXml(item.span.start, Box::new(event))),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) ->
+        Result<S::Value, XmlDocumentError> {
+        match self.pending.take() {
+            Some(Pending::Text(text)) => seed.deserialize(TextDeserializer(text)),
+            Some(Pending::NoText(text)) => seed.deserialize(NoTextDeserializer(text)),
+            Some(Pending::Subelements(matcher)) => seed.deserialize(SubelementsDeserializer {
+                de: &mut *self.de,
+                matcher,
+            }),
+            None => Err(XmlDocumentError::Custom("value requested before key".to_string())),
+        }
+    }
+}
+
+/*
+ * Deserializer for the value of a subelement key in an ElementMap, which
+ * is the start of the subelement. A sequence takes the run of adjacent
+ * subelements that match; anything else takes just the first.
+ */
+struct SubelementsDeserializer<'a, R: Read> {
+    de:         &'a mut Deserializer<R>,
+    matcher:    Matcher,
+}
+
+impl<'a, R: Read> SubelementsDeserializer<'a, R> {
+    fn subelement(self) -> Result<ElementDeserializer<'a, R>, XmlDocumentError> {
+        let element_info = self.de.start_subelement()?;
+        Ok(ElementDeserializer {
+            de:         self.de,
+            element_info,
+            variant:    matches!(self.matcher, Matcher::Others(_)),
+        })
+    }
+}
+
+/*
+ * Deserialize the first subelement
+ */
+macro_rules! deserialize_subelement {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+            self.subelement()?.$method(visitor)
+        }
+    )*};
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for SubelementsDeserializer<'_, R> {
+    type Error = XmlDocumentError;
+
+    deserialize_subelement! {
+        deserialize_any deserialize_bool
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_unit deserialize_map
+        deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        self.subelement()?.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        visitor.visit_seq(ChildSeq {
+            de:         self.de,
+            matcher:    self.matcher,
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize,
+        visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.subelement()?.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str,
+        variants: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.subelement()?.deserialize_enum(name, variants, visitor)
+    }
+}
+
+/*
+ * Run of adjacent subelements that match, as a sequence
+ */
+struct ChildSeq<'a, R: Read> {
+    de:         &'a mut Deserializer<R>,
+    matcher:    Matcher,
+}
+
+impl<'de, R: Read> de::SeqAccess<'de> for ChildSeq<'_, R> {
+    type Error = XmlDocumentError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) ->
+        Result<Option<S::Value>, XmlDocumentError> {
+        match self.de.skip_ignorable()?.event {
+            XmlEvent::StartElement { name, .. } if self.matcher.matches(&name.local_name) => {
+                self.de.skipped.clear();
+                let element_info = self.de.start_subelement()?;
+                let subelement = ElementDeserializer {
+                    de:         &mut *self.de,
+                    element_info,
+                    variant:    matches!(self.matcher, Matcher::Others(_)),
+                };
+                seed.deserialize(subelement).map(Some)
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+/*
+ * Deserializer for an attribute value or text
+ */
+struct TextDeserializer(String);
+
+impl TextDeserializer {
+    fn parse<T>(&self) -> Result<T, XmlDocumentError>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.0.trim().parse().map_err(|e| {
+            XmlDocumentError::Custom(format!("bad value \"{}\": {}", self.0.trim(), e))
+        })
+    }
+}
+
+/*
+ * Deserialize text by parsing it
+ */
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+            visitor.$visit(self.parse()?)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for TextDeserializer {
+    type Error = XmlDocumentError;
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        visitor.visit_string(self.0)
+    }
+
+    // As for xs:boolean
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        match self.0.trim() {
+            "true" | "1" => visitor.visit_bool(true),
+            "false" | "0" => visitor.visit_bool(false),
+            _ => Err(de::Error::invalid_value(Unexpected::Str(&self.0), &visitor)),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        let mut chars = self.0.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(de::Error::invalid_value(Unexpected::Str(&self.0), &visitor)),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_byte_buf(self.0.into_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_byte_buf(self.0.into_bytes())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // A whitespace-separated list, as for xs:list
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        let items: Vec<TextDeserializer> = self.0.split_whitespace()
+            .map(|item| TextDeserializer(item.to_string()))
+            .collect();
+        let mut seq = SeqDeserializer::new(items.into_iter());
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize,
+        visitor: V) -> Result<V::Value, XmlDocumentError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+        Err(de::Error::invalid_type(Unexpected::Str(&self.0), &visitor))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+        _fields: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        Err(de::Error::invalid_type(Unexpected::Str(&self.0), &visitor))
+    }
+
+    // The name of a unit variant
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+        _variants: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        let name: StringDeserializer<XmlDocumentError> =
+            self.0.trim().to_string().into_deserializer();
+        visitor.visit_enum(name)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_unit()
+    }
+}
+
+/*
+ * Deserializer for the text of an element with only whitespace, or nothing,
+ * in it. It's None as an Option, as if there were no text, and otherwise
+ * the same as TextDeserializer.
+ */
+struct NoTextDeserializer(String);
+
+/*
+ * Deserialize as TextDeserializer does
+ */
+macro_rules! deserialize_as_text {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlDocumentError> {
+            TextDeserializer(self.0).$method(visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for NoTextDeserializer {
+    type Error = XmlDocumentError;
+
+    deserialize_as_text! {
+        deserialize_any deserialize_bool
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_unit deserialize_seq deserialize_map
+        deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        visitor.visit_none()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        TextDeserializer(self.0).deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        TextDeserializer(self.0).deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) ->
+        Result<V::Value, XmlDocumentError> {
+        TextDeserializer(self.0).deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, name: &'static str, len: usize,
+        visitor: V) -> Result<V::Value, XmlDocumentError> {
+        TextDeserializer(self.0).deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        TextDeserializer(self.0).deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str,
+        variants: &'static [&'static str], visitor: V) -> Result<V::Value, XmlDocumentError> {
+        TextDeserializer(self.0).deserialize_enum(name, variants, visitor)
+    }
+}
+
+impl IntoDeserializer<'_, XmlDocumentError> for TextDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::{from_path, from_str};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Encoding {
+        #[serde(rename = "unsigned")]
+        Unsigned,
+        #[serde(rename = "twosComplement")]
+        TwosComplement,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Parameter {
+        #[serde(rename = "@name")]
+        name: String,
+        #[serde(rename = "@encoding")]
+        encoding: Option<Encoding>,
+        #[serde(rename = "@sizeInBits", default)]
+        size_in_bits: u32,
+        #[serde(rename = "$text")]
+        description: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Container {
+        #[serde(rename = "@name")]
+        name: String,
+        #[serde(rename = "@abstract", default)]
+        is_abstract: bool,
+        #[serde(rename = "Parameter", default)]
+        parameters: Vec<Parameter>,
+        #[serde(rename = "@offsets", default)]
+        offsets: Vec<i64>,
+        #[serde(rename = "Base")]
+        base: Option<String>,
+    }
+
+    #[test]
+    fn test_struct() {
+        println!("Running test {}", function_name!());
+        let container: Container = from_str(concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<!-- comments are skipped -->\n",
+            "<Container name=\"Header\" abstract=\"1\" offsets=\" 0 8\t-16\" xmlns=\"urn:x\">\n",
+            "  <Unknown a=\"1\"><Deeper/></Unknown>\n",
+            "  <Parameter name=\"A\" encoding=\"unsigned\" sizeInBits=\" 8 \"/>\n",
+            "  <Parameter name=\"B\">second <![CDATA[<one>]]></Parameter>\n",
+            "</Container>\n")).unwrap();
+        assert_eq!(container, Container {
+            name:           "Header".to_string(),
+            is_abstract:    true,
+            parameters:     vec![
+                Parameter {
+                    name:           "A".to_string(),
+                    encoding:       Some(Encoding::Unsigned),
+                    size_in_bits:   8,
+                    description:    None,
+                },
+                Parameter {
+                    name:           "B".to_string(),
+                    encoding:       None,
+                    size_in_bits:   0,
+                    description:    Some("second <one>".to_string()),
+                },
+            ],
+            offsets:        vec![0, 8, -16],
+            base:           None,
+        });
+
+        // A map takes everything
+        let map: BTreeMap<String, String> =
+            from_str("<a x=\"1\"><b>2</b><c>3</c></a>").unwrap();
+        assert_eq!(map.into_iter().collect::<Vec<_>>(), [
+            ("@x".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+            ("c".to_string(), "3".to_string()),
+        ]);
+
+        // Subelements with the same name have to be together
+        let err = from_str::<Container>(concat!(
+            "<Container name=\"C\">\n",
+            "  <Parameter name=\"A\"/>\n",
+            "  <Base/>\n",
+            "  <Parameter name=\"B\"/>\n",
+            "</Container>")).unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 1, column 1: Can't deserialize <Container>: duplicate field `Parameter`");
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Note {
+        #[serde(rename = "@a", default)]
+        a: String,
+        #[serde(rename = "b", default)]
+        b: Vec<String>,
+        #[serde(rename = "$text")]
+        text: String,
+    }
+
+    #[test]
+    fn test_empty_text() {
+        println!("Running test {}", function_name!());
+        // A "$text" field that isn't an Option gets the empty text, or the
+        // whitespace if there's nothing else
+        let note = |a: &str, b: &[&str], text: &str| Note {
+            a:      a.to_string(),
+            b:      b.iter().map(|b| b.to_string()).collect(),
+            text:   text.to_string(),
+        };
+        assert_eq!(from_str::<Note>("<T a=\"\"/>").unwrap(), note("", &[], ""));
+        assert_eq!(from_str::<Note>("<T></T>").unwrap(), note("", &[], ""));
+        assert_eq!(from_str::<Note>("<T> </T>").unwrap(), note("", &[], " "));
+        assert_eq!(from_str::<Note>("<T a=\"1\">\n  <b>x</b>\n</T>").unwrap(),
+            note("1", &["x"], ""));
+        assert_eq!(from_str::<Note>("<T><b>x</b>y</T>").unwrap(), note("", &["x"], "y"));
+
+        // With xml:space="preserve", whitespace beside subelements is text
+        assert_eq!(from_str::<Note>("<T xml:space=\"preserve\"><b>x</b> </T>").unwrap(),
+            note("", &["x"], " "));
+        assert_eq!(from_str::<Note>("<T xml:space=\"preserve\">\t<b>x</b></T>").unwrap(),
+            note("", &["x"], "\t"));
+        assert_eq!(from_str::<Note>("<T xml:space=\"default\"><b>x</b> </T>").unwrap(),
+            note("", &["x"], ""));
+        assert_eq!(from_str::<Note>("<T xml:space=\"preserve\"><b>x</b> y </T>").unwrap(),
+            note("", &["x"], " y "));
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum ListItem {
+        ParameterRefEntry {
+            #[serde(rename = "@parameterRef")]
+            parameter_ref: String,
+        },
+        ContainerRefEntry(String),
+        #[serde(other)]
+        Other,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct EntryList {
+        #[serde(rename = "@count")]
+        count: usize,
+        #[serde(rename = "$value")]
+        entries: Vec<ListItem>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Size {
+        Fixed(u32),
+        Dynamic {
+            #[serde(rename = "@ref")]
+            reference: String,
+        },
+        Unknown,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Sizes {
+        #[serde(rename = "Size")]
+        sizes: Vec<Size>,
+    }
+
+    #[test]
+    fn test_enums() {
+        println!("Running test {}", function_name!());
+        // With "$value", the names of the subelements pick the variants
+        let list: EntryList = from_str(concat!(
+            "<EntryList count=\"3\">\n",
+            "  <ParameterRefEntry parameterRef=\"A\"/>\n",
+            "  <ContainerRefEntry>Header</ContainerRefEntry>\n",
+            "  <ArrayParameterRefEntry parameterRef=\"B\"><Dimension/></ArrayParameterRefEntry>\n",
+            "</EntryList>")).unwrap();
+        assert_eq!(list, EntryList {
+            count:      3,
+            entries:    vec![
+                ListItem::ParameterRefEntry { parameter_ref: "A".to_string() },
+                ListItem::ContainerRefEntry("Header".to_string()),
+                ListItem::Other,
+            ],
+        });
+
+        // Otherwise, the variant is named by the text or by a subelement
+        let sizes: Sizes = from_str(concat!(
+            "<Sizes>",
+            "<Size><Fixed>16</Fixed></Size>",
+            "<Size>\n  <Dynamic ref=\"Length\"/>\n</Size>",
+            "<Size> Unknown </Size>",
+            "</Sizes>")).unwrap();
+        assert_eq!(sizes.sizes, [
+            Size::Fixed(16),
+            Size::Dynamic { reference: "Length".to_string() },
+            Size::Unknown,
+        ]);
+    }
+
+    #[test]
+    fn test_errors() {
+        println!("Running test {}", function_name!());
+        // Errors give the position of the innermost element
+        let err = from_str::<Container>(concat!(
+            "<Container name=\"C\">\n",
+            "  <Parameter name=\"A\"\n",
+            "    sizeInBits=\"eight\"/>\n",
+            "</Container>")).unwrap_err();
+        assert!(matches!(err, XmlDocumentError::Deserialize(..)));
+        assert_eq!(err.to_string(), concat!(
+            "line 2, column 3: Can't deserialize <Parameter>: ",
+            "bad value \"eight\": invalid digit found in string"));
+        assert_eq!(err.position().unwrap().line, 2);
+        assert_eq!(err.code(), "XT0033");
+
+        let err = from_str::<Container>("<Container><Base/></Container>").unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 1, column 1: Can't deserialize <Container>: missing field `@name`");
+
+        let err = from_str::<Parameter>("<Parameter name=\"A\">a<b/>c</Parameter>")
+            .unwrap_err();
+        assert_eq!(err.to_string(), concat!(
+            "line 1, column 1: Can't deserialize <Parameter>: ",
+            "text \"c\" in more than one place"));
+
+        let err = from_str::<BTreeMap<String, u32>>("<a>\n  <b><c/></b>\n</a>").unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 2, column 6: Can't deserialize <b>: expected text, found <c>");
+
+        // Errors in the XML itself are reported as they are by the parser
+        let err = from_str::<Parameter>("<Parameter name=\"A\"></Param>").unwrap_err();
+        assert!(matches!(err, XmlDocumentError::XmlError(..)), "{:?}", err);
+    }
+
+    #[derive(Debug, Deserialize)]
+    enum SchemaItem {
+        #[serde(rename = "complexType")]
+        ComplexType {
+            #[serde(rename = "@name")]
+            name: String,
+        },
+        #[serde(rename = "simpleType")]
+        SimpleType {
+            #[serde(rename = "@name")]
+            name: String,
+        },
+        #[serde(other)]
+        Other,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Schema {
+        #[serde(rename = "@targetNamespace")]
+        target_namespace: String,
+        #[serde(rename = "$value")]
+        items: Vec<SchemaItem>,
+    }
+
+    #[test]
+    fn test_schema() {
+        println!("Running test {}", function_name!());
+        let schema: Schema = from_path("schema/SpaceSystem-patched.xsd").unwrap();
+        assert_eq!(schema.target_namespace, "http://www.omg.org/spec/XTCE/20180204");
+
+        let complex_types: Vec<&str> = schema.items.iter()
+            .filter_map(|item| match item {
+                SchemaItem::ComplexType { name } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        let simple_types: Vec<&str> = schema.items.iter()
+            .filter_map(|item| match item {
+                SchemaItem::SimpleType { name } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        // Three more complexTypes are anonymous, within elements
+        assert_eq!(complex_types.len(), 281);
+        assert!(complex_types.contains(&"SpaceSystemType"));
+        assert!(simple_types.contains(&"NameType"));
+
+        let err = from_path::<Schema>("schema/no-such-file.xsd").unwrap_err();
+        assert!(matches!(err, XmlDocumentError::IoError(..)));
+    }
+}
@@ -0,0 +1,369 @@
+/*
+ * Errors and warnings to be reported to the user, rendered either for
+ * people, with the offending source line and a caret under the column, or
+ * as JSON for tools.
+ */
+
+use serde::Serialize;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::parse_item::SourceSpan;
+use crate::xml_document_error::XmlDocumentError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/**
+ * A single error or warning
+ * severity:    Whether this is an error or a warning
+ * code:        Stable code for the kind of problem, as from
+ *              XmlDocumentError::code()
+ * message:     Description of the problem, without the position
+ * span:        Part of the input with the problem, if known. A span with no
+ *              length marks a single column.
+ * notes:       Further information to help fix the problem
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostic {
+    pub severity:   Severity,
+    pub code:       &'static str,
+    pub message:    String,
+    pub span:       Option<SourceSpan>,
+    pub notes:      Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message,
+            span:   None,
+            notes:  vec!(),
+        }
+    }
+
+    /*
+     * Create a Diagnostic from an XmlDocumentError, using its code, message
+     * and position
+     */
+    pub fn from_error(severity: Severity, err: &XmlDocumentError) -> Diagnostic {
+        let diagnostic = Diagnostic::new(severity, err.code(), err.message());
+        match err.position() {
+            None => diagnostic,
+            Some(pos) => diagnostic.with_span(SourceSpan::new(pos, pos)),
+        }
+    }
+
+    pub fn with_span(mut self, span: SourceSpan) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /**
+     * Render the diagnostic with the source line holding the start of the
+     * span and a caret under the problem.
+     *
+     * source:  Text of the input the span refers to
+     * origin:  Name of the input, such as a file name
+     */
+    pub fn render(&self, source: &str, origin: &str) -> String {
+        let mut out = String::new();
+        self.render_to(&mut out, source, origin)
+            .expect("Writing to a String can't fail");
+        out
+    }
+
+    /*
+     * Convert the diagnostic to a JSON object
+     */
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Diagnostic can always be serialized")
+    }
+
+    fn render_to(&self, out: &mut String, source: &str, origin: &str) -> fmt::Result {
+        writeln!(out, "{}[{}]: {}", self.severity, self.code, self.message)?;
+
+        let span = match self.span {
+            None => {
+                writeln!(out, "  --> {}", origin)?;
+                return self.render_notes(out, 0);
+            },
+            Some(span) => span,
+        };
+        let start = span.start;
+        let gutter = start.line.to_string().len();
+        writeln!(out, "{:gutter$}--> {}:{}:{}", "", origin, start.line, start.column)?;
+
+        if let Some(line) = source_line(source, start.line) {
+            writeln!(out, "{:gutter$} |", "")?;
+            writeln!(out, "{} | {}", start.line, line)?;
+
+            // Copy tabs from the source line so that the caret lines up
+            let padding: String = line.chars()
+                .take(start.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let line_length = line.chars().count();
+            let width = if span.end.line == start.line {
+                span.end.column.saturating_sub(start.column)
+            } else {
+                (line_length + 1).saturating_sub(start.column)
+            };
+            writeln!(out, "{:gutter$} | {}{}", "", padding, "^".repeat(width.max(1)))?;
+        }
+
+        self.render_notes(out, gutter)
+    }
+
+    fn render_notes(&self, out: &mut String, gutter: usize) -> fmt::Result {
+        for note in &self.notes {
+            writeln!(out, "{:gutter$} = note: {}", "", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&XmlDocumentError> for Diagnostic {
+    fn from(err: &XmlDocumentError) -> Diagnostic {
+        Diagnostic::from_error(Severity::Error, err)
+    }
+}
+
+/*
+ * Without the source text, a diagnostic is a single line giving the
+ * position
+ */
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]", self.severity, self.code)?;
+        if let Some(span) = self.span {
+            write!(f, " {}", span.start)?;
+        }
+        write!(f, ": {}", self.message)?;
+        for note in &self.notes {
+            write!(f, " (note: {})", note)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Get a line, numbered from one, without its line end
+ */
+fn source_line(source: &str, line: usize) -> Option<&str> {
+    source.split('\n')
+        .nth(line.checked_sub(1)?)
+        .map(|text| text.strip_suffix('\r').unwrap_or(text))
+}
+
+/**
+ * Collection of diagnostics, in the order they were found. Code that finds
+ * problems adds them here and the caller decides how to report them.
+ */
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics {
+            diagnostics: vec!(),
+        }
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /*
+     * Add an XmlDocumentError as an error
+     */
+    pub fn error(&mut self, err: &XmlDocumentError) {
+        self.push(Diagnostic::from_error(Severity::Error, err));
+    }
+
+    /*
+     * Add an XmlDocumentError as a warning
+     */
+    pub fn warning(&mut self, err: &XmlDocumentError) {
+        self.push(Diagnostic::from_error(Severity::Warning, err));
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() != 0
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == severity).count()
+    }
+
+    /**
+     * Render all diagnostics, separated by blank lines
+     *
+     * source:  Text of the input the spans refer to
+     * origin:  Name of the input, such as a file name
+     */
+    pub fn render(&self, source: &str, origin: &str) -> String {
+        self.diagnostics.iter()
+            .map(|diagnostic| diagnostic.render(source, origin))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /*
+     * Convert the diagnostics to a JSON array
+     */
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Diagnostics can always be serialized")
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<T: IntoIterator<Item = Diagnostic>>(&mut self, iter: T) {
+        self.diagnostics.extend(iter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use super::*;
+    use crate::parse_item::SourcePosition;
+    use crate::parse_tree::parse_document;
+
+    const INPUT: &str = concat!(
+        "<a>\n",
+        "  <b>\n",
+        "  </c>\n",
+        "</a>\n");
+
+    #[test]
+    fn test_render_error() {
+        println!("Running test {}", function_name!());
+        let err = parse_document(INPUT).expect_err("Parse unexpectedly succeeded");
+        let diagnostic = Diagnostic::from(&err)
+            .with_note("every start tag needs a matching end tag");
+        let rendered = diagnostic.render(INPUT, "test.xml");
+        print!("{}", rendered);
+
+        let pos = err.position().unwrap();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], format!("error[XT0026]: {}", err.message()));
+        assert_eq!(lines[1], format!(" --> test.xml:3:{}", pos.column));
+        assert_eq!(lines[2], "  |");
+        assert_eq!(lines[3], "3 |   </c>");
+        assert_eq!(lines[4], format!("  | {}^", " ".repeat(pos.column - 1)));
+        assert_eq!(lines[5], "  = note: every start tag needs a matching end tag");
+    }
+
+    #[test]
+    fn test_render_span() {
+        println!("Running test {}", function_name!());
+        let source = "<a>\n\t<b x='1'>\n</a>\n";
+        let span = SourceSpan::new(SourcePosition::new(2, 2, 5),
+            SourcePosition::new(2, 11, 14));
+        let diagnostic = Diagnostic::new(Severity::Warning, "XT0025", "Unknown element <b>".to_string())
+            .with_span(span);
+        let rendered = diagnostic.render(source, "tab.xml");
+        print!("{}", rendered);
+
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "warning[XT0025]: Unknown element <b>");
+        assert_eq!(lines[3], "2 | \t<b x='1'>");
+        assert_eq!(lines[4], "  | \t^^^^^^^^^");
+        assert_eq!(diagnostic.to_string(), "warning[XT0025] line 2, column 2: Unknown element <b>");
+    }
+
+    #[test]
+    fn test_json() {
+        println!("Running test {}", function_name!());
+        let err = parse_document(INPUT).expect_err("Parse unexpectedly succeeded");
+        let diagnostic = Diagnostic::from(&err).with_note("a note");
+        let json: serde_json::Value = serde_json::from_str(&diagnostic.to_json()).unwrap();
+        println!("{}", json);
+
+        assert_eq!(json["severity"], "error");
+        assert_eq!(json["code"], "XT0026");
+        assert_eq!(json["message"], err.message());
+        assert_eq!(json["span"]["start"]["line"], 3);
+        assert_eq!(json["notes"][0], "a note");
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        println!("Running test {}", function_name!());
+        let mut diagnostics = Diagnostics::new();
+        assert!(diagnostics.is_empty());
+
+        let err = parse_document(INPUT).expect_err("Parse unexpectedly succeeded");
+        diagnostics.warning(&XmlDocumentError::IoError("missing.xml".to_string(),
+            std::sync::Arc::new(std::io::Error::from(std::io::ErrorKind::NotFound))));
+        diagnostics.error(&err);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics.error_count(), 1);
+        assert_eq!(diagnostics.warning_count(), 1);
+        assert!(diagnostics.has_errors());
+
+        let rendered = diagnostics.render(INPUT, "test.xml");
+        print!("{}", rendered);
+        assert!(rendered.starts_with("warning[XT0008]: missing.xml: "));
+        assert!(rendered.contains("\n\nerror[XT0026]: "));
+
+        let json: serde_json::Value = serde_json::from_str(&diagnostics.to_json()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert!(json[0]["span"].is_null());
+    }
+}
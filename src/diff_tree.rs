@@ -0,0 +1,809 @@
+/*
+ * Structural differences between two Element trees. Subelements are
+ * matched up by identity rather than by position, so that reordering
+ * siblings shows up as moves rather than as changes to every element:
+ *
+ * - Subelements with a key attribute, one of DiffOptions::keys such as
+ *   "name", match the subelement with the same element name and key value.
+ * - Other subelements match the subelements with the same element name in
+ *   the same order.
+ *
+ * Matched subelements that aren't in the same order on both sides are
+ * reported as moved, choosing the fewest moves that explain the new order.
+ * Keyed elements removed from one place and added in another are also
+ * reported as moved.
+ *
+ * Elements are identified by paths in the style of XPath, e.g.
+ * /SpaceSystem/TelemetryMetaData/ParameterSet/Parameter[@name='Voltage'], along
+ * with the line of their start tag. Only attributes and the character data
+ * directly within elements are compared, not comments or processing
+ * instructions.
+ */
+
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::fmt;
+
+use crate::element::Element;
+use crate::parse_item::LineNumber;
+use crate::parse_tree::{ParseTree, TreeLevelInfo};
+use crate::qname::QName;
+use crate::write_tree::qualified_name;
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * How to compare trees
+ * keys:                Attributes that identify an element among its
+ *                      siblings, tried in order
+ * ignore_whitespace:   Compare text with leading and trailing whitespace
+ *                      removed and other runs of whitespace as one space
+ */
+#[derive(Clone, Debug)]
+pub struct DiffOptions {
+    pub keys:               Vec<String>,
+    pub ignore_whitespace:  bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            keys:               vec!["name".to_string()],
+            ignore_whitespace:  true,
+        }
+    }
+}
+
+impl DiffOptions {
+    /*
+     * The first key attribute an element has, with its value
+     */
+    pub(crate) fn key(&self, element: &dyn Element) -> Option<(String, String)> {
+        self.keys.iter()
+            .find_map(|key| element.attribute(key).map(|value| (key.clone(), value.to_string())))
+    }
+
+    /*
+     * Hash of everything that is compared in an element and its
+     * descendants
+     */
+    pub(crate) fn fingerprint(&self, element: &dyn Element) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        element.qname().hash(&mut hasher);
+        let mut attributes: Vec<_> = element.attributes()
+            .map(|attribute| (&attribute.name.namespace, &attribute.name.local_name,
+                &attribute.value))
+            .collect();
+        attributes.sort();
+        attributes.hash(&mut hasher);
+        self.text(element).hash(&mut hasher);
+        for subelement in element.subelements() {
+            self.fingerprint(subelement.as_ref()).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /*
+     * Text directly within an element, as it is compared
+     */
+    pub(crate) fn text(&self, element: &dyn Element) -> String {
+        self.normalize(&element.text())
+    }
+
+    pub(crate) fn normalize(&self, text: &str) -> String {
+        match self.ignore_whitespace {
+            false => text.to_string(),
+            true => text.split_whitespace().collect::<Vec<&str>>().join(" "),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Moved,
+    Modified,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeKind::Added => write!(f, "+"),
+            ChangeKind::Removed => write!(f, "-"),
+            ChangeKind::Moved => write!(f, ">"),
+            ChangeKind::Modified => write!(f, "~"),
+        }
+    }
+}
+
+/**
+ * Where an element is in one of the trees
+ * path:    Path to the element from the root
+ * line:    Line of the start tag of the element
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub path:   String,
+    pub line:   LineNumber,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (line {})", self.path, self.line)
+    }
+}
+
+/**
+ * Change to an attribute of an element
+ * name:    Qualified name of the attribute
+ * old:     Value in the old tree, None if it was added
+ * new:     Value in the new tree, None if it was removed
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AttributeChange {
+    pub name:   String,
+    pub old:    Option<String>,
+    pub new:    Option<String>,
+}
+
+/**
+ * Change to the text directly within an element
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TextChange {
+    pub old:    String,
+    pub new:    String,
+}
+
+/**
+ * A difference between the trees
+ * kind:        What happened to the element. Added and removed elements
+ *              are reported once, for the root of the subtree.
+ * old:         Where the element is in the old tree, unless it was added
+ * new:         Where the element is in the new tree, unless it was removed
+ * attributes:  Changes to the attributes of a moved or modified element
+ * text:        Change to the text of a moved or modified element
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub kind:       ChangeKind,
+    pub old:        Option<Location>,
+    pub new:        Option<Location>,
+    pub attributes: Vec<AttributeChange>,
+    pub text:       Option<TextChange>,
+}
+
+impl Change {
+    fn new(kind: ChangeKind, old: Option<Location>, new: Option<Location>) -> Change {
+        Change { kind, old, new, attributes: vec![], text: None }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(old) = &self.old {
+            write!(f, " {}", old)?;
+        }
+        if self.old.is_some() && self.new.is_some() {
+            write!(f, " ->")?;
+        }
+        if let Some(new) = &self.new {
+            write!(f, " {}", new)?;
+        }
+
+        for attribute in &self.attributes {
+            match (&attribute.old, &attribute.new) {
+                (Some(old), Some(new)) => write!(f, "\n    @{}: {:?} -> {:?}", attribute.name,
+                    old, new)?,
+                (None, Some(new)) => write!(f, "\n    @{}: added {:?}", attribute.name, new)?,
+                (Some(old), None) => write!(f, "\n    @{}: removed {:?}", attribute.name, old)?,
+                (None, None) => {},
+            }
+        }
+        if let Some(text) = &self.text {
+            write!(f, "\n    text: {:?} -> {:?}", text.old, text.new)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * All of the differences between two trees, in document order
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct TreeDiff {
+    pub changes: Vec<Change>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Change> {
+        self.changes.iter()
+    }
+
+    /*
+     * Convert the differences to a JSON array
+     */
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("TreeDiff can always be serialized")
+    }
+}
+
+/*
+ * One change per line, with attribute and text changes indented under the
+ * element they belong to
+ */
+impl fmt::Display for TreeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Compare two trees
+ */
+pub fn diff(old: &dyn Element, new: &dyn Element, options: &DiffOptions) -> TreeDiff {
+    let mut differ = Differ { options, changes: vec![], removed: vec![], added: vec![],
+        moves: HashMap::new() };
+    let old_side = Side { element: old, path: format!("/{}", qualified_name(
+        &old.element_info().owned_name)) };
+    let new_side = Side { element: new, path: format!("/{}", qualified_name(
+        &new.element_info().owned_name)) };
+
+    if old.qname() == new.qname() {
+        differ.element(&old_side, &new_side, false);
+    } else {
+        differ.changes.push(Change::new(ChangeKind::Removed, Some(old_side.location()), None));
+        differ.changes.push(Change::new(ChangeKind::Added, None, Some(new_side.location())));
+    }
+    differ.finish()
+}
+
+/**
+ * Parse two files and compare them
+ */
+pub fn diff_paths(old_path: &str, new_path: &str, options: &DiffOptions) ->
+    Result<TreeDiff, XmlDocumentError> {
+    let (_, old) = ParseTree::parse_path(old_path, &TreeLevelInfo::new())?;
+    let (_, new) = ParseTree::parse_path(new_path, &TreeLevelInfo::new())?;
+    Ok(diff(old.as_ref(), new.as_ref(), options))
+}
+
+/*
+ * An element in one of the trees, with its path
+ */
+struct Side<'a> {
+    element:    &'a dyn Element,
+    path:       String,
+}
+
+impl Side<'_> {
+    fn location(&self) -> Location {
+        Location { path: self.path.clone(), line: self.element.lineno() }
+    }
+}
+
+/*
+ * Identity of an element among its siblings
+ */
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Identity {
+    name:   QName,
+    key:    Option<(String, String)>,
+}
+
+/*
+ * Comparison in progress
+ * changes:     Changes found so far
+ * removed:     Keyed elements reported as removed, with the index of the
+ *              change, which may turn out to be moves
+ * added:       Keyed elements reported as added, likewise
+ * moves:       Changes found within elements that moved to another parent,
+ *              by the index of the change for the move
+ */
+struct Differ<'o, 'a> {
+    options:    &'o DiffOptions,
+    changes:    Vec<Change>,
+    removed:    Vec<(Identity, usize, Side<'a>)>,
+    added:      Vec<(Identity, usize, Side<'a>)>,
+    moves:      HashMap<usize, Vec<Change>>,
+}
+
+impl<'a> Differ<'_, 'a> {
+    /*
+     * Compare two elements that match
+     */
+    fn element(&mut self, old: &Side<'a>, new: &Side<'a>, moved: bool) {
+        let mut change = Change::new(if moved { ChangeKind::Moved } else { ChangeKind::Modified },
+            Some(old.location()), Some(new.location()));
+        change.attributes = attribute_changes(old.element, new.element);
+        let old_text = self.text(old.element);
+        let new_text = self.text(new.element);
+        if old_text != new_text {
+            change.text = Some(TextChange { old: old_text, new: new_text });
+        }
+        if moved || !change.attributes.is_empty() || change.text.is_some() {
+            self.changes.push(change);
+        }
+
+        self.subelements(old, new);
+    }
+
+    /*
+     * Match up the subelements of two matching elements and compare them
+     */
+    fn subelements(&mut self, old: &Side<'a>, new: &Side<'a>) {
+        let old_children = self.children(old);
+        let new_children = self.children(new);
+        let old_ids: Vec<Identity> = old_children.iter()
+            .map(|side| self.identity(side.element)).collect();
+        let new_ids: Vec<Identity> = new_children.iter()
+            .map(|side| self.identity(side.element)).collect();
+
+        let mut new_match: Vec<Option<usize>> = vec![None; new_children.len()];
+        let mut old_match: Vec<Option<usize>> = vec![None; old_children.len()];
+
+        // Keys that are used more than once on either side identify nothing
+        let unique = |ids: &[Identity]| {
+            let mut counts = HashMap::new();
+            for id in ids.iter().filter(|id| id.key.is_some()) {
+                *counts.entry(id.clone()).or_insert(0) += 1;
+            }
+            counts.into_iter()
+                .filter(|(_, count)| *count == 1)
+                .map(|(id, _)| id)
+                .collect::<HashSet<Identity>>()
+        };
+        let old_unique = unique(&old_ids);
+        let new_unique = unique(&new_ids);
+        let is_keyed = |id: &Identity| old_unique.contains(id) || new_unique.contains(id);
+
+        let new_positions: HashMap<&Identity, usize> = new_ids.iter().enumerate()
+            .filter(|(_, id)| new_unique.contains(id))
+            .map(|(j, id)| (id, j))
+            .collect();
+        for (i, id) in old_ids.iter().enumerate() {
+            if old_unique.contains(id) {
+                if let Some(&j) = new_positions.get(id) {
+                    old_match[i] = Some(j);
+                    new_match[j] = Some(i);
+                }
+            }
+        }
+
+        // Then unkeyed elements that are the same on both sides, so that
+        // moving one doesn't look like changes to its namesakes
+        let fingerprints = |children: &[Side], ids: &[Identity]| -> Vec<Option<u64>> {
+            children.iter().zip(ids)
+                .map(|(side, id)| (!is_keyed(id)).then(|| self.fingerprint(side.element)))
+                .collect()
+        };
+        let old_prints = fingerprints(&old_children, &old_ids);
+        let mut same: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (j, print) in fingerprints(&new_children, &new_ids).iter().enumerate().rev() {
+            if let Some(print) = print {
+                same.entry(*print).or_default().push(j);
+            }
+        }
+        for (i, print) in old_prints.iter().enumerate() {
+            let found = print.and_then(|print| same.get_mut(&print)
+                .and_then(|positions| positions.pop()));
+            if let Some(j) = found {
+                old_match[i] = Some(j);
+                new_match[j] = Some(i);
+            }
+        }
+
+        // And the rest by element name, in order
+        let mut unkeyed: HashMap<&QName, Vec<usize>> = HashMap::new();
+        for (j, id) in new_ids.iter().enumerate().rev() {
+            if !is_keyed(id) && new_match[j].is_none() {
+                unkeyed.entry(&id.name).or_default().push(j);
+            }
+        }
+        for (i, id) in old_ids.iter().enumerate() {
+            if !is_keyed(id) && old_match[i].is_none() {
+                if let Some(j) = unkeyed.get_mut(&id.name).and_then(|positions| positions.pop()) {
+                    old_match[i] = Some(j);
+                    new_match[j] = Some(i);
+                }
+            }
+        }
+
+        // The matches in the longest run that keeps its order stay put
+        let order: Vec<usize> = old_match.iter().flatten().copied().collect();
+        let in_place = longest_increasing(&order);
+        let stayed: Vec<bool> = {
+            let mut stayed = vec![false; new_children.len()];
+            for (k, &j) in order.iter().enumerate() {
+                stayed[j] = in_place[k];
+            }
+            stayed
+        };
+
+        // Go through the new subelements in order. The old subelements that
+        // were removed are reported before whatever follows them in the new
+        // tree, which is the next subelement that stayed put.
+        let mut anchors = vec![old_children.len(); new_children.len() + 1];
+        for j in (0..new_children.len()).rev() {
+            anchors[j] = match (stayed[j], new_match[j]) {
+                (true, Some(i)) => i,
+                _ => anchors[j + 1],
+            };
+        }
+        let mut old_children: Vec<Option<Side<'a>>> = old_children.into_iter()
+            .map(Some).collect();
+        let mut next_old = 0;
+        for (j, side) in new_children.into_iter().enumerate() {
+            match new_match[j] {
+                Some(i) => {
+                    if stayed[j] {
+                        self.removed_before(&mut old_children, &old_match, &old_ids, &mut next_old,
+                            i);
+                    }
+                    let old_side = old_children[i].take().expect("matched once");
+                    self.element(&old_side, &side, !stayed[j]);
+                },
+                None => {
+                    self.removed_before(&mut old_children, &old_match, &old_ids, &mut next_old,
+                        anchors[j]);
+                    let index = self.changes.len();
+                    self.changes.push(Change::new(ChangeKind::Added, None, Some(side.location())));
+                    if new_ids[j].key.is_some() {
+                        self.added.push((new_ids[j].clone(), index, side));
+                    }
+                },
+            }
+        }
+        let end = old_children.len();
+        self.removed_before(&mut old_children, &old_match, &old_ids, &mut next_old, end);
+    }
+
+    /*
+     * Report the old subelements that weren't matched, from next up to end
+     */
+    fn removed_before(&mut self, old_children: &mut [Option<Side<'a>>],
+        old_match: &[Option<usize>], old_ids: &[Identity], next: &mut usize, end: usize) {
+        while *next < end {
+            let i = *next;
+            *next += 1;
+            if old_match[i].is_some() {
+                continue;
+            }
+            let side = old_children[i].take().expect("removed once");
+            let index = self.changes.len();
+            self.changes.push(Change::new(ChangeKind::Removed, Some(side.location()), None));
+            if old_ids[i].key.is_some() {
+                self.removed.push((old_ids[i].clone(), index, side));
+            }
+        }
+    }
+
+    /*
+     * Turn keyed elements that were removed from one place and added in
+     * another into moves, and put the changes in order
+     */
+    fn finish(mut self) -> TreeDiff {
+        let mut dropped = vec![false; self.changes.len()];
+        let removed = std::mem::take(&mut self.removed);
+        let added = std::mem::take(&mut self.added);
+        let removed_counts = count(&removed);
+        let added_counts = count(&added);
+
+        for (id, removed_index, old) in &removed {
+            let (_, added_index, new) = match (removed_counts[id], added_counts.get(id)) {
+                ((1, _), Some(&(1, k))) => &added[k],
+                _ => continue,
+            };
+
+            let mut differ = Differ { options: self.options, changes: vec![], removed: vec![],
+                added: vec![], moves: HashMap::new() };
+            differ.element(old, new, true);
+            let mut changes = differ.finish().changes;
+            self.changes[*removed_index] = changes.remove(0);
+            self.moves.insert(*removed_index, changes);
+            dropped[*added_index] = true;
+        }
+
+        let mut changes = vec![];
+        for (index, change) in self.changes.into_iter().enumerate() {
+            if !dropped[index] {
+                changes.push(change);
+            }
+            if let Some(moved) = self.moves.remove(&index) {
+                changes.extend(moved);
+            }
+        }
+        TreeDiff { changes }
+    }
+
+    /*
+     * Subelements of an element, with their paths. Elements with a key get
+     * it in their path, others their position among the subelements with
+     * the same name, if there is more than one.
+     */
+    fn children(&self, parent: &Side<'a>) -> Vec<Side<'a>> {
+        let subelements = parent.element.subelements();
+        let mut counts: HashMap<QName, usize> = HashMap::new();
+        for subelement in subelements {
+            *counts.entry(subelement.qname()).or_insert(0) += 1;
+        }
+
+        let mut positions: HashMap<QName, usize> = HashMap::new();
+        subelements.iter()
+            .map(|subelement| {
+                let element = subelement.as_ref();
+                let name = qualified_name(&element.element_info().owned_name);
+                let position = positions.entry(element.qname()).or_insert(0);
+                *position += 1;
+                let step = match self.key(element) {
+                    Some((key, value)) if value.contains('\'') =>
+                        format!("{}[@{}=\"{}\"]", name, key, value),
+                    Some((key, value)) => format!("{}[@{}='{}']", name, key, value),
+                    None if counts[&element.qname()] > 1 => format!("{}[{}]", name, position),
+                    None => name.to_string(),
+                };
+                Side { element, path: format!("{}/{}", parent.path, step) }
+            })
+            .collect()
+    }
+
+    fn key(&self, element: &dyn Element) -> Option<(String, String)> {
+        self.options.key(element)
+    }
+
+    fn identity(&self, element: &dyn Element) -> Identity {
+        Identity { name: element.qname(), key: self.key(element) }
+    }
+
+    fn fingerprint(&self, element: &dyn Element) -> u64 {
+        self.options.fingerprint(element)
+    }
+
+    fn text(&self, element: &dyn Element) -> String {
+        self.options.text(element)
+    }
+}
+
+/*
+ * How many times each identity appears in a list of removed or added
+ * elements, with the position of the first
+ */
+fn count<'l>(list: &'l [(Identity, usize, Side)]) -> HashMap<&'l Identity, (usize, usize)> {
+    let mut counts = HashMap::new();
+    for (k, (id, _, _)) in list.iter().enumerate() {
+        counts.entry(id).or_insert((0, k)).0 += 1;
+    }
+    counts
+}
+
+/*
+ * Differences between the attributes of two elements, in the order they
+ * appear in the old element followed by those only in the new one
+ */
+fn attribute_changes(old: &dyn Element, new: &dyn Element) -> Vec<AttributeChange> {
+    let same = |a: &xml::attribute::OwnedAttribute, b: &xml::attribute::OwnedAttribute| {
+        a.name.local_name == b.name.local_name && a.name.namespace == b.name.namespace
+    };
+    let mut changes = vec![];
+
+    for attribute in old.attributes() {
+        let name = qualified_name(&attribute.name).to_string();
+        match new.attributes().find(|other| same(attribute, other)) {
+            None => changes.push(AttributeChange {
+                name, old: Some(attribute.value.clone()), new: None,
+            }),
+            Some(other) if other.value != attribute.value => changes.push(AttributeChange {
+                name, old: Some(attribute.value.clone()), new: Some(other.value.clone()),
+            }),
+            Some(_) => {},
+        }
+    }
+    for attribute in new.attributes() {
+        if !old.attributes().any(|other| same(attribute, other)) {
+            changes.push(AttributeChange {
+                name:   qualified_name(&attribute.name).to_string(),
+                old:    None,
+                new:    Some(attribute.value.clone()),
+            });
+        }
+    }
+
+    changes
+}
+
+/*
+ * Mark the members of a longest strictly increasing subsequence
+ */
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+    // tails[k] is the index of the smallest value ending a run of length k + 1
+    let mut tails: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![None; values.len()];
+
+    for (i, &value) in values.iter().enumerate() {
+        let k = tails.partition_point(|&t| values[t] < value);
+        previous[i] = k.checked_sub(1).map(|k| tails[k]);
+        match k == tails.len() {
+            true => tails.push(i),
+            false => tails[k] = i,
+        }
+    }
+
+    let mut member = vec![false; values.len()];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        member[i] = true;
+        next = previous[i];
+    }
+    member
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use crate::element::Element;
+    use crate::parse_tree::{parse, ParseTree, TreeLevelInfo};
+
+    use super::{diff, diff_paths, longest_increasing, ChangeKind, DiffOptions};
+
+    fn diff_str(old: &str, new: &str) -> String {
+        diff(parse(old).as_ref(), parse(new).as_ref(), &DiffOptions::default()).to_string()
+    }
+
+    #[test]
+    fn test_diff() {
+        println!("Running test {}", function_name!());
+        let old = concat!(
+            "<s>\n",
+            "<p name=\"a\" type=\"int\"/>\n",
+            "<p name=\"b\">old text</p>\n",
+            "<p name=\"c\"/>\n",
+            "<g><x/><x/></g>\n",
+            "</s>");
+        let new = concat!(
+            "<s>\n",
+            "<p name=\"c\"/>\n",
+            "<p name=\"a\" type=\"long\" units=\"m\"/>\n",
+            "<p name=\"b\">  new\n  text </p>\n",
+            "<g><x/><x a=\"1\"/><y/></g>\n",
+            "</s>");
+        assert_eq!(diff_str(old, new), concat!(
+            "> /s/p[@name='c'] (line 4) -> /s/p[@name='c'] (line 2)\n",
+            "~ /s/p[@name='a'] (line 2) -> /s/p[@name='a'] (line 3)\n",
+            "    @type: \"int\" -> \"long\"\n",
+            "    @units: added \"m\"\n",
+            "~ /s/p[@name='b'] (line 3) -> /s/p[@name='b'] (line 4)\n",
+            "    text: \"old text\" -> \"new text\"\n",
+            "~ /s/g/x[2] (line 5) -> /s/g/x[2] (line 6)\n",
+            "    @a: added \"1\"\n",
+            "+ /s/g/y (line 6)\n"));
+
+        // Identical trees, apart from whitespace
+        assert_eq!(diff_str("<a>\n  <b> x  y </b>\n</a>", "<a><b>x y</b></a>"), "");
+        let options = DiffOptions { ignore_whitespace: false, ..Default::default() };
+        assert_eq!(diff(parse("<a><b> x</b></a>").as_ref(), parse("<a><b>x</b></a>").as_ref(),
+            &options).len(), 1);
+
+        assert_eq!(diff_str("<a/>", "<b/>"), "- /a (line 1)\n+ /b (line 1)\n");
+    }
+
+    #[test]
+    fn test_moves() {
+        println!("Running test {}", function_name!());
+        // Keyed elements moving to another parent
+        let old = "<s><g name=\"1\"><p name=\"a\" v=\"1\"/></g><g name=\"2\"/><q/></s>";
+        let new = "<s><g name=\"1\"/><g name=\"2\"><p name=\"a\" v=\"2\"/></g></s>";
+        let changes = diff(parse(old).as_ref(), parse(new).as_ref(), &DiffOptions::default());
+        assert_eq!(changes.to_string(), concat!(
+            "> /s/g[@name='1']/p[@name='a'] (line 1) -> /s/g[@name='2']/p[@name='a'] (line 1)\n",
+            "    @v: \"1\" -> \"2\"\n",
+            "- /s/q (line 1)\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&changes.to_json()).unwrap();
+        assert_eq!(json[0]["kind"], "moved");
+        assert_eq!(json[0]["new"]["path"], "/s/g[@name='2']/p[@name='a']");
+        assert_eq!(json[0]["attributes"][0]["old"], "1");
+        assert_eq!(json[1]["kind"], "removed");
+        assert!(json[1]["new"].is_null());
+
+        // Only the elements that break the order are moved
+        let old = "<s><p name=\"a\"/><p name=\"b\"/><p name=\"c\"/><p name=\"d\"/></s>";
+        let new = "<s><p name=\"b\"/><p name=\"c\"/><p name=\"d\"/><p name=\"a\"/></s>";
+        let changes = diff(parse(old).as_ref(), parse(new).as_ref(), &DiffOptions::default());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes.changes[0].kind, ChangeKind::Moved);
+
+        assert_eq!(longest_increasing(&[3, 0, 1, 4, 2]), vec![false, true, true, false, true]);
+        assert!(longest_increasing(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_edge_cases() {
+        println!("Running test {}", function_name!());
+        // Attribute-only changes, including removal and namespaced attributes
+        assert_eq!(diff_str(
+            "<a xmlns:p=\"urn:p\"><b x=\"1\" y=\"2\" p:z=\"3\"/></a>",
+            "<a xmlns:p=\"urn:p\"><b x=\"1\" p:z=\"4\"/></a>"), concat!(
+            "~ /a/b (line 1) -> /a/b (line 1)\n",
+            "    @y: removed \"2\"\n",
+            "    @p:z: \"3\" -> \"4\"\n"));
+
+        // A moved element that also changed
+        assert_eq!(diff_str(
+            "<s><p name=\"a\" v=\"1\"/><p name=\"b\"/></s>",
+            "<s><p name=\"b\"/><p name=\"a\" v=\"2\">text</p></s>"), concat!(
+            "> /s/p[@name='a'] (line 1) -> /s/p[@name='a'] (line 1)\n",
+            "    @v: \"1\" -> \"2\"\n",
+            "    text: \"\" -> \"text\"\n"));
+
+        // Renaming an element or changing its key is a removal and an addition,
+        // even when it also moves
+        assert_eq!(diff_str(
+            "<s><p name=\"a\"/><p name=\"b\"/></s>",
+            "<s><p name=\"b\"/><q name=\"a\"/><p name=\"c\"/></s>"), concat!(
+            "- /s/p[@name='a'] (line 1)\n",
+            "+ /s/q[@name='a'] (line 1)\n",
+            "+ /s/p[@name='c'] (line 1)\n"));
+
+        // Elements are compared by namespace, not by prefix
+        assert_eq!(diff_str("<a xmlns=\"urn:a\"><b/></a>", "<x:a xmlns:x=\"urn:a\"><x:b/></x:a>"),
+            "");
+        assert_eq!(diff_str("<a xmlns:p=\"urn:1\"><p:b/></a>", "<a xmlns:p=\"urn:2\"><p:b/></a>"),
+            "- /a/p:b (line 1)\n+ /a/p:b (line 1)\n");
+    }
+
+    #[test]
+    fn test_diff_schema() {
+        println!("Running test {}", function_name!());
+        let path = "schema/SpaceSystem-patched.xsd";
+        let changes = diff_paths(path, path, &DiffOptions::default()).unwrap();
+        assert!(changes.is_empty());
+
+        let (_, old) = ParseTree::parse_path(path, &TreeLevelInfo::new()).unwrap();
+        let mut new = old.clone();
+        let position = |root: &dyn Element, name: &str| root.subelements().iter()
+            .position(|subelement| subelement.attribute("name") == Some(name))
+            .unwrap();
+
+        // Move a type to the end, rename another and change some
+        // documentation
+        let moved = new.remove_child(position(new.as_ref(), "SpaceSystemType")).unwrap();
+        new.append_child(moved);
+        let renamed = position(new.as_ref(), "HeaderType");
+        new.subelements_mut()[renamed].set_attribute("name", "NewHeaderType").unwrap();
+        new.subelements_mut()[1].subelements_mut()[0].set_text("Changed");
+
+        let changes = diff(old.as_ref(), new.as_ref(), &DiffOptions::default());
+        let summary: Vec<(ChangeKind, &str)> = changes.iter()
+            .map(|change| (change.kind, change.new.as_ref().or(change.old.as_ref()).unwrap()
+                .path.as_str()))
+            .collect();
+        assert_eq!(summary, vec![
+            (ChangeKind::Modified, "/schema/annotation[1]/documentation"),
+            (ChangeKind::Removed, "/schema/complexType[@name='HeaderType']"),
+            (ChangeKind::Added, "/schema/complexType[@name='NewHeaderType']"),
+            (ChangeKind::Moved, "/schema/complexType[@name='SpaceSystemType']"),
+        ]);
+        assert_eq!(changes.changes[0].text.as_ref().unwrap().new, "Changed");
+
+        // Edited elements keep the line numbers they were parsed from
+        let moved = changes.changes[3].clone();
+        assert_eq!(moved.old.unwrap().line, moved.new.unwrap().line);
+    }
+}
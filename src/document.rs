@@ -1,5 +1,7 @@
 /**
  * Basic information about the document
+ * declared:    True if the XML declaration is written out. For a parsed
+ *              document, this is whether it had one.
  */
 use xml::common::XmlVersion;
 
@@ -8,6 +10,7 @@ pub struct DocumentInfo {
     pub version: XmlVersion,
     pub encoding: String,
     pub standalone: Option<bool>,
+    pub declared: bool,
 }
 
 impl DocumentInfo {
@@ -16,6 +19,7 @@ impl DocumentInfo {
             version,
             encoding,
             standalone,
+            declared: true,
         }
     }
 }
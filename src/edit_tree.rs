@@ -0,0 +1,455 @@
+/*
+ * Edit parsed Element trees. The namespace of each element holds all of
+ * the mappings in scope for it, and the names of the element and its
+ * attributes carry the namespace URIs their prefixes are bound to. The
+ * edits here keep those consistent, so that writing out an edited tree and
+ * parsing it again gives back the same names:
+ *
+ * - An element inserted under a new parent picks up the parent's mappings
+ *   for any prefixes it doesn't declare itself. When written out, it gets
+ *   declarations for the mappings that differ from its new parent's.
+ * - Names given as "prefix:local" must use a prefix that is in scope, and
+ *   take their namespace from it.
+ * - Declaring a namespace prefix changes the mapping for the element and
+ *   every descendant that inherited the old mapping, along with the names
+ *   that use the prefix.
+ *
+ * Read-only elements, such as the views of an ArenaDocument, can't be
+ * edited. The edits that return a Result give an error for them.
+ */
+
+use xml::attribute::OwnedAttribute;
+use xml::name::OwnedName;
+use xml::namespace::{Namespace, NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XML_PREFIX};
+use xml::reader::XmlEvent;
+
+use crate::element::{Element, ElementInfo};
+use crate::xml_document_error::XmlDocumentError;
+
+impl dyn Element {
+    /**
+     * Insert a subelement before the subelement at index, or at the end if
+     * index is the number of subelements
+     */
+    pub fn insert_child(&mut self, index: usize, mut child: Box<dyn Element>) ->
+        Result<(), XmlDocumentError> {
+        self.writable()?;
+        child.writable()?;
+        if index > self.subelements().len() {
+            return Err(self.no_such_subelement(index));
+        }
+        inherit(child.as_mut(), &self.element_info().namespace);
+        self.subelements_mut().insert(index, child);
+        Ok(())
+    }
+
+    /**
+     * Add a subelement after the existing ones. This panics if either
+     * element is read-only.
+     */
+    pub fn append_child(&mut self, child: Box<dyn Element>) {
+        let index = self.subelements().len();
+        if let Err(e) = self.insert_child(index, child) {
+            panic!("Can't append: {}", e);
+        }
+    }
+
+    /**
+     * Remove and return the subelement at index. The text, comments and
+     * processing instructions before it go with it.
+     */
+    pub fn remove_child(&mut self, index: usize) -> Result<Box<dyn Element>, XmlDocumentError> {
+        self.writable()?;
+        if index >= self.subelements().len() {
+            return Err(self.no_such_subelement(index));
+        }
+        Ok(self.subelements_mut().remove(index))
+    }
+
+    /**
+     * Replace the subelement at index, returning the old one. The new
+     * subelement takes the place of the old one, including whatever came
+     * before it, such as indentation.
+     */
+    pub fn replace_child(&mut self, index: usize, mut child: Box<dyn Element>) ->
+        Result<Box<dyn Element>, XmlDocumentError> {
+        self.writable()?;
+        child.writable()?;
+        if index >= self.subelements().len() {
+            return Err(self.no_such_subelement(index));
+        }
+        inherit(child.as_mut(), &self.element_info().namespace);
+        let old = &mut self.subelements_mut()[index];
+        std::mem::swap(old.before_element_mut(), child.before_element_mut());
+        Ok(std::mem::replace(old, child))
+    }
+
+    /**
+     * Move the subelement at from so that it ends up at index to. To move
+     * an element to another parent, use remove_child() and insert_child().
+     */
+    pub fn move_child(&mut self, from: usize, to: usize) -> Result<(), XmlDocumentError> {
+        self.writable()?;
+        let len = self.subelements().len();
+        if from >= len || to >= len {
+            return Err(self.no_such_subelement(from.max(to)));
+        }
+        let child = self.subelements_mut().remove(from);
+        self.subelements_mut().insert(to, child);
+        Ok(())
+    }
+
+    /**
+     * Rename the element. A prefixed name, e.g. "xtce:Parameter", must use
+     * a prefix that is in scope. An unprefixed name is in the default
+     * namespace, if there is one.
+     */
+    pub fn rename(&mut self, qualified_name: &str) -> Result<(), XmlDocumentError> {
+        self.writable()?;
+        let (prefix, local_name) = split_name(qualified_name)?;
+        let namespace = self.bound_uri(prefix.unwrap_or(NS_NO_PREFIX))?;
+        self.element_info_mut().owned_name = OwnedName {
+            local_name: local_name.to_string(),
+            namespace,
+            prefix:     prefix.map(|prefix| prefix.to_string()),
+        };
+        Ok(())
+    }
+
+    /**
+     * Set the value of an attribute, adding it if it isn't there. Unprefixed
+     * attributes aren't in any namespace; prefixed ones must use a prefix
+     * that is in scope. Use declare_namespace() rather than setting xmlns
+     * attributes.
+     */
+    pub fn set_attribute(&mut self, qualified_name: &str, value: &str) ->
+        Result<(), XmlDocumentError> {
+        self.writable()?;
+        let name = self.attribute_name(qualified_name)?;
+        let attributes = &mut self.element_info_mut().attributes;
+
+        match attributes.iter_mut().find(|attribute| same_name(&attribute.name, &name)) {
+            Some(attribute) => attribute.value = value.to_string(),
+            None => attributes.push(OwnedAttribute::new(name, value)),
+        }
+        Ok(())
+    }
+
+    /**
+     * Remove an attribute, returning its value if it was there
+     */
+    pub fn remove_attribute(&mut self, qualified_name: &str) ->
+        Result<Option<String>, XmlDocumentError> {
+        self.writable()?;
+        let name = self.attribute_name(qualified_name)?;
+        let attributes = &mut self.element_info_mut().attributes;
+
+        Ok(attributes.iter()
+            .position(|attribute| same_name(&attribute.name, &name))
+            .map(|i| attributes.remove(i).value))
+    }
+
+    /**
+     * Bind a namespace prefix, or the default namespace if the prefix is
+     * "", for this element and the descendants that don't bind the prefix
+     * themselves. Names using the prefix move to the new namespace. An empty
+     * URI undeclares the default namespace.
+     */
+    pub fn declare_namespace(&mut self, prefix: &str, uri: &str) -> Result<(), XmlDocumentError> {
+        self.writable()?;
+        let reserved = prefix == NS_XML_PREFIX || prefix == NS_XMLNS_PREFIX;
+        if reserved || (prefix != NS_NO_PREFIX && (!is_ncname(prefix) || uri.is_empty())) {
+            return Err(XmlDocumentError::InvalidName(format!("xmlns:{}", prefix)));
+        }
+        let old = self.element_info().namespace.get(prefix).map(|uri| uri.to_string());
+        if old.as_deref() != Some(uri) {
+            rebind(self, prefix, old.as_deref(), uri);
+        }
+        Ok(())
+    }
+
+    /**
+     * Replace the character data directly within the element with text, so
+     * that text() then returns text. Comments and processing instructions
+     * stay where they are. If the element has subelements, the text goes
+     * after the last one, and the whitespace between them goes too, so the
+     * element may need formatting again. This panics if the element is
+     * read-only.
+     */
+    pub fn set_text(&mut self, text: &str) {
+        let keep = |event: &XmlEvent| !matches!(event,
+            XmlEvent::Characters(_) | XmlEvent::Whitespace(_) | XmlEvent::CData(_));
+
+        for subelement in self.subelements_mut() {
+            subelement.before_element_mut().retain(keep);
+        }
+        let content = self.content_mut();
+        content.retain(keep);
+        if !text.is_empty() {
+            content.push(XmlEvent::Characters(text.to_string()));
+        }
+    }
+
+    fn writable(&self) -> Result<(), XmlDocumentError> {
+        match self.is_read_only() {
+            false => Ok(()),
+            true => Err(XmlDocumentError::ReadOnly(self.qname().to_string())),
+        }
+    }
+
+    fn no_such_subelement(&self, index: usize) -> XmlDocumentError {
+        XmlDocumentError::NoSuchSubelement(index, self.qname().to_string())
+    }
+
+    /*
+     * Namespace URI bound to a prefix, None for no namespace, or an error
+     * if a non-empty prefix isn't bound
+     */
+    fn bound_uri(&self, prefix: &str) -> Result<Option<String>, XmlDocumentError> {
+        match self.element_info().resolve_prefix(prefix) {
+            Some(uri) => Ok(Some(uri.to_string())),
+            None if prefix == NS_NO_PREFIX => Ok(None),
+            None => Err(XmlDocumentError::UnboundPrefix(prefix.to_string(),
+                self.qname().to_string())),
+        }
+    }
+
+    fn attribute_name(&self, qualified_name: &str) -> Result<OwnedName, XmlDocumentError> {
+        let (prefix, local_name) = split_name(qualified_name)?;
+        if prefix == Some(NS_XMLNS_PREFIX) || (prefix.is_none() && local_name == "xmlns") {
+            return Err(XmlDocumentError::InvalidName(qualified_name.to_string()));
+        }
+        let namespace = match prefix {
+            None => None,
+            Some(prefix) => self.bound_uri(prefix)?,
+        };
+        Ok(OwnedName {
+            local_name: local_name.to_string(),
+            namespace,
+            prefix:     prefix.map(|prefix| prefix.to_string()),
+        })
+    }
+}
+
+/*
+ * Give an element, and its descendants, the mappings from namespace for
+ * the prefixes it doesn't map itself
+ */
+fn inherit(element: &mut dyn Element, namespace: &Namespace) {
+    let mut added = Namespace::empty();
+    for (prefix, uri) in namespace {
+        if !element.element_info().namespace.contains(prefix) {
+            added.put(prefix, uri);
+        }
+    }
+    if added.0.is_empty() {
+        return;
+    }
+    element.element_info_mut().namespace.extend(&added);
+    for subelement in element.subelements_mut() {
+        inherit(subelement.as_mut(), &added);
+    }
+}
+
+/*
+ * Change the mapping for prefix from old to uri in an element, and in its
+ * descendants, stopping where the prefix has been mapped to something else
+ */
+fn rebind(element: &mut dyn Element, prefix: &str, old: Option<&str>, uri: &str) {
+    if element.element_info().namespace.get(prefix) != old {
+        return;
+    }
+    let element_info = element.element_info_mut();
+    element_info.namespace.force_put(prefix, uri);
+    rename_prefix(element_info, prefix, uri);
+
+    for subelement in element.subelements_mut() {
+        rebind(subelement.as_mut(), prefix, old, uri);
+    }
+}
+
+/*
+ * Move the names using prefix to the namespace uri
+ */
+fn rename_prefix(element_info: &mut ElementInfo, prefix: &str, uri: &str) {
+    let namespace = match uri {
+        "" => None,
+        uri => Some(uri.to_string()),
+    };
+    let name = &mut element_info.owned_name;
+    if name.prefix.as_deref().unwrap_or(NS_NO_PREFIX) == prefix {
+        name.namespace = namespace.clone();
+    }
+    for attribute in &mut element_info.attributes {
+        if prefix != NS_NO_PREFIX && attribute.name.prefix.as_deref() == Some(prefix) {
+            attribute.name.namespace = namespace.clone();
+        }
+    }
+}
+
+fn same_name(a: &OwnedName, b: &OwnedName) -> bool {
+    a.local_name == b.local_name && a.namespace == b.namespace
+}
+
+/*
+ * Split a qualified name into its prefix, if any, and local name, checking
+ * that both are valid
+ */
+fn split_name(qualified_name: &str) -> Result<(Option<&str>, &str), XmlDocumentError> {
+    let (prefix, local_name) = match qualified_name.split_once(':') {
+        None => (None, qualified_name),
+        Some((prefix, local_name)) => (Some(prefix), local_name),
+    };
+    match is_ncname(local_name) && prefix.is_none_or(is_ncname) {
+        true => Ok((prefix, local_name)),
+        false => Err(XmlDocumentError::InvalidName(qualified_name.to_string())),
+    }
+}
+
+/*
+ * Check for an XML name without a colon
+ */
+fn is_ncname(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') &&
+        chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '\u{b7}'))
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use crate::element::Element;
+    use crate::parse_tree::parse;
+    use crate::write_tree::write_element;
+    use crate::xml_document_error::XmlDocumentError;
+
+    fn write(element: &dyn Element) -> String {
+        let mut output = Vec::new();
+        write_element(&mut output, element).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    /*
+     * Check that writing out an element and parsing it again gives the
+     * same names and namespaces
+     */
+    fn assert_consistent(element: &dyn Element) {
+        fn compare(a: &dyn Element, b: &dyn Element) {
+            assert_eq!(a.element_info().owned_name, b.element_info().owned_name);
+            assert_eq!(a.element_info().attributes, b.element_info().attributes);
+            assert_eq!(a.subelements().len(), b.subelements().len());
+            for (a_sub, b_sub) in a.subelements().iter().zip(b.subelements()) {
+                compare(a_sub.as_ref(), b_sub.as_ref());
+            }
+        }
+        compare(element, parse(&write(element)).as_ref());
+    }
+
+    #[test]
+    fn test_children() {
+        println!("Running test {}", function_name!());
+        let mut root = parse("<a>\n  <b/>\n  <c/>\n</a>");
+
+        root.insert_child(1, parse("<x/>")).unwrap();
+        assert_eq!(write(root.as_ref()), "<a>\n  <b/><x/>\n  <c/>\n</a>");
+        root.move_child(1, 2).unwrap();
+        assert_eq!(write(root.as_ref()), "<a>\n  <b/>\n  <c/><x/>\n</a>");
+
+        let old = root.replace_child(0, parse("<y/>")).unwrap();
+        assert_eq!(old.name(), "b");
+        assert_eq!(write(root.as_ref()), "<a>\n  <y/>\n  <c/><x/>\n</a>");
+
+        let removed = root.remove_child(1).unwrap();
+        assert_eq!(removed.name(), "c");
+        root.append_child(removed);
+        assert_eq!(write(root.as_ref()), "<a>\n  <y/><x/>\n  <c/>\n</a>");
+
+        assert!(matches!(root.remove_child(3), Err(XmlDocumentError::NoSuchSubelement(3, _))));
+        assert!(root.insert_child(4, parse("<z/>")).is_err());
+        assert!(root.move_child(0, 3).is_err());
+    }
+
+    #[test]
+    fn test_namespaces() {
+        println!("Running test {}", function_name!());
+        let mut root = parse("<a xmlns=\"urn:a\" xmlns:p=\"urn:p\"><b/></a>");
+
+        // Subelements from elsewhere keep their own namespaces
+        root.append_child(parse("<c><d p:x=\"1\" xmlns:p=\"urn:other\"/></c>"));
+        root.append_child(parse("<p:e xmlns:p=\"urn:p\"/>"));
+        assert_eq!(write(root.as_ref()), concat!(
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\"><b/><c xmlns=\"\">",
+            "<d p:x=\"1\" xmlns:p=\"urn:other\"/></c><p:e xmlns=\"\"/></a>"));
+        assert_consistent(root.as_ref());
+
+        // Inserted elements can use the prefixes in scope
+        let c = &mut root.subelements_mut()[1];
+        c.rename("p:c").unwrap();
+        assert_eq!(c.element_info().owned_name.namespace.as_deref(), Some("urn:p"));
+        c.subelements_mut()[0].set_attribute("p:y", "2").unwrap();
+        assert_consistent(root.as_ref());
+
+        // Rebinding a prefix moves the names that use it
+        root.declare_namespace("p", "urn:q").unwrap();
+        assert_eq!(root.subelements()[2].qname().to_string(), "{urn:q}e");
+        assert_eq!(root.subelements()[1].subelements()[0].attribute_qualified("p:x"), Some("1"));
+        assert_eq!(root.subelements()[1].subelements()[0].element_info().attributes[0]
+            .name.namespace.as_deref(), Some("urn:other"));
+        root.declare_namespace("", "urn:new").unwrap();
+        assert_eq!(root.subelements()[0].qname().to_string(), "{urn:new}b");
+        assert_consistent(root.as_ref());
+
+        assert!(matches!(root.rename("q:a"), Err(XmlDocumentError::UnboundPrefix(..))));
+        assert!(matches!(root.declare_namespace("xml", "urn:x"),
+            Err(XmlDocumentError::InvalidName(_))));
+        assert!(root.declare_namespace("p", "").is_err());
+        assert!(root.rename("1a").is_err());
+    }
+
+    #[test]
+    fn test_attributes() {
+        println!("Running test {}", function_name!());
+        let mut root = parse("<a x=\"1\" xmlns:p=\"urn:p\" p:x=\"2\"/>");
+
+        root.set_attribute("x", "one").unwrap();
+        root.set_attribute("p:x", "two").unwrap();
+        root.set_attribute("y", "three").unwrap();
+        root.set_attribute("xml:lang", "en").unwrap();
+        assert_eq!(write(root.as_ref()),
+            "<a x=\"one\" xmlns:p=\"urn:p\" p:x=\"two\" y=\"three\" xml:lang=\"en\"/>");
+
+        assert_eq!(root.remove_attribute("p:x").unwrap(), Some("two".to_string()));
+        assert_eq!(root.remove_attribute("p:x").unwrap(), None);
+        assert_eq!(root.attribute("x"), Some("one"));
+        assert_consistent(root.as_ref());
+
+        assert!(matches!(root.set_attribute("q:x", "1"), Err(XmlDocumentError::UnboundPrefix(..))));
+        assert!(matches!(root.set_attribute("xmlns", "urn:x"),
+            Err(XmlDocumentError::InvalidName(_))));
+        assert!(root.set_attribute("xmlns:q", "urn:x").is_err());
+        assert!(root.set_attribute("a b", "1").is_err());
+    }
+
+    #[test]
+    fn test_text() {
+        println!("Running test {}", function_name!());
+        let mut root = parse("<a>old <!-- note -->text<![CDATA[ more ]]></a>");
+        root.set_text("new & improved");
+        assert_eq!(write(root.as_ref()), "<a><!-- note -->new &amp; improved</a>");
+        root.set_text("");
+        assert_eq!(write(root.as_ref()), "<a><!-- note --></a>");
+
+        let mut root = parse("<a>\n  one<b/>\n  two<c/>\n</a>");
+        root.set_text("three");
+        assert_eq!(write(root.as_ref()), "<a><b/><c/>three</a>");
+        assert_eq!(root.text(), "three");
+
+        let mut root = parse("<a>\n  <b/><!-- note -->\n</a>");
+        root.set_text("x");
+        assert_eq!(write(root.as_ref()), "<a><b/><!-- note -->x</a>");
+        assert_eq!(root.text(), "x");
+        root.set_text("");
+        assert_eq!(root.text(), "");
+    }
+}
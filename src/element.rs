@@ -6,14 +6,18 @@ use dyn_clone::DynClone;
 //use std::convert::Infallible;
 use std::fmt;
 //use std::ops::{FromResidual, Try};
+use std::str::FromStr;
 use xml::attribute::OwnedAttribute;
 use xml::name::OwnedName;
 use xml::namespace::Namespace;
+use xml::reader::XmlEvent;
 
 // FIXME: split into walk and parse sets of errors
-//use crate::xml_document_error::XmlDocumentError;
+use crate::xml_document_error::XmlDocumentError;
 use crate::misc::nl_indent;
-use crate::parse_item::LineNumber;
+use crate::parse_item::{LineNumber, SourceSpan};
+use crate::qname::QName;
+use crate::xpath::{XPath, XPathNode};
 
 /*
  * trait making TreeElement and IndirectElement work well together
@@ -22,10 +26,22 @@ use crate::parse_item::LineNumber;
  *                  future expansion.
  * name:            Returns the name for the element. FIXME: This really only
  *                  makes sense for TreeElements and should probably be removed
+ * element_info:    Returns the ElementInfo, with the attributes and namespace,
+ *                  for the element
+ * element_info_mut: Like element_info but returns a mutable value
  * subelements:     Returns a reference to a vector of Elements. These are
  *                  sub-elements for TreeElements and a linear set of elements
  *                  at the same depth as the parent element for IndirectElements.
  * subelements_mut: Like subelements but returns a mutable value
+ * before_element:  Text, comments and processing instructions that precede
+ *                  the element. For subelements, these are the items between
+ *                  the previous sibling (or the start of the parent) and the
+ *                  start of the element. For the root element, this is the
+ *                  document prolog.
+ * content:         Text, comments and processing instructions between the end
+ *                  of the last subelement (or the start of the element, if
+ *                  there are no subelements) and the end of the element.
+ * after_element:   For the root element, the document epilog. Otherwise empty.
  */
 pub trait Element: DynClone {
     fn display(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result;
@@ -34,32 +50,309 @@ pub trait Element: DynClone {
     fn name(&self) -> &str;
     // This is actually available in XmlEvent. Use that.
     fn lineno(&self) -> LineNumber;
+    fn element_info(&self) -> &ElementInfo;
+    fn element_info_mut(&mut self) -> &mut ElementInfo;
     fn subelements(&self) -> &Vec<Box<dyn Element>>;
     fn subelements_mut(&mut self) -> &mut Vec<Box<dyn Element>>;
+    fn before_element(&self) -> &Vec<XmlEvent>;
+    fn before_element_mut(&mut self) -> &mut Vec<XmlEvent>;
+    fn content(&self) -> &Vec<XmlEvent>;
+    fn content_mut(&mut self) -> &mut Vec<XmlEvent>;
+    fn after_element(&self) -> &Vec<XmlEvent>;
+    fn after_element_mut(&mut self) -> &mut Vec<XmlEvent>;
+
+    /**
+     * Part of the input holding the start tag of the element
+     */
+    fn span(&self) -> SourceSpan {
+        self.element_info().span
+    }
+
+    /**
+     * Namespace-qualified name of the element
+     */
+    fn qname(&self) -> QName {
+        self.element_info().qname()
+    }
+
+    /**
+     * True if the element is a view that can't be changed through the
+     * *_mut() methods
+     */
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /**
+     * Return the character data directly within this element, in document
+     * order, including CDATA sections but not comments or the text of
+     * subelements.
+     */
+    fn text(&self) -> String {
+        let mut text = String::new();
+        let pieces = self.subelements().iter()
+            .flat_map(|subelement| subelement.before_element().iter())
+            .chain(self.content().iter());
+
+        for piece in pieces {
+            match piece {
+                XmlEvent::Characters(s) | XmlEvent::Whitespace(s) | XmlEvent::CData(s) =>
+                    text.push_str(s),
+                _ => {},
+            }
+        }
+
+        text
+    }
+
+    /**
+     * Look up an attribute value by its local name
+     */
+    fn attribute(&self, local_name: &str) -> Option<&str> {
+        self.element_info().attribute(local_name)
+    }
+
+    /**
+     * Look up an attribute value by its qualified name, e.g. "xsi:type"
+     */
+    fn attribute_qualified(&self, qualified_name: &str) -> Option<&str> {
+        self.element_info().attribute_qualified(qualified_name)
+    }
+
+    /**
+     * Iterate over all attributes, in document order
+     */
+    fn attributes(&self) -> std::slice::Iter<'_, OwnedAttribute> {
+        self.element_info().attributes.iter()
+    }
+
+    /**
+     * The namespace mappings in scope for this element
+     */
+    fn namespace(&self) -> &Namespace {
+        &self.element_info().namespace
+    }
 }
 
+/*
+ * Typed attribute helpers. These are generic, so they can't be part of the
+ * Element trait itself without making it unusable as a trait object.
+ */
+impl<'a> dyn Element + 'a {
+    /**
+     * Parse the attribute with the given local name, if it is present
+     */
+    pub fn attribute_as<T>(&self, local_name: &str) -> Result<Option<T>, XmlDocumentError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.element_info().attribute_as(local_name)
+    }
+
+    /**
+     * Parse the attribute with the given local name, which must be present
+     */
+    pub fn required_attribute_as<T>(&self, local_name: &str) -> Result<T, XmlDocumentError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.element_info().required_attribute_as(local_name)
+    }
+
+    /**
+     * Select the nodes matching an XPath expression, with this element as
+     * the context node. See the xpath module for what is supported.
+     */
+    pub fn select(&self, xpath: &str) -> Result<Vec<XPathNode<'_>>, XmlDocumentError> {
+        XPath::new(xpath)?.select(self)
+    }
+}
+
+/*
+ * Information from the start of an element
+ * owned_name:  Name of the element
+ * span:        Part of the input holding the start tag of the element
+ * attributes:  Attributes, in document order
+ * namespace:   All namespace mappings in scope for the element
+ * attribute_order: Names of the namespace declarations and attributes in
+ *              the order they were written, e.g. "xmlns:p", "name". This is
+ *              empty for elements that weren't parsed.
+ */
 #[derive(Clone, Debug)]
 pub struct ElementInfo {
     pub owned_name: OwnedName,
-    pub lineno:     LineNumber,
+    pub span:       SourceSpan,
+    pub attributes: Vec<OwnedAttribute>,
+    pub namespace:  Namespace,
+    pub attribute_order: Vec<String>,
 }
 
 impl ElementInfo {
     pub fn new(
         owned_name:     OwnedName,
-        lineno:         LineNumber,
-        _attributes:    Vec<OwnedAttribute>,
-        _namespace:     Namespace,
+        span:           SourceSpan,
+        attributes:     Vec<OwnedAttribute>,
+        namespace:      Namespace,
     ) -> ElementInfo {
         ElementInfo {
             owned_name,
-            lineno,
+            span,
+            attributes,
+            namespace,
+            attribute_order: Vec::new(),
+        }
+    }
+
+    /**
+     * Give the order in which the namespace declarations and attributes
+     * were written
+     */
+    pub fn with_attribute_order(mut self, attribute_order: Vec<String>) -> ElementInfo {
+        self.attribute_order = attribute_order;
+        self
+    }
+
+    /**
+     * Line number of the start of the element
+     */
+    pub fn lineno(&self) -> LineNumber {
+        self.span.start.line
+    }
+
+    /**
+     * Namespace-qualified name of the element
+     */
+    pub fn qname(&self) -> QName {
+        QName::from(&self.owned_name)
+    }
+
+    /**
+     * Return the namespace URI bound to a prefix in the scope of the
+     * element. The empty prefix gives the default namespace. Returns None
+     * if the prefix isn't bound.
+     */
+    pub fn resolve_prefix(&self, prefix: &str) -> Option<&str> {
+        self.namespace.get(prefix)
+            .filter(|uri| !uri.is_empty())
+    }
+
+    /**
+     * Return a prefix bound to the given namespace URI in the scope of the
+     * element, preferring the default namespace, which gives "".
+     */
+    pub fn prefix_for(&self, namespace: &str) -> Option<&str> {
+        self.namespace.0.iter()
+            .filter(|(_, uri)| uri.as_str() == namespace)
+            .map(|(prefix, _)| prefix.as_str())
+            .min_by_key(|prefix| !prefix.is_empty())
+    }
+
+    /**
+     * Resolve a qualified name appearing in content or an attribute value,
+     * such as the "xs:string" in type="xs:string", using the namespace
+     * mappings in scope for the element. Unprefixed names are in the
+     * default namespace. Returns None if the prefix isn't bound.
+     */
+    pub fn resolve_qname(&self, qualified_name: &str) -> Option<QName> {
+        match qualified_name.split_once(':') {
+            None => Some(QName::new(self.resolve_prefix(""), qualified_name)),
+            Some((prefix, local_name)) => self.resolve_prefix(prefix)
+                .map(|uri| QName::new(Some(uri), local_name)),
+        }
+    }
+
+    /**
+     * Check whether the element has the given namespace and local name
+     */
+    pub fn is(&self, namespace: &str, local_name: &str) -> bool {
+        self.owned_name.namespace.as_deref() == Some(namespace) &&
+            self.owned_name.local_name == local_name
+    }
+
+    /**
+     * Look up an attribute by local name. Unprefixed attributes are preferred
+     * over prefixed attributes with the same local name.
+     */
+    pub fn attribute(&self, local_name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .filter(|attr| attr.name.local_name == local_name)
+            .min_by_key(|attr| attr.name.prefix.is_some())
+            .map(|attr| attr.value.as_str())
+    }
+
+    /**
+     * Look up an attribute by qualified name. A name without a colon only
+     * matches attributes without a prefix.
+     */
+    pub fn attribute_qualified(&self, qualified_name: &str) -> Option<&str> {
+        let (prefix, local_name) = match qualified_name.split_once(':') {
+            None => (None, qualified_name),
+            Some((prefix, local_name)) => (Some(prefix), local_name),
+        };
+
+        self.attributes.iter()
+            .find(|attr| attr.name.local_name == local_name &&
+                attr.name.prefix.as_deref() == prefix)
+            .map(|attr| attr.value.as_str())
+    }
+
+    /**
+     * Return the attribute with the given local name or, if it's missing,
+     * a MissingAttribute error.
+     */
+    pub fn required_attribute(&self, local_name: &str) -> Result<&str, XmlDocumentError> {
+        self.attribute(local_name)
+            .ok_or_else(|| XmlDocumentError::MissingAttribute(self.span.start,
+                local_name.to_string(), self.owned_name.local_name.clone()))
+    }
+
+    /**
+     * Parse an optional attribute into a T
+     */
+    pub fn attribute_as<T>(&self, local_name: &str) -> Result<Option<T>, XmlDocumentError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.attribute(local_name) {
+            None => Ok(None),
+            Some(value) => self.parse_value(local_name, value).map(Some),
         }
     }
+
+    /**
+     * Parse a required attribute into a T
+     */
+    pub fn required_attribute_as<T>(&self, local_name: &str) -> Result<T, XmlDocumentError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.required_attribute(local_name)?;
+        self.parse_value(local_name, value)
+    }
+
+    fn parse_value<T>(&self, local_name: &str, value: &str) -> Result<T, XmlDocumentError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        value.trim().parse::<T>()
+            .map_err(|e| XmlDocumentError::BadAttributeValue(self.span.start,
+                local_name.to_string(), value.to_string(), e.to_string()))
+    }
+}
+
+impl Default for ElementInfo {
+    fn default() -> ElementInfo {
+        ElementInfo::new(OwnedName::local(""), SourceSpan::default(), vec!(), Namespace::empty())
+    }
 }
 
 pub fn element_info_display(f: &mut fmt::Formatter<'_>, depth: usize, element_info: &ElementInfo) -> fmt::Result {
-    write!(f, "{}ElementInfo::new({}, vec!(),", nl_indent(depth), element_info.lineno)?;
+    write!(f, "{}ElementInfo::new({:?}, vec!(),", nl_indent(depth), element_info.span)?;
     write!(f, "{}Namespace(BTreeMap::<String, String>::new())),", nl_indent(depth + 1))
 }
 
@@ -78,3 +371,134 @@ impl fmt::Debug for Box<dyn Element> {
         self.display(f, 0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use crate::parse_tree::parse;
+    use crate::qname::{QName, XSD_NAMESPACE, XTCE_NAMESPACE};
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::Element;
+
+    const INPUT: &str = concat!(
+        "<SpaceSystem xmlns=\"http://www.omg.org/spec/XTCE/20180204\"\n",
+        "    xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"\n",
+        "    xsi:schemaLocation=\"http://www.omg.org/spec/XTCE/20180204 SpaceSystem.xsd\"\n",
+        "    name=\"Sat\">\n",
+        "  <IntegerParameterType name=\"Temp\" sizeInBits=\"16\" signed=\"yes\"/>\n",
+        "</SpaceSystem>\n");
+
+    #[test]
+    fn test_attribute_lookup() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+
+        assert_eq!(root.attribute("name"), Some("Sat"));
+        assert_eq!(root.attribute("schemaLocation"),
+            Some("http://www.omg.org/spec/XTCE/20180204 SpaceSystem.xsd"));
+        assert_eq!(root.attribute_qualified("xsi:schemaLocation"),
+            Some("http://www.omg.org/spec/XTCE/20180204 SpaceSystem.xsd"));
+        assert_eq!(root.attribute_qualified("schemaLocation"), None);
+        assert_eq!(root.attribute("missing"), None);
+
+        let names: Vec<&str> = root.attributes()
+            .map(|attr| attr.name.local_name.as_str())
+            .collect();
+        assert_eq!(names, vec!("schemaLocation", "name"));
+    }
+
+    #[test]
+    fn test_namespace() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+
+        assert_eq!(root.namespace().get(""), Some("http://www.omg.org/spec/XTCE/20180204"));
+        assert_eq!(root.namespace().get("xsi"), Some("http://www.w3.org/2001/XMLSchema-instance"));
+
+        // Namespaces declared on an ancestor are in scope for subelements
+        let param = &root.subelements()[0];
+        assert_eq!(param.namespace().get("xsi"), Some("http://www.w3.org/2001/XMLSchema-instance"));
+    }
+
+    #[test]
+    fn test_qname() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\"\n",
+            "    xmlns:xtce=\"http://www.omg.org/spec/XTCE/20180204\">\n",
+            "  <xs:annotation/>\n",
+            "  <xtce:annotation/>\n",
+            "  <annotation/>\n",
+            "  <xs:element name=\"Temp\" type=\"xtce:IntegerParameterType\"/>\n",
+            "</xs:schema>\n");
+        let root = parse(input);
+        let subelements = root.subelements();
+
+        assert_eq!(root.qname(), QName::new(Some(XSD_NAMESPACE), "schema"));
+        assert!(root.element_info().is(XSD_NAMESPACE, "schema"));
+
+        // Same local name, different vocabularies
+        assert!(subelements[0].element_info().is(XSD_NAMESPACE, "annotation"));
+        assert!(subelements[1].element_info().is(XTCE_NAMESPACE, "annotation"));
+        assert_ne!(subelements[0].qname(), subelements[1].qname());
+        assert_eq!(subelements[2].qname(), QName::local("annotation"));
+
+        let element_info = subelements[3].element_info();
+        assert_eq!(element_info.resolve_prefix("xs"), Some(XSD_NAMESPACE));
+        assert_eq!(element_info.resolve_prefix("xtce"), Some(XTCE_NAMESPACE));
+        assert_eq!(element_info.resolve_prefix(""), None);
+        assert_eq!(element_info.resolve_prefix("nope"), None);
+        assert_eq!(element_info.prefix_for(XTCE_NAMESPACE), Some("xtce"));
+        assert_eq!(element_info.resolve_qname(element_info.attribute("type").unwrap()),
+            Some(QName::new(Some(XTCE_NAMESPACE), "IntegerParameterType")));
+        assert_eq!(element_info.resolve_qname("xs:string"),
+            Some(QName::new(Some(XSD_NAMESPACE), "string")));
+        assert_eq!(element_info.resolve_qname("nope:string"), None);
+        assert_eq!(element_info.resolve_qname("string"), Some(QName::local("string")));
+    }
+
+    #[test]
+    fn test_span() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+        let span = root.span();
+        assert_eq!((span.start.line, span.start.column), (1, 1));
+        assert_eq!((span.end.line, span.end.column), (4, 16));
+        assert_eq!(root.lineno(), 1);
+
+        let param = &root.subelements()[0];
+        assert_eq!(param.span().start.line, 5);
+        assert_eq!(param.span().start.offset, INPUT.find("<IntegerParameterType").unwrap());
+        assert_eq!(param.span().end.offset, INPUT.find("/>").unwrap() + 2);
+    }
+
+    #[test]
+    fn test_typed_attributes() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+        let param: &dyn Element = &*root.subelements()[0];
+
+        assert_eq!(param.attribute_as::<u32>("sizeInBits").unwrap(), Some(16));
+        assert_eq!(param.attribute_as::<u32>("initialValue").unwrap(), None);
+        assert_eq!(param.required_attribute_as::<u32>("sizeInBits").unwrap(), 16);
+
+        match param.attribute_as::<u32>("signed") {
+            Err(XmlDocumentError::BadAttributeValue(pos, name, value, _)) => {
+                assert_eq!(name, "signed");
+                assert_eq!(value, "yes");
+                assert_eq!((pos.line, pos.column), (5, 3));
+            },
+            other => panic!("Expected BadAttributeValue, got {:?}", other),
+        }
+
+        match param.required_attribute_as::<u32>("initialValue") {
+            Err(XmlDocumentError::MissingAttribute(_, name, element)) => {
+                assert_eq!(name, "initialValue");
+                assert_eq!(element, "IntegerParameterType");
+            },
+            other => panic!("Expected MissingAttribute, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,381 @@
+/*
+ * Format an XML tree by re-indenting it. Only whitespace that can't
+ * matter is changed: the whitespace between the subelements of an element
+ * that has no text of its own. An element that has text, such as a
+ * <documentation> element, is written exactly as it was parsed, with
+ * everything in it, and so is anything inside an element with
+ * xml:space="preserve".
+ */
+
+use std::io::{self, BufReader, Read, Write};
+use std::sync::Arc;
+use xml::namespace::{Namespace, NS_XML_URI};
+use xml::reader::XmlEvent;
+
+use crate::document::DocumentInfo;
+use crate::element::Element;
+use crate::parse_tree::{ParseTree, TreeLevelInfo};
+use crate::write_tree::{qualified_name, tag_attributes, write_declaration, write_event,
+    write_tag};
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * How to format a document
+ * indent:                  Number of spaces for each level of nesting
+ * line_width:              Start tags longer than this have their attributes
+ *                          put one per line
+ * wrap_attributes:         Start tags with more than this many attributes
+ *                          have them put one per line, whatever their length
+ * self_close:              Write elements with nothing in them as <name/>
+ *                          rather than <name></name>
+ * preserve_blank_lines:    Keep a blank line between subelements where the
+ *                          input had one or more
+ */
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    pub indent:                 usize,
+    pub line_width:             usize,
+    pub wrap_attributes:        Option<usize>,
+    pub self_close:             bool,
+    pub preserve_blank_lines:   bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent:                 4,
+            line_width:             100,
+            wrap_attributes:        None,
+            self_close:             true,
+            preserve_blank_lines:   true,
+        }
+    }
+}
+
+/**
+ * Parse a document and write it out formatted
+ */
+pub fn format<R: Read, W: Write>(buf_reader: BufReader<R>, writer: &mut W,
+    options: &FormatOptions) -> Result<(), XmlDocumentError> {
+    let (document_info, root) = ParseTree::parse(buf_reader, &TreeLevelInfo::new())?;
+    format_document(writer, &document_info, root.as_ref(), options)
+        .map_err(|e| XmlDocumentError::IoError("output".to_string(), Arc::new(e)))
+}
+
+/**
+ * Write a document, formatted
+ * writer:          Where to write the document
+ * document_info:   Information for the XML declaration
+ * root:            Root element of the document
+ * options:         How to format the document
+ */
+pub fn format_document<W: Write>(writer: &mut W, document_info: &DocumentInfo,
+    root: &dyn Element, options: &FormatOptions) -> io::Result<()> {
+    write_declaration(writer, document_info)?;
+
+    for event in root.before_element() {
+        write_event(writer, event)?;
+        writeln!(writer)?;
+    }
+
+    let mut formatter = Formatter { writer, options };
+    formatter.element(root, &Namespace::empty(), 0, false)?;
+
+    for event in root.after_element() {
+        writeln!(formatter.writer)?;
+        write_event(formatter.writer, event)?;
+    }
+    writeln!(formatter.writer)
+}
+
+struct Formatter<'a, W: Write> {
+    writer:     &'a mut W,
+    options:    &'a FormatOptions,
+}
+
+impl<W: Write> Formatter<'_, W> {
+    /*
+     * Write an element whose start tag has already been indented
+     * element:             Element to write
+     * parent_namespace:    Namespaces in scope for the parent
+     * depth:               Nesting depth of the element
+     * preserve:            True if inside an element with xml:space="preserve"
+     */
+    fn element(&mut self, element: &dyn Element, parent_namespace: &Namespace, depth: usize,
+        preserve: bool) -> io::Result<()> {
+        let preserve = match xml_space(element) {
+            Some("preserve") => true,
+            Some("default") => false,
+            _ => preserve,
+        };
+        let namespace = &element.element_info().namespace;
+        let name = qualified_name(&element.element_info().owned_name);
+
+        // Whitespace within a start tag never matters
+        self.start_tag(element, parent_namespace, depth)?;
+
+        if element.subelements().is_empty() && element.content().is_empty() {
+            return match self.options.self_close {
+                true => write!(self.writer, "/>"),
+                false => write!(self.writer, "></{}>", name),
+            };
+        }
+        write!(self.writer, ">")?;
+
+        if preserve || !is_element_only(element) {
+            for subelement in element.subelements() {
+                for event in subelement.before_element() {
+                    write_event(self.writer, event)?;
+                }
+                write_tag(self.writer, subelement.as_ref(), namespace)?;
+            }
+            for event in element.content() {
+                write_event(self.writer, event)?;
+            }
+        } else {
+            for subelement in element.subelements() {
+                let blank_line = self.misc(subelement.before_element(), depth + 1)?;
+                self.newline(depth + 1, blank_line)?;
+                self.element(subelement.as_ref(), namespace, depth + 1, preserve)?;
+            }
+
+            // Blank lines before the end tag are dropped
+            self.misc(element.content(), depth + 1)?;
+            self.newline(depth, false)?;
+        }
+
+        write!(self.writer, "</{}>", name)
+    }
+
+    /*
+     * Write the start tag of an element, without the closing '>'
+     */
+    fn start_tag(&mut self, element: &dyn Element, parent_namespace: &Namespace,
+        depth: usize) -> io::Result<()> {
+        let name = qualified_name(&element.element_info().owned_name);
+        let attributes = tag_attributes(element.element_info(), parent_namespace);
+
+        let length = depth * self.options.indent + 1 + name.chars().count() +
+            attributes.iter().map(|attribute| 1 + attribute.chars().count()).sum::<usize>() + 2;
+        let wrap = attributes.len() > 1 && (length > self.options.line_width ||
+            self.options.wrap_attributes.is_some_and(|n| attributes.len() > n));
+
+        write!(self.writer, "<{}", name)?;
+        for attribute in attributes {
+            match wrap {
+                false => write!(self.writer, " ")?,
+                true => self.newline(depth + 1, false)?,
+            }
+            write!(self.writer, "{}", attribute)?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Write the comments and processing instructions in element-only
+     * content, each on a line of its own. The only text is whitespace,
+     * which is replaced by indentation.
+     *
+     * Returns true if there was a blank line after the last item written
+     */
+    fn misc(&mut self, events: &[XmlEvent], depth: usize) -> io::Result<bool> {
+        let mut blank_line = false;
+
+        for event in events {
+            match event {
+                XmlEvent::Whitespace(whitespace) => {
+                    blank_line |= whitespace.matches('\n').count() > 1;
+                },
+                event => {
+                    self.newline(depth, blank_line)?;
+                    write_event(self.writer, event)?;
+                    blank_line = false;
+                },
+            }
+        }
+
+        Ok(blank_line)
+    }
+
+    fn newline(&mut self, depth: usize, blank_line: bool) -> io::Result<()> {
+        if blank_line && self.options.preserve_blank_lines {
+            writeln!(self.writer)?;
+        }
+        write!(self.writer, "\n{}", " ".repeat(depth * self.options.indent))
+    }
+}
+
+/*
+ * Value of any xml:space attribute on an element
+ */
+fn xml_space(element: &dyn Element) -> Option<&str> {
+    element.attributes()
+        .find(|attribute| attribute.name.local_name == "space" &&
+            attribute.name.namespace.as_deref() == Some(NS_XML_URI))
+        .map(|attribute| attribute.value.as_str())
+}
+
+/*
+ * Check whether an element has subelements but no text, other than
+ * whitespace, so its whitespace can be changed without changing its
+ * meaning
+ */
+fn is_element_only(element: &dyn Element) -> bool {
+    let text = |event: &XmlEvent| matches!(event, XmlEvent::Characters(_) | XmlEvent::CData(_));
+
+    !element.subelements().is_empty() &&
+        !element.content().iter().any(text) &&
+        !element.subelements().iter()
+            .any(|subelement| subelement.before_element().iter().any(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+    use xml::reader::XmlEvent;
+
+    use crate::element::Element;
+    use crate::parse_tree::{ParseTree, TreeLevelInfo};
+
+    use super::{format, FormatOptions};
+
+    fn format_str(input: &str, options: &FormatOptions) -> String {
+        let mut output = Vec::new();
+        format(BufReader::new(Cursor::new(input.as_bytes())), &mut output, options)
+            .expect("format failed");
+        String::from_utf8(output).expect("output not UTF-8")
+    }
+
+    /*
+     * Check that two trees are the same apart from whitespace between
+     * subelements
+     */
+    fn assert_same(a: &dyn Element, b: &dyn Element) {
+        fn significant(events: &[XmlEvent]) -> Vec<&XmlEvent> {
+            events.iter().filter(|event| !matches!(event, XmlEvent::Whitespace(_))).collect()
+        }
+
+        assert_eq!(a.qname(), b.qname());
+        assert_eq!(a.element_info().attributes, b.element_info().attributes);
+        assert_eq!(a.namespace(), b.namespace());
+        assert_eq!(significant(a.before_element()), significant(b.before_element()));
+        assert_eq!(significant(a.content()), significant(b.content()),
+            "content of <{}>", a.name());
+        assert_eq!(a.subelements().len(), b.subelements().len());
+        for (a_sub, b_sub) in a.subelements().iter().zip(b.subelements()) {
+            assert_same(a_sub.as_ref(), b_sub.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<schema xmlns=\"urn:s\"><element name=\"a\" type=\"b\">",
+            "<annotation>  <documentation>  Keep   this\n  as it is  </documentation>",
+            "</annotation>\n\n\n<!-- note --><empty></empty>",
+            "<p>Some <b>mixed</b>  content</p>",
+            "<pre xml:space=\"preserve\"><x>  <y/></x></pre></element></schema>");
+        let expected = concat!(
+            "<schema xmlns=\"urn:s\">\n",
+            "  <element name=\"a\" type=\"b\">\n",
+            "    <annotation>\n",
+            "      <documentation>  Keep   this\n  as it is  </documentation>\n",
+            "    </annotation>\n",
+            "\n",
+            "    <!-- note -->\n",
+            "    <empty/>\n",
+            "    <p>Some <b>mixed</b>  content</p>\n",
+            "    <pre xml:space=\"preserve\"><x>  <y/></x></pre>\n",
+            "  </element>\n",
+            "</schema>\n");
+        let options = FormatOptions { indent: 2, ..Default::default() };
+        assert_eq!(format_str(input, &options), expected);
+
+        // Formatting is idempotent
+        assert_eq!(format_str(expected, &options), expected);
+
+        let options = FormatOptions {
+            indent:                 4,
+            wrap_attributes:        Some(1),
+            self_close:             false,
+            preserve_blank_lines:   false,
+            ..Default::default()
+        };
+        assert_eq!(format_str("<a>\n\n<b x=\"1\" y=\"2\"/><c x=\"1\"/></a>", &options), concat!(
+            "<a>\n",
+            "    <b\n",
+            "        x=\"1\"\n",
+            "        y=\"2\"></b>\n",
+            "    <c x=\"1\"></c>\n",
+            "</a>\n"));
+
+        // Long start tags are wrapped
+        let options = FormatOptions { line_width: 20, ..Default::default() };
+        assert_eq!(format_str("<a><b first=\"1\" second=\"2\"/></a>", &options), concat!(
+            "<a>\n",
+            "    <b\n",
+            "        first=\"1\"\n",
+            "        second=\"2\"/>\n",
+            "</a>\n"));
+    }
+
+    #[test]
+    fn test_space_and_mixed() {
+        println!("Running test {}", function_name!());
+        let options = FormatOptions { indent: 2, ..Default::default() };
+
+        // Everything inside xml:space="preserve" is kept, even where
+        // xml:space="default" is given again
+        let input = concat!(
+            "<a><pre xml:space=\"preserve\">\n<b xml:space=\"default\">  <c/>  </b>\n</pre>",
+            "<d xml:space=\"default\"><e/></d></a>");
+        assert_eq!(format_str(input, &options), concat!(
+            "<a>\n",
+            "  <pre xml:space=\"preserve\">\n<b xml:space=\"default\">  <c/>  </b>\n</pre>\n",
+            "  <d xml:space=\"default\">\n",
+            "    <e/>\n",
+            "  </d>\n",
+            "</a>\n"));
+
+        // Text anywhere among the subelements, including CDATA and escaped
+        // characters, keeps the element as it was
+        let input = concat!(
+            "<a><p><b/>text<c/></p><q><![CDATA[<x>]]> <b/></q><r>a &amp; b <b/></r>",
+            "<s>   </s></a>");
+        assert_eq!(format_str(input, &options), concat!(
+            "<a>\n",
+            "  <p><b/>text<c/></p>\n",
+            "  <q><![CDATA[<x>]]> <b/></q>\n",
+            "  <r>a &amp; b <b/></r>\n",
+            "  <s>   </s>\n",
+            "</a>\n"));
+        assert_eq!(format_str(&format_str(input, &options), &options),
+            format_str(input, &options));
+    }
+
+    #[test]
+    fn test_format_schema() {
+        println!("Running test {}", function_name!());
+        let path = "schema/SpaceSystem-patched.xsd";
+        let file = File::open(path).expect("Unable to open SpaceSystem-patched.xsd");
+        let mut output = Vec::new();
+        format(BufReader::new(file), &mut output, &FormatOptions::default())
+            .expect("format failed");
+
+        let level_info = TreeLevelInfo::new();
+        let file = File::open(path).unwrap();
+        let (_, original) = ParseTree::parse(BufReader::new(file), &level_info).unwrap();
+        let (_, formatted) = ParseTree::parse(BufReader::new(Cursor::new(&output)), &level_info)
+            .expect("formatted output doesn't parse");
+        assert_same(original.as_ref(), formatted.as_ref());
+
+        let mut again = Vec::new();
+        format(BufReader::new(Cursor::new(&output)), &mut again, &FormatOptions::default())
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), String::from_utf8(again).unwrap());
+    }
+}
@@ -0,0 +1,415 @@
+/*
+ * Convert Element trees to JSON and back. Each element is a JSON object:
+ *
+ * o    "$name" is the element's name as written, with its prefix, e.g.
+ *      "xtce:Parameter".
+ * o    "@name" is the value of the attribute "name", again with any prefix,
+ *      e.g. "@xsi:type".
+ * o    "@xmlns" and "@xmlns:prefix" are the namespace declarations made by
+ *      the element, i.e. the mappings that differ from its parent's.
+ * o    If the element holds only text, it is "$text". CDATA sections are
+ *      text, too.
+ * o    Otherwise, if it holds anything at all, "$value" is an array of what
+ *      it holds in document order: a string for each run of text, an
+ *      object for each subelement, {"$comment": text} for each comment and
+ *      {"$pi": target, "$data": data} for each processing instruction.
+ *      Repeated subelements are just the same name appearing more than
+ *      once in the array, so mixed content and the order of subelements
+ *      are kept.
+ *
+ * For example, <a xmlns:p="urn:p" p:x="1">one<b/>two</a> is
+ *
+ *     {"$name": "a", "@xmlns:p": "urn:p", "@p:x": "1",
+ *      "$value": ["one", {"$name": "b"}, "two"]}
+ *
+ * Text that is only whitespace, usually indentation, is dropped unless
+ * JsonOptions::whitespace is set, and comments and processing instructions
+ * are dropped unless JsonOptions::comments is set. With both set, going to
+ * JSON and back gives the same element tree, except that CDATA sections
+ * become text and attributes may be reordered. Anything outside the root
+ * element is dropped.
+ */
+
+use serde_json::{Map, Value};
+use xml::namespace::{Namespace, NS_NO_PREFIX};
+use xml::reader::XmlEvent;
+
+use crate::element::{Element, ElementInfo};
+use crate::serialize_xml::new_element;
+use crate::write_tree::{namespace_declarations, qualified_name};
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * What to keep when converting to JSON
+ * whitespace:  Keep text that is only whitespace
+ * comments:    Keep comments and processing instructions
+ */
+#[derive(Clone, Debug, Default)]
+pub struct JsonOptions {
+    pub whitespace: bool,
+    pub comments:   bool,
+}
+
+/**
+ * Convert an element and everything within it to JSON
+ */
+pub fn to_json(element: &dyn Element, options: &JsonOptions) -> Value {
+    element_to_json(element, &Namespace::empty(), options)
+}
+
+/**
+ * Convert JSON in the form to_json() produces to an element tree
+ */
+pub fn from_json(value: &Value) -> Result<Box<dyn Element>, XmlDocumentError> {
+    element_from_json(value, &Namespace::empty(), "")
+}
+
+fn element_to_json(element: &dyn Element, parent_namespace: &Namespace,
+    options: &JsonOptions) -> Value {
+    let namespace = &element.element_info().namespace;
+    let mut items = Items::new(options);
+
+    for subelement in element.subelements() {
+        for event in subelement.before_element() {
+            items.event(event);
+        }
+        items.item(element_to_json(subelement.as_ref(), namespace, options));
+    }
+    for event in element.content() {
+        items.event(event);
+    }
+
+    let mut object = element_head(element.element_info(), parent_namespace);
+    items.finish(&mut object);
+    Value::Object(object)
+}
+
+/*
+ * The name, namespace declarations and attributes of an element
+ */
+pub(crate) fn element_head(element_info: &ElementInfo, parent_namespace: &Namespace) ->
+    Map<String, Value> {
+    let mut object = Map::new();
+    object.insert("$name".to_string(), qualified_name(&element_info.owned_name).into());
+
+    for (prefix, uri) in namespace_declarations(&element_info.namespace, parent_namespace) {
+        let key = match prefix {
+            NS_NO_PREFIX => "@xmlns".to_string(),
+            prefix => format!("@xmlns:{}", prefix),
+        };
+        object.insert(key, uri.into());
+    }
+    for attribute in &element_info.attributes {
+        object.insert(format!("@{}", qualified_name(&attribute.name)), attribute.value.clone()
+            .into());
+    }
+
+    object
+}
+
+/**
+ * What an element holds, as it is converted. Adjacent text is collected
+ * into one string.
+ * options:     What to keep
+ * text:        Text not yet added to the items
+ * items:       Items so far
+ * has_others:  True if there are items other than text
+ */
+pub(crate) struct Items {
+    options:    JsonOptions,
+    text:       String,
+    items:      Vec<Value>,
+    has_others: bool,
+}
+
+impl Items {
+    pub(crate) fn new(options: &JsonOptions) -> Self {
+        Items {
+            options:    options.clone(),
+            text:       String::new(),
+            items:      vec![],
+            has_others: false,
+        }
+    }
+
+    /*
+     * Add text, a comment or a processing instruction
+     */
+    pub(crate) fn event(&mut self, event: &XmlEvent) {
+        match event {
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text) | XmlEvent::CData(text) =>
+                self.text.push_str(text),
+            XmlEvent::Comment(comment) if self.options.comments => {
+                let mut object = Map::new();
+                object.insert("$comment".to_string(), comment.clone().into());
+                self.item(Value::Object(object));
+            },
+            XmlEvent::ProcessingInstruction { name, data } if self.options.comments => {
+                let mut object = Map::new();
+                object.insert("$pi".to_string(), name.clone().into());
+                if let Some(data) = data {
+                    object.insert("$data".to_string(), data.clone().into());
+                }
+                self.item(Value::Object(object));
+            },
+            _ => {},
+        }
+    }
+
+    /*
+     * Add anything other than text
+     */
+    pub(crate) fn item(&mut self, value: Value) {
+        self.subelement();
+        self.items.push(value);
+    }
+
+    /*
+     * Note that a subelement comes next, when it is written out separately
+     */
+    pub(crate) fn subelement(&mut self) {
+        self.flush();
+        self.has_others = true;
+    }
+
+    /*
+     * If the element needs "$value", take the items added since the last
+     * call, so that they can be written out as they come. Text is only
+     * taken once something follows it.
+     */
+    pub(crate) fn take(&mut self) -> Option<Vec<Value>> {
+        match self.has_others {
+            true => Some(std::mem::take(&mut self.items)),
+            false => None,
+        }
+    }
+
+    /*
+     * Add what is left to the object for the element
+     */
+    pub(crate) fn finish(&mut self, object: &mut Map<String, Value>) {
+        self.flush();
+        if self.has_others {
+            object.insert("$value".to_string(), Value::Array(std::mem::take(&mut self.items)));
+        } else if let Some(text) = self.items.pop() {
+            object.insert("$text".to_string(), text);
+        }
+    }
+
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        if !text.is_empty() && (self.options.whitespace || !text.trim().is_empty()) {
+            self.items.push(text.into());
+        }
+    }
+}
+
+/*
+ * Convert an element. The path is a JSON pointer to the element, for
+ * errors.
+ */
+fn element_from_json(value: &Value, parent_namespace: &Namespace, path: &str) ->
+    Result<Box<dyn Element>, XmlDocumentError> {
+    let invalid = |key: &str, message: &str|
+        XmlDocumentError::InvalidJson(format!("{}/{}", path, key), message.to_string());
+
+    let object = value.as_object()
+        .ok_or_else(|| XmlDocumentError::InvalidJson(path.to_string(),
+            "expected an object for an element".to_string()))?;
+    let mut element = new_element(parent_namespace);
+
+    // Declarations first, so that the prefixes can be used in names
+    for (key, value) in object {
+        let prefix = match declared_prefix(key) {
+            Some(prefix) => prefix,
+            None => continue,
+        };
+        let uri = value.as_str().ok_or_else(|| invalid(key, "expected a string"))?;
+        element.declare_namespace(prefix, uri)?;
+    }
+
+    let name = object.get("$name").and_then(|name| name.as_str())
+        .ok_or_else(|| invalid("$name", "expected a string for the element name"))?;
+    element.rename(name)?;
+
+    for (key, value) in object {
+        match key.as_str() {
+            "$name" => {},
+            "$text" => {
+                let text = value.as_str().ok_or_else(|| invalid(key, "expected a string"))?;
+                element.set_text(text);
+            },
+            "$value" => {
+                let items = value.as_array().ok_or_else(|| invalid(key, "expected an array"))?;
+                let namespace = element.element_info().namespace.clone();
+                let mut pending = vec![];
+                for (i, item) in items.iter().enumerate() {
+                    let path = format!("{}/$value/{}", path, i);
+                    match item_from_json(item, &path)? {
+                        Some(event) => pending.push(event),
+                        None => {
+                            let mut subelement = element_from_json(item, &namespace, &path)?;
+                            *subelement.before_element_mut() = std::mem::take(&mut pending);
+                            element.append_child(subelement);
+                        },
+                    }
+                }
+                element.content_mut().append(&mut pending);
+            },
+            key if declared_prefix(key).is_some() => {},
+            key => match key.strip_prefix('@') {
+                Some(name) => {
+                    let value = match value {
+                        Value::String(value) => value.clone(),
+                        Value::Number(_) | Value::Bool(_) => value.to_string(),
+                        _ => return Err(invalid(key, "expected a string, number or boolean")),
+                    };
+                    element.set_attribute(name, &value)?;
+                },
+                None => return Err(invalid(key, "unknown key")),
+            },
+        }
+    }
+
+    if object.contains_key("$text") && object.contains_key("$value") {
+        return Err(invalid("$value", "an element can't have both \"$text\" and \"$value\""));
+    }
+    Ok(element)
+}
+
+/*
+ * The prefix declared by an "@xmlns" or "@xmlns:prefix" key
+ */
+fn declared_prefix(key: &str) -> Option<&str> {
+    match key {
+        "@xmlns" => Some(NS_NO_PREFIX),
+        key => key.strip_prefix("@xmlns:"),
+    }
+}
+
+/*
+ * Convert an item in "$value" that isn't an element
+ */
+fn item_from_json(item: &Value, path: &str) -> Result<Option<XmlEvent>, XmlDocumentError> {
+    let string = |key: &str| match item.get(key) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(XmlDocumentError::InvalidJson(format!("{}/{}", path, key),
+            "expected a string".to_string())),
+    };
+
+    if let Value::String(text) = item {
+        return Ok(Some(XmlEvent::Characters(text.clone())));
+    }
+    if let Some(comment) = string("$comment")? {
+        return Ok(Some(XmlEvent::Comment(comment)));
+    }
+    match string("$pi")? {
+        Some(name) => Ok(Some(XmlEvent::ProcessingInstruction { name, data: string("$data")? })),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use serde_json::json;
+
+    use crate::element::Element;
+    use crate::parse_tree::parse;
+    use crate::write_tree::write_element;
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::{from_json, to_json, JsonOptions};
+
+    fn written(element: &dyn Element) -> String {
+        let mut output = Vec::new();
+        write_element(&mut output, element).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_mapping() {
+        println!("Running test {}", function_name!());
+        let root = parse("<a xmlns:p=\"urn:p\" p:x=\"1\">one<b/>two</a>");
+        assert_eq!(to_json(root.as_ref(), &JsonOptions::default()), json!({
+            "$name": "a", "@xmlns:p": "urn:p", "@p:x": "1",
+            "$value": ["one", {"$name": "b"}, "two"],
+        }));
+
+        let root = parse(concat!(
+            "<SpaceSystem xmlns=\"urn:xtce\" name=\"S\">\n",
+            "  <!-- parameters -->\n",
+            "  <Parameter name=\"A\"><![CDATA[<a>]]> &amp; b</Parameter>\n",
+            "  <Parameter name=\"B\"/>\n",
+            "  <Other xmlns=\"\"><?pi data?></Other>\n",
+            "</SpaceSystem>\n"));
+        assert_eq!(to_json(root.as_ref(), &JsonOptions::default()), json!({
+            "$name": "SpaceSystem", "@xmlns": "urn:xtce", "@name": "S",
+            "$value": [
+                {"$name": "Parameter", "@name": "A", "$text": "<a> & b"},
+                {"$name": "Parameter", "@name": "B"},
+                {"$name": "Other", "@xmlns": ""},
+            ],
+        }));
+
+        let options = JsonOptions { whitespace: true, comments: true };
+        assert_eq!(to_json(root.as_ref(), &options)["$value"], json!([
+            "\n  ",
+            {"$comment": " parameters "},
+            "\n  ",
+            {"$name": "Parameter", "@name": "A", "$text": "<a> & b"},
+            "\n  ",
+            {"$name": "Parameter", "@name": "B"},
+            "\n  ",
+            {"$name": "Other", "@xmlns": "", "$value": [{"$pi": "pi", "$data": "data"}]},
+            "\n",
+        ]));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        println!("Running test {}", function_name!());
+        let options = JsonOptions { whitespace: true, comments: true };
+        let input = concat!(
+            "<x:a xmlns=\"urn:d\" xmlns:x=\"urn:x\" x:k=\"v\">",
+            "text <b>bold</b> more<!--c--><?go now?>",
+            "<c xmlns=\"\"><x:d xmlns:x=\"urn:y\"/></c>\n",
+            "  <b/>",
+            "</x:a>");
+        let root = parse(input);
+        let json = to_json(root.as_ref(), &options);
+        let back = from_json(&json).unwrap();
+        assert_eq!(written(back.as_ref()), input);
+        assert_eq!(to_json(back.as_ref(), &options), json);
+
+        // The JSON survives being written out and read back
+        let text = serde_json::to_string_pretty(&json).unwrap();
+        let reread = from_json(&serde_json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(written(reread.as_ref()), input);
+    }
+
+    #[test]
+    fn test_errors() {
+        println!("Running test {}", function_name!());
+        let error = |value| from_json(&value).unwrap_err().to_string();
+
+        assert_eq!(error(json!("a")),
+            "Can't convert JSON to XML at \"\": expected an object for an element");
+        assert_eq!(error(json!({"@x": "1"})),
+            "Can't convert JSON to XML at \"/$name\": expected a string for the element name");
+        assert_eq!(error(json!({"$name": "a", "$value": [{"$name": "b", "children": []}]})),
+            "Can't convert JSON to XML at \"/$value/0/children\": unknown key");
+        assert_eq!(error(json!({"$name": "a", "$text": "t", "$value": []})),
+            concat!("Can't convert JSON to XML at \"/$value\": ",
+                "an element can't have both \"$text\" and \"$value\""));
+        match from_json(&json!({"$name": "p:a"})) {
+            Err(XmlDocumentError::UnboundPrefix(..)) => {},
+            result => panic!("expected UnboundPrefix, got {:?}", result.map(|_| ())),
+        }
+
+        // Attribute values can be numbers or booleans
+        let element = from_json(&json!({"$name": "a", "@n": 8, "@b": true})).unwrap();
+        assert_eq!(written(element.as_ref()), "<a b=\"true\" n=\"8\"/>");
+    }
+}
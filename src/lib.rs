@@ -1,32 +1,65 @@
-#![feature(try_trait_v2)]
-#![feature(try_trait_v2_residual)]
-
 pub mod xml_document;
 pub mod xml_document_error;
 pub mod xml_schema;
 
+pub mod arena_tree;
 pub mod banner;
+pub mod canonicalize_tree;
+pub mod deserialize_xml;
+pub mod diagnostics;
+pub mod diff_tree;
 pub mod document;
+pub mod edit_tree;
 pub mod element;
+pub mod format_tree;
+pub mod json_tree;
+pub mod merge_tree;
 mod misc;
 pub mod multiterator; // FIXME: toss this
+pub mod navigate;
+pub mod parse_canonical;
 pub mod parse_doc;
 pub mod parse_echo;
 pub mod parse_item;
+pub mod parse_json;
 pub mod parse_schema;
 pub mod parse_tree;
 pub mod parse_xsd;
+pub mod qname;
+pub mod schema_graph;
+pub mod serialize_xml;
 pub mod walk_tree;
+pub mod write_dot;
+pub mod write_tree;
+pub mod xpath;
 pub mod xsd_data;
 pub mod xsd_schema;
 
+pub use crate::arena_tree::{ArenaDocument, ArenaElement, NodeId, ParseArena};
 pub use crate::banner::set_banner_file_name;
+pub use crate::canonicalize_tree::{canonicalize_document, canonicalize_element, CanonicalOptions};
+pub use crate::deserialize_xml::{from_path, from_reader, from_str, Deserializer};
+pub use crate::diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use crate::diff_tree::{diff, diff_paths, DiffOptions, TreeDiff};
 pub use crate::document::DocumentInfo;
 pub use crate::element::{Element, ElementInfo};
-pub use crate::parse_doc::{Accumulator, ParseDoc};
+pub use crate::format_tree::{format, format_document, FormatOptions};
+pub use crate::json_tree::{from_json, to_json, JsonOptions};
+pub use crate::merge_tree::{merge, merge_paths, Conflict, MergeResult};
+pub use crate::navigate::{ElementRef, TreeIndex};
+pub use crate::parse_canonical::{CanonicalAccumulator, CanonicalLevelInfo, ParseCanonical};
+pub use crate::parse_doc::{Accumulator, DocumentStream, Fragment, ParseDoc, PushDocument,
+    RecoveredDocument};
 pub use crate::parse_echo::{EchoAccumulator, EchoLevelInfo, ParseEcho};
+pub use crate::parse_json::{JsonAccumulator, JsonLevelInfo, ParseJson};
 pub use crate::parse_schema::{ParseSchema, ParseSchemaParams, SchemaElement, SchemaAccumulator, SchemaLevelInfo};
 pub use crate::parse_tree::{ParseTree, TreeElement, TreeAccumulator, TreeLevelInfo};
 pub use crate::parse_xsd::{ParseXsd, XsdAccumulator, XsdLevelInfo};
+pub use crate::qname::QName;
+pub use crate::schema_graph::{ComponentKind, Reference, ReferenceKind, SchemaComponent, SchemaGraph};
+pub use crate::serialize_xml::{to_element, to_string, to_writer, SerializeOptions};
+pub use crate::write_dot::{write_container_dot, write_schema_dot, write_tree_dot, DotOptions, SchemaView};
+pub use crate::write_tree::{write_document, write_element};
 pub use crate::xml_document_error::XmlDocumentError;
+pub use crate::xpath::{XPath, XPathNode, XPathValue};
 pub use crate::xsd_schema::XSD_SCHEMA;
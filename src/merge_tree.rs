@@ -0,0 +1,777 @@
+/*
+ * Three-way merge of Element trees. Given a base tree and two trees derived
+ * from it, "ours" and "theirs", build a tree with the changes from both.
+ * Subelements are matched up by element name and key attribute, as for
+ * diff_tree. The subelements of each side are first aligned with those of
+ * the base, so that unchanged siblings match even when elements with the
+ * same name and no key are added or removed around them.
+ *
+ * For each element, attribute and piece of text, a change on only one side
+ * is taken. If both sides changed it the same way, that is taken too.
+ * Otherwise there is a conflict, which is resolved in favour of ours and
+ * marked by a comment starting with "CONFLICT:" just before the element,
+ * so that the output is still well-formed XML. Conflicts are:
+ *
+ * - An attribute or the text of an element changed differently on the two
+ *   sides. The comment gives their version of text.
+ * - An element deleted on one side and changed on the other. The changed
+ *   element is kept.
+ *
+ * Subelements added on either side are inserted after the sibling they
+ * follow on that side. If only one side reorders the subelements of an
+ * element, its order is used.
+ *
+ * Unlike diff_tree, comments and processing instructions are merged along
+ * with the text around them, so a change to one is never dropped.
+ */
+
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::cmp::max;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent;
+
+use crate::diff_tree::DiffOptions;
+use crate::document::DocumentInfo;
+use crate::element::Element;
+use crate::parse_item::LineNumber;
+use crate::parse_tree::{ParseTree, TreeElement, TreeLevelInfo};
+use crate::qname::QName;
+use crate::write_tree::qualified_name;
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * A change that couldn't be merged
+ * path:    Path to the element, in the style of XPath
+ * line:    Line of the element in ours or, if it isn't in ours, theirs
+ * message: What the conflict was
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Conflict {
+    pub path:       String,
+    pub line:       LineNumber,
+    pub message:    String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (line {}): {}", self.path, self.line, self.message)
+    }
+}
+
+/**
+ * Result of a merge
+ * root:        Root of the merged tree
+ * conflicts:   Conflicts, in document order. Each is also marked by a
+ *              comment in the merged tree.
+ */
+pub struct MergeResult {
+    pub root:       Box<dyn Element>,
+    pub conflicts:  Vec<Conflict>,
+}
+
+impl MergeResult {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/**
+ * Merge the changes from base to ours and from base to theirs
+ */
+pub fn merge(base: &(dyn Element + 'static), ours: &(dyn Element + 'static),
+    theirs: &(dyn Element + 'static), options: &DiffOptions) -> MergeResult {
+    let mut merger = Merger { options, conflicts: vec![] };
+    let path = format!("/{}", qualified_name(&ours.element_info().owned_name));
+
+    let root = match base.qname() == ours.qname() && base.qname() == theirs.qname() {
+        true => merger.element(Some(base), ours, theirs, &path),
+        false => match merger.pick(Some(&base), &ours, &theirs, |element| {
+            merger.fingerprint(*element)
+        }) {
+            Pick::Theirs => dyn_clone::clone_box(theirs),
+            Pick::Ours => dyn_clone::clone_box(ours),
+            Pick::Conflict => {
+                let mut root = dyn_clone::clone_box(ours);
+                merger.conflict(root.as_mut(), &path,
+                    "root element replaced on both sides; keeping ours".to_string());
+                root
+            },
+        },
+    };
+
+    MergeResult { root, conflicts: merger.conflicts }
+}
+
+/**
+ * Parse three files and merge them. The document information, such as the
+ * encoding, comes from ours.
+ */
+pub fn merge_paths(base_path: &str, ours_path: &str, theirs_path: &str, options: &DiffOptions) ->
+    Result<(DocumentInfo, MergeResult), XmlDocumentError> {
+    let (_, base) = ParseTree::parse_path(base_path, &TreeLevelInfo::new())?;
+    let (document_info, ours) = ParseTree::parse_path(ours_path, &TreeLevelInfo::new())?;
+    let (_, theirs) = ParseTree::parse_path(theirs_path, &TreeLevelInfo::new())?;
+    Ok((document_info, merge(base.as_ref(), ours.as_ref(), theirs.as_ref(), options)))
+}
+
+/*
+ * Which side a three-way choice goes to
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pick {
+    Ours,
+    Theirs,
+    Conflict,
+}
+
+/*
+ * Identity of a subelement among its siblings
+ * name:        Element name
+ * key:         Key attribute and its value, if any
+ * occurrence:  Number of earlier siblings in the base with the same name
+ *              and key or, for an added subelement, of earlier additions
+ * added:       Whether the subelement was added on one or both sides
+ */
+/*
+ * Element name and key attribute of a subelement
+ */
+type Name = (QName, Option<(String, String)>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Identity {
+    name:       QName,
+    key:        Option<(String, String)>,
+    occurrence: usize,
+    added:      bool,
+}
+
+/*
+ * Subelements of one of the versions of an element, by identity
+ * ids:         Identities of the subelements, in order
+ * positions:   Position of the subelement with each identity
+ * prints:      Fingerprint of each subelement
+ * steps:       Path step for each subelement
+ */
+struct Children<'a> {
+    subelements:    &'a [Box<dyn Element>],
+    ids:            Vec<Identity>,
+    positions:      HashMap<Identity, usize>,
+    prints:         Vec<u64>,
+    steps:          Vec<String>,
+}
+
+impl<'a> Children<'a> {
+    fn new(subelements: &'a [Box<dyn Element>], ids: Vec<Identity>, prints: Vec<u64>) ->
+        Children<'a> {
+        let mut counts: HashMap<Name, usize> = HashMap::new();
+        let mut occurrences = vec![];
+        for id in &ids {
+            let count = counts.entry((id.name.clone(), id.key.clone())).or_insert(0);
+            occurrences.push(*count);
+            *count += 1;
+        }
+
+        let steps = subelements.iter().zip(&ids).zip(occurrences)
+            .map(|((subelement, id), occurrence)| {
+                let mut step = qualified_name(&subelement.element_info().owned_name).to_string();
+                if let Some((key, value)) = &id.key {
+                    step += &format!("[@{}='{}']", key, value);
+                }
+                if counts[&(id.name.clone(), id.key.clone())] > 1 {
+                    step += &format!("[{}]", occurrence + 1);
+                }
+                step
+            })
+            .collect();
+        let positions = ids.iter().cloned().enumerate().map(|(i, id)| (id, i)).collect();
+
+        Children { subelements, ids, positions, prints, steps }
+    }
+
+    fn get(&self, id: &Identity) -> Option<&'a (dyn Element + 'static)> {
+        self.positions.get(id).map(|&i| self.subelements[i].as_ref())
+    }
+
+    fn step(&self, id: &Identity) -> Option<&str> {
+        self.positions.get(id).map(|&i| self.steps[i].as_str())
+    }
+
+    /*
+     * Identities of the subelements that are also in other, in order
+     */
+    fn common(&self, other: &Children) -> Vec<&Identity> {
+        self.ids.iter().filter(|id| other.positions.contains_key(id)).collect()
+    }
+}
+
+struct Merger<'o> {
+    options:    &'o DiffOptions,
+    conflicts:  Vec<Conflict>,
+}
+
+impl Merger<'_> {
+    /*
+     * Choose between ours and theirs, comparing them, and the base if there
+     * is one, by a value derived from each
+     */
+    fn pick<T, V: PartialEq>(&self, base: Option<&T>, ours: &T, theirs: &T,
+        value: impl Fn(&T) -> V) -> Pick {
+        let (ours, theirs) = (value(ours), value(theirs));
+        let base = base.map(&value);
+        if ours == theirs || base.as_ref() == Some(&theirs) {
+            Pick::Ours
+        } else if base.as_ref() == Some(&ours) {
+            Pick::Theirs
+        } else {
+            Pick::Conflict
+        }
+    }
+
+    /*
+     * Merge versions of an element that match
+     */
+    fn element(&mut self, base: Option<&(dyn Element + 'static)>,
+        ours: &(dyn Element + 'static), theirs: &(dyn Element + 'static), path: &str) ->
+        Box<dyn Element> {
+        let fingerprint = |element: &&(dyn Element + 'static)| self.fingerprint(*element);
+        match self.pick(base.as_ref(), &ours, &theirs, fingerprint) {
+            Pick::Ours => return dyn_clone::clone_box(ours),
+            Pick::Theirs => return dyn_clone::clone_box(theirs),
+            Pick::Conflict => {},
+        }
+
+        let mut notes = vec![];
+        let mut element_info = ours.element_info().clone();
+        element_info.attributes = self.attributes(base, ours, theirs, &mut notes);
+
+        let before_element = self.events(base.map(|base| base.before_element()),
+            ours.before_element(), theirs.before_element(), "text before the element",
+            &mut notes);
+        let content = self.events(base.map(|base| base.content()), ours.content(),
+            theirs.content(), "text at the end of the element", &mut notes);
+        let after_element = self.events(base.map(|base| base.after_element()),
+            ours.after_element(), theirs.after_element(), "text after the element", &mut notes);
+
+        let mut merged: Box<dyn Element> = Box::new(TreeElement::new(element_info, before_element,
+            content, after_element, vec![]));
+        for note in notes {
+            self.conflict(merged.as_mut(), path, note);
+        }
+        *merged.subelements_mut() = self.subelements(base, ours, theirs, path);
+        merged
+    }
+
+    /*
+     * Merge the attributes of an element, in the order of ours followed by
+     * those only in theirs
+     */
+    fn attributes(&self, base: Option<&dyn Element>, ours: &dyn Element, theirs: &dyn Element,
+        notes: &mut Vec<String>) -> Vec<OwnedAttribute> {
+        let same = |a: &OwnedAttribute, b: &OwnedAttribute| {
+            a.name.local_name == b.name.local_name && a.name.namespace == b.name.namespace
+        };
+        let find = |element: &dyn Element, attribute: &OwnedAttribute| element.attributes()
+            .find(|other| same(other, attribute))
+            .cloned();
+        let mut names: Vec<&OwnedAttribute> = ours.attributes().collect();
+        for attribute in theirs.attributes().chain(base.iter().flat_map(|base| base.attributes())) {
+            if !names.iter().any(|other| same(other, attribute)) {
+                names.push(attribute);
+            }
+        }
+
+        let mut attributes = vec![];
+        for name in names {
+            let base_attribute = base.map(|base| find(base, name));
+            let ours_attribute = find(ours, name);
+            let theirs_attribute = find(theirs, name);
+            let value = |attribute: &Option<OwnedAttribute>| attribute.as_ref()
+                .map(|attribute| attribute.value.clone());
+
+            let chosen = match self.pick(base_attribute.as_ref(), &ours_attribute,
+                &theirs_attribute, value) {
+                Pick::Ours => ours_attribute,
+                Pick::Theirs => theirs_attribute,
+                Pick::Conflict => {
+                    notes.push(format!("attribute \"{}\" {} in ours and {} in theirs; keeping ours",
+                        qualified_name(&name.name), describe(value(&ours_attribute)),
+                        describe(value(&theirs_attribute))));
+                    ours_attribute
+                },
+            };
+            attributes.extend(chosen);
+        }
+        attributes
+    }
+
+    /*
+     * Merge text, comments and processing instructions
+     */
+    fn events(&self, base: Option<&Vec<XmlEvent>>, ours: &Vec<XmlEvent>,
+        theirs: &Vec<XmlEvent>, what: &str, notes: &mut Vec<String>) -> Vec<XmlEvent> {
+        let text = |events: &&Vec<XmlEvent>| self.options.normalize(&events_text(events));
+        match self.pick(base.as_ref(), &ours, &theirs, text) {
+            Pick::Ours => ours.clone(),
+            Pick::Theirs => theirs.clone(),
+            Pick::Conflict => {
+                notes.push(format!("{} changed on both sides; theirs is {:?}", what,
+                    text(&theirs)));
+                ours.clone()
+            },
+        }
+    }
+
+    /*
+     * Merge the subelements of versions of an element that match
+     */
+    fn subelements(&mut self, base: Option<&(dyn Element + 'static)>,
+        ours: &(dyn Element + 'static), theirs: &(dyn Element + 'static), path: &str) ->
+        Vec<Box<dyn Element>> {
+        let base = self.base_children(base.map(|base| base.subelements().as_slice())
+            .unwrap_or(&[]));
+        let ours = self.side_children(&base, ours.subelements(), None);
+        let theirs = self.side_children(&base, theirs.subelements(), Some(&ours));
+
+        // Follow the order of theirs only if they reordered and we didn't
+        let reordered = |side: &Children| side.common(&base) != base.common(side);
+        let (primary, secondary) = match !reordered(&ours) && reordered(&theirs) {
+            true => (&theirs, &ours),
+            false => (&ours, &theirs),
+        };
+
+        // Put subelements only in the other side after the one they follow,
+        // and after any that follow it only in the side whose order is used
+        let mut order: Vec<&Identity> = primary.ids.iter().collect();
+        let mut anchor = None;
+        for id in &secondary.ids {
+            match order.iter().position(|other| *other == id) {
+                Some(i) => anchor = Some(i),
+                None => {
+                    let mut i = anchor.map_or(0, |i| i + 1);
+                    while i < order.len() && !secondary.positions.contains_key(order[i]) {
+                        i += 1;
+                    }
+                    order.insert(i, id);
+                    anchor = Some(i);
+                },
+            }
+        }
+
+        let mut subelements = vec![];
+        for id in order {
+            let step = ours.step(id).or(theirs.step(id)).expect("identity from one side");
+            let path = format!("{}/{}", path, step);
+            subelements.extend(self.subelement(base.get(id), ours.get(id), theirs.get(id), &path));
+        }
+        subelements
+    }
+
+    /*
+     * Subelements of the base, each identified by the number of earlier
+     * siblings with the same name and key
+     */
+    fn base_children<'a>(&self, subelements: &'a [Box<dyn Element>]) -> Children<'a> {
+        let mut counts: HashMap<Name, usize> = HashMap::new();
+        let mut ids = vec![];
+        for subelement in subelements {
+            let name = subelement.qname();
+            let key = self.options.key(subelement.as_ref());
+            let count = counts.entry((name.clone(), key.clone())).or_insert(0);
+            ids.push(Identity { name, key, occurrence: *count, added: false });
+            *count += 1;
+        }
+        let prints = subelements.iter().map(|subelement| self.fingerprint(subelement.as_ref()))
+            .collect();
+        Children::new(subelements, ids, prints)
+    }
+
+    /*
+     * Subelements of one side, identified by the base subelements they
+     * match. Unchanged subelements are aligned with the base by a longest
+     * common subsequence. Each of the others takes the first unmatched base
+     * subelement with the same name and key, preferring one between the
+     * same unchanged neighbours. Any left over were added, and are the same
+     * as an identical addition in other, if given.
+     */
+    fn side_children<'a>(&self, base: &Children, subelements: &'a [Box<dyn Element>],
+        other: Option<&Children>) -> Children<'a> {
+        let names: Vec<Name> = subelements.iter()
+            .map(|subelement| (subelement.qname(), self.options.key(subelement.as_ref())))
+            .collect();
+        let prints: Vec<u64> = subelements.iter()
+            .map(|subelement| self.fingerprint(subelement.as_ref()))
+            .collect();
+        let same_name = |b: usize, s: usize| {
+            base.ids[b].name == names[s].0 && base.ids[b].key == names[s].1
+        };
+        let same = |b: usize, s: usize| same_name(b, s) && base.prints[b] == prints[s];
+
+        // lengths[b][s] is the length of the longest common subsequence of
+        // the base from b and this side from s
+        let (n, m) = (base.ids.len(), subelements.len());
+        let mut lengths = vec![vec![0; m + 1]; n + 1];
+        for b in (0..n).rev() {
+            for s in (0..m).rev() {
+                lengths[b][s] = match same(b, s) {
+                    true => lengths[b + 1][s + 1] + 1,
+                    false => max(lengths[b + 1][s], lengths[b][s + 1]),
+                };
+            }
+        }
+        let mut matches: Vec<Option<usize>> = vec![None; m];
+        let (mut b, mut s) = (0, 0);
+        while b < n && s < m {
+            if same(b, s) {
+                matches[s] = Some(b);
+                b += 1;
+                s += 1;
+            } else if lengths[b + 1][s] >= lengths[b][s + 1] {
+                b += 1;
+            } else {
+                s += 1;
+            }
+        }
+
+        // Match changed subelements, first between the same unchanged
+        // neighbours and then anywhere
+        let mut used = vec![false; n];
+        for b in matches.iter().flatten() {
+            used[*b] = true;
+        }
+        let aligned = matches.clone();
+        for nearby in [true, false] {
+            for s in 0..m {
+                if matches[s].is_some() {
+                    continue;
+                }
+                let (low, high) = match nearby {
+                    true => (aligned[..s].iter().rev().flatten().next().map_or(0, |b| b + 1),
+                        aligned[s..].iter().flatten().next().copied().unwrap_or(n)),
+                    false => (0, n),
+                };
+                if let Some(b) = (low..high).find(|&b| !used[b] && same_name(b, s)) {
+                    matches[s] = Some(b);
+                    used[b] = true;
+                }
+            }
+        }
+
+        let mut added: HashMap<Name, usize> = HashMap::new();
+        let mut paired = vec![false; other.map_or(0, |other| other.ids.len())];
+        let mut ids = vec![];
+        for (s, (name, key)) in names.into_iter().enumerate() {
+            if let Some(b) = matches[s] {
+                ids.push(base.ids[b].clone());
+                continue;
+            }
+            let other_added = |o: &usize| other.is_some_and(|other| {
+                let id = &other.ids[*o];
+                id.added && id.name == name && id.key == key
+            });
+            let pair = (0..paired.len())
+                .find(|o| !paired[*o] && other_added(o) &&
+                    other.is_some_and(|other| other.prints[*o] == prints[s]));
+            match (other, pair) {
+                (Some(other), Some(o)) => {
+                    paired[o] = true;
+                    ids.push(other.ids[o].clone());
+                },
+                _ => {
+                    let taken = (0..paired.len()).filter(other_added).count();
+                    let count = added.entry((name.clone(), key.clone())).or_insert(taken);
+                    ids.push(Identity { name, key, occurrence: *count, added: true });
+                    *count += 1;
+                },
+            }
+        }
+        Children::new(subelements, ids, prints)
+    }
+
+    /*
+     * Merge the versions of a subelement, any of which might be missing
+     */
+    fn subelement(&mut self, base: Option<&(dyn Element + 'static)>,
+        ours: Option<&(dyn Element + 'static)>, theirs: Option<&(dyn Element + 'static)>,
+        path: &str) -> Option<Box<dyn Element>> {
+        let (kept, deleted_in, changed_in) = match (ours, theirs) {
+            (Some(ours), Some(theirs)) => return Some(self.element(base, ours, theirs, path)),
+            (None, None) => return None,
+            (Some(ours), None) => (ours, "theirs", "ours"),
+            (None, Some(theirs)) => (theirs, "ours", "theirs"),
+        };
+
+        match base {
+            // Added on one side
+            None => Some(dyn_clone::clone_box(kept)),
+            // Deleted on one side and not changed on the other
+            Some(base) if self.fingerprint(base) == self.fingerprint(kept) => None,
+            Some(_) => {
+                let mut kept = dyn_clone::clone_box(kept);
+                self.conflict(kept.as_mut(), path, format!(
+                    "deleted in {} but changed in {}; keeping the changed element", deleted_in,
+                    changed_in));
+                Some(kept)
+            },
+        }
+    }
+
+    /*
+     * Hash of everything that is merged in an element and its descendants:
+     * what diff_tree compares, along with comments and processing
+     * instructions
+     */
+    fn fingerprint(&self, element: &dyn Element) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.options.fingerprint(element).hash(&mut hasher);
+        self.hash_events(element, &mut hasher);
+        hasher.finish()
+    }
+
+    fn hash_events(&self, element: &dyn Element, hasher: &mut DefaultHasher) {
+        for events in [element.before_element(), element.content(), element.after_element()] {
+            self.options.normalize(&events_text(events)).hash(hasher);
+        }
+        for subelement in element.subelements() {
+            self.hash_events(subelement.as_ref(), hasher);
+        }
+    }
+
+    /*
+     * Record a conflict and mark it with a comment before the element
+     */
+    fn conflict(&mut self, element: &mut dyn Element, path: &str, message: String) {
+        let comment = format!(" CONFLICT: {} ", comment_text(&message));
+        element.before_element_mut().push(XmlEvent::Comment(comment));
+        self.conflicts.push(Conflict {
+            path:       path.to_string(),
+            line:       element.lineno(),
+            message,
+        });
+    }
+}
+
+/*
+ * Describe the value of an attribute on one side
+ */
+fn describe(value: Option<String>) -> String {
+    match value {
+        None => "removed".to_string(),
+        Some(value) => format!("set to {:?}", value),
+    }
+}
+
+/*
+ * Make text safe to put in a comment, which can't hold "--" or end with
+ * "-", by putting a space after each "-" that follows another
+ */
+fn comment_text(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if c == '-' && escaped.ends_with('-') {
+            escaped.push(' ');
+        }
+        escaped.push(c);
+    }
+    if escaped.ends_with('-') {
+        escaped.push(' ');
+    }
+    escaped
+}
+
+/*
+ * Text of a sequence of events, with comments and processing instructions
+ * written as they would be in XML, so that changes to them count
+ */
+fn events_text(events: &[XmlEvent]) -> String {
+    let mut text = String::new();
+    for event in events {
+        match event {
+            XmlEvent::Characters(s) | XmlEvent::Whitespace(s) | XmlEvent::CData(s) =>
+                text.push_str(s),
+            XmlEvent::Comment(comment) => text += &format!("<!--{}-->", comment),
+            XmlEvent::ProcessingInstruction { name, data } =>
+                text += &format!("<?{} {}?>", name, data.as_deref().unwrap_or("")),
+            _ => {},
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use std::fs;
+
+    use crate::diff_tree::DiffOptions;
+    use crate::parse_tree::parse;
+    use crate::write_tree::write_element;
+
+    use super::{merge, merge_paths, MergeResult};
+
+    fn merge_str(base: &str, ours: &str, theirs: &str) -> (String, MergeResult) {
+        let result = merge(parse(base).as_ref(), parse(ours).as_ref(), parse(theirs).as_ref(),
+            &DiffOptions::default());
+        let mut output = Vec::new();
+        write_element(&mut output, result.root.as_ref()).unwrap();
+        (String::from_utf8(output).unwrap(), result)
+    }
+
+    #[test]
+    fn test_merge() {
+        println!("Running test {}", function_name!());
+        let base = "<s>\n<p name=\"a\" t=\"1\"/>\n<p name=\"b\">text</p>\n<p name=\"c\"/>\n</s>";
+        let ours = "<s>\n<p name=\"a\" t=\"2\"/>\n<p name=\"b\">text</p>\n<p name=\"n\"/>\n</s>";
+        let theirs = concat!("<s>\n<p name=\"a\" t=\"1\" u=\"x\"/>\n<p name=\"b\">new</p>\n",
+            "<p name=\"c\"/>\n<p name=\"m\"/>\n</s>");
+        let (output, result) = merge_str(base, ours, theirs);
+        assert!(result.is_clean());
+        assert_eq!(output, concat!("<s>\n<p name=\"a\" t=\"2\" u=\"x\"/>\n<p name=\"b\">new</p>\n",
+            "<p name=\"n\"/>\n<p name=\"m\"/>\n</s>"));
+
+        // The same change on both sides
+        let (output, result) = merge_str(base, ours, ours);
+        assert!(result.is_clean());
+        assert_eq!(output, ours);
+
+        // Their reordering is kept if we didn't reorder
+        let theirs = "<s>\n<p name=\"c\"/>\n<p name=\"b\">text</p>\n<p name=\"a\" t=\"1\"/>\n</s>";
+        let ours = "<s>\n<p name=\"a\" t=\"3\"/>\n<p name=\"b\">text</p>\n<p name=\"c\"/>\n</s>";
+        let (output, result) = merge_str(base, ours, theirs);
+        assert!(result.is_clean());
+        assert_eq!(output,
+            "<s>\n<p name=\"c\"/>\n<p name=\"b\">text</p>\n<p name=\"a\" t=\"3\"/>\n</s>");
+
+        // Changes to comments and processing instructions are kept
+        let base = "<s><b>x</b><c/></s>";
+        let (output, result) = merge_str(base, base, "<s><b>x<!-- note --></b><c/></s>");
+        assert!(result.is_clean());
+        assert_eq!(output, "<s><b>x<!-- note --></b><c/></s>");
+        let (output, result) = merge_str(base, "<s><b>x</b><c t=\"1\"/></s>",
+            "<s><b>x<?pi data?></b><c/></s>");
+        assert!(result.is_clean());
+        assert_eq!(output, "<s><b>x<?pi data?></b><c t=\"1\"/></s>");
+    }
+
+    #[test]
+    fn test_conflicts() {
+        println!("Running test {}", function_name!());
+        let base = "<s>\n<p name=\"a\" t=\"1\">x</p>\n<p name=\"b\"/>\n</s>";
+        let ours = "<s>\n<p name=\"a\" t=\"2\">y</p>\n</s>";
+        let theirs = "<s>\n<p name=\"a\" t=\"3\">z</p>\n<p name=\"b\" v=\"1\"/>\n</s>";
+        let (output, result) = merge_str(base, ours, theirs);
+
+        let messages: Vec<String> = result.conflicts.iter().map(|c| c.to_string()).collect();
+        assert_eq!(messages, vec![
+            "/s/p[@name='a'] (line 2): attribute \"t\" set to \"2\" in ours and set to \"3\" in \
+                theirs; keeping ours",
+            "/s/p[@name='a'] (line 2): text at the end of the element changed on both sides; \
+                theirs is \"z\"",
+            "/s/p[@name='b'] (line 3): deleted in ours but changed in theirs; keeping the \
+                changed element",
+        ]);
+        assert_eq!(output, concat!(
+            "<s>\n",
+            "<!-- CONFLICT: attribute \"t\" set to \"2\" in ours and set to \"3\" in theirs; ",
+            "keeping ours -->",
+            "<!-- CONFLICT: text at the end of the element changed on both sides; ",
+            "theirs is \"z\" -->",
+            "<p name=\"a\" t=\"2\">y</p>\n",
+            "<!-- CONFLICT: deleted in ours but changed in theirs; keeping the changed ",
+            "element --><p name=\"b\" v=\"1\"/>\n",
+            "</s>"));
+
+        // The output with conflict markers is still XML
+        parse(&output);
+
+        // Even when the text in the conflict has dashes in it
+        let (output, result) = merge_str("<s><b>x</b></s>", "<s><b>y</b></s>",
+            "<s><b>z---w-</b></s>");
+        assert_eq!(result.conflicts.len(), 1);
+        assert!(output.contains("theirs is \"z- - -w-\" -->"), "{}", output);
+        parse(&output);
+    }
+
+    #[test]
+    fn test_unkeyed() {
+        println!("Running test {}", function_name!());
+        // Additions at either end don't shift the siblings they are next to
+        let base = "<s><a/><a/></s>";
+        let (output, result) = merge_str(base, "<s><a/><a/><a k=\"1\"/></s>",
+            "<s><a k=\"2\"/><a/><a/></s>");
+        assert!(result.is_clean(), "{:?}", result.conflicts);
+        assert_eq!(output, "<s><a k=\"2\"/><a/><a/><a k=\"1\"/></s>");
+
+        // The same addition on both sides is made once
+        let (output, result) = merge_str(base, "<s><a/><a/><a k=\"1\"/></s>",
+            "<s><a/><a/><a k=\"1\"/></s>");
+        assert!(result.is_clean());
+        assert_eq!(output, "<s><a/><a/><a k=\"1\"/></s>");
+
+        // Different additions in the same place are both made
+        let (output, result) = merge_str(base, "<s><a/><a/><a k=\"1\"/></s>",
+            "<s><a/><a/><a k=\"2\"/></s>");
+        assert!(result.is_clean());
+        assert_eq!(output, "<s><a/><a/><a k=\"1\"/><a k=\"2\"/></s>");
+
+        // A change to one is merged with a deletion of another
+        let base = "<s><a>1</a><a>2</a><a>3</a></s>";
+        let (output, result) = merge_str(base, "<s><a>1</a><a>3</a></s>",
+            "<s><a>1</a><a>2</a><a>three</a></s>");
+        assert!(result.is_clean());
+        assert_eq!(output, "<s><a>1</a><a>three</a></s>");
+
+        // An insertion on one side and a change after it on the other
+        let (output, result) = merge_str(base, "<s><a>0</a><a>1</a><a>2</a><a>3</a></s>",
+            "<s><a>1</a><a>two</a><a>3</a></s>");
+        assert!(result.is_clean());
+        assert_eq!(output, "<s><a>0</a><a>1</a><a>two</a><a>3</a></s>");
+
+        // Different changes to the same one conflict
+        let (output, result) = merge_str(base, "<s><a>1</a><a>ours</a><a>3</a></s>",
+            "<s><a>1</a><a>theirs</a><a>3</a></s>");
+        let messages: Vec<String> = result.conflicts.iter().map(|c| c.to_string()).collect();
+        assert_eq!(messages, vec![
+            "/s/a[2] (line 1): text at the end of the element changed on both sides; theirs is \
+                \"theirs\"",
+        ]);
+        assert!(output.ends_with("--><a>ours</a><a>3</a></s>"), "{}", output);
+
+        // Deleting one that the other side changed is a conflict
+        let (_, result) = merge_str(base, "<s><a>1</a><a>3</a></s>",
+            "<s><a>1</a><a>two</a><a>3</a></s>");
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].path, "/s/a[2]");
+    }
+
+    #[test]
+    fn test_merge_paths() {
+        println!("Running test {}", function_name!());
+        let dir = std::env::temp_dir().join(format!("xml-tree-merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, text: &str| {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let base = write("base.xml", "<a>\n  <b name=\"1\"/>\n</a>\n");
+        let ours = write("ours.xml", "<a>\n  <b name=\"1\" x=\"1\"/>\n</a>\n");
+        let theirs = write("theirs.xml", "<a>\n  <b name=\"1\"/>\n  <b name=\"2\"/>\n</a>\n");
+
+        let (document_info, result) = merge_paths(&base, &ours, &theirs, &DiffOptions::default())
+            .unwrap();
+        assert!(result.is_clean());
+        let mut output = Vec::new();
+        crate::write_tree::write_document(&mut output, &document_info, result.root.as_ref())
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(),
+            "<a>\n  <b name=\"1\" x=\"1\"/>\n  <b name=\"2\"/>\n</a>\n");
+
+        assert!(merge_paths(&base, &dir.join("missing.xml").to_string_lossy(), &theirs,
+            &DiffOptions::default()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+/*
+ * Navigate around an Element tree. Elements only know their subelements,
+ * so a TreeIndex is built for the tree, recording the parent of each
+ * element. ElementRefs into the index can then move up, down and across
+ * the tree.
+ *
+ * Elements are numbered in document order, which is also depth first
+ * order, so the descendants of an element are the elements numbered from
+ * just after it up to the end of its subtree.
+ */
+
+use std::collections::VecDeque;
+
+use crate::element::Element;
+
+/*
+ * An element in the tree
+ * element:     The element
+ * parent:      Index of the parent element, if any
+ * children:    Indices of the subelements
+ * end:         Index after that of the last descendant
+ * depth:       Depth of the element, zero for the root
+ */
+pub(crate) struct Entry<'a> {
+    pub(crate) element:     &'a dyn Element,
+    pub(crate) parent:      Option<usize>,
+    pub(crate) children:    Vec<usize>,
+    pub(crate) end:         usize,
+    pub(crate) depth:       usize,
+}
+
+/**
+ * Index of the elements of a tree, in document order
+ */
+pub struct TreeIndex<'a> {
+    pub(crate) entries: Vec<Entry<'a>>,
+}
+
+impl<'a> TreeIndex<'a> {
+    pub fn new(root: &'a dyn Element) -> TreeIndex<'a> {
+        let mut index = TreeIndex { entries: vec![] };
+        index.add(root, None, 0);
+        index
+    }
+
+    fn add(&mut self, element: &'a dyn Element, parent: Option<usize>, depth: usize) -> usize {
+        let position = self.entries.len();
+        self.entries.push(Entry { element, parent, children: vec![], end: 0, depth });
+        for subelement in element.subelements() {
+            let child = self.add(subelement.as_ref(), Some(position), depth + 1);
+            self.entries[position].children.push(child);
+        }
+        self.entries[position].end = self.entries.len();
+        position
+    }
+
+    /**
+     * Reference to the root element
+     */
+    pub fn root(&self) -> ElementRef<'_, 'a> {
+        ElementRef { index: self, position: 0 }
+    }
+
+    /**
+     * Reference to an element in the tree, found by its address
+     */
+    pub fn find(&self, element: &dyn Element) -> Option<ElementRef<'_, 'a>> {
+        let target = element as *const dyn Element as *const ();
+        self.entries.iter()
+            .position(|entry| std::ptr::eq(entry.element as *const dyn Element as *const (),
+                target))
+            .map(|position| ElementRef { index: self, position })
+    }
+
+    /**
+     * Number of elements in the tree
+     */
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/**
+ * Reference to an element in a TreeIndex
+ * index:       Index of the tree
+ * position:    Position of the element in document order
+ */
+#[derive(Clone, Copy)]
+pub struct ElementRef<'t, 'a> {
+    index:      &'t TreeIndex<'a>,
+    position:   usize,
+}
+
+impl<'t, 'a> ElementRef<'t, 'a> {
+    fn at(&self, position: usize) -> ElementRef<'t, 'a> {
+        ElementRef { index: self.index, position }
+    }
+
+    fn entry(&self) -> &'t Entry<'a> {
+        &self.index.entries[self.position]
+    }
+
+    pub fn element(&self) -> &'a dyn Element {
+        self.entry().element
+    }
+
+    /**
+     * Position of the element in document order, starting from zero for
+     * the root
+     */
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /**
+     * Number of ancestors of the element
+     */
+    pub fn depth(&self) -> usize {
+        self.entry().depth
+    }
+
+    pub fn parent(&self) -> Option<ElementRef<'t, 'a>> {
+        self.entry().parent.map(|parent| self.at(parent))
+    }
+
+    /*
+     * Position of the element among its parent's subelements
+     */
+    fn sibling_position(&self) -> Option<(usize, usize)> {
+        let parent = self.entry().parent?;
+        let siblings = &self.index.entries[parent].children;
+        let i = siblings.iter().position(|&sibling| sibling == self.position)?;
+        Some((parent, i))
+    }
+
+    pub fn previous_sibling(&self) -> Option<ElementRef<'t, 'a>> {
+        let (parent, i) = self.sibling_position()?;
+        let previous = i.checked_sub(1)?;
+        Some(self.at(self.index.entries[parent].children[previous]))
+    }
+
+    pub fn next_sibling(&self) -> Option<ElementRef<'t, 'a>> {
+        let (parent, i) = self.sibling_position()?;
+        self.index.entries[parent].children.get(i + 1).map(|&next| self.at(next))
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = ElementRef<'t, 'a>> + '_ {
+        self.entry().children.iter().map(|&child| self.at(child))
+    }
+
+    /**
+     * Ancestors of the element, starting with its parent and ending with
+     * the root
+     */
+    pub fn ancestors(&self) -> Ancestors<'t, 'a> {
+        Ancestors { next: self.parent() }
+    }
+
+    /**
+     * Descendants of the element, depth first
+     */
+    pub fn descendants(&self) -> DepthFirst<'t, 'a> {
+        DepthFirst {
+            index:  self.index,
+            next:   self.position + 1,
+            end:    self.entry().end,
+            last:   None,
+        }
+    }
+
+    /**
+     * Descendants of the element, breadth first
+     */
+    pub fn descendants_breadth_first(&self) -> BreadthFirst<'t, 'a> {
+        BreadthFirst {
+            index:  self.index,
+            queue:  self.entry().children.iter().copied().collect(),
+            last:   None,
+        }
+    }
+
+    /**
+     * Follow a path of local names, separated by '/', from this element.
+     * ".." is the parent, "." is the element itself and any other name is
+     * the first subelement with that name, e.g. "../Foo/Bar". A path
+     * starting with '/' starts at the root.
+     */
+    pub fn walk_path(&self, path: &str) -> Option<ElementRef<'t, 'a>> {
+        let (mut current, path) = match path.strip_prefix('/') {
+            Some(path) => (self.at(0), path),
+            None => (*self, path),
+        };
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            current = match name {
+                "." => current,
+                ".." => current.parent()?,
+                name => current.children()
+                    .find(|child| child.element().name() == name)?,
+            };
+        }
+
+        Some(current)
+    }
+}
+
+impl PartialEq for ElementRef<'_, '_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.index, other.index) && self.position == other.position
+    }
+}
+
+impl std::fmt::Debug for ElementRef<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ElementRef({}, line {})", self.element().qname(), self.element().lineno())
+    }
+}
+
+/**
+ * Iterator over the ancestors of an element
+ */
+pub struct Ancestors<'t, 'a> {
+    next: Option<ElementRef<'t, 'a>>,
+}
+
+impl<'t, 'a> Iterator for Ancestors<'t, 'a> {
+    type Item = ElementRef<'t, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        self.next = current.parent();
+        Some(current)
+    }
+}
+
+/**
+ * Depth first iterator over descendants. skip_subtree() stops the
+ * iterator from going into the element it last returned.
+ * next:    Position of the next element
+ * end:     Position after the last element to be returned
+ * last:    Position of the element last returned
+ */
+pub struct DepthFirst<'t, 'a> {
+    index:  &'t TreeIndex<'a>,
+    next:   usize,
+    end:    usize,
+    last:   Option<usize>,
+}
+
+impl DepthFirst<'_, '_> {
+    pub fn skip_subtree(&mut self) {
+        if let Some(last) = self.last {
+            self.next = self.index.entries[last].end;
+        }
+    }
+}
+
+impl<'t, 'a> Iterator for DepthFirst<'t, 'a> {
+    type Item = ElementRef<'t, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let position = self.next;
+        self.next += 1;
+        self.last = Some(position);
+        Some(ElementRef { index: self.index, position })
+    }
+}
+
+/**
+ * Breadth first iterator over descendants. skip_subtree() stops the
+ * iterator from going into the element it last returned.
+ * queue:   Positions of elements still to be returned
+ * last:    Position of the element last returned, whose subelements are
+ *          added to the queue when the next element is wanted
+ */
+pub struct BreadthFirst<'t, 'a> {
+    index:  &'t TreeIndex<'a>,
+    queue:  VecDeque<usize>,
+    last:   Option<usize>,
+}
+
+impl BreadthFirst<'_, '_> {
+    pub fn skip_subtree(&mut self) {
+        self.last = None;
+    }
+}
+
+impl<'t, 'a> Iterator for BreadthFirst<'t, 'a> {
+    type Item = ElementRef<'t, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(last) = self.last.take() {
+            self.queue.extend(self.index.entries[last].children.iter().copied());
+        }
+        let position = self.queue.pop_front()?;
+        self.last = Some(position);
+        Some(ElementRef { index: self.index, position })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use crate::parse_tree::parse;
+
+    use super::{ElementRef, TreeIndex};
+
+    fn names<'t, 'a>(refs: impl Iterator<Item = ElementRef<'t, 'a>>) -> Vec<String>
+    where
+        'a: 't,
+    {
+        refs.map(|element_ref| element_ref.element().name().to_string()).collect()
+    }
+
+    #[test]
+    fn test_navigate() {
+        println!("Running test {}", function_name!());
+        let root = parse("<a><b><c/><d/></b><e><f><g/></f></e><h/></a>");
+        let index = TreeIndex::new(root.as_ref());
+        assert_eq!(index.len(), 8);
+
+        let g = index.root().walk_path("e/f/g").unwrap();
+        assert_eq!(g.depth(), 3);
+        assert_eq!(names(g.ancestors()), vec!["f", "e", "a"]);
+        assert_eq!(g.walk_path("../../../b/d").unwrap().element().name(), "d");
+        assert_eq!(g.walk_path("/h").unwrap().element().name(), "h");
+        assert!(g.walk_path("../x").is_none());
+        assert!(index.root().parent().is_none());
+
+        let e = g.parent().unwrap().parent().unwrap();
+        assert_eq!(e.previous_sibling().unwrap().element().name(), "b");
+        assert_eq!(e.next_sibling().unwrap().element().name(), "h");
+        assert!(e.next_sibling().unwrap().next_sibling().is_none());
+        assert!(index.root().walk_path("b/c").unwrap().previous_sibling().is_none());
+        assert_eq!(names(index.root().children()), vec!["b", "e", "h"]);
+
+        // Finding an element by its address
+        let f = root.subelements()[1].subelements()[0].as_ref();
+        assert_eq!(index.find(f), g.parent());
+        assert!(index.find(parse("<f/>").as_ref()).is_none());
+    }
+
+    #[test]
+    fn test_iterators() {
+        println!("Running test {}", function_name!());
+        let root = parse("<a><b><c/><d/></b><e><f><g/></f></e><h/></a>");
+        let index = TreeIndex::new(root.as_ref());
+
+        assert_eq!(names(index.root().descendants()), vec!["b", "c", "d", "e", "f", "g", "h"]);
+        assert_eq!(names(index.root().descendants_breadth_first()),
+            vec!["b", "e", "h", "c", "d", "f", "g"]);
+        assert_eq!(names(index.root().walk_path("e").unwrap().descendants()), vec!["f", "g"]);
+
+        // Skip the subtrees of b and f
+        let mut visited = vec![];
+        let mut iter = index.root().descendants();
+        while let Some(element_ref) = iter.next() {
+            let name = element_ref.element().name();
+            visited.push(name);
+            if name == "b" || name == "f" {
+                iter.skip_subtree();
+            }
+        }
+        assert_eq!(visited, vec!["b", "e", "f", "h"]);
+
+        let mut visited = vec![];
+        let mut iter = index.root().descendants_breadth_first();
+        while let Some(element_ref) = iter.next() {
+            let name = element_ref.element().name();
+            visited.push(name);
+            if name == "b" || name == "f" {
+                iter.skip_subtree();
+            }
+        }
+        assert_eq!(visited, vec!["b", "e", "h", "f"]);
+    }
+
+    #[test]
+    fn test_leaves() {
+        println!("Running test {}", function_name!());
+        let root = parse("<a><b><c/></b><d/></a>");
+        let index = TreeIndex::new(root.as_ref());
+
+        // Finding the root and leaves
+        assert_eq!(index.find(root.as_ref()), Some(index.root()));
+        let c = index.find(root.subelements()[0].subelements()[0].as_ref()).unwrap();
+        assert_eq!(c.element().name(), "c");
+        assert_eq!(c.depth(), 2);
+        assert_eq!(c.children().count(), 0);
+        assert_eq!(c.descendants().count(), 0);
+        assert_eq!(c.descendants_breadth_first().count(), 0);
+
+        // A tree with only a root
+        let single = parse("<a/>");
+        let single_index = TreeIndex::new(single.as_ref());
+        assert_eq!(single_index.len(), 1);
+        assert_eq!(single_index.find(single.as_ref()), Some(single_index.root()));
+        assert_eq!(single_index.root().descendants().count(), 0);
+
+        // Skipping the subtree of a leaf, or before anything was returned,
+        // changes nothing
+        for skip in ["", "c", "d"] {
+            let mut visited = vec![];
+            let mut iter = index.root().descendants();
+            iter.skip_subtree();
+            while let Some(element_ref) = iter.next() {
+                let name = element_ref.element().name();
+                visited.push(name);
+                if name == skip {
+                    iter.skip_subtree();
+                }
+            }
+            assert_eq!(visited, vec!["b", "c", "d"]);
+            assert!(iter.next().is_none());
+
+            let mut visited = vec![];
+            let mut iter = index.root().descendants_breadth_first();
+            iter.skip_subtree();
+            while let Some(element_ref) = iter.next() {
+                let name = element_ref.element().name();
+                visited.push(name);
+                if name == skip {
+                    iter.skip_subtree();
+                }
+            }
+            assert_eq!(visited, vec!["b", "d", "c"]);
+            assert!(iter.next().is_none());
+        }
+    }
+}
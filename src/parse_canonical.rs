@@ -0,0 +1,321 @@
+/*
+ * Parse XML text input and write it out in canonical form as it is
+ * parsed, without building a tree. See canonicalize_tree for what the
+ * canonical form is.
+ */
+
+use std::io::{BufReader, Read, Write};
+use std::marker::PhantomData;
+use xml::namespace::Namespace;
+use xml::reader::XmlEvent;
+
+use crate::canonicalize_tree::{is_outside_item, start_tag, write_event, CanonicalOptions};
+use crate::element::ElementInfo;
+use crate::parse_doc::{Accumulator, DocumentResult, LevelInfo, ParseDoc, SharedOutput};
+use crate::parse_item::LineNumber;
+use crate::qname::QName;
+use crate::write_tree::qualified_name;
+use crate::xml_document_error::XmlDocumentError;
+
+pub struct ParseCanonical<W: Write> {
+    writer: PhantomData<W>,
+}
+
+impl<W: Write> ParseCanonical<W> {
+    pub fn parse_path(
+        path: &str,
+        element_level_info: &<ParseCanonical<W> as ParseDoc>::LI,
+    ) -> DocumentResult<<ParseCanonical<W> as ParseDoc>::LI>
+    {
+        let result = Self::parse_path_base(path, element_level_info);
+        element_level_info.output.check(result)
+    }
+
+    pub fn parse<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &<ParseCanonical<W> as ParseDoc>::LI,
+    ) -> DocumentResult<<ParseCanonical<W> as ParseDoc>::LI>
+    where
+        R: Read,
+    {
+        let result = Self::parse_base(buf_reader, element_level_info);
+        element_level_info.output.check(result)
+    }
+}
+
+impl<W: Write> ParseDoc for ParseCanonical<W> {
+    type LI = CanonicalLevelInfo<W>;
+    type AC = CanonicalAccumulator<W>;
+}
+
+/**
+ * LevelInfo that shares the output between the levels
+ */
+pub struct CanonicalLevelInfo<W: Write> {
+    output: SharedOutput<W, CanonicalOptions>,
+}
+
+impl<W: Write> CanonicalLevelInfo<W> {
+    pub fn new(writer: W, options: CanonicalOptions) -> Self {
+        CanonicalLevelInfo {
+            output: SharedOutput::new(writer, options, "canonical output"),
+        }
+    }
+}
+
+impl<W: Write> LevelInfo for CanonicalLevelInfo<W> {
+    type AccumulatorType = CanonicalAccumulator<W>;
+
+    fn next_level(&self) -> Self {
+        CanonicalLevelInfo {
+            output: self.output.clone(),
+        }
+    }
+
+    fn create_accumulator(&self, element_info: ElementInfo) ->
+        Result<CanonicalAccumulator<W>, XmlDocumentError>
+    {
+        // Stop the parse at the first write error
+        self.output.error()?;
+        Ok(CanonicalAccumulator::new(element_info, self.output.clone()))
+    }
+}
+
+/**
+ * Accumulator that writes the element in canonical form as it is parsed.
+ * The start tag isn't written until something follows it, so that the
+ * prolog can come first.
+ * element_name:            Name of the element
+ * element_lineno:          Line number of the start of the element
+ * end_tag:                 End tag for the element
+ * start_tag:               Start tag for the element, without the closing
+ *                          '>', until it has been written
+ * closed:                  True once the end of the element has been written
+ * current_subelement_name: Name of the subelement being parsed, if any
+ * output:                  Where the canonical form is written
+ */
+pub struct CanonicalAccumulator<W: Write> {
+    element_name: QName,
+    element_lineno: LineNumber,
+    end_tag: String,
+    start_tag: Option<String>,
+    closed: bool,
+    current_subelement_name: Option<QName>,
+    output: SharedOutput<W, CanonicalOptions>,
+}
+
+impl<W: Write> CanonicalAccumulator<W> {
+    fn new(element_info: ElementInfo, output: SharedOutput<W, CanonicalOptions>) -> Self {
+        let start_tag = {
+            let mut output = output.borrow_mut();
+            let rendered = output.namespaces.last().cloned().unwrap_or_else(Namespace::empty);
+            let (start_tag, rendered) = start_tag(&element_info, &rendered, &output.options);
+            output.namespaces.push(rendered);
+            start_tag
+        };
+
+        CanonicalAccumulator {
+            element_name: element_info.qname(),
+            element_lineno: element_info.lineno(),
+            end_tag: format!("</{}>", qualified_name(&element_info.owned_name)),
+            start_tag: Some(start_tag),
+            closed: false,
+            current_subelement_name: None,
+            output,
+        }
+    }
+
+    /*
+     * Write the start tag, if it hasn't been written yet
+     */
+    fn open(&mut self) {
+        if let Some(start_tag) = self.start_tag.take() {
+            self.output.borrow_mut().write(|writer, _| write!(writer, "{}>", start_tag));
+        }
+    }
+
+    /*
+     * Write the end of the element, if it hasn't been written yet
+     */
+    fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.open();
+        self.closed = true;
+
+        let mut output = self.output.borrow_mut();
+        output.namespaces.pop();
+        output.write(|writer, _| write!(writer, "{}", self.end_tag));
+    }
+
+    fn event(&mut self, event: XmlEvent) {
+        self.open();
+        self.output.borrow_mut().write(|writer, options| write_event(writer, &event, options));
+    }
+}
+
+impl<W: Write> Accumulator for CanonicalAccumulator<W> {
+    type Value = ();
+
+    fn start_subelement(&mut self, element_info: &ElementInfo) {
+        self.open();
+        self.current_subelement_name = Some(element_info.qname());
+    }
+
+    fn add_subelement(&mut self, _subelement: ()) {
+        // Subelements have already been written
+    }
+
+    fn end_subelement(&mut self) {
+        self.current_subelement_name = None;
+    }
+
+    fn has_open_subelement(&self) -> bool {
+        self.current_subelement_name.is_some()
+    }
+
+    fn current_subelement_name(&self) -> Option<&QName> {
+        self.current_subelement_name.as_ref()
+    }
+
+    fn finish(mut self) {
+        self.close();
+    }
+
+    fn element_name(&self) -> &QName {
+        &self.element_name
+    }
+
+    fn element_lineno(&self) -> LineNumber {
+        self.element_lineno
+    }
+
+    fn characters(&mut self, characters: &str) {
+        self.event(XmlEvent::Characters(characters.to_string()));
+    }
+
+    fn whitespace(&mut self, whitespace: &str) {
+        self.event(XmlEvent::Whitespace(whitespace.to_string()));
+    }
+
+    fn cdata(&mut self, cdata: &str) {
+        self.event(XmlEvent::CData(cdata.to_string()));
+    }
+
+    fn comment(&mut self, comment: &str) {
+        self.event(XmlEvent::Comment(comment.to_string()));
+    }
+
+    fn processing_instruction(&mut self, name: &str, data: Option<&str>) {
+        self.event(XmlEvent::ProcessingInstruction {
+            name: name.to_string(),
+            data: data.map(|d| d.to_string()),
+        });
+    }
+
+    // Whitespace outside the root element is dropped, and each item goes
+    // on a line of its own
+    fn prolog(&mut self, event: &XmlEvent) {
+        self.output.borrow_mut().write(|writer, options| {
+            if is_outside_item(event, options) {
+                write_event(writer, event, options)?;
+                writeln!(writer)?;
+            }
+            Ok(())
+        });
+    }
+
+    fn epilog(&mut self, event: &XmlEvent) {
+        self.close();
+        self.output.borrow_mut().write(|writer, options| {
+            if is_outside_item(event, options) {
+                writeln!(writer)?;
+                write_event(writer, event, options)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor, Read};
+
+    use crate::canonicalize_tree::{canonicalize_document, CanonicalOptions};
+    use crate::parse_tree::parse_document;
+
+    use super::{CanonicalLevelInfo, ParseCanonical};
+
+    /*
+     * Canonicalize while parsing and by building a tree, checking that
+     * both give the same result
+     */
+    fn canonicalize(input: &[u8], options: &CanonicalOptions) -> String {
+        let mut streamed = Vec::new();
+        let level_info = CanonicalLevelInfo::new(&mut streamed, options.clone());
+        ParseCanonical::parse(BufReader::new(Cursor::new(input)), &level_info)
+            .expect("canonicalize failed");
+        drop(level_info);
+
+        let (_, root) = parse_document(input).expect("parse failed");
+        let mut written = Vec::new();
+        canonicalize_document(&mut written, root.as_ref(), options).unwrap();
+        assert_eq!(String::from_utf8_lossy(&streamed), String::from_utf8_lossy(&written));
+
+        String::from_utf8(streamed).expect("output not UTF-8")
+    }
+
+    #[test]
+    fn test_stream() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<!-- before -->\n",
+            "<?pi  data?>\n",
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\" z=\"1\" p:y=\"2\">\r\n",
+            "  <p:b><![CDATA[<x>]]><!-- inside --></p:b>\n",
+            "  <c xmlns=\"\"><p:d/></c>\n",
+            "</a>\n",
+            "<!-- after -->\n");
+
+        assert_eq!(canonicalize(input.as_bytes(), &CanonicalOptions::default()), concat!(
+            "<?pi data?>\n",
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\" z=\"1\" p:y=\"2\">\n",
+            "  <p:b>&lt;x&gt;</p:b>\n",
+            "  <c xmlns=\"\"><p:d></p:d></c>\n",
+            "</a>"));
+
+        let options = CanonicalOptions { exclusive: true, with_comments: true,
+            ..Default::default() };
+        assert_eq!(canonicalize(input.as_bytes(), &options), concat!(
+            "<!-- before -->\n",
+            "<?pi data?>\n",
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\" z=\"1\" p:y=\"2\">\n",
+            "  <p:b>&lt;x&gt;<!-- inside --></p:b>\n",
+            "  <c xmlns=\"\"><p:d></p:d></c>\n",
+            "</a>\n",
+            "<!-- after -->"));
+    }
+
+    #[test]
+    fn test_schema() {
+        println!("Running test {}", function_name!());
+        let mut input = Vec::new();
+        File::open("schema/SpaceSystem-patched.xsd")
+            .expect("Unable to open SpaceSystem-patched.xsd")
+            .read_to_end(&mut input)
+            .unwrap();
+        let canonical = canonicalize(&input, &CanonicalOptions::default());
+        assert!(canonical.starts_with("<schema xmlns=\"http://www.w3.org/2001/XMLSchema\""));
+
+        // The canonical form is its own canonical form
+        assert_eq!(canonicalize(canonical.as_bytes(), &CanonicalOptions::default()), canonical);
+        let options = CanonicalOptions { exclusive: true, with_comments: true,
+            ..Default::default() };
+        let exclusive = canonicalize(&input, &options);
+        assert_eq!(canonicalize(exclusive.as_bytes(), &options), exclusive);
+    }
+}
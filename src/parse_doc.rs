@@ -5,16 +5,58 @@
  */
 // FIXME: delete all uses of expect(), everywhere
 
+use std::cell::{RefCell, RefMut};
 use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, Read, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use xml::namespace::Namespace;
 use xml::reader::XmlEvent;
 
+use crate::diagnostics::Diagnostics;
 use crate::document::DocumentInfo;
 use crate::element::{ElementInfo};
-use crate::parse_item::{LineNumber, Parser};
+use crate::parse_item::{LineNumber, Parser, PushSource, SourceSpan, TreeElement};
+use crate::qname::QName;
 pub use crate::xml_document_error::XmlDocumentError;
 
+/*
+ * Value produced by the accumulator for the top level element when parsing
+ * with level information LI
+ */
+pub type AccumulatorValue<LI> = <<LI as LevelInfo>::AccumulatorType as Accumulator>::Value;
+
+/*
+ * Result of parsing an entire document
+ */
+pub type DocumentResult<LI> = Result<(DocumentInfo, AccumulatorValue<LI>), XmlDocumentError>;
+
+/*
+ * Result of parsing a document where the root element might not produce a
+ * value
+ */
+type PartialResult<LI> = Result<(DocumentInfo, Option<AccumulatorValue<LI>>), XmlDocumentError>;
+
+/*
+ * Result of parsing a document in recovery mode
+ * document_info:   Information from the start of the document, if it was
+ *                  reached
+ * value:           Value produced by the accumulator for the root element,
+ *                  if there was one that could be parsed
+ * diagnostics:     Problems found in the document
+ */
+pub struct RecoveredDocument<LI: LevelInfo> {
+    pub document_info:  Option<DocumentInfo>,
+    pub value:          Option<AccumulatorValue<LI>>,
+    pub diagnostics:    Diagnostics,
+}
+
+/*
+ * Result of parsing one of the top level elements of a fragment
+ */
+pub type ElementResult<LI> = Result<AccumulatorValue<LI>, XmlDocumentError>;
+
 /**
  * ParseDoc - Parses an entire XML document
  * LI   Information passed top down during the parse which is specific to each
@@ -26,15 +68,13 @@ pub trait ParseDoc {
     type AC: Accumulator;
 
     // FIXME: rename to something like parse_from_path
-    fn parse_path_base<'b>(
-        path: &'b str,
+    fn parse_path_base(
+        path: &str,
         element_level_info: &Self::LI,
-    ) -> Result<(DocumentInfo, <<Self::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    ) -> DocumentResult<Self::LI>
     {
         let file = match File::open(path) {
-            Err(e) => {
-                panic!("FIXME: unable to open {}: {}", path, e);
-            },
+            Err(e) => return Err(XmlDocumentError::IoError(path.to_string(), Arc::new(e))),
             Ok(f) => f,
         };
         let reader = BufReader::new(file);
@@ -48,13 +88,210 @@ pub trait ParseDoc {
     fn parse_base<R>(
         buf_reader: BufReader<R>,
         element_level_info: &Self::LI,
-    ) -> Result<(DocumentInfo, <<Self::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    ) -> DocumentResult<Self::LI>
     where
         R: Read,
     {
         // Create the factory using the reader and XML definition
         let mut parse_item = Parser::new(buf_reader);
-        Self::parse_document(&mut parse_item, &element_level_info)
+        Self::parse_document(&mut parse_item, element_level_info)
+    }
+
+    /**
+     * Parse a document from a file in recovery mode. See parse_recovering_base().
+     */
+    fn parse_path_recovering_base(
+        path: &str,
+        element_level_info: &Self::LI,
+    ) -> RecoveredDocument<Self::LI>
+    {
+        match File::open(path) {
+            Err(e) => {
+                let mut diagnostics = Diagnostics::new();
+                diagnostics.error(&XmlDocumentError::IoError(path.to_string(), Arc::new(e)));
+                RecoveredDocument {
+                    document_info:  None,
+                    value:          None,
+                    diagnostics,
+                }
+            },
+            Ok(file) => Self::parse_recovering_base(BufReader::new(file), element_level_info),
+        }
+    }
+
+    /**
+     * Parse a document without stopping at the first problem. Malformed
+     * input is skipped, mismatched elements are closed and elements the
+     * level information can't create an accumulator for are skipped, with
+     * each problem recorded in the diagnostics. The value is whatever the
+     * accumulators produced for the parts of the document that could be
+     * parsed.
+     */
+    fn parse_recovering_base<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &Self::LI,
+    ) -> RecoveredDocument<Self::LI>
+    where
+        R: Read,
+    {
+        let mut parse_item = Parser::with_recovery(buf_reader);
+        let result = Self::parse_document_partial(&mut parse_item, element_level_info);
+        let mut diagnostics = parse_item.take_diagnostics();
+
+        match result {
+            Err(e) => {
+                diagnostics.error(&e);
+                RecoveredDocument {
+                    document_info:  None,
+                    value:          None,
+                    diagnostics,
+                }
+            },
+            Ok((document_info, value)) => RecoveredDocument {
+                document_info:  Some(document_info),
+                value,
+                diagnostics,
+            },
+        }
+    }
+
+    /**
+     * Parse a file holding an XML fragment. See parse_fragment_base().
+     */
+    fn parse_path_fragment_base<'a>(
+        path: &str,
+        element_level_info: &'a Self::LI,
+    ) -> Result<Fragment<'a, Self, File>, XmlDocumentError>
+    where
+        Self: Sized,
+    {
+        match File::open(path) {
+            Err(e) => Err(XmlDocumentError::IoError(path.to_string(), Arc::new(e))),
+            Ok(file) => Ok(Self::parse_fragment_base(BufReader::new(file), element_level_info)),
+        }
+    }
+
+    /**
+     * Parse an XML fragment, which can have any number of top level
+     * elements. The value for each top level element is returned by the
+     * iterator as soon as its end has been read.
+     */
+    fn parse_fragment_base<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &Self::LI,
+    ) -> Fragment<'_, Self, R>
+    where
+        Self: Sized,
+        R: Read,
+    {
+        Fragment {
+            parse_item:         Parser::new(buf_reader),
+            element_level_info,
+            document_info:      None,
+            prolog:             vec!(),
+            done:               false,
+        }
+    }
+
+    /**
+     * Parse a file holding a series of XML documents. See
+     * parse_stream_base().
+     */
+    fn parse_path_stream_base<'a>(
+        path: &str,
+        element_level_info: &'a Self::LI,
+    ) -> Result<DocumentStream<'a, Self, File>, XmlDocumentError>
+    where
+        Self: Sized,
+    {
+        match File::open(path) {
+            Err(e) => Err(XmlDocumentError::IoError(path.to_string(), Arc::new(e))),
+            Ok(file) => Ok(Self::parse_stream_base(BufReader::new(file), element_level_info)),
+        }
+    }
+
+    /**
+     * Parse a series of XML documents written one after the other, each
+     * with its own optional XML declaration and one root element. The
+     * iterator returns the DocumentInfo and root element value for each
+     * document in turn, with line numbers carrying on from one document to
+     * the next.
+     */
+    fn parse_stream_base<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &Self::LI,
+    ) -> DocumentStream<'_, Self, R>
+    where
+        Self: Sized,
+        R: Read,
+    {
+        DocumentStream {
+            parse_item:         Parser::new(buf_reader),
+            element_level_info,
+            done:               false,
+        }
+    }
+
+    /**
+     * Parse a document from input that is pushed in as it arrives, such as
+     * from a socket. See PushDocument.
+     */
+    fn parse_push_base(element_level_info: &Self::LI) -> PushDocument<'_, Self>
+    where
+        Self: Sized,
+    {
+        PushDocument {
+            parse_item:         Parser::new_push(),
+            element_level_info,
+            document_info:      None,
+            prolog:             vec!(),
+            open:               vec!(),
+            root:               None,
+            ended:              false,
+            error:              None,
+        }
+    }
+
+    /*
+     * Parse a top level element whose start is the lookahead item, giving
+     * its accumulator the document_info, if it is the root element of a
+     * document, and the prolog. Returns the accumulator, so that the
+     * caller can add the epilog, with the end of the element consumed.
+     * Returns None at the end of the document.
+     */
+    fn parse_top_element<R>(
+        parse_item: &mut Parser<R>,
+        element_level_info: &Self::LI,
+        document_info: Option<&DocumentInfo>,
+        prolog: Vec<XmlEvent>,
+    ) -> Result<Option<<Self::LI as LevelInfo>::AccumulatorType>, XmlDocumentError>
+    where
+        R: Read,
+    {
+        let parse_element = parse_item.lookahead()?;
+        let element_info = match parse_element.event {
+            XmlEvent::StartElement{name, attributes, namespace} =>
+                ElementInfo::new(name, parse_element.span, attributes, namespace)
+                    .with_attribute_order(parse_element.attribute_order),
+            XmlEvent::EndDocument => return Ok(None),
+            event => return Err(XmlDocumentError::UnexpectedXml(parse_element.span.start,
+                Box::new(event))),
+        };
+        parse_item.skip();
+
+        let mut accumulator = element_level_info.create_accumulator(element_info)?;
+        if let Some(document_info) = document_info {
+            accumulator.declaration(document_info);
+        }
+        for event in &prolog {
+            accumulator.prolog(event);
+        }
+
+        Self::parse_element_content(parse_item, &mut accumulator,
+            &element_level_info.next_level())?;
+        parse_item.skip();
+
+        Ok(Some(accumulator))
     }
 
     fn _display_piece(&self, f: &mut fmt::Formatter<'_>, pieces: &Vec<XmlEvent>) -> fmt::Result {
@@ -74,43 +311,81 @@ pub trait ParseDoc {
     fn parse_document<R>(
         parse_item: &mut Parser<R>, 
         element_level_info: &Self::LI
-    ) -> Result<(DocumentInfo, <<Self::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    ) -> DocumentResult<Self::LI>
     where
         R: Read,
     {
-//println!("---> entering parse_document");
-        let document_info = match Self::parse_start_document(parse_item) {
-            Err(e) => return Err(e),
-            Ok(doc_info) => doc_info,
-        };
+        let (document_info, top_element) = Self::parse_document_partial(parse_item,
+            element_level_info)?;
 
-        // Read the next XML event, which is expected to be the start of an
-        // element. We use a lookahead so that we can be specific about an error
-        // if one occurred
-        let lookahead_item = parse_item.lookahead();
-        let parse_element = match lookahead_item {
-            Err(e) => return Err(e),
-            Ok(xml_elem) => xml_elem,
-        };
+        // There is always a value unless we are recovering from errors
+        match top_element {
+            None => Err(XmlDocumentError::InternalError(parse_item.position(),
+                "No value for the root element".to_string())),
+            Some(top_element) => Ok((document_info, top_element)),
+        }
+    }
+
+    /*
+     * Parse a document, returning the value for the root element if there
+     * is one
+     */
+    fn parse_document_partial<R>(
+        parse_item: &mut Parser<R>, 
+        element_level_info: &Self::LI
+    ) -> PartialResult<Self::LI>
+    where
+        R: Read,
+    {
+        let document_info = Self::parse_start_document(parse_item)?;
+
+        // Comments, processing instructions and whitespace can precede the
+        // root element. When this returns, the lookahead item is the start
+        // of the root element.
+        let prolog = Self::parse_prolog(parse_item)?;
 
         // Now verify that the token we just read starts an element.
+        let parse_element = parse_item.lookahead()?;
         let top_element = match parse_element.event {
             XmlEvent::StartElement{name, attributes, namespace} => {
-                let element_info = ElementInfo::new(name, parse_element.lineno, attributes, namespace);
-                match Self::parse_element(parse_item, element_info, element_level_info) {
-                    Err(e) => return Err(e),
-                    Ok(top_elem) => top_elem,
+                let element_info = ElementInfo::new(name, parse_element.span, attributes, namespace)
+                    .with_attribute_order(parse_element.attribute_order);
+                parse_item.skip();
+
+                match element_level_info.create_accumulator(element_info) {
+                    Err(e) => {
+                        parse_item.recover(e)?;
+                        parse_item.skip_content()?;
+                        Self::parse_end_document(parse_item)?;
+                        None
+                    },
+
+                    Ok(mut accumulator) => {
+                        accumulator.declaration(&document_info);
+                        for event in &prolog {
+                            accumulator.prolog(event);
+                        }
+
+                        Self::parse_element_content(parse_item, &mut accumulator,
+                            &element_level_info.next_level())?;
+
+                        // And, wrap up by making sure things conclude as expected.
+                        let epilog = Self::parse_end_document(parse_item)?;
+                        for event in &epilog {
+                            accumulator.epilog(event);
+                        }
+
+                        Some(accumulator.finish())
+                    },
                 }
             },
 
-            _ => panic!("FIXME: Expected element, got {:?}", parse_element.event),
-        };
+            // When recovering, a document without a root element has
+            // already been reported
+            XmlEvent::EndDocument if parse_item.is_recovering() => None,
 
-        // And, wrap up by making sure things conclude as expected.
-        match Self::parse_end_document(parse_item) {
-            Err(e) => return Err(e),
-            Ok(_) => {}
-        }
+            event => return Err(XmlDocumentError::UnexpectedXml(parse_element.span.start, Box::new(event))),
+        };
 
         Ok((document_info, top_element))
     }
@@ -125,13 +400,46 @@ pub trait ParseDoc {
     {
         let parse_element = parse_item.next()?;
 
-        if let XmlEvent::StartDocument{version, encoding, standalone} = parse_element.event {
-            Ok(DocumentInfo::new(version, encoding, standalone))
-        } else {
-            panic!("FIXME: document doesn't start with StartDocument")
+        match parse_element.event {
+            XmlEvent::StartDocument{version, encoding, standalone} => {
+                let mut document_info = DocumentInfo::new(version, encoding, standalone);
+                document_info.declared = !parse_element.span.is_empty();
+                Ok(document_info)
+            },
+            event => Err(XmlDocumentError::NoDocumentFound(parse_element.span.start, Box::new(event))),
         }
     }
 
+    /*
+     * Collect the comments, processing instructions and whitespace between
+     * the StartDocument and the root element, along with the character
+     * data allowed there in a fragment. The root element is left as the
+     * lookahead item.
+     */
+    fn parse_prolog<R>(parse_item: &mut Parser<R>) -> Result<Vec<XmlEvent>, XmlDocumentError>
+    where
+        R: Read,
+    {
+        let mut prolog = vec!();
+
+        loop {
+            let parse_element = parse_item.lookahead()?;
+
+            match parse_element.event {
+                XmlEvent::Whitespace(_) |
+                    XmlEvent::Characters(_) |
+                    XmlEvent::Comment(_) |
+                    XmlEvent::ProcessingInstruction{..} => {
+                    parse_item.skip();
+                    prolog.push(parse_element.event);
+                },
+                _ => break,
+            }
+        }
+
+        Ok(prolog)
+    }
+
     /*
      * Parse an element. We have already seen the XmlStartElement as a lookahead.
      */
@@ -139,31 +447,59 @@ pub trait ParseDoc {
         parse_item: &mut Parser<R>, 
         element_info: ElementInfo, 
         element_level_info: &Self::LI
-    ) -> Result<<<Self::LI as LevelInfo>::AccumulatorType as Accumulator>::Value, XmlDocumentError>
+    ) -> Result<Option<AccumulatorValue<Self::LI>>, XmlDocumentError>
     where
         R: Read,
     {
         parse_item.skip();
         
-        // Create accumulator for this element
-        let mut accumulator = element_level_info.create_accumulator(element_info)?;
+        // Create accumulator for this element. If that fails and we are
+        // recovering, skip the element, leaving its end as the lookahead
+        // so the parent's accumulator sees it.
+        let mut accumulator = match element_level_info.create_accumulator(element_info) {
+            Err(e) => {
+                parse_item.recover(e)?;
+                parse_item.skip_content()?;
+                return Ok(None);
+            },
+            Ok(accumulator) => accumulator,
+        };
         
-        // Get level info for subelements
-        let subelement_level_info = element_level_info.next_level();
+        // Get level info for subelements and parse the body of the element
+        Self::parse_element_content(parse_item, &mut accumulator,
+            &element_level_info.next_level())?;
 
+        Ok(Some(accumulator.finish()))
+    }
+
+    /*
+     * Parse everything between the start of an element and its end, passing
+     * subelements, text, comments and processing instructions to the
+     * accumulator. Returns with the end of the element as the lookahead item.
+     */
+    fn parse_element_content<R>(
+        parse_item: &mut Parser<R>,
+        accumulator: &mut <Self::LI as LevelInfo>::AccumulatorType,
+        subelement_level_info: &Self::LI,
+    ) -> Result<(), XmlDocumentError>
+    where
+        R: Read,
+    {
         // Parse all subelements until we hit the EndElement
         loop {
             let parse_element = parse_item.lookahead()?;
 
             match parse_element.event {
                 XmlEvent::StartElement{name, attributes, namespace} => {
-                    let subelement_info = ElementInfo::new(name, parse_element.lineno,
-                        attributes, namespace);
+                    let subelement_info = ElementInfo::new(name, parse_element.span,
+                        attributes, namespace).with_attribute_order(parse_element.attribute_order);
                     accumulator.start_subelement(&subelement_info);
                     let subelement_result = Self::parse_element(parse_item,
-                        subelement_info, &subelement_level_info)?;
+                        subelement_info, subelement_level_info)?;
                     
-                    accumulator.add_subelement(subelement_result);
+                    if let Some(subelement_result) = subelement_result {
+                        accumulator.add_subelement(subelement_result);
+                    }
                 },
 
                 XmlEvent::EndElement{name} => {
@@ -171,65 +507,472 @@ pub trait ParseDoc {
                         // We have an element optn at this level, process it
                         parse_item.skip();
                         
-                        if name.local_name != accumulator.current_subelement_name() {
-                            panic!("FIXME: Mismatched element tags: expected {}, got {}", 
-                                   accumulator.current_subelement_name(), name.local_name);
+                        // Prefixes don't matter, only the namespace URI
+                        let end_name = QName::from(&name);
+                        if accumulator.current_subelement_name() != Some(&end_name) {
+                            parse_item.recover(XmlDocumentError::MisplacedElementEnd(
+                                parse_element.span.start,
+                                subelement_name(accumulator),
+                                end_name.to_string()))?;
                         }
                         
                         accumulator.end_subelement();
                     } else {
                         // No open element on this level, it must be from the
                         // level above.
+                        accumulator.end_element(parse_element.span);
                         break;
                     }
                 },
 
                 XmlEvent::EndDocument => {
                     if accumulator.has_open_subelement() {
-                        panic!("FIXME: Document ended with unclosed subelement");
+                        parse_item.recover(XmlDocumentError::UnclosedElement(parse_element.span.start,
+                            subelement_name(accumulator)))?;
+                        accumulator.end_subelement();
                     }
                     break;
                 }
 
-                XmlEvent::Whitespace(_) | XmlEvent::Characters(_) => {
+                XmlEvent::Whitespace(ws) => {
+                    parse_item.skip();
+                    accumulator.whitespace(&ws);
+                },
+
+                XmlEvent::Characters(characters) => {
+                    parse_item.skip();
+                    accumulator.characters(&characters);
+                },
+
+                XmlEvent::CData(cdata) => {
                     parse_item.skip();
+                    accumulator.cdata(&cdata);
                 },
 
-                _ => {
-                    panic!("FIXME: Unexpected XML event: {:?}", parse_element.event);
+                XmlEvent::Comment(comment) => {
+                    parse_item.skip();
+                    accumulator.comment(&comment);
+                },
+
+                XmlEvent::ProcessingInstruction{name, data} => {
+                    parse_item.skip();
+                    accumulator.processing_instruction(&name, data.as_deref());
+                },
+
+                event => {
+                    parse_item.recover(XmlDocumentError::UnexpectedXml(parse_element.span.start,
+                        Box::new(event)))?;
+                    parse_item.skip();
                 }
             }
         }
 
-        Ok(accumulator.finish())
+        Ok(())
     }
 
     /*
-     * We expect EndDocument, parsed as a lookahead
+     * We expect EndDocument, parsed as a lookahead. Returns the comments,
+     * processing instructions and whitespace following the root element.
      */
-    fn parse_end_document<R>(parse_item: &mut Parser<R>) -> Result<(), XmlDocumentError>
+    fn parse_end_document<R>(parse_item: &mut Parser<R>) -> Result<Vec<XmlEvent>, XmlDocumentError>
     where
         R: Read,
     {
         parse_item.skip();
 
+        let mut epilog = vec!();
+
         loop {
             let parse_element = parse_item.next()?;
 
             match parse_element.event {
                 XmlEvent::Whitespace(_) |
-                    XmlEvent::Characters(_) => {},
+                    XmlEvent::Comment(_) |
+                    XmlEvent::ProcessingInstruction{..} => epilog.push(parse_element.event),
 
                 XmlEvent::EndDocument => break,
 
-                _ => panic!("FIXME: Expected end of document but found {:?}", parse_element.event)
+                XmlEvent::EndElement{name} =>
+                    parse_item.recover(XmlDocumentError::MisplacedElementEnd(parse_element.span.start,
+                        "end of document".to_string(), QName::from(&name).to_string()))?,
+
+                // Another root element. When recovering, it is skipped.
+                XmlEvent::StartElement{..} => {
+                    parse_item.recover(XmlDocumentError::OnlyOneRootElementAllowed(
+                        parse_element.span.start))?;
+                    parse_item.skip_content()?;
+                    parse_item.skip();
+                },
+
+                event => parse_item.recover(XmlDocumentError::UnexpectedXml(parse_element.span.start,
+                    Box::new(event)))?,
             }
         }
 
+        Ok(epilog)
+    }
+}
+
+/**
+ * Iterator over the values for the top level elements of an XML fragment,
+ * in document order. The character data, comments, processing instructions
+ * and whitespace before each top level element are its prolog and those
+ * after the last one are its epilog. Iteration stops after an error.
+ * parse_item:          Source of the fragment
+ * element_level_info:  Level information for the top level elements
+ * document_info:       Information from the XML declaration, once the start
+ *                      of the fragment has been read
+ * prolog:              Prolog for the next top level element
+ * done:                True once the end of the fragment or an error has
+ *                      been reached
+ */
+pub struct Fragment<'a, PD: ParseDoc, R: Read> {
+    parse_item:         Parser<BufReader<R>>,
+    element_level_info: &'a PD::LI,
+    document_info:      Option<DocumentInfo>,
+    prolog:             Vec<XmlEvent>,
+    done:               bool,
+}
+
+impl<PD: ParseDoc, R: Read> Fragment<'_, PD, R> {
+    /*
+     * Information from the XML declaration, if the start of the fragment
+     * has been read
+     */
+    pub fn document_info(&self) -> Option<&DocumentInfo> {
+        self.document_info.as_ref()
+    }
+
+    fn next_element(&mut self) -> Result<Option<AccumulatorValue<PD::LI>>, XmlDocumentError> {
+        if self.document_info.is_none() {
+            if !self.parse_item.next_document(true) {
+                return Ok(None);
+            }
+            self.document_info = Some(PD::parse_start_document(&mut self.parse_item)?);
+            self.prolog = PD::parse_prolog(&mut self.parse_item)?;
+        }
+
+        let prolog = std::mem::take(&mut self.prolog);
+        let mut accumulator = match PD::parse_top_element(&mut self.parse_item,
+            self.element_level_info, None, prolog)? {
+            None => return Ok(None),
+            Some(accumulator) => accumulator,
+        };
+
+        // Whatever follows is the prolog of the next top level element or,
+        // if there isn't one, the epilog of this one. An error is left as
+        // the lookahead item, to be returned by the next call.
+        if let Ok(following) = PD::parse_prolog(&mut self.parse_item) {
+            match self.parse_item.lookahead() {
+                Ok(TreeElement { event: XmlEvent::EndDocument, .. }) => {
+                    for event in &following {
+                        accumulator.epilog(event);
+                    }
+                },
+                _ => self.prolog = following,
+            }
+        }
+
+        Ok(Some(accumulator.finish()))
+    }
+}
+
+impl<PD: ParseDoc, R: Read> Iterator for Fragment<'_, PD, R> {
+    type Item = ElementResult<PD::LI>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_element() {
+            Ok(Some(value)) => Some(Ok(value)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+/**
+ * Iterator over a series of XML documents, returning the DocumentInfo and
+ * root element value for each. The comments, processing instructions and
+ * whitespace between the root element of one document and the start of
+ * the next are the epilog of the first. Iteration stops after an error.
+ * parse_item:          Source of the documents
+ * element_level_info:  Level information for the root elements
+ * done:                True once the end of the input or an error has been
+ *                      reached
+ */
+pub struct DocumentStream<'a, PD: ParseDoc, R: Read> {
+    parse_item:         Parser<BufReader<R>>,
+    element_level_info: &'a PD::LI,
+    done:               bool,
+}
+
+impl<PD: ParseDoc, R: Read> DocumentStream<'_, PD, R> {
+    /*
+     * Parse the next document, which has already been started
+     */
+    fn parse_document(&mut self) -> DocumentResult<PD::LI> {
+        let document_info = PD::parse_start_document(&mut self.parse_item)?;
+        let prolog = PD::parse_prolog(&mut self.parse_item)?;
+        let mut accumulator = match PD::parse_top_element(&mut self.parse_item,
+            self.element_level_info, Some(&document_info), prolog)? {
+            None => {
+                let pos = self.parse_item.position();
+                return Err(XmlDocumentError::NoDocumentFound(pos,
+                    Box::new(XmlEvent::EndDocument)));
+            },
+            Some(accumulator) => accumulator,
+        };
+
+        // The next document starts at the first thing that can't be part
+        // of the epilog, which xml-rs reports as an error because it
+        // expects only one root element and XML declaration.
+        while let Ok(parse_element) = self.parse_item.lookahead() {
+            match parse_element.event {
+                XmlEvent::Whitespace(_) |
+                    XmlEvent::Comment(_) |
+                    XmlEvent::ProcessingInstruction{..} => {
+                    self.parse_item.skip();
+                    accumulator.epilog(&parse_element.event);
+                },
+                _ => break,
+            }
+        }
+
+        Ok((document_info, accumulator.finish()))
+    }
+}
+
+impl<PD: ParseDoc, R: Read> Iterator for DocumentStream<'_, PD, R> {
+    type Item = DocumentResult<PD::LI>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || !self.parse_item.next_document(false) {
+            self.done = true;
+            return None;
+        }
+
+        let result = self.parse_document();
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+/*
+ * Accumulator type used when parsing with level information LI
+ */
+type AccumulatorOf<LI> = <LI as LevelInfo>::AccumulatorType;
+
+/**
+ * Parser for a document whose input is pushed in as it arrives. Each
+ * push() hands the events that are complete to the accumulators and
+ * returns, so parsing is suspended between chunks of input. The
+ * accumulators are used just as they are by ParseDoc::parse_base().
+ * parse_item:          Source of events
+ * element_level_info:  Level information for the root element
+ * document_info:       Information from the start of the document, once it
+ *                      has been read
+ * prolog:              Items before the root element
+ * open:                Accumulators for the elements that have been started
+ *                      but not ended, with the level information for their
+ *                      subelements
+ * root:                Accumulator for the root element, once it has ended
+ * ended:               True once the end of the document has been read
+ * error:               The first error found. Parsing stops there.
+ */
+pub struct PushDocument<'a, PD: ParseDoc> {
+    parse_item:         Parser<PushSource>,
+    element_level_info: &'a PD::LI,
+    document_info:      Option<DocumentInfo>,
+    prolog:             Vec<XmlEvent>,
+    open:               Vec<(AccumulatorOf<PD::LI>, PD::LI)>,
+    root:               Option<AccumulatorOf<PD::LI>>,
+    ended:              bool,
+    error:              Option<XmlDocumentError>,
+}
+
+impl<PD: ParseDoc> PushDocument<'_, PD> {
+    /**
+     * Add more input and process whatever events are complete
+     */
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), XmlDocumentError> {
+        self.parse_item.push(bytes);
+        self.process()
+    }
+
+    /**
+     * Mark the end of the input and return the result for the document
+     */
+    pub fn finish(mut self) -> DocumentResult<PD::LI> {
+        self.parse_item.close();
+        self.process()?;
+
+        match (self.document_info, self.root) {
+            (Some(document_info), Some(root)) if self.ended =>
+                Ok((document_info, root.finish())),
+            _ => Err(XmlDocumentError::NoEndDocument(self.parse_item.position())),
+        }
+    }
+
+    /*
+     * Information from the start of the document, once it has been read
+     */
+    pub fn document_info(&self) -> Option<&DocumentInfo> {
+        self.document_info.as_ref()
+    }
+
+    /*
+     * Number of elements that have been started but not ended
+     */
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    fn process(&mut self) -> Result<(), XmlDocumentError> {
+        if let Some(e) = &self.error {
+            return Err(e.clone());
+        }
+
+        // Once the end of the document has been read, there's nothing more
+        // to do
+        while !self.ended {
+            let result = match self.parse_item.poll() {
+                Ok(None) => return Ok(()),
+                Ok(Some(parse_element)) => self.handle(parse_element),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                self.error = Some(e.clone());
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Hand one event to the right accumulator
+     */
+    fn handle(&mut self, parse_element: TreeElement) -> Result<(), XmlDocumentError> {
+        let pos = parse_element.span.start;
+
+        if self.document_info.is_none() {
+            return match parse_element.event {
+                XmlEvent::StartDocument{version, encoding, standalone} => {
+                    let mut document_info = DocumentInfo::new(version, encoding, standalone);
+                    document_info.declared = !parse_element.span.is_empty();
+                    self.document_info = Some(document_info);
+                    Ok(())
+                },
+                event => Err(XmlDocumentError::NoDocumentFound(pos, Box::new(event))),
+            };
+        }
+
+        match parse_element.event {
+            XmlEvent::StartElement{name, attributes, namespace} => {
+                let element_info = ElementInfo::new(name, parse_element.span, attributes,
+                    namespace).with_attribute_order(parse_element.attribute_order);
+                let (accumulator, level_info) = match self.open.last_mut() {
+                    Some((parent, level_info)) => {
+                        parent.start_subelement(&element_info);
+                        (level_info.create_accumulator(element_info)?, level_info.next_level())
+                    },
+                    None if self.root.is_some() =>
+                        return Err(XmlDocumentError::OnlyOneRootElementAllowed(pos)),
+                    None => {
+                        let mut accumulator =
+                            self.element_level_info.create_accumulator(element_info)?;
+                        if let Some(document_info) = &self.document_info {
+                            accumulator.declaration(document_info);
+                        }
+                        for event in self.prolog.drain(..) {
+                            accumulator.prolog(&event);
+                        }
+                        (accumulator, self.element_level_info.next_level())
+                    },
+                };
+                self.open.push((accumulator, level_info));
+            },
+
+            XmlEvent::EndElement{name} => {
+                let (mut accumulator, _) = self.open.pop()
+                    .ok_or_else(|| XmlDocumentError::MisplacedElementEnd(pos,
+                        "end of document".to_string(), QName::from(&name).to_string()))?;
+                accumulator.end_element(parse_element.span);
+
+                match self.open.last_mut() {
+                    None => self.root = Some(accumulator),
+                    Some((parent, _)) => {
+                        parent.add_subelement(accumulator.finish());
+                        if parent.has_open_subelement() {
+                            let end_name = QName::from(&name);
+                            if parent.current_subelement_name() != Some(&end_name) {
+                                return Err(XmlDocumentError::MisplacedElementEnd(pos,
+                                    subelement_name(parent), end_name.to_string()));
+                            }
+                            parent.end_subelement();
+                        }
+                    },
+                }
+            },
+
+            XmlEvent::EndDocument => {
+                if let Some((accumulator, _)) = self.open.last() {
+                    return Err(XmlDocumentError::UnclosedElement(pos,
+                        accumulator.element_name().to_string()));
+                }
+                if self.root.is_none() {
+                    return Err(XmlDocumentError::UnexpectedXml(pos,
+                        Box::new(XmlEvent::EndDocument)));
+                }
+                self.ended = true;
+            },
+
+            event => match (self.open.last_mut(), &mut self.root) {
+                (Some((accumulator, _)), _) => content(accumulator, event),
+
+                // Before or after the root element
+                (None, root) => match event {
+                    XmlEvent::Whitespace(_) |
+                        XmlEvent::Comment(_) |
+                        XmlEvent::ProcessingInstruction{..} => match root {
+                        None => self.prolog.push(event),
+                        Some(root) => root.epilog(&event),
+                    },
+                    XmlEvent::Characters(_) if root.is_some() => {},
+                    event => return Err(XmlDocumentError::UnexpectedXml(pos, Box::new(event))),
+                },
+            },
+        }
+
         Ok(())
     }
 }
 
+/*
+ * Hand text, comments and processing instructions to an accumulator
+ */
+fn content<AC: Accumulator>(accumulator: &mut AC, event: XmlEvent) {
+    match event {
+        XmlEvent::Whitespace(ws) => accumulator.whitespace(&ws),
+        XmlEvent::Characters(characters) => accumulator.characters(&characters),
+        XmlEvent::CData(cdata) => accumulator.cdata(&cdata),
+        XmlEvent::Comment(comment) => accumulator.comment(&comment),
+        XmlEvent::ProcessingInstruction{name, data} =>
+            accumulator.processing_instruction(&name, data.as_deref()),
+        _ => {},
+    }
+}
+
 /**
  * LevelInfo trait - tracks nesting information passed down to subelements
  */
@@ -264,15 +1007,709 @@ pub trait Accumulator {
     /// Returns: true if we are nested in a subelement, false otherwise
     fn has_open_subelement(&self) -> bool;
     
-    /// Get the name of the current subelement
-    fn current_subelement_name(&self) -> &str;
+    /// Get the namespace-qualified name of the current subelement
+    /// Returns: None if there is no open subelement
+    fn current_subelement_name(&self) -> Option<&QName>;
     
     /// Return the final result for this element
     fn finish(self) -> Self::Value;
     
-    /// Get element name (for error reporting)
-    fn element_name(&self) -> &str;
+    /// Get namespace-qualified element name (for error reporting)
+    fn element_name(&self) -> &QName;
     
     /// Get element line number (for error reporting)
     fn element_lineno(&self) -> LineNumber;
+
+    /// Called with non-whitespace character data directly within this element
+    fn characters(&mut self, _characters: &str) {}
+
+    /// Called with whitespace directly within this element
+    fn whitespace(&mut self, _whitespace: &str) {}
+
+    /// Called with the contents of a CDATA section directly within this element
+    fn cdata(&mut self, _cdata: &str) {}
+
+    /// Called with the span of the end tag of this element, before
+    /// finish(). For an empty element tag, <name/>, this is the span of the
+    /// start tag.
+    fn end_element(&mut self, _span: SourceSpan) {}
+
+    /// Called with the text of a comment directly within this element
+    fn comment(&mut self, _comment: &str) {}
+
+    /// Called for a processing instruction directly within this element
+    fn processing_instruction(&mut self, _name: &str, _data: Option<&str>) {}
+
+    /// Called for the root element of a document only, before prolog(),
+    /// with the information from the start of the document
+    fn declaration(&mut self, _document_info: &DocumentInfo) {}
+
+    /// Called for the root element only, with each comment, processing
+    /// instruction and whitespace item that precedes it in the document
+    fn prolog(&mut self, _event: &XmlEvent) {}
+
+    /// Called for the root element only, with each comment, processing
+    /// instruction and whitespace item that follows it in the document
+    fn epilog(&mut self, _event: &XmlEvent) {}
+}
+
+/**
+ * Output written by the accumulators as the input is parsed
+ * writer:      Where the output goes
+ * options:     How to write it
+ * namespaces:  Namespaces for each of the elements that are open, as
+ *              written to the output
+ * what:        What the output is, for error messages
+ * error:       The first error writing the output. Once there is one,
+ *              nothing more is written.
+ */
+pub(crate) struct Output<W: Write, O> {
+    pub writer:     W,
+    pub options:    O,
+    pub namespaces: Vec<Namespace>,
+    what:           &'static str,
+    error:          Option<XmlDocumentError>,
+}
+
+impl<W: Write, O> Output<W, O> {
+    /*
+     * Write using a function that takes the writer and the options,
+     * keeping the first error
+     */
+    pub fn write<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut W, &O) -> io::Result<()>,
+    {
+        if self.error.is_none() {
+            if let Err(e) = f(&mut self.writer, &self.options) {
+                self.error = Some(XmlDocumentError::IoError(self.what.to_string(),
+                    Arc::new(e)));
+            }
+        }
+    }
+}
+
+/**
+ * Output shared by the levels and accumulators of a parse that writes as
+ * it goes, rather than building a value
+ */
+pub(crate) struct SharedOutput<W: Write, O> {
+    output: Rc<RefCell<Output<W, O>>>,
+}
+
+impl<W: Write, O> SharedOutput<W, O> {
+    pub fn new(writer: W, options: O, what: &'static str) -> Self {
+        SharedOutput {
+            output: Rc::new(RefCell::new(Output {
+                writer,
+                options,
+                namespaces: vec![],
+                what,
+                error:      None,
+            })),
+        }
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, Output<W, O>> {
+        self.output.borrow_mut()
+    }
+
+    /*
+     * Return the first error writing the output, if any, so that
+     * create_accumulator() can stop the parse
+     */
+    pub fn error(&self) -> Result<(), XmlDocumentError> {
+        match &self.output.borrow().error {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /*
+     * Return any error writing the output in preference to the result of
+     * the parse
+     */
+    pub fn check<T>(&self, result: Result<T, XmlDocumentError>) -> Result<T, XmlDocumentError> {
+        match self.output.borrow_mut().error.take() {
+            Some(e) => Err(e),
+            None => result,
+        }
+    }
+}
+
+impl<W: Write, O> Clone for SharedOutput<W, O> {
+    fn clone(&self) -> Self {
+        SharedOutput {
+            output: self.output.clone(),
+        }
+    }
+}
+
+/*
+ * Name of the open subelement, for error messages
+ */
+fn subelement_name<AC: Accumulator>(accumulator: &AC) -> String {
+    accumulator.current_subelement_name()
+        .map(|name| name.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use std::io::{BufReader, Cursor};
+    use xml::reader::XmlEvent;
+
+    use crate::element::{Element, ElementInfo};
+    use crate::parse_item::LineNumber;
+    use crate::parse_tree::{ParseTree, TreeAccumulator, TreeLevelInfo};
+    use crate::qname::QName;
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::{Accumulator, DocumentResult, LevelInfo, ParseDoc, RecoveredDocument};
+
+    fn parse_tree(input: &str) -> DocumentResult<TreeLevelInfo> {
+        let cursor = Cursor::new(input.as_bytes());
+        ParseTree::parse(BufReader::new(cursor), &TreeLevelInfo::new())
+    }
+
+    fn parse_recovering(input: &str) -> RecoveredDocument<TreeLevelInfo> {
+        let cursor = Cursor::new(input.as_bytes());
+        let result = ParseTree::parse_recovering(BufReader::new(cursor), &TreeLevelInfo::new());
+        print!("{}", result.diagnostics.render(input, "input"));
+        result
+    }
+
+    /*
+     * Names of an element and its subelements, as "a(b,c(d))"
+     */
+    fn shape(element: &dyn Element) -> String {
+        if element.subelements().is_empty() {
+            return element.name().to_string();
+        }
+        let subelements: Vec<String> = element.subelements().iter()
+            .map(|subelement| shape(subelement.as_ref()))
+            .collect();
+        format!("{}({})", element.name(), subelements.join(","))
+    }
+
+    /*
+     * Builds trees, but doesn't know about elements named "unknown"
+     */
+    struct CheckedLevelInfo(TreeLevelInfo);
+
+    struct ParseChecked;
+
+    impl ParseDoc for ParseChecked {
+        type LI = CheckedLevelInfo;
+        type AC = TreeAccumulator;
+    }
+
+    impl LevelInfo for CheckedLevelInfo {
+        type AccumulatorType = TreeAccumulator;
+
+        fn next_level(&self) -> Self {
+            CheckedLevelInfo(self.0.next_level())
+        }
+
+        fn create_accumulator(&self, element_info: ElementInfo) ->
+            Result<TreeAccumulator, XmlDocumentError> {
+            if element_info.owned_name.local_name == "unknown" {
+                return Err(XmlDocumentError::UnknownElement(element_info.span.start,
+                    "unknown".to_string(), "?".to_string()));
+            }
+            self.0.create_accumulator(element_info)
+        }
+    }
+
+    /*
+     * Accumulator that always claims the open subelement is named "bogus",
+     * so that the end tag checks in the parse driver fire.
+     */
+    struct BogusLevelInfo;
+
+    struct BogusAccumulator {
+        element_info:   ElementInfo,
+        name:           QName,
+        bogus:          QName,
+        open:           bool,
+    }
+
+    struct ParseBogus;
+
+    impl ParseDoc for ParseBogus {
+        type LI = BogusLevelInfo;
+        type AC = BogusAccumulator;
+    }
+
+    impl LevelInfo for BogusLevelInfo {
+        type AccumulatorType = BogusAccumulator;
+
+        fn next_level(&self) -> Self {
+            BogusLevelInfo
+        }
+
+        fn create_accumulator(&self, element_info: ElementInfo) ->
+            Result<BogusAccumulator, XmlDocumentError> {
+            Ok(BogusAccumulator {
+                name:           element_info.qname(),
+                bogus:          QName::local("bogus"),
+                element_info,
+                open:           false,
+            })
+        }
+    }
+
+    impl Accumulator for BogusAccumulator {
+        type Value = ();
+
+        fn start_subelement(&mut self, _element_info: &ElementInfo) {
+            self.open = true;
+        }
+
+        fn end_subelement(&mut self) {
+            self.open = false;
+        }
+
+        fn add_subelement(&mut self, _subelement: ()) {}
+
+        fn has_open_subelement(&self) -> bool {
+            self.open
+        }
+
+        fn current_subelement_name(&self) -> Option<&QName> {
+            Some(&self.bogus)
+        }
+
+        fn finish(self) {}
+
+        fn element_name(&self) -> &QName {
+            &self.name
+        }
+
+        fn element_lineno(&self) -> LineNumber {
+            self.element_info.lineno()
+        }
+    }
+
+    #[test]
+    fn test_good_document() {
+        println!("Running test {}", function_name!());
+        let result = parse_tree("<a>\n  <b>\n  </b>\n</a>\n");
+        let (_, root) = result.expect("valid document failed to parse");
+        assert_eq!(root.name(), "a");
+        assert_eq!(root.subelements().len(), 1);
+    }
+
+    #[test]
+    fn test_unopenable_file() {
+        println!("Running test {}", function_name!());
+        let result = ParseTree::parse_path("/nonexistent/file.xml", &TreeLevelInfo::new());
+        match result {
+            Err(XmlDocumentError::IoError(path, _)) => assert_eq!(path, "/nonexistent/file.xml"),
+            other => panic!("Expected IoError, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_mismatched_end_tag() {
+        println!("Running test {}", function_name!());
+        match parse_tree("<a>\n  <b>\n  </c>\n</a>\n") {
+            Err(XmlDocumentError::XmlError(pos, e)) => {
+                println!("{}: {}", pos, e);
+                assert_eq!(pos.line, 3);
+            },
+            other => panic!("Expected XmlError, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_unclosed_element() {
+        println!("Running test {}", function_name!());
+        match parse_tree("<a>\n  <b>\n") {
+            Err(XmlDocumentError::XmlError(pos, e)) => println!("{}: {}", pos, e),
+            other => panic!("Expected XmlError, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_misc_in_element() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<a>\n",
+            "  <?target data?>\n",
+            "  <!-- comment -->\n",
+            "  <b>text</b>\n",
+            "  <![CDATA[<raw>]]>\n",
+            "</a>\n");
+        let (_, root) = parse_tree(input).expect("parse failed");
+
+        let before = root.subelements()[0].before_element();
+        assert!(matches!(&before[1], XmlEvent::ProcessingInstruction { name, data }
+            if name == "target" && data.as_deref() == Some("data")));
+        assert!(matches!(&before[3], XmlEvent::Comment(c) if c == " comment "));
+
+        assert_eq!(root.subelements()[0].text(), "text");
+        assert!(root.content().iter()
+            .any(|event| matches!(event, XmlEvent::CData(c) if c == "<raw>")));
+        assert_eq!(root.text(), "\n  \n  \n  \n  <raw>\n");
+    }
+
+    #[test]
+    fn test_prolog_and_epilog() {
+        println!("Running test {}", function_name!());
+        let input = "<?xml version=\"1.0\"?>\n<!-- before -->\n<?target data?>\n<a/>\n<!-- after -->\n";
+        let (_, root) = parse_tree(input).expect("parse failed");
+
+        let before: Vec<&XmlEvent> = root.before_element().iter()
+            .filter(|event| !matches!(event, XmlEvent::Whitespace(_)))
+            .collect();
+        assert_eq!(before.len(), 2);
+        assert!(matches!(before[0], XmlEvent::Comment(c) if c == " before "));
+        assert!(matches!(before[1], XmlEvent::ProcessingInstruction { name, .. } if name == "target"));
+        assert!(matches!(&root.after_element()[..], [XmlEvent::Comment(c)] if c == " after "));
+    }
+
+    #[test]
+    fn test_misplaced_element_end() {
+        println!("Running test {}", function_name!());
+        let cursor = Cursor::new("<a>\n  <b/>\n</a>\n".as_bytes());
+        match ParseBogus::parse_base(BufReader::new(cursor), &BogusLevelInfo) {
+            Err(XmlDocumentError::MisplacedElementEnd(_, expected, found)) => {
+                assert_eq!(expected, "bogus");
+                assert_eq!(found, "b");
+            },
+            other => panic!("Expected MisplacedElementEnd, got {:?}", other.err()),
+        }
+
+        // The namespace is part of the name, so a "bogus" in the default
+        // namespace doesn't match the expected "bogus" in no namespace
+        let cursor = Cursor::new("<a xmlns=\"urn:x\"><bogus/></a>".as_bytes());
+        match ParseBogus::parse_base(BufReader::new(cursor), &BogusLevelInfo) {
+            Err(XmlDocumentError::MisplacedElementEnd(_, expected, found)) => {
+                assert_eq!(expected, "bogus");
+                assert_eq!(found, "{urn:x}bogus");
+            },
+            other => panic!("Expected MisplacedElementEnd, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_recover_mismatched_end() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<a>\n",
+            "  <b>\n",
+            "    <c>\n",
+            "  </b>\n",
+            "  <d/>\n",
+            "</a>\n");
+        let result = parse_recovering(input);
+        assert_eq!(shape(result.value.as_deref().unwrap()), "a(b(c),d)");
+
+        let diagnostics: Vec<_> = result.diagnostics.iter().collect();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "XT0010");
+        assert_eq!(diagnostics[0].span.unwrap().start.line, 4);
+        assert!(diagnostics[0].notes[0].starts_with("<c> at line 3, column 5"));
+    }
+
+    #[test]
+    fn test_recover_many_problems() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<a>\n",
+            "  <b>x < y &bogus; z</b>\n",
+            "  </stray>\n",
+            "  <c><!-- a -- b --></c>\n",
+            "  <d>\n");
+        let result = parse_recovering(input);
+        assert!(result.document_info.is_some());
+        assert_eq!(shape(result.value.as_deref().unwrap()), "a(b,c,d)");
+
+        let lines: Vec<usize> = result.diagnostics.iter()
+            .map(|diagnostic| diagnostic.span.unwrap().start.line)
+            .collect();
+        assert_eq!(lines, [2, 2, 3, 4, 5, 1]);
+        assert_eq!(result.diagnostics.iter().last().unwrap().code, "XT0023");
+    }
+
+    #[test]
+    fn test_recover_after_root() {
+        println!("Running test {}", function_name!());
+        let result = parse_recovering("<a><b></a></b>\n<!-- c -->\n<e/>\n");
+        let root = result.value.unwrap();
+        assert_eq!(shape(root.as_ref()), "a(b)");
+        assert!(matches!(&root.after_element()[..], [XmlEvent::Comment(c)] if c == " c "));
+
+        let codes: Vec<&str> = result.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, ["XT0010", "XT0010", "XT0016"]);
+    }
+
+    #[test]
+    fn test_text_after_root() {
+        println!("Running test {}", function_name!());
+        let input = "<a><b/></a>\n<!-- c -->\nstray text\n<!-- d -->\nmore\n";
+
+        match parse_tree(input) {
+            Err(XmlDocumentError::XmlError(pos, _)) => assert_eq!((pos.line, pos.column), (3, 1)),
+            other => panic!("Expected XmlError, got {:?}", other.err()),
+        }
+
+        // Each run of text is reported once and the markup after it is kept
+        let result = parse_recovering(input);
+        let root = result.value.unwrap();
+        assert_eq!(shape(root.as_ref()), "a(b)");
+        let comments: Vec<&str> = root.after_element().iter()
+            .filter_map(|event| match event {
+                XmlEvent::Comment(comment) => Some(comment.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(comments, [" c ", " d "]);
+
+        let lines: Vec<usize> = result.diagnostics.iter()
+            .map(|diagnostic| diagnostic.span.unwrap().start.line)
+            .collect();
+        assert_eq!(lines, [3, 5]);
+    }
+
+    #[test]
+    fn test_recover_unknown_element() {
+        println!("Running test {}", function_name!());
+        let input = "<a><b/><unknown><x/></unknown><c/></a>";
+        let cursor = Cursor::new(input.as_bytes());
+        let level_info = CheckedLevelInfo(TreeLevelInfo::new());
+
+        // Without recovery, this is an error
+        match ParseChecked::parse_base(BufReader::new(cursor.clone()), &level_info) {
+            Err(XmlDocumentError::UnknownElement(..)) => {},
+            other => panic!("Expected UnknownElement, got {:?}", other.err()),
+        }
+
+        let result = ParseChecked::parse_recovering_base(BufReader::new(cursor), &level_info);
+        assert_eq!(shape(result.value.as_deref().unwrap()), "a(b,c)");
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics.iter().next().unwrap().code, "XT0025");
+    }
+
+    #[test]
+    fn test_recover_no_document() {
+        println!("Running test {}", function_name!());
+        let result = parse_recovering("<!-- nothing here -->\n");
+        assert!(result.value.is_none());
+        assert_eq!(result.diagnostics.len(), 1);
+
+        let result = parse_recovering("<a><b/></a>");
+        assert_eq!(shape(result.value.as_deref().unwrap()), "a(b)");
+        assert!(result.diagnostics.is_empty());
+    }
+
+    /*
+     * Stand-in for a socket, handing out the input in chunks of the given
+     * sizes, used in turn
+     */
+    struct ChunkedSocket<'a> {
+        input:  &'a [u8],
+        sizes:  std::iter::Cycle<std::slice::Iter<'a, usize>>,
+    }
+
+    impl<'a> ChunkedSocket<'a> {
+        fn new(input: &'a str, sizes: &'a [usize]) -> Self {
+            ChunkedSocket {
+                input:  input.as_bytes(),
+                sizes:  sizes.iter().cycle(),
+            }
+        }
+    }
+
+    impl<'a> Iterator for ChunkedSocket<'a> {
+        type Item = &'a [u8];
+
+        fn next(&mut self) -> Option<&'a [u8]> {
+            if self.input.is_empty() {
+                return None;
+            }
+            let size = (*self.sizes.next().unwrap()).min(self.input.len());
+            let (chunk, rest) = self.input.split_at(size);
+            self.input = rest;
+            Some(chunk)
+        }
+    }
+
+    #[test]
+    fn test_push() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<!-- before -->\n",
+            "<a xmlns:p=\"urn:p\">\n",
+            "  <p:b>text</p:b>\n",
+            "  <c>\n",
+            "    <d attr=\"x &gt; y\"/>caf\u{e9}\n",
+            "  </c>\n",
+            "</a>\n",
+            "<!-- after -->\n");
+        let level_info = TreeLevelInfo::new();
+        let (_, expected) = parse_tree(input).expect("parse failed");
+
+        for sizes in [&[1][..], &[2, 5][..], &[13][..], &[1000][..]] {
+            let mut document = ParseTree::parse_push(&level_info);
+            for chunk in ChunkedSocket::new(input, sizes) {
+                document.push(chunk).expect("push failed");
+            }
+            assert_eq!(document.depth(), 0);
+            let (document_info, root) = document.finish().expect("push parse failed");
+
+            assert_eq!(document_info.version, xml::common::XmlVersion::Version10);
+            assert_eq!(shape(root.as_ref()), shape(expected.as_ref()));
+            assert_eq!(root.text(), expected.text());
+            let d = &root.subelements()[1].subelements()[0];
+            assert_eq!(d.span().start, expected.subelements()[1].subelements()[0].span().start);
+            assert_eq!(d.attribute("attr"), Some("x > y"));
+            assert!(matches!(&root.before_element()[..], [XmlEvent::Comment(c)] if c == " before "));
+            assert!(matches!(&root.after_element()[..], [XmlEvent::Comment(c)] if c == " after "));
+        }
+
+        // Parsing is suspended part way through
+        let mut document = ParseTree::parse_push(&level_info);
+        document.push(b"<a><b><c").unwrap();
+        assert_eq!(document.depth(), 2);
+        document.push(b"/></b>").unwrap();
+        assert_eq!(document.depth(), 1);
+        document.push(b"</a>").unwrap();
+        let (_, root) = document.finish().unwrap();
+        assert_eq!(shape(root.as_ref()), "a(b(c))");
+
+        // An error stops the parse
+        let mut document = ParseTree::parse_push(&level_info);
+        document.push(b"<a>\n<b>").unwrap();
+        match document.push(b"</c>") {
+            Err(XmlDocumentError::XmlError(pos, _)) => assert_eq!(pos.line, 2),
+            other => panic!("Expected XmlError, got {:?}", other),
+        }
+        assert!(document.push(b"</b></a>").is_err());
+
+        // Running out of input before the end of the document
+        let mut document = ParseTree::parse_push(&level_info);
+        document.push(b"<a>").unwrap();
+        assert!(document.finish().is_err());
+    }
+
+    #[test]
+    fn test_fragment() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<!-- first -->\n",
+            "<a><b/></a>\n",
+            "<c/><!-- second -->\n",
+            "<d>\n",
+            "  <e/>\n",
+            "</d>\n",
+            "<!-- last -->\n");
+        let cursor = Cursor::new(input.as_bytes());
+        let level_info = TreeLevelInfo::new();
+        let roots: Vec<Box<dyn Element>> = ParseTree::parse_fragment(BufReader::new(cursor),
+            &level_info)
+            .collect::<Result<_, _>>()
+            .expect("fragment parse failed");
+
+        let shapes: Vec<String> = roots.iter().map(|root| shape(root.as_ref())).collect();
+        assert_eq!(shapes, vec!("a(b)", "c", "d(e)"));
+        assert_eq!(roots[2].subelements()[0].lineno(), 5);
+
+        assert!(matches!(&roots[0].before_element()[..],
+            [XmlEvent::Comment(c)] if c == " first "));
+        assert!(roots[1].before_element().is_empty());
+        assert!(matches!(&roots[2].before_element()[..],
+            [XmlEvent::Comment(c)] if c == " second "));
+        assert!(matches!(&roots[2].after_element()[..],
+            [XmlEvent::Comment(c)] if c == " last "));
+
+        // An empty fragment has no top level elements
+        let cursor = Cursor::new(" \n".as_bytes());
+        assert_eq!(ParseTree::parse_fragment(BufReader::new(cursor), &level_info).count(), 0);
+
+        // Text can be outside of the elements, including at the start
+        let cursor = Cursor::new("text &amp; more<a/>\n<!-- c -->tail <b>x</b> end\n".as_bytes());
+        let roots: Vec<Box<dyn Element>> = ParseTree::parse_fragment(BufReader::new(cursor),
+            &level_info)
+            .collect::<Result<_, _>>()
+            .expect("fragment with text failed");
+        assert_eq!(roots.len(), 2);
+        assert!(matches!(&roots[0].before_element()[..],
+            [XmlEvent::Characters(t)] if t == "text & more"));
+        assert!(matches!(&roots[1].before_element()[..],
+            [XmlEvent::Comment(_), XmlEvent::Characters(t)]
+            if t == "tail "));
+        assert!(matches!(&roots[1].after_element()[..],
+            [XmlEvent::Characters(t)] if t == " end\n"));
+        assert_eq!(roots[1].lineno(), 2);
+        let cursor = Cursor::new("<?xml version=\"1.0\"?>\ntext<a/>".as_bytes());
+        let mut fragment = ParseTree::parse_fragment(BufReader::new(cursor), &level_info);
+        let a = fragment.next().unwrap().unwrap();
+        assert!(matches!(&a.before_element()[..], [XmlEvent::Characters(t)] if t == "\ntext"));
+        assert!(fragment.document_info().unwrap().declared);
+
+        // But it must be well-formed
+        let cursor = Cursor::new("<a/>\ntext & more<b/>".as_bytes());
+        let mut fragment = ParseTree::parse_fragment(BufReader::new(cursor), &level_info);
+        assert_eq!(fragment.next().unwrap().unwrap().name(), "a");
+        match fragment.next() {
+            Some(Err(XmlDocumentError::XmlError(pos, _))) => assert_eq!(pos.line, 2),
+            other => panic!("Expected XmlError, got {:?}", other.map(|r| r.err())),
+        }
+        assert!(fragment.next().is_none());
+
+        // Text outside the root is still an error in a document
+        let cursor = Cursor::new("text<a/>".as_bytes());
+        assert!(ParseTree::parse(BufReader::new(cursor), &level_info).is_err());
+    }
+
+    #[test]
+    fn test_stream() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<message id=\"1\"><value/></message>\n",
+            "<!-- after the first -->\n",
+            "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n",
+            "<message id=\"2\">\n",
+            "  <value/>\n",
+            "</message>\n",
+            "<message id=\"3\"/>\n",
+            "\n");
+        let cursor = Cursor::new(input.as_bytes());
+        let level_info = TreeLevelInfo::new();
+        let documents: Vec<_> = ParseTree::parse_stream(BufReader::new(cursor), &level_info)
+            .collect::<Result<_, _>>()
+            .expect("stream parse failed");
+        assert_eq!(documents.len(), 3);
+
+        let ids: Vec<&str> = documents.iter()
+            .map(|(_, root)| root.attribute("id").unwrap())
+            .collect();
+        assert_eq!(ids, vec!("1", "2", "3"));
+        assert_eq!(documents[0].0.encoding, "UTF-8");
+        assert_eq!(documents[1].0.encoding, "ISO-8859-1");
+
+        // Line numbers carry on from one document to the next
+        let lines: Vec<usize> = documents.iter().map(|(_, root)| root.lineno()).collect();
+        assert_eq!(lines, vec!(2, 5, 8));
+        assert_eq!(documents[1].1.subelements()[0].lineno(), 6);
+        assert_eq!(documents[1].1.subelements()[0].span().start.column, 3);
+
+        assert!(matches!(&documents[0].1.after_element()[..],
+            [XmlEvent::Comment(c)] if c == " after the first "));
+
+        // Errors are reported where they are in the whole stream
+        let cursor = Cursor::new("<a/>\n<b>\n</c>\n<d/>\n".as_bytes());
+        let mut stream = ParseTree::parse_stream(BufReader::new(cursor), &level_info);
+        assert_eq!(stream.next().unwrap().unwrap().1.name(), "a");
+        match stream.next() {
+            Some(Err(XmlDocumentError::XmlError(pos, _))) => assert_eq!(pos.line, 3),
+            other => panic!("Expected XmlError, got {:?}", other.map(|r| r.err())),
+        }
+        assert!(stream.next().is_none());
+    }
 }
@@ -1,56 +1,63 @@
-/**
- * Parse XML text input and produce an XML echo
+/*
+ * Parse XML text input and write it back out as it is parsed
  */
 
 use std::fmt;
-use std::io::{BufReader, Read};
-use std::ops::{ControlFlow, FromResidual, Try};
+use std::io::{BufReader, Read, Write};
+use std::marker::PhantomData;
+use xml::namespace::Namespace;
+use xml::reader::XmlEvent;
 
 use crate::element::{Element, ElementInfo};
-use crate::misc::nl_indent;
-use crate::parse_item::LineNumber;
+use crate::parse_item::{LineNumber, SourceSpan};
 pub use crate::xml_document_error::XmlDocumentError;
-use crate::parse_doc::{Accumulator, LevelInfo, ParseDoc};
+use crate::parse_doc::{Accumulator, DocumentResult, LevelInfo, ParseDoc, SharedOutput};
+use crate::qname::QName;
 use crate::document::DocumentInfo;
+use crate::write_tree::{qualified_name, tag_attributes, write_declaration, write_event};
 
-pub struct ParseEcho {
+pub struct ParseEcho<W: Write> {
     pub document_info:  DocumentInfo,
     pub root:           Box<dyn Element>,
+    writer:             PhantomData<W>,
 }
 
-impl ParseEcho {
+impl<W: Write> ParseEcho<W> {
     pub fn new(document_info: DocumentInfo, root: Box<dyn Element>) -> Self {
         ParseEcho {
             document_info,
             root,
+            writer: PhantomData,
         }
     }
 
-    pub fn parse_path<'b>(
-        path: &'b str,
-        element_level_info: &<ParseEcho as ParseDoc>::LI,
-    ) -> Result<(DocumentInfo, <<<ParseEcho as ParseDoc>::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    pub fn parse_path(
+        path: &str,
+        element_level_info: &<ParseEcho<W> as ParseDoc>::LI,
+    ) -> DocumentResult<<ParseEcho<W> as ParseDoc>::LI>
     {
-        Self::parse_path_base(path, element_level_info)
+        let result = Self::parse_path_base(path, element_level_info);
+        element_level_info.output.check(result)
     }
 
     pub fn parse<R>(
         buf_reader: BufReader<R>,
-        element_level_info: &<ParseEcho as ParseDoc>::LI,
-    ) -> Result<(DocumentInfo, <<<ParseEcho as ParseDoc>::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+        element_level_info: &<ParseEcho<W> as ParseDoc>::LI,
+    ) -> DocumentResult<<ParseEcho<W> as ParseDoc>::LI>
     where
         R: Read,
     {
-        Self::parse_base(buf_reader, element_level_info)
+        let result = Self::parse_base(buf_reader, element_level_info);
+        element_level_info.output.check(result)
     }
 }
 
-impl ParseDoc for ParseEcho {
-    type LI = EchoLevelInfo;
-    type AC = EchoAccumulator;
+impl<W: Write> ParseDoc for ParseEcho<W> {
+    type LI = EchoLevelInfo<W>;
+    type AC = EchoAccumulator<W>;
 }
 
-impl fmt::Display for ParseEcho {
+impl<W: Write> fmt::Display for ParseEcho<W> {
 // FIXME: make this work
     fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
@@ -59,7 +66,7 @@ impl fmt::Display for ParseEcho {
     }
 }
 
-impl fmt::Debug for ParseEcho {
+impl<W: Write> fmt::Debug for ParseEcho<W> {
 // FIXME: make this work
     fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
         todo!();
@@ -67,83 +74,147 @@ impl fmt::Debug for ParseEcho {
     }
 }
 
-impl Try for ParseEcho
-{
-    type Output = <<ParseEcho as ParseDoc>::AC as Accumulator>::Value;
-    type Residual = XmlDocumentError;
-    fn from_output(_: <Self as Try>::Output) -> Self
-    { todo!() }
-    fn branch(self) -> ControlFlow<<Self as Try>::Residual, <Self as Try>::Output>
-    { todo!() }
-}
-
-impl FromResidual for ParseEcho {
-    fn from_residual(_: <ParseEcho as Try>::Residual) -> Self
-    { todo!() }
+/// LevelInfo that tracks depth so the root element can be told apart
+pub struct EchoLevelInfo<W: Write> {
+    depth:  usize,
+    output: SharedOutput<W, ()>,
 }
 
-/// LevelInfo that tracks depth for indented output
-#[derive(Debug, Clone)]
-pub struct EchoLevelInfo {
-    depth: usize,
-}
-
-impl EchoLevelInfo {
-    pub fn new() -> Self {
-        EchoLevelInfo { depth: 0 }
+impl<W: Write> EchoLevelInfo<W> {
+    pub fn new(writer: W) -> Self {
+        EchoLevelInfo {
+            depth:  0,
+            output: SharedOutput::new(writer, (), "echo output"),
+        }
     }
 }
 
-impl LevelInfo for EchoLevelInfo {
-    type AccumulatorType = EchoAccumulator;
+impl<W: Write> LevelInfo for EchoLevelInfo<W> {
+    type AccumulatorType = EchoAccumulator<W>;
 
     fn next_level(&self) -> Self {
-        EchoLevelInfo { depth: self.depth + 1 }
+        EchoLevelInfo {
+            depth:  self.depth + 1,
+            output: self.output.clone(),
+        }
     }
 
     fn create_accumulator(&self, element_info: ElementInfo) ->
-        Result<EchoAccumulator, XmlDocumentError>
+        Result<EchoAccumulator<W>, XmlDocumentError>
     {
-        print!("{}<{}>", nl_indent(self.depth), element_info.owned_name.local_name);
-        Ok(EchoAccumulator::new(element_info, self.depth))
+        // Stop the parse at the first write error
+        self.output.error()?;
+        Ok(EchoAccumulator::new(element_info, self.depth, self.output.clone()))
     }
 }
 
-/// Accumulator that just echoes structure (doesn't build elements)
-pub struct EchoAccumulator {
-    element_name: String,
-    element_lineno: LineNumber,
+/**
+ * Accumulator that writes the element as it is parsed (doesn't build
+ * elements). The start tag isn't written until something follows it, so
+ * that the prolog can come first and an element with nothing in it can be
+ * written as <name/> if that's how it was written in the input.
+ * element_name:            Name of the element
+ * element_span:            Span of the start tag
+ * end_tag:                 End tag for the element
+ * start_tag:               Start tag for the element, without the closing
+ *                          '>', until it has been written
+ * empty_tag:               False once the end tag has been found to be
+ *                          separate from the start tag
+ * closed:                  True once the end of the element has been written
+ * depth:                   Depth of the element, zero for the root element
+ * current_subelement_name: Name of the subelement being parsed, if any
+ * output:                  Where the echo is written
+ */
+pub struct EchoAccumulator<W: Write> {
+    element_name: QName,
+    element_span: SourceSpan,
+    end_tag: String,
+    start_tag: Option<String>,
+    empty_tag: bool,
+    closed: bool,
     depth: usize,
-    current_subelement_name: Option<String>,
+    current_subelement_name: Option<QName>,
+    output: SharedOutput<W, ()>,
 }
 
-impl EchoAccumulator {
-    pub fn new(element_info: ElementInfo, depth: usize) -> Self {
+impl<W: Write> EchoAccumulator<W> {
+    fn new(element_info: ElementInfo, depth: usize, output: SharedOutput<W, ()>) ->
+        Self {
+        let name = qualified_name(&element_info.owned_name).to_string();
+        let mut start_tag = format!("<{}", name);
+        {
+            let mut output = output.borrow_mut();
+            let parent_namespace = output.namespaces.last().cloned()
+                .unwrap_or_else(Namespace::empty);
+            for attribute in tag_attributes(&element_info, &parent_namespace) {
+                start_tag += " ";
+                start_tag += &attribute;
+            }
+            output.namespaces.push(element_info.namespace.clone());
+        }
+
         EchoAccumulator {
-            element_name: element_info.owned_name.local_name.clone(),
-            element_lineno: element_info.lineno,
-            depth: depth + 1,
+            element_name: element_info.qname(),
+            element_span: element_info.span,
+            end_tag: format!("</{}>", name),
+            start_tag: Some(start_tag),
+            empty_tag: true,
+            closed: false,
+            depth,
             current_subelement_name: None,
+            output,
         }
     }
+
+    /*
+     * Write the start tag, if it hasn't been written yet
+     */
+    fn open(&mut self) {
+        if let Some(start_tag) = self.start_tag.take() {
+            self.output.borrow_mut().write(|writer, _| write!(writer, "{}>", start_tag));
+        }
+    }
+
+    /*
+     * Write the end of the element, if it hasn't been written yet
+     */
+    fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+
+        let mut output = self.output.borrow_mut();
+        output.namespaces.pop();
+        match self.start_tag.take() {
+            Some(start_tag) if self.empty_tag =>
+                output.write(|writer, _| write!(writer, "{}/>", start_tag)),
+            Some(start_tag) =>
+                output.write(|writer, _| write!(writer, "{}>{}", start_tag, self.end_tag)),
+            None => output.write(|writer, _| write!(writer, "{}", self.end_tag)),
+        }
+    }
+
+    fn event(&mut self, event: XmlEvent) {
+        self.open();
+        self.output.borrow_mut().write(|writer, _| write_event(writer, &event));
+    }
 }
 
-impl Accumulator for EchoAccumulator {
+impl<W: Write> Accumulator for EchoAccumulator<W> {
     type Value = ();  // Echo doesn't return meaningful data
 
-    fn start_subelement(&mut self, _element_info: &ElementInfo) {
-        // Nothing special needed
+    fn start_subelement(&mut self, element_info: &ElementInfo) {
+        self.open();
+        self.current_subelement_name = Some(element_info.qname());
     }
     
     fn add_subelement(&mut self, _subelement: ()) {
-        // For echo, subelements have already been printed
+        // For echo, subelements have already been written
         // We don't need to do anything with the () value
     }
     
     fn end_subelement(&mut self) {
-        if let Some(name) = &self.current_subelement_name {
-            print!("{}</{}>", nl_indent(self.depth + 1), name);
-        }
         self.current_subelement_name = None;
     }
     
@@ -151,65 +222,203 @@ impl Accumulator for EchoAccumulator {
         self.current_subelement_name.is_some()
     }
     
-    fn current_subelement_name(&self) -> &str {
+    fn current_subelement_name(&self) -> Option<&QName> {
         self.current_subelement_name.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("")
     }
     
-    fn finish(self) -> () {
-        print!("{}</{}>", nl_indent(self.depth), self.element_name);
-        ()
+    fn finish(mut self) {
+        self.close();
+
+        // The root element ends the document
+        if self.depth == 0 {
+            self.output.borrow_mut().write(|writer, _| writeln!(writer));
+        }
     }
     
-    fn element_name(&self) -> &str {
+    fn element_name(&self) -> &QName {
         &self.element_name
     }
     
     fn element_lineno(&self) -> LineNumber {
-        self.element_lineno
+        self.element_span.start.line
+    }
+
+    fn end_element(&mut self, span: SourceSpan) {
+        self.empty_tag = span == self.element_span;
+    }
+
+    fn characters(&mut self, characters: &str) {
+        self.event(XmlEvent::Characters(characters.to_string()));
+    }
+
+    fn whitespace(&mut self, whitespace: &str) {
+        self.event(XmlEvent::Whitespace(whitespace.to_string()));
+    }
+
+    fn cdata(&mut self, cdata: &str) {
+        self.event(XmlEvent::CData(cdata.to_string()));
+    }
+
+    fn comment(&mut self, comment: &str) {
+        self.event(XmlEvent::Comment(comment.to_string()));
+    }
+
+    fn processing_instruction(&mut self, name: &str, data: Option<&str>) {
+        self.event(XmlEvent::ProcessingInstruction {
+            name: name.to_string(),
+            data: data.map(|d| d.to_string()),
+        });
+    }
+
+    fn declaration(&mut self, document_info: &DocumentInfo) {
+        self.output.borrow_mut().write(|writer, _| write_declaration(writer, document_info));
+    }
+
+    // Whitespace outside the root element isn't kept, so each item goes on
+    // a line of its own
+    fn prolog(&mut self, event: &XmlEvent) {
+        self.output.borrow_mut().write(|writer, _| {
+            write_event(writer, event)?;
+            writeln!(writer)
+        });
+    }
+
+    fn epilog(&mut self, event: &XmlEvent) {
+        self.close();
+        self.output.borrow_mut().write(|writer, _| {
+            writeln!(writer)?;
+            write_event(writer, event)
+        });
     }
 }
 
 #[cfg(test)]
 mod tests {
     use stdext::function_name;
-    use std::io::{BufReader, Cursor};
+    use std::io::{self, BufReader, Cursor, Write};
 
-    use crate::parse_doc::ParseDoc;
+    use crate::parse_doc::Accumulator;
+    use crate::parse_tree::{ParseTree, TreeLevelInfo};
+    use crate::write_tree::write_document;
+    use crate::xml_document_error::XmlDocumentError;
 
     use super::{EchoLevelInfo, ParseEcho};
 
+    fn echo(input: &str) -> String {
+        let mut output = Vec::new();
+        let echo_level_info = EchoLevelInfo::new(&mut output);
+        ParseEcho::parse(BufReader::new(Cursor::new(input.as_bytes())), &echo_level_info)
+            .expect("echo failed");
+        drop(echo_level_info);
+        String::from_utf8(output).expect("output not UTF-8")
+    }
+
+    /*
+     * Writer that fails after a given number of bytes
+     */
+    struct FailingWriter {
+        room: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.room {
+                return Err(io::Error::other("disk full"));
+            }
+            self.room -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
     #[test]
     fn testit() {
         println!("Running test {}", function_name!());
 
         let input_str = 
-            "<!--  \n".to_owned() +
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_owned() +
+            "<!--  \n" +
             "\n" +
             "Just supply a few elements. This will only work for non-checking code.\n" +
             " -->\n" +
-            "<schema xmlns:xtce=\"http://www.omg.org/spec/XTCE/20180204\" xmlns=\"http://www.w3.org/2001/XMLSchema\" targetNamespace=\"http://www.omg.org/spec/XTCE/20180204\" elementFormDefault=\"qualified\" attributeFormDefault=\"unqualified\" version=\"1.2\">\n" +
-            "    <one>\n" +
-            "       <two>\n" +
-            "          <three>\n" +
-            "          </three>\n" +
-            "       </two>\n" +
+            "<schema xmlns=\"http://www.w3.org/2001/XMLSchema\" xmlns:xtce=\"http://www.omg.org/spec/XTCE/20180204\" targetNamespace=\"http://www.omg.org/spec/XTCE/20180204\" elementFormDefault=\"qualified\" attributeFormDefault=\"unqualified\" version=\"1.2\">\n" +
+            "    <one a=\"&lt;&amp;&quot;\">\n" +
+            "       <two>text &amp; more<![CDATA[<cdata>]]></two>\n" +
+            "       <xtce:three/>\n" +
+            "       <?pi data?><!-- comment -->\n" +
             "    </one>\n" +
-            "    <four>\n" +
+            "    <four xmlns=\"\">\n" +
             "    </four>\n" +
-            "</schema>\n";
+            "</schema>\n" +
+            "<!-- after -->\n";
         for (lineno, line) in input_str.split('\n').enumerate() {
             println!("{} {}", lineno, line);
         }
 
-        let cursor = Cursor::new((&input_str).as_bytes());
-        let reader = BufReader::new(cursor);
+        // Attributes and namespace declarations keep their order
+        let output = echo(&input_str);
+        print!("{}", output);
+        assert_eq!(output, input_str);
 
-        let echo_level_info = EchoLevelInfo::new();
+        // The echo is the same as writing out the tree
+        let cursor = Cursor::new(input_str.as_bytes());
+        let (document_info, root) = ParseTree::parse(BufReader::new(cursor),
+            &TreeLevelInfo::new()).unwrap();
+        let mut written = Vec::new();
+        write_document(&mut written, &document_info, root.as_ref()).unwrap();
+        assert_eq!(output.as_bytes(), written);
+    }
+
+    #[test]
+    fn test_empty_elements() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<a><b/><c></c><p:d xmlns:p=\"urn:p\"></p:d><e>\n</e><f/></a>\n");
+        assert_eq!(echo(input), input);
+    }
+
+    #[test]
+    fn test_no_declaration() {
+        println!("Running test {}", function_name!());
+        let input = std::fs::read_to_string("schema/SpaceSystem-patched.xsd").unwrap();
+        let output = echo(&input);
+        assert!(!input.starts_with("<?xml"));
+        assert!(output.starts_with("<!--"));
+        let schema_line = input.lines().find(|line| line.starts_with("<schema")).unwrap();
+        assert!(output.lines().any(|line| line == schema_line));
+        assert_eq!(echo("<a/>"), "<a/>\n");
+    }
+
+    #[test]
+    fn test_write_error() {
+        println!("Running test {}", function_name!());
+        let input = "<a><b>".to_owned() + &"<c>text</c>".repeat(100) + "</b></a>";
+        let echo_level_info = EchoLevelInfo::new(FailingWriter { room: 100 });
+        match ParseEcho::parse(BufReader::new(Cursor::new(input.as_bytes())), &echo_level_info) {
+            Err(XmlDocumentError::IoError(_, e)) => assert_eq!(e.to_string(), "disk full"),
+            other => panic!("Expected IoError, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_end_subelement() {
+        println!("Running test {}", function_name!());
+        let mut output = Vec::new();
+        let echo_level_info = EchoLevelInfo::new(&mut output);
+        let cursor = Cursor::new("<a xmlns:p=\"urn:p\"><p:b/></a>".as_bytes());
+        let (_, root) = ParseTree::parse(BufReader::new(cursor), &TreeLevelInfo::new()).unwrap();
+        let b = root.subelements()[0].element_info();
 
-        // FIXME: Handle returned error
-        let _ = ParseEcho::parse(reader, &echo_level_info);
-        println!();
+        let mut accumulator = super::EchoAccumulator::new(root.element_info().clone(), 0,
+            echo_level_info.output.clone());
+        assert!(!accumulator.has_open_subelement());
+        accumulator.start_subelement(b);
+        assert_eq!(accumulator.current_subelement_name().unwrap().to_string(), "{urn:p}b");
+        accumulator.end_subelement();
+        assert!(!accumulator.has_open_subelement());
     }
 }
@@ -1,212 +1,1550 @@
 /*
- * A layer built on top of Xml::EventReader to provide look-ahead and line
- * numbers.
+ * A layer built on top of Xml::EventReader to provide look-ahead and source
+ * positions.
  */
 
 //use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::fmt;
-use std::io::Read;
+use std::collections::VecDeque;
+use std::io::{self, Chain, Cursor, Read};
 use std::rc::Rc;
 //use xml::attribute::OwnedAttribute;
-//use xml::common::XmlVersion;
-//use xml::name::OwnedName;
-//use xml::namespace::Namespace;
-use xml::reader::{EventReader, XmlEvent};
+use serde::Serialize;
+use xml::common::{Position, TextPosition, XmlVersion};
+use xml::name::OwnedName;
+use xml::namespace::{Namespace, NS_NO_PREFIX, NS_XML_PREFIX, NS_XMLNS_PREFIX};
+use xml::reader::{ErrorKind, EventReader, ParserConfig, XmlEvent};
 
+use crate::diagnostics::{Diagnostic, Diagnostics};
 use crate::xml_document_error::XmlDocumentError;
 
 pub type LineNumber = usize;
 
+/**
+ * A position in the input
+ * line:    Line number, starting at 1
+ * column:  Column number, counted in characters and starting at 1
+ * offset:  Byte offset from the start of the input, starting at 0
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct SourcePosition {
+    pub line:   LineNumber,
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl SourcePosition {
+    pub fn new(line: LineNumber, column: usize, offset: usize) -> Self {
+        SourcePosition {
+            line,
+            column,
+            offset,
+        }
+    }
+}
+
+// The default is the start of the input
+impl Default for SourcePosition {
+    fn default() -> Self {
+        SourcePosition::new(1, 1, 0)
+    }
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/**
+ * The part of the input that holds an XmlEvent
+ * start:   Position of the first character
+ * end:     Position just past the last character
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct SourceSpan {
+    pub start:  SourcePosition,
+    pub end:    SourcePosition,
+}
+
+impl SourceSpan {
+    pub fn new(start: SourcePosition, end: SourcePosition) -> Self {
+        SourceSpan {
+            start,
+            end,
+        }
+    }
+
+    /*
+     * Number of bytes in the span
+     */
+    pub fn len(&self) -> usize {
+        self.end.offset - self.start.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {}", self.start, self.end)
+    }
+}
+
 /**
  * An XML element
- * lineno:  Line number of the start of this element
- * event:   XmlEvent returned by the XML low level parse_item
+ * span:            Part of the input holding this element
+ * event:           XmlEvent returned by the XML low level parse_item
+ * attribute_order: For a StartElement, the names of the namespace
+ *                  declarations and attributes in the order they were
+ *                  written, since xml-rs keeps the declarations apart
  */
 #[derive(Clone, Debug)]
 pub struct TreeElement {
-    pub lineno: LineNumber,
+    pub span: SourceSpan,
     pub event: XmlEvent,
+    pub attribute_order: Vec<String>,
 }
 
-impl TreeElement {
-    fn new(lineno: LineNumber, event: XmlEvent) -> TreeElement {
-        TreeElement {
-            lineno,
-            event,
+impl TreeElement {
+    fn new(span: SourceSpan, event: XmlEvent) -> TreeElement {
+        TreeElement {
+            span,
+            event,
+            attribute_order: Vec::new(),
+        }
+    }
+
+    /*
+     * Line number of the start of this element
+     */
+    pub fn lineno(&self) -> LineNumber {
+        self.span.start.line
+    }
+
+    pub fn name(&self) -> String {
+        let result = match &self.event {
+            XmlEvent::StartDocument{version: _, encoding: _, standalone: _} => "StartDocument".to_string(),
+            XmlEvent::EndDocument => "EndDocument".to_string(),
+            XmlEvent::StartElement{name, attributes: _, namespace: _} => format!("StartElement<{}>", name.local_name),
+            XmlEvent::EndElement{name} => format!("EndElement<{}>", name.local_name),
+            XmlEvent::ProcessingInstruction{name: _, data: _} => "ProcessingInstruction".to_string(),
+            XmlEvent::CData(_) => "CData".to_string(),
+            XmlEvent::Comment(_) => "Comment".to_string(),
+            XmlEvent::Characters(_) => "Characters".to_string(),
+            XmlEvent::Whitespace(_) => "Whitespace".to_string(),
+        };
+        result.to_string()
+    }
+}
+
+/**
+ * Parser
+ * source_map:      Record of the input read so far, shared with the
+ *                  PositionReader feeding event_reader
+ * pending:         If None, we don't have a lookahead token. Otherwise,
+ *                  this is the lookahead token wrapped in Some()
+ * event_reader:    Object for reading the next XmlEvent. The input is a
+ *                  prefix, which is only non-empty after the EventReader
+ *                  has been restarted, followed by the real input. This is
+ *                  None once recovery has reached the end of the input.
+ * base:            Byte offset in the input where the current EventReader
+ *                  started reading
+ * multiple_roots:  True if more than one root element is allowed, as in a
+ *                  fragment. Otherwise, a second root element is an error.
+ * fragment:        True if the input is a fragment, which can also have
+ *                  character data outside of its elements
+ * last_end:        Byte offset just past the last event read
+ * open_span:       Span of the last event read if it was a StartElement.
+ *                  A self-closing element gets this span for its
+ *                  EndElement, too.
+ * context:         If Some, what's needed to restart the EventReader part
+ *                  way through the input. This is only kept when
+ *                  recovering, pushing input or reading a fragment.
+ * recovery:        If Some, errors are recorded and parsing carries on
+ * push:            True if the input is pushed in by the caller, so that
+ *                  running out of input suspends parsing
+ */
+pub struct Parser<R: Read> {
+    source_map: Rc<RefCell<SourceMap>>,
+    pending: Option<Result<TreeElement, XmlDocumentError>>,
+    event_reader: Option<EventReader<PrefixedReader<R>>>,
+    base: usize,
+    multiple_roots: bool,
+    fragment: bool,
+    last_end: usize,
+    open_span: Option<SourceSpan>,
+    context: Option<Context>,
+    recovery: Option<Recovery>,
+    push: bool,
+}
+
+type PrefixedReader<R> = Chain<Cursor<Vec<u8>>, PositionReader<R>>;
+
+impl<R: Read> Parser<R> {
+    pub fn new(reader: R) -> Self {
+        let position_reader = PositionReader::new(reader);
+        let source_map = position_reader.source_map();
+
+        Parser {
+            source_map,
+            pending: None,
+            event_reader: Some(Self::event_reader(Vec::new(), position_reader, true)),
+            base: 0,
+            multiple_roots: true,
+            fragment: false,
+            last_end: 0,
+            open_span: None,
+            context: None,
+            recovery: None,
+            push: false,
+        }
+    }
+
+    /**
+     * Create a Parser that recovers from errors in the input rather than
+     * stopping at the first one. Errors are recorded in the diagnostics
+     * and the events returned are always well-formed:
+     * o    An end tag that matches an open element closes all of the
+     *      elements opened after it, too.
+     * o    Other misplaced end tags are skipped.
+     * o    Other malformed input is skipped up to the end of the markup
+     *      where the error was found.
+     * o    The end of the input closes all open elements.
+     */
+    pub fn with_recovery(reader: R) -> Self {
+        let mut parser = Self::new(reader);
+        parser.context = Some(Context::default());
+        parser.recovery = Some(Recovery::default());
+        parser
+    }
+
+    fn event_reader(prefix: Vec<u8>, position_reader: PositionReader<R>,
+        multiple_roots: bool) -> EventReader<PrefixedReader<R>> {
+        // Comments are passed through so that they can be given to the
+        // accumulators
+        ParserConfig::new()
+            .ignore_comments(false)
+            .allow_multiple_root_elements(multiple_roots)
+            .create_reader(Cursor::new(prefix).chain(position_reader))
+    }
+
+    /**
+     * Start reading the next document from input holding a series of
+     * documents, such as a log of XML messages. A new EventReader is
+     * started just past the last event read, so any lookahead item is
+     * dropped, and whitespace before the next document is skipped. Positions
+     * carry on from the previous documents.
+     *
+     * multiple_roots:  True if the input is a fragment, which can have more
+     *                  than one root element and character data outside of
+     *                  them. When this is false, a
+     *                  second root element or XML declaration makes the
+     *                  next lookahead an error, which is where the next
+     *                  document starts.
+     *
+     * Returns: false if there is nothing but whitespace left
+     */
+    pub fn next_document(&mut self, multiple_roots: bool) -> bool {
+        let mut position_reader = match self.event_reader.take() {
+            None => return false,
+            Some(event_reader) => event_reader.into_inner().into_inner().1,
+        };
+        let map = self.source_map.clone();
+        let mut start = self.last_end;
+
+        loop {
+            let byte = map.borrow().byte(start);
+            match byte {
+                Some(b' ' | b'\t' | b'\r' | b'\n') => start += 1,
+                Some(_) => break,
+                None => match position_reader.read(&mut [0u8]) {
+                    Ok(0) => {
+                        self.pending = None;
+                        self.last_end = start;
+                        return false;
+                    },
+                    Ok(_) => {},
+                    // Leave the error for the new EventReader to report
+                    Err(_) => break,
+                },
+            }
+        }
+
+        // Anything read past the start is given to the new EventReader again
+        let prefix = map.borrow().bytes(start, map.borrow().consumed());
+        self.event_reader = Some(Self::event_reader(prefix, position_reader, multiple_roots));
+        self.base = start;
+        self.multiple_roots = multiple_roots;
+        self.fragment = multiple_roots;
+        self.last_end = start;
+        self.open_span = None;
+        self.pending = None;
+        if self.context.is_some() || multiple_roots {
+            self.context = Some(Context::default());
+        }
+
+        true
+    }
+
+    /**
+     * Read the next TreeElement. Each read returns a new value. This
+     * TreeElement is always an TreeElement
+     *
+     * self:    &mut Parser
+     *
+     * Returns:
+     * Ok(TreeElement)
+     * Err(XmlDocumentError)
+     */
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<TreeElement, XmlDocumentError> {
+        let result = self.lookahead()?;
+/*
+        if let Err(e) = result {
+            return Err(e);
+        }
+*/
+//        print!("(next {})", result.name());
+
+        self.skip();
+        Ok(result)
+    }
+
+    /*
+     * Discard the current TreeElement, forcing a fetch of the next item
+     * if current() is used. This TreeElement is always an TreeElement
+     *
+     * self:    &mut Parser
+     */
+    pub fn skip(&mut self) {
+//        print!("(skip)");
+        self.pending = None;
+    }
+
+    /*
+     * Read the next TreeElement from the input stream, without removing
+     * it from the stream. This TreeElement is always an TreeElement
+     *
+     * self:    &mut Parser
+     *
+     * Returns:
+     * Ok(TreeElement)
+     * Err(XmlDocumentError)
+     */
+    pub fn lookahead(&mut self) -> Result<TreeElement, XmlDocumentError> {
+        // If we don't have any lookahead token, read another token to be
+        // the lookahead token. We might get an error, in which case the
+        // error is what we have as the lookahead.
+        if self.pending.is_none() {
+            let item = self.read_item();
+//println!("(lookahead {:?})", item);
+            self.pending = Some(item.clone());
+            item
+        } else {
+            // We do have a pending token. If it's an error, return that. If
+            // it's a token, return that, but in either case, don't remove it.
+            match &self.pending {
+                None => Err(XmlDocumentError::InternalError(
+                    self.position(),
+                    "self.pending is None when it must be Some".to_string(),
+                )),
+                Some(element) => element.clone(),
+            }
+        }
+    }
+
+    /*
+     * Position just past the last event read
+     */
+    pub fn position(&self) -> SourcePosition {
+        self.source_map.borrow().position(self.last_end)
+    }
+
+    /*
+     * True if this Parser was created by with_recovery()
+     */
+    pub fn is_recovering(&self) -> bool {
+        self.recovery.is_some()
+    }
+
+    /**
+     * Handle an error found by the caller. When recovering, the error is
+     * recorded and Ok(()) is returned so the caller can carry on.
+     * Otherwise, the error is returned.
+     */
+    pub fn recover(&mut self, err: XmlDocumentError) -> Result<(), XmlDocumentError> {
+        match &mut self.recovery {
+            None => Err(err),
+            Some(recovery) => {
+                recovery.diagnostics.error(&err);
+                Ok(())
+            },
+        }
+    }
+
+    /*
+     * Diagnostics recorded while recovering from errors, if any
+     */
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        self.recovery.as_ref().map(|recovery| &recovery.diagnostics)
+    }
+
+    /*
+     * Remove and return the diagnostics recorded so far
+     */
+    pub fn take_diagnostics(&mut self) -> Diagnostics {
+        match &mut self.recovery {
+            None => Diagnostics::new(),
+            Some(recovery) => std::mem::take(&mut recovery.diagnostics),
+        }
+    }
+
+    /**
+     * Skip everything up to the end of the element whose start was just
+     * read, leaving the EndElement as the lookahead item.
+     */
+    pub fn skip_content(&mut self) -> Result<(), XmlDocumentError> {
+        let mut depth = 0;
+
+        loop {
+            match self.lookahead()?.event {
+                XmlEvent::StartElement { .. } => depth += 1,
+                XmlEvent::EndElement { .. } if depth == 0 => break,
+                XmlEvent::EndElement { .. } => depth -= 1,
+                XmlEvent::EndDocument => break,
+                _ => {},
+            }
+            self.skip();
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Get the next item. For a push parser, running out of input is an
+     * error here, so poll() is used instead.
+     */
+    fn read_item(&mut self) -> Result<TreeElement, XmlDocumentError> {
+        match self.read_event()? {
+            None => Err(XmlDocumentError::InternalError(self.position(),
+                "Out of input in a push parser".to_string())),
+            Some(item) => Ok(item),
+        }
+    }
+
+    /*
+     * Get the next item, either one made up while recovering or one from
+     * the EventReader. Returns None if this is a push parser and more
+     * input is needed.
+     */
+    fn read_event(&mut self) -> Result<Option<TreeElement>, XmlDocumentError> {
+        loop {
+            if let Some(item) = self.recovery.as_mut()
+                .and_then(|recovery| recovery.queued.pop_front()) {
+                return Ok(Some(item));
+            }
+
+            let evt = match &mut self.event_reader {
+                // Recovery reached the end of the input
+                None => {
+                    let end = self.source_map.borrow().position(self.last_end);
+                    return Ok(Some(TreeElement::new(SourceSpan::new(end, end),
+                        XmlEvent::EndDocument)));
+                },
+                Some(event_reader) => event_reader.next(),
+            };
+
+            match evt {
+                Err(e) if self.push &&
+                    matches!(e.kind(), ErrorKind::Io(io) if io.kind() == io::ErrorKind::WouldBlock) => {
+                    self.suspend();
+                    return Ok(None);
+                },
+                Err(e) => {
+                    if let Some(item) = self.fragment_text() {
+                        return Ok(Some(item));
+                    }
+                    let pos = self.error_position(&e);
+                    let error = XmlDocumentError::XmlError(pos, e);
+                    if self.recovery.is_none() {
+                        return Err(error);
+                    }
+                    self.resync(error);
+                },
+                Ok(xml_event) => {
+                    if let Some(context) = &mut self.context {
+                        // Discard events for the prefix given to a new
+                        // EventReader
+                        if context.suppress != 0 {
+                            context.suppress -= 1;
+                            continue;
+                        }
+                    }
+
+                    let element = self.event_item(xml_event);
+                    if let Some(context) = &mut self.context {
+                        context.track(&element);
+                    }
+                    return Ok(Some(element));
+                },
+            }
+        }
+    }
+
+    /*
+     * Handle character data outside of the elements of a fragment, which
+     * xml-rs reports as an error. The text runs up to the next markup,
+     * where a new EventReader is started. If xml-rs hadn't returned the
+     * StartDocument yet, that is returned instead and the text is read
+     * again by the new EventReader.
+     *
+     * Returns: None if the error wasn't for text in a fragment or the text
+     * isn't well-formed, in which case nothing more is read
+     */
+    fn fragment_text(&mut self) -> Option<TreeElement> {
+        let map = self.source_map.clone();
+        let context = match &mut self.context {
+            Some(context) if self.fragment && context.open.is_empty() => context,
+            _ => return None,
+        };
+        if map.borrow().markup_start_before(self.last_end).is_some() {
+            return None;
+        }
+        let mut position_reader = self.event_reader.take()?.into_inner().into_inner().1;
+
+        if !context.started {
+            let start = map.borrow().position(self.base);
+            let item = TreeElement::new(SourceSpan::new(start, start), XmlEvent::StartDocument {
+                version:    XmlVersion::Version10,
+                encoding:   "UTF-8".to_string(),
+                standalone: None,
+            });
+            context.track(&item);
+            let prefix = map.borrow().bytes(self.base, map.borrow().consumed());
+            self.event_reader = Some(Self::event_reader(prefix, position_reader,
+                self.multiple_roots));
+            return Some(item);
+        }
+
+        position_reader.skip_past(b'<');
+        let consumed = map.borrow().consumed();
+        let replay = usize::from(map.borrow().byte(consumed - 1) == Some(b'<'));
+        let resume = consumed - replay;
+        let text = decode_text(&map.borrow().text(self.last_end, resume))?;
+        let span = SourceSpan::new(map.borrow().position(self.last_end),
+            map.borrow().position(resume));
+
+        let mut prefix = context.prefix().into_bytes();
+        prefix.extend(map.borrow().bytes(resume, resume + replay));
+        self.event_reader = Some(Self::event_reader(prefix, position_reader,
+            self.multiple_roots));
+        self.base = resume;
+        self.last_end = resume;
+        self.open_span = None;
+        Some(TreeElement::new(span, XmlEvent::Characters(text)))
+    }
+
+    /*
+     * Convert the position of an xml-rs error to a SourcePosition
+     */
+    fn error_position(&self, e: &xml::reader::Error) -> SourcePosition {
+        let (prefix_lines, prefix_chars) = self.context.as_ref()
+            .map_or((0, 0), |context| (context.prefix_lines, context.prefix_chars));
+        self.source_map.borrow().text_position_after(self.base, prefix_lines, prefix_chars,
+            e.position())
+    }
+
+    /*
+     * Recover from an error reported by xml-rs. xml-rs can't carry on after
+     * an error so, after working out what to do about the input that
+     * caused the error, a new EventReader is started on the rest of the
+     * input. It's given a prefix that reopens the elements that are still
+     * open so that it sees the same context as the old one.
+     */
+    fn resync(&mut self, error: XmlDocumentError) {
+        let mut position_reader = match self.event_reader.take() {
+            None => return,
+            Some(event_reader) => event_reader.into_inner().into_inner().1,
+        };
+        let map = self.source_map.clone();
+        let (context, recovery) = match (&mut self.context, &mut self.recovery) {
+            (Some(context), Some(recovery)) => (context, recovery),
+            _ => return,
+        };
+
+        let is_io = matches!(&error, XmlDocumentError::XmlError(_, e)
+            if matches!(e.kind(), ErrorKind::Io(_)));
+        if is_io || map.borrow().at_eof() {
+            // Nothing more can be read, so finish off the document
+            if is_io || context.open.is_empty() {
+                recovery.diagnostics.error(&error);
+            }
+            let end = map.borrow().position(map.borrow().consumed());
+            recovery.close_all(context, SourceSpan::new(end, end));
+            recovery.queued.push_back(TreeElement::new(SourceSpan::new(end, end),
+                XmlEvent::EndDocument));
+            self.last_end = end.offset;
+            return;
+        }
+
+        // If the error was found in markup, skip the rest of it. Text
+        // outside the root element is skipped up to the next markup, which
+        // is given to the new EventReader again.
+        let token_start = map.borrow().markup_start_before(self.last_end);
+        let mut replay = 0;
+        if let Some(start) = token_start {
+            if map.borrow().find(start, b'>').is_none() {
+                position_reader.skip_past(b'>');
+            }
+        } else if context.open.is_empty() {
+            position_reader.skip_past(b'<');
+            let consumed = map.borrow().consumed();
+            replay = usize::from(map.borrow().byte(consumed - 1) == Some(b'<'));
+        } else if map.borrow().consumed() == self.base {
+            // Make sure we don't get stuck on the same input
+            position_reader.skip_past(b'>');
+        }
+
+        let resume = map.borrow().consumed() - replay;
+        let end_tag = token_start.and_then(|start| {
+            let map = map.borrow();
+            let token = map.text(start, resume);
+            let name = token.strip_prefix("</")?.strip_suffix('>')?.trim().to_string();
+            let span = SourceSpan::new(map.position(start), map.position(resume));
+            Some((name, span))
+        });
+
+        match end_tag {
+            None => recovery.diagnostics.error(&error),
+            Some((name, span)) => recovery.misplaced_end(context, &name, span),
+        }
+
+        // Start over on the rest of the input
+        let mut prefix = context.prefix().into_bytes();
+        prefix.extend(map.borrow().bytes(resume, resume + replay));
+        self.event_reader = Some(Self::event_reader(prefix, position_reader,
+            self.multiple_roots));
+        self.base = resume;
+        self.last_end = resume;
+        self.open_span = None;
+    }
+
+    /*
+     * A push parser has run out of input. xml-rs can't carry on after that,
+     * so a new EventReader is started at the end of the last event. It is
+     * given a prefix that reopens the elements that are still open, then
+     * the input the old EventReader read past the end of the last event, so
+     * that it picks up where the old one left off once more input arrives.
+     * The one difference is that whitespace after a CDATA section, which
+     * xml-rs reports as Characters, can come back as Whitespace.
+     */
+    fn suspend(&mut self) {
+        let position_reader = match self.event_reader.take() {
+            None => return,
+            Some(event_reader) => event_reader.into_inner().into_inner().1,
+        };
+        let context = match &mut self.context {
+            None => return,
+            Some(context) => context,
+        };
+
+        let mut prefix = context.prefix().into_bytes();
+        let map = self.source_map.borrow();
+        prefix.extend(map.bytes(self.last_end, map.consumed()));
+        drop(map);
+
+        self.event_reader = Some(Self::event_reader(prefix, position_reader,
+            self.multiple_roots));
+        self.base = self.last_end;
+        self.open_span = None;
+    }
+
+    /*
+     * Work out the part of the input holding an event that was just read.
+     * xml-rs reads its input a byte at a time and stops right after the
+     * closing '>' of markup, so markup ends at the number of bytes read so
+     * far and starts at the first '<' after the previous event. Text runs
+     * from the end of the previous event up to the next '<'. The event is
+     * normalized, and the order of the attributes of a start tag noted,
+     * while its input is still at hand.
+     */
+    fn event_item(&mut self, event: XmlEvent) -> TreeElement {
+        let mut map = self.source_map.borrow_mut();
+        let consumed = map.consumed();
+
+        let (start, end) = match &event {
+            XmlEvent::StartDocument { .. } => {
+                // The StartDocument event is produced even when there is no
+                // XML declaration. In that case, it takes no space at all.
+                let start = map.markup_start(self.last_end);
+                match map.declaration_end(start) {
+                    Some(end) => (start, end),
+                    None => (self.last_end, self.last_end),
+                }
+            },
+            XmlEvent::EndDocument => (consumed, consumed),
+            XmlEvent::Characters(_) | XmlEvent::Whitespace(_) => {
+                let end = map.find(self.last_end, b'<').unwrap_or(consumed);
+                (self.last_end, end)
+            },
+            XmlEvent::EndElement { .. } if consumed == self.last_end &&
+                self.open_span.is_some() => {
+                // Self-closing element, so the start and end are the same
+                let span = self.open_span.take().unwrap();
+                return TreeElement::new(span, event);
+            },
+            _ => (map.markup_start(self.last_end), consumed),
+        };
+
+        // The document type declaration is kept so that a new EventReader
+        // knows about the entities it declares
+        if let Some(context) = &mut self.context {
+            if context.doctype.is_none() {
+                context.doctype = map.doctype_between(self.last_end, start);
+            }
+        }
+
+        let span = SourceSpan::new(map.position(start), map.position(end));
+        let mut item = TreeElement::new(span, event);
+        self.open_span = None;
+        if let XmlEvent::StartElement { .. } = item.event {
+            self.open_span = Some(span);
+            item.attribute_order = raw_attributes(&map.bytes(start, end)).iter()
+                .map(|(name, _)| String::from_utf8_lossy(name).into_owned())
+                .collect();
+        }
+        normalize_event(&map, start, end, &mut item.event);
+        self.last_end = end;
+        map.release(end);
+        item
+    }
+}
+
+/*
+ * Parsers for input that is pushed in by the caller as it arrives, rather
+ * than read from a blocking Read
+ */
+impl Parser<PushSource> {
+    pub fn new_push() -> Self {
+        let mut parser = Self::new(PushSource::new());
+        parser.context = Some(Context::default());
+        parser.push = true;
+        parser
+    }
+
+    /**
+     * Add more input. Events that are complete can then be read with poll().
+     */
+    pub fn push(&self, bytes: &[u8]) {
+        if let Some(source) = self.push_source() {
+            source.push(bytes);
+        }
+    }
+
+    /**
+     * Mark the end of the input, after which poll() returns the rest of the
+     * events, ending with EndDocument.
+     */
+    pub fn close(&self) {
+        if let Some(source) = self.push_source() {
+            source.close();
+        }
+    }
+
+    /**
+     * Read the next TreeElement if there is enough input for it.
+     *
+     * Returns:
+     * Ok(Some(TreeElement))
+     * Ok(None)                 More input must be pushed first
+     * Err(XmlDocumentError)
+     */
+    pub fn poll(&mut self) -> Result<Option<TreeElement>, XmlDocumentError> {
+        match self.pending.take() {
+            Some(item) => item.map(Some),
+            None => self.read_event(),
+        }
+    }
+
+    fn push_source(&self) -> Option<&PushSource> {
+        self.event_reader.as_ref()
+            .map(|event_reader| &event_reader.source().get_ref().1.inner)
+    }
+}
+
+impl<R: Read> fmt::Debug for Parser<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parser: {}", self.position())
+    }
+}
+
+/**
+ * What's needed to start a new EventReader part way through the input so
+ * that it sees the same context as the old one
+ * declaration:     XML declaration, if the input had one, so that the new
+ *                  EventReader uses the same encoding
+ * doctype:         Document type declaration, if the input had one, so
+ *                  that the new EventReader has the same entities
+ * open:            Elements that have been started but not ended
+ * suppress:        Number of events still to be discarded from a new
+ *                  EventReader, which are for the prefix it was given
+ * started:         True once the StartDocument has been returned
+ * root_seen:       True once the root element has been started
+ * prefix_lines:    Number of line ends in the prefix given to the current
+ *                  EventReader
+ * prefix_chars:    Number of characters after the last line end in the
+ *                  prefix given to the current EventReader
+ */
+#[derive(Debug, Default)]
+struct Context {
+    declaration:    Option<String>,
+    doctype:        Option<String>,
+    open:           Vec<OpenElement>,
+    suppress:       usize,
+    started:        bool,
+    root_seen:      bool,
+    prefix_lines:   usize,
+    prefix_chars:   usize,
+}
+
+impl Context {
+    /*
+     * Keep track of the open elements
+     */
+    fn track(&mut self, element: &TreeElement) {
+        match &element.event {
+            XmlEvent::StartDocument { version, encoding, standalone } => {
+                self.started = true;
+                if !element.span.is_empty() {
+                    let standalone = match standalone {
+                        None => String::new(),
+                        Some(true) => " standalone=\"yes\"".to_string(),
+                        Some(false) => " standalone=\"no\"".to_string(),
+                    };
+                    self.declaration = Some(format!("<?xml version=\"{}\" encoding=\"{}\"{}?>",
+                        version, encoding, standalone));
+                }
+            },
+            XmlEvent::StartElement { name, namespace, .. } => {
+                self.root_seen = true;
+                self.open.push(OpenElement {
+                    name:       name.clone(),
+                    namespace:  namespace.clone(),
+                    span:       element.span,
+                });
+            },
+            XmlEvent::EndElement { .. } => {
+                self.open.pop();
+            },
+            _ => {},
+        }
+    }
+
+    /*
+     * Make the prefix for a new EventReader, which repeats the XML and
+     * document type declarations and reopens the elements
+     * that are still open. If the root element has already been closed, a
+     * dummy root element is used instead so that the rest of the input is
+     * treated as the end of the document. The events for the prefix will
+     * be suppressed.
+     */
+    fn prefix(&mut self) -> String {
+        let (elements, events) = if self.open.is_empty() && self.root_seen {
+            ("<x/>".to_string(), 2)
+        } else {
+            (self.open.iter().map(OpenElement::start_tag).collect(), self.open.len())
+        };
+        let prefix = format!("{}{}{}", self.declaration.as_deref().unwrap_or(""),
+            self.doctype.as_deref().unwrap_or(""), elements);
+
+        self.suppress = events + usize::from(self.started);
+        let last_line = prefix.rfind('\n').map_or(0, |i| i + 1);
+        self.prefix_lines = prefix.matches('\n').count();
+        self.prefix_chars = prefix[last_line..].chars().count();
+        prefix
+    }
+}
+
+/**
+ * State for recovering from errors
+ * diagnostics:     Errors found so far
+ * queued:          Events made up during recovery, to be returned before
+ *                  reading any more
+ */
+#[derive(Debug, Default)]
+struct Recovery {
+    diagnostics:    Diagnostics,
+    queued:         VecDeque<TreeElement>,
+}
+
+impl Recovery {
+    /*
+     * Handle an end tag that doesn't match the innermost open element. If
+     * it matches another open element, the elements inside that one are
+     * closed. Otherwise, it is skipped.
+     */
+    fn misplaced_end(&mut self, context: &mut Context, name: &str, span: SourceSpan) {
+        let expected = match context.open.last() {
+            None => "end of document".to_string(),
+            Some(open) => open.qualified_name(),
+        };
+        let err = XmlDocumentError::MisplacedElementEnd(span.start, expected,
+            name.to_string());
+        let mut diagnostic = Diagnostic::from(&err).with_span(span);
+
+        match context.open.iter().rposition(|open| open.qualified_name() == name) {
+            None => diagnostic = diagnostic.with_note("the end tag was skipped"),
+            Some(index) => {
+                let closed = SourceSpan::new(span.start, span.start);
+                while context.open.len() > index + 1 {
+                    let open = self.close(context, closed);
+                    diagnostic = diagnostic.with_note(&format!(
+                        "<{}> at {} was closed here", open.qualified_name(), open.span.start));
+                }
+                self.close(context, span);
+            },
+        }
+
+        self.diagnostics.push(diagnostic);
+    }
+
+    /*
+     * Close all open elements at the end of the input
+     */
+    fn close_all(&mut self, context: &mut Context, span: SourceSpan) {
+        while !context.open.is_empty() {
+            let open = self.close(context, span);
+            self.diagnostics.error(&XmlDocumentError::UnclosedElement(open.span.start,
+                open.qualified_name()));
+        }
+    }
+
+    /*
+     * Close the innermost open element, queueing an EndElement for it
+     */
+    fn close(&mut self, context: &mut Context, span: SourceSpan) -> OpenElement {
+        let open = context.open.pop().expect("close() called with no open elements");
+        self.queued.push_back(TreeElement::new(span,
+            XmlEvent::EndElement { name: open.name.clone() }));
+        open
+    }
+}
+
+/*
+ * Replace the references in character data by having xml-rs read it as
+ * the content of an element. Returns None if it isn't well-formed.
+ */
+fn decode_text(text: &str) -> Option<String> {
+    let reader = ParserConfig::new().create_reader(Cursor::new(format!("<x>{}</x>", text)));
+    let mut decoded = String::new();
+    for event in reader {
+        match event.ok()? {
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text) => decoded += &text,
+            XmlEvent::StartDocument { .. } | XmlEvent::EndDocument |
+                XmlEvent::StartElement { .. } | XmlEvent::EndElement { .. } => {},
+            _ => return None,
+        }
+    }
+    Some(decoded)
+}
+
+/**
+ * An element that has been started but not ended
+ * name:        Name of the element
+ * namespace:   All namespace mappings in scope for the element
+ * span:        Part of the input holding the start tag
+ */
+#[derive(Debug)]
+struct OpenElement {
+    name:       OwnedName,
+    namespace:  Namespace,
+    span:       SourceSpan,
+}
+
+impl OpenElement {
+    fn qualified_name(&self) -> String {
+        match &self.name.prefix {
+            None => self.name.local_name.clone(),
+            Some(prefix) => format!("{}:{}", prefix, self.name.local_name),
+        }
+    }
+
+    /*
+     * Start tag reopening the element, declaring all the namespaces in
+     * scope for it
+     */
+    fn start_tag(&self) -> String {
+        let mut tag = format!("<{}", self.qualified_name());
+        for (prefix, uri) in &self.namespace {
+            match prefix {
+                NS_XML_PREFIX | NS_XMLNS_PREFIX => {},
+                NS_NO_PREFIX => tag += &format!(" xmlns=\"{}\"", escape_attribute(uri)),
+                _ => tag += &format!(" xmlns:{}=\"{}\"", prefix, escape_attribute(uri)),
+            }
+        }
+        tag + ">"
+    }
+}
+
+fn escape_attribute(value: &str) -> String {
+    value.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\t' | '\n' | '\r' => format!("&#{};", c as u32),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+/*
+ * xml-rs replaces character references but doesn't normalize line ends or
+ * attribute values, so that is done here. It needs the input, to tell a
+ * carriage return written as "&#xD;", which is kept, from a literal one,
+ * which is a line end.
+ * map:         Input read so far
+ * start, end:  Offsets of the input holding the event
+ * event:       Event to normalize
+ */
+fn normalize_event(map: &SourceMap, start: usize, end: usize, event: &mut XmlEvent) {
+    match event {
+        XmlEvent::Characters(text) | XmlEvent::Whitespace(text) if text.contains('\r') =>
+            *text = normalize_whitespace(&map.bytes(start, end), text, false),
+        // These can't hold character references
+        XmlEvent::CData(text) | XmlEvent::Comment(text) |
+        XmlEvent::ProcessingInstruction { data: Some(text), .. } if text.contains('\r') =>
+            *text = text.replace("\r\n", "\n").replace('\r', "\n"),
+        XmlEvent::StartElement { attributes, .. } => {
+            if !attributes.iter().any(|attribute| attribute.value.contains(is_whitespace_char)) {
+                return;
+            }
+            let tag = map.bytes(start, end);
+            let values = attribute_values(&tag);
+            if values.len() == attributes.len() {
+                for (attribute, raw) in attributes.iter_mut().zip(values) {
+                    attribute.value = normalize_whitespace(raw, &attribute.value, true);
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+fn is_whitespace_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r')
+}
+
+/*
+ * How a tab, line feed or carriage return was written in the input
+ * Literal:     The character itself, followed by the given byte
+ * Reference:   A character reference
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum Written {
+    Literal(Option<u8>),
+    Reference,
+}
+
+/*
+ * Normalize the whitespace in text or an attribute value, as in sections
+ * 2.11 and 3.3.3 of the XML specification. Literal line ends become a line
+ * feed in text and literal whitespace becomes a space in an attribute
+ * value. Whitespace written as a character reference is kept.
+ * raw:             The input holding the text
+ * text:            The text after xml-rs replaced the references
+ * in_attribute:    True for an attribute value
+ */
+fn normalize_whitespace(raw: &[u8], text: &str, in_attribute: bool) -> String {
+    let mut written = whitespace_written(raw);
+    if written.len() != text.chars().filter(|&c| is_whitespace_char(c)).count() {
+        // Not something we can match up with the input, such as input in
+        // UTF-16, so take all of it to be literal
+        let chars: Vec<char> = text.chars().collect();
+        written = chars.iter().enumerate()
+            .filter(|(_, &c)| is_whitespace_char(c))
+            .map(|(i, _)| Written::Literal(chars.get(i + 1).and_then(|&next| u8::try_from(next).ok())))
+            .collect();
+    }
+
+    let mut written = written.into_iter();
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !is_whitespace_char(c) {
+            result.push(c);
+            continue;
+        }
+        match (c, written.next()) {
+            // The line feed that follows stands for both
+            ('\r', Some(Written::Literal(Some(b'\n')))) => {},
+            (_, Some(Written::Literal(_))) if in_attribute => result.push(' '),
+            ('\r', Some(Written::Literal(_))) => result.push('\n'),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/*
+ * Find how each tab, line feed and carriage return in some input was
+ * written, in order
+ */
+fn whitespace_written(raw: &[u8]) -> Vec<Written> {
+    let mut written = Vec::new();
+    let mut i = 0;
+
+    while i < raw.len() {
+        match raw[i] {
+            b'\t' | b'\n' | b'\r' => written.push(Written::Literal(raw.get(i + 1).copied())),
+            b'&' => {
+                let end = match raw[i..].iter().position(|&b| b == b';') {
+                    None => break,
+                    Some(length) => i + length,
+                };
+                let reference = String::from_utf8_lossy(&raw[i + 1..end]);
+                let code = match reference.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => reference.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                if matches!(code, Some(0x9 | 0xA | 0xD)) {
+                    written.push(Written::Reference);
+                }
+                i = end;
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+
+    written
+}
+
+/*
+ * The values of the attributes in a start tag, as written, leaving out
+ * namespace declarations as xml-rs does
+ */
+fn attribute_values(tag: &[u8]) -> Vec<&[u8]> {
+    raw_attributes(tag).into_iter()
+        .filter(|(name, _)| *name != b"xmlns" && !name.starts_with(b"xmlns:"))
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/*
+ * The names and values of the attributes in a start tag, including
+ * namespace declarations, as written
+ */
+fn raw_attributes(tag: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut attributes = Vec::new();
+    let is_space = |b: u8| b.is_ascii_whitespace();
+    let mut i = tag.iter().position(|&b| is_space(b) || b == b'/' || b == b'>')
+        .unwrap_or(tag.len());
+
+    loop {
+        while i < tag.len() && is_space(tag[i]) {
+            i += 1;
+        }
+        if i >= tag.len() || tag[i] == b'/' || tag[i] == b'>' {
+            break;
+        }
+
+        let name_start = i;
+        while i < tag.len() && !is_space(tag[i]) && tag[i] != b'=' {
+            i += 1;
+        }
+        let name = &tag[name_start..i];
+        while i < tag.len() && (is_space(tag[i]) || tag[i] == b'=') {
+            i += 1;
+        }
+
+        let quote = match tag.get(i) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => quote,
+            _ => break,
+        };
+        let value_start = i + 1;
+        let value_end = match tag[value_start..].iter().position(|&b| b == quote) {
+            None => break,
+            Some(length) => value_start + length,
+        };
+        attributes.push((name, &tag[value_start..value_end]));
+        i = value_end + 1;
+    }
+
+    attributes
+}
+
+/**
+ * Record of the input read so far, used to turn byte offsets into
+ * positions
+ * consumed:        Number of bytes read
+ * line_starts:     Byte offset of the start of each line. Lines end with
+ *                  "\n", "\r\n" or "\r", as in XML
+ * window:          Input bytes, starting at window_start. Bytes before the
+ *                  line holding the end of the last event are dropped.
+ * window_start:    Byte offset of the first byte in window
+ * last_byte:       Last byte read, so that "\r\n" split across reads is
+ *                  still a single line end
+ * eof:             True once the end of the input has been reached
+ */
+#[derive(Debug)]
+pub struct SourceMap {
+    consumed:       usize,
+    line_starts:    Vec<usize>,
+    window:         Vec<u8>,
+    window_start:   usize,
+    last_byte:      Option<u8>,
+    eof:            bool,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap {
+            consumed:       0,
+            line_starts:    vec![0],
+            window:         Vec::new(),
+            window_start:   0,
+            last_byte:      None,
+            eof:            false,
+        }
+    }
+
+    /*
+     * Add bytes just read from the input
+     */
+    pub fn record(&mut self, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let next = self.consumed + i + 1;
+            match byte {
+                b'\r' => self.line_starts.push(next),
+                b'\n' if self.last_byte == Some(b'\r') => {
+                    *self.line_starts.last_mut().unwrap() = next;
+                },
+                b'\n' => self.line_starts.push(next),
+                _ => {},
+            }
+            self.last_byte = Some(byte);
+        }
+
+        self.window.extend_from_slice(bytes);
+        self.consumed += bytes.len();
+    }
+
+    /*
+     * Number of bytes read so far
+     */
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /*
+     * True if the end of the input has been reached
+     */
+    pub fn at_eof(&self) -> bool {
+        self.eof
+    }
+
+    /*
+     * Convert a byte offset to a position.
+     */
+    pub fn position(&self, offset: usize) -> SourcePosition {
+        let index = self.line_index(offset);
+        let line_start = self.line_starts[index];
+
+        // Count characters, not bytes, by skipping UTF-8 continuation bytes
+        let column = (line_start..offset)
+            .filter(|&o| self.byte(o).is_none_or(|b| !is_continuation(b)))
+            .count();
+        SourcePosition::new(index + 1, column + 1, offset)
+    }
+
+    /*
+     * Convert a position reported by xml-rs, which has a row and column
+     * in characters that both start at zero, into a SourcePosition
+     */
+    pub fn text_position(&self, text_position: TextPosition) -> SourcePosition {
+        self.text_position_after(0, 0, 0, text_position)
+    }
+
+    /*
+     * Convert a position reported by xml-rs when it started reading at byte
+     * offset base, after being given a prefix of prefix_lines line ends
+     * followed by prefix_chars characters.
+     */
+    pub fn text_position_after(&self, base: usize, prefix_lines: usize, prefix_chars: usize,
+        text_position: TextPosition) -> SourcePosition {
+        let row = match (text_position.row as usize).checked_sub(prefix_lines) {
+            None => return self.position(base),
+            Some(row) => row,
+        };
+        let (mut offset, columns) = if row == 0 {
+            (base, (text_position.column as usize).saturating_sub(prefix_chars))
+        } else {
+            match self.line_starts.get(self.line_index(base) + row) {
+                None => return self.position(self.consumed),
+                Some(line_start) => (*line_start, text_position.column as usize),
+            }
+        };
+
+        for _ in 0..columns {
+            if offset >= self.consumed {
+                break;
+            }
+            offset += 1;
+            while self.byte(offset).is_some_and(is_continuation) {
+                offset += 1;
+            }
         }
+
+        self.position(offset)
     }
 
-    pub fn name(&self) -> String {
-        let result = match &self.event {
-            XmlEvent::StartDocument{version: _, encoding: _, standalone: _} => "StartDocument".to_string(),
-            XmlEvent::EndDocument => "EndDocument".to_string(),
-            XmlEvent::StartElement{name, attributes: _, namespace: _} => format!("StartElement<{}>", name.local_name),
-            XmlEvent::EndElement{name} => format!("EndElement<{}>", name.local_name),
-            XmlEvent::ProcessingInstruction{name: _, data: _} => "ProcessingInstruction".to_string(),
-            XmlEvent::CData(_) => "CData".to_string(),
-            XmlEvent::Comment(_) => "Comment".to_string(),
-            XmlEvent::Characters(_) => "Characters".to_string(),
-            XmlEvent::Whitespace(_) => "Whitespace".to_string(),
-        };
-        result.to_string()
+    /*
+     * Drop saved bytes that can no longer be needed to compute the column
+     * of a position at or after offset
+     */
+    fn release(&mut self, offset: usize) {
+        let keep = self.line_starts[self.line_index(offset)];
+        if keep > self.window_start {
+            self.window.drain(..keep - self.window_start);
+            self.window_start = keep;
+        }
     }
-}
 
-/**
- * Parser
- * lineno_ref:      Reference counted reference to current line number
- *                  FIXME: check that this is appropriate
- * pending:         If None, we don't have a lookahead token. Otherwise,
- *                  this is the lookahead token wrapped in Some()
- * event_reader:    Object for reading the next XmlEvent
- */
-pub struct Parser<R: Read> {
-    lineno_ref: Rc<RefCell<LineNumber>>,
-    pending: Option<Result<TreeElement, XmlDocumentError>>,
-    event_reader: EventReader<LinenoReader<R>>,
-}
+    fn line_index(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
 
-impl<R: Read> Parser<R> {
-    pub fn new(reader: R) -> Self {
-        let line_reader = LinenoReader::new(reader);
-        let lineno_ref = line_reader.lineno_ref();
-        let event_reader = EventReader::new(line_reader);
+    fn byte(&self, offset: usize) -> Option<u8> {
+        offset.checked_sub(self.window_start)
+            .and_then(|i| self.window.get(i))
+            .copied()
+    }
 
-        Parser {
-            lineno_ref,
-            pending: None,
-            event_reader,
-        }
+    fn starts_with(&self, offset: usize, prefix: &[u8]) -> bool {
+        prefix.iter().enumerate().all(|(i, &b)| self.byte(offset + i) == Some(b))
     }
 
-    /**
-     * Read the next TreeElement. Each read returns a new value. This
-     * TreeElement is always an TreeElement
-     *
-     * self:    &mut Parser
-     *
-     * Returns:
-     * Ok(TreeElement)
-     * Err(XmlDocumentError)
+    /*
+     * Find the first instance of a byte at or after an offset
      */
-    pub fn next(&mut self) -> Result<TreeElement, XmlDocumentError> {
-        let result = self.lookahead()?;
-/*
-        if let Err(e) = result {
-            return Err(e);
-        }
-*/
-//        print!("(next {})", result.name());
+    fn find(&self, from: usize, target: u8) -> Option<usize> {
+        (from..self.consumed).find(|&offset| self.byte(offset) == Some(target))
+    }
 
-        self.skip();
-        Ok(result)
+    /*
+     * Find the last '<' at or after an offset that looks like the start of
+     * markup
+     */
+    fn markup_start_before(&self, from: usize) -> Option<usize> {
+        (from..self.consumed).rev()
+            .filter(|&offset| self.byte(offset) == Some(b'<'))
+            .find(|&offset| match self.byte(offset + 1) {
+                None => offset + 1 == self.consumed,
+                Some(byte) => matches!(byte, b'/' | b'!' | b'?' | b'_' | b':') ||
+                    byte.is_ascii_alphabetic() || byte >= 0x80,
+            })
     }
 
     /*
-     * Discard the current TreeElement, forcing a fetch of the next item
-     * if current() is used. This TreeElement is always an TreeElement
-     *
-     * self:    &mut Parser
+     * The input between two offsets, as text
      */
-    pub fn skip(&mut self) {
-//        print!("(skip)");
-        self.pending = None;
+    fn text(&self, start: usize, end: usize) -> String {
+        String::from_utf8_lossy(&self.bytes(start, end)).into_owned()
     }
 
     /*
-     * Read the next TreeElement from the input stream, without removing
-     * it from the stream. This TreeElement is always an TreeElement
-     *
-     * self:    &mut Parser
-     *
-     * Returns:
-     * Ok(TreeElement)
-     * Err(XmlDocumentError)
+     * The input between two offsets
      */
-    pub fn lookahead(&mut self) -> Result<TreeElement, XmlDocumentError> {
-        // If we don't have any lookahead token, read another token to be
-        // the lookahead token.
-        if self.pending.is_none() {
-            let lineno = *self.lineno_ref.borrow();
-            let evt = self.event_reader.next();
+    fn bytes(&self, start: usize, end: usize) -> Vec<u8> {
+        (start..end).filter_map(|offset| self.byte(offset)).collect()
+    }
 
-            // We tried to read another lookahead token, but we might have
-            // gotten an error. Check for this.
-            match evt {
-                Err(e) => {
-                    // Indicate we have something, but that the something
-                    // we have is an error
-                    let error = XmlDocumentError::XmlError(lineno, e);
-                    let err = Err(error.clone());
-                    let pending_err = Some(Err(error));
-                    self.pending = pending_err;
-                    err
+    /*
+     * Find the '<' that starts the markup following offset. Document type
+     * declarations are not reported by xml-rs, so they are passed over.
+     */
+    fn markup_start(&self, from: usize) -> usize {
+        let mut offset = from;
+
+        while let Some(start) = self.find(offset, b'<') {
+            if !self.starts_with(start, b"<!DOCTYPE") {
+                return start;
+            }
+            offset = self.doctype_end(start);
+        }
+
+        from
+    }
+
+    /*
+     * Text of the document type declaration, if there is one, at the first
+     * markup between two offsets
+     */
+    fn doctype_between(&self, from: usize, to: usize) -> Option<String> {
+        let start = self.find(from, b'<')
+            .filter(|&start| start < to && self.starts_with(start, b"<!DOCTYPE"))?;
+        Some(self.text(start, self.doctype_end(start)))
+    }
+
+    /*
+     * Offset just past the document type declaration starting at offset,
+     * allowing for quoted strings, comments and an internal subset.
+     */
+    fn doctype_end(&self, start: usize) -> usize {
+        let mut offset = start + 1;
+        let mut depth = 0;
+        let mut quote = None;
+
+        while offset < self.consumed {
+            let byte = self.byte(offset).unwrap_or(b' ');
+            match quote {
+                Some(q) if byte == q => quote = None,
+                Some(_) => {},
+                None if self.starts_with(offset, b"<!--") => {
+                    while offset < self.consumed && !self.starts_with(offset, b"-->") {
+                        offset += 1;
+                    }
+                    offset += 2;
+                },
+                None => match byte {
+                    b'"' | b'\'' => quote = Some(byte),
+                    b'[' => depth += 1,
+                    b']' => depth -= 1,
+                    b'>' if depth == 0 => return offset + 1,
+                    _ => {},
                 },
-                Ok(xml_event) => {
-                    let element = TreeElement::new(lineno, xml_event);
-//println!("(lookahead {})", element.name());
-                    let ok = Ok(element.clone());
-                    let pending_ok = Some(Ok(element));
-                    self.pending = pending_ok;
-                    ok
-                }
             }
-        } else {
-            // We do have a pending token. If it's an error, return that. If
-            // it's a token, return that, but in either case, don't remove it.
-let e = {
-            match self.pending.take() {
-                None => Err(XmlDocumentError::InternalError(
-                    *self.lineno_ref.borrow(),
-                    "self.pending is None when it must be Some".to_string(),
-                )),
-                Some(element) => element,
+            offset += 1;
+        }
+
+        self.consumed
+    }
+
+    /*
+     * If an XML declaration starts at offset, return the offset just past it
+     */
+    fn declaration_end(&self, start: usize) -> Option<usize> {
+        if !self.starts_with(start, b"<?xml") ||
+            !self.byte(start + 5).is_some_and(|b| b.is_ascii_whitespace()) {
+            return None;
+        }
+
+        let mut offset = start;
+        while offset < self.consumed {
+            if self.starts_with(offset, b"?>") {
+                return Some(offset + 2);
             }
-};
-//println!("(lookahead {})", e.clone().unwrap().name());
-e
+            offset += 1;
         }
+
+        None
     }
 }
 
-impl<R: Read> fmt::Debug for Parser<R> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Parser: lineno: {}", *self.lineno_ref.borrow())
+impl Default for SourceMap {
+    fn default() -> Self {
+        SourceMap::new()
     }
 }
 
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
 /**
- * Object for reading an std::io::Read implementation, as annotated with
- * a line number.
+ * Object for reading an std::io::Read implementation that records what
+ * was read in a SourceMap
  */
-pub struct LinenoReader<R: Read> {
+pub struct PositionReader<R: Read> {
     inner: R,
-    lineno: Rc<RefCell<LineNumber>>,
+    source_map: Rc<RefCell<SourceMap>>,
 }
 
-impl<R: Read> LinenoReader<R> {
+impl<R: Read> PositionReader<R> {
     pub fn new(inner: R) -> Self {
-        LinenoReader {
+        PositionReader {
             inner,
-            lineno: Rc::new(RefCell::new(1)),
+            source_map: Rc::new(RefCell::new(SourceMap::new())),
         }
     }
 
-    pub fn lineno_ref(&self) -> Rc<RefCell<LineNumber>> {
-        self.lineno.clone()
+    pub fn source_map(&self) -> Rc<RefCell<SourceMap>> {
+        self.source_map.clone()
+    }
+
+    /*
+     * Read and discard input up to and including the given byte
+     */
+    fn skip_past(&mut self, target: u8) {
+        let mut byte = [0u8];
+        while let Ok(1) = self.read(&mut byte) {
+            if byte[0] == target {
+                break;
+            }
+        }
     }
 }
 
-impl<R: Read> Read for LinenoReader<R> {
+impl<R: Read> Read for PositionReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let bytes_read = self.inner.read(buf)?;
-        let mut lineno = self.lineno.borrow_mut();
-        *lineno += buf[..bytes_read].iter().filter(|&&c| c == b'\n').count();
+        let mut source_map = self.source_map.borrow_mut();
+        source_map.record(&buf[..bytes_read]);
+        if bytes_read == 0 && !buf.is_empty() {
+            source_map.eof = true;
+        }
         Ok(bytes_read)
     }
 }
 
+/**
+ * Input for a push parser. Bytes are added as they arrive. Reading them
+ * returns a WouldBlock error when there are no more until the input is
+ * closed, after which it returns the end of the input.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct PushSource {
+    buffer: Rc<RefCell<PushBuffer>>,
+}
+
+/*
+ * bytes:   Input pushed but not read yet
+ * closed:  True once there is no more input to come
+ */
+#[derive(Debug, Default)]
+struct PushBuffer {
+    bytes:  VecDeque<u8>,
+    closed: bool,
+}
+
+impl PushSource {
+    pub fn new() -> Self {
+        PushSource::default()
+    }
+
+    pub fn push(&self, bytes: &[u8]) {
+        self.buffer.borrow_mut().bytes.extend(bytes);
+    }
+
+    pub fn close(&self) {
+        self.buffer.borrow_mut().closed = true;
+    }
+}
+
+impl Read for PushSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.buffer.borrow_mut();
+        if buffer.bytes.is_empty() && !buffer.closed && !buf.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::WouldBlock));
+        }
+        buffer.bytes.read(buf)
+    }
+}
+
 /*
 /*
  * xml::XmlEvent isn't clonable, so this maps to local events
@@ -248,10 +1586,10 @@ mod tests {
     use stdext::function_name;
     use std::io::{BufReader, Cursor};
 //    use xml::name::OwnedName;
-    use xml::reader::ErrorKind;
+    use xml::reader::{ErrorKind, XmlEvent};
     use xml::common::Position;
 
-    use crate::parse_item::Parser;
+    use crate::parse_item::{Parser, SourcePosition, SourceSpan};
     use crate::xml_document_error::XmlDocumentError;
 //    use crate::xml_document_error::XmlDocumentError::XmlError;
 
@@ -426,6 +1764,7 @@ mod tests {
         let mut parser = parser_new(INPUT);
 
         start_document(&mut parser);
+        comment(&mut parser);
         start_element_lookahead(&mut parser, &"schema".to_string());
 
         // top of parse_element
@@ -485,6 +1824,7 @@ mod tests {
 
         // top of parse_element
         skip(&mut parser);
+        whitespace(&mut parser);
 
         // top of loop
         start_element_lookahead(&mut parser, &"one".to_string());
@@ -501,8 +1841,9 @@ mod tests {
         whitespace(&mut parser);
         end_element(&mut parser, &"one".to_string());
         whitespace(&mut parser);
+
+        // A lookahead followed by next() returns the same item
         start_element_lookahead(&mut parser, &"four".to_string());
-        skip(&mut parser);
         start_element(&mut parser, &"four".to_string());
         whitespace(&mut parser);
         end_element(&mut parser, &"four".to_string());
@@ -513,6 +1854,315 @@ mod tests {
         println!();
     }
 
+    #[test]
+    fn test_spans() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<!DOCTYPE a [ <!ENTITY e \"<x>\"> ]>\n",
+            "<!-- comment -->\n",
+            "<a\n",
+            "   attr=\"v\">caf\u{e9} <b/><![CDATA[<c>]]><?pi data?></a>\n");
+
+        let mut parser = parser_new(INPUT);
+        let mut items = Vec::new();
+        loop {
+            let item = parser.next().unwrap();
+            let text = &INPUT[item.span.start.offset..item.span.end.offset];
+            println!("{}: {} {:?}", item.span, item.name(), text);
+            if let xml::reader::XmlEvent::EndDocument = item.event {
+                break;
+            }
+            items.push((item.name(), text.to_string(), item.span));
+        }
+
+        let expected = [
+            ("StartDocument", "<?xml version=\"1.0\"?>"),
+            ("Comment", "<!-- comment -->"),
+            ("StartElement<a>", "<a\n   attr=\"v\">"),
+            ("Characters", "caf\u{e9} "),
+            ("StartElement<b>", "<b/>"),
+            ("EndElement<b>", "<b/>"),
+            ("CData", "<![CDATA[<c>]]>"),
+            ("ProcessingInstruction", "<?pi data?>"),
+            ("EndElement<a>", "</a>"),
+        ];
+        assert_eq!(items.len(), expected.len());
+        for ((name, text, _), (expected_name, expected_text)) in items.iter().zip(expected) {
+            assert_eq!(name, expected_name);
+            assert_eq!(text, expected_text);
+        }
+
+        // Line and column of the multi-line start tag, the text and the
+        // element after the non-ASCII character
+        let a = items[2].2;
+        assert_eq!((a.start.line, a.start.column), (4, 1));
+        assert_eq!((a.end.line, a.end.column), (5, 13));
+        let text = items[3].2;
+        assert_eq!((text.start.line, text.start.column), (5, 13));
+        let b = items[4].2;
+        assert_eq!((b.start.line, b.start.column), (5, 18));
+        assert_eq!(b.start.offset, INPUT.find("<b/>").unwrap());
+    }
+
+    #[test]
+    fn test_spans_no_declaration() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = "\r\n<a>\r\n  <b>x</b>\r\n</a>";
+
+        let mut parser = parser_new(INPUT);
+        let start = parser.next().unwrap();
+        assert!(start.span.is_empty());
+
+        let a = parser.next().unwrap();
+        assert_eq!(a.span.start, SourcePosition::new(2, 1, 2));
+        assert_eq!(a.span.end, SourcePosition::new(2, 4, 5));
+        whitespace(&mut parser);
+
+        let b = parser.next().unwrap();
+        assert_eq!(b.lineno(), 3);
+        assert_eq!(b.span.start, SourcePosition::new(3, 3, 9));
+        let x = parser.next().unwrap();
+        assert_eq!(x.span.start, SourcePosition::new(3, 6, 12));
+        assert_eq!(x.span.len(), 1);
+    }
+
+    #[test]
+    fn test_normalize() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = concat!(
+            "<a xmlns:p='u\r\nv' b='1\r\n2\r3\t4&#13;&#xA;&#x9;5\r&#10;6' p:c=\"&#xd;\n\">",
+            "x\r\ny\rz&#xD;&#10;w\r&#xa;v<![CDATA[c\r\nd]]><!--e\rf--><?pi g\r\nh?></a>");
+
+        let mut parser = parser_new(INPUT);
+        start_document(&mut parser);
+        match parser.next().unwrap().event {
+            XmlEvent::StartElement { attributes, .. } => {
+                let values: Vec<&str> = attributes.iter().map(|a| a.value.as_str()).collect();
+                assert_eq!(values, ["1 2 3 4\r\n\t5 \n6", "\r "]);
+            },
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        let texts: Vec<String> = (0..4).map(|_| match parser.next().unwrap().event {
+            XmlEvent::Characters(text) | XmlEvent::CData(text) | XmlEvent::Comment(text) |
+            XmlEvent::ProcessingInstruction { data: Some(text), .. } => text,
+            other => panic!("Unexpected event: {:?}", other),
+        }).collect();
+        assert_eq!(texts, ["x\ny\nz\r\nw\n\nv", "c\nd", "e\nf", "g\nh"]);
+    }
+
+    #[test]
+    fn test_error_position() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = "<a>\n  <b>\n  </c>\n</a>\n";
+
+        let mut parser = parser_new(INPUT);
+        loop {
+            match parser.next() {
+                Ok(_) => {},
+                Err(e) => {
+                    println!("{}", e);
+                    let pos = e.position().unwrap();
+                    assert_eq!(pos.line, 3);
+                    assert_eq!(&INPUT[pos.offset - pos.column + 1..pos.offset], "  </c");
+                    break;
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_recovery_events() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = "<a xmlns:p=\"urn:p\"><p:b><c></p:b>text</a>";
+
+        let input_bytes = INPUT.as_bytes().to_vec();
+        let mut parser = Parser::with_recovery(BufReader::new(Cursor::new(input_bytes)));
+        start_document(&mut parser);
+        start_element(&mut parser, &"a".to_string());
+        start_element(&mut parser, &"b".to_string());
+        start_element(&mut parser, &"c".to_string());
+
+        // <c> is closed where </p:b> starts, then </p:b> closes <b>
+        let end_c = parser.next().unwrap();
+        assert_eq!(end_c.name(), "EndElement<c>");
+        assert!(end_c.span.is_empty());
+        assert_eq!(end_c.span.start.offset, INPUT.find("</p:b>").unwrap());
+        let end_b = parser.next().unwrap();
+        assert_eq!(end_b.name(), "EndElement<b>");
+        assert_eq!(&INPUT[end_b.span.start.offset..end_b.span.end.offset], "</p:b>");
+
+        // The prefix is still bound after the EventReader is restarted
+        let text = parser.next().unwrap();
+        assert_eq!(&INPUT[text.span.start.offset..text.span.end.offset], "text");
+        end_element(&mut parser, &"a".to_string());
+        end_document(&mut parser);
+
+        assert_eq!(parser.diagnostics().unwrap().len(), 1);
+    }
+
+    /*
+     * Push the input in chunks, reading all the events available after each
+     */
+    fn push_chunks(input: &[u8], chunk_size: usize) -> Vec<(String, SourceSpan)> {
+        let mut parser = Parser::new_push();
+        let mut events = vec!();
+        let mut chunks = input.chunks(chunk_size);
+
+        loop {
+            match parser.poll().expect("push parse failed") {
+                Some(element) => {
+                    let done = matches!(element.event, XmlEvent::EndDocument);
+                    events.push((format!("{:?}", element.event), element.span));
+                    if done {
+                        return events;
+                    }
+                },
+                None => match chunks.next() {
+                    None => parser.close(),
+                    Some(chunk) => parser.push(chunk),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_push() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!-- comment -->\n",
+            "<a xmlns:p=\"urn:p\" x=\"1 > 0\">\n",
+            "  <p:b>Gr\u{fc}\u{df}e &amp; more</p:b>\n",
+            "  <c/><![CDATA[<raw>]]>text\n",
+            "</a>\n");
+
+        // The same events and spans, whatever the chunks are
+        let mut parser = Parser::new(BufReader::new(Cursor::new(INPUT.as_bytes().to_vec())));
+        let mut expected = vec!();
+        loop {
+            let element = parser.next().unwrap();
+            let done = matches!(element.event, XmlEvent::EndDocument);
+            expected.push((format!("{:?}", element.event), element.span));
+            if done {
+                break;
+            }
+        }
+
+        for chunk_size in [1, 2, 3, 7, 64, INPUT.len()] {
+            println!("chunk size {}", chunk_size);
+            assert_eq!(push_chunks(INPUT.as_bytes(), chunk_size), expected);
+        }
+
+        // Nothing can be read until there is enough input
+        let mut parser = Parser::new_push();
+        parser.push(b"<a><b");
+        assert!(matches!(parser.poll().unwrap().unwrap().event, XmlEvent::StartDocument { .. }));
+        assert!(matches!(parser.poll().unwrap().unwrap().event, XmlEvent::StartElement { .. }));
+        assert!(parser.poll().unwrap().is_none());
+        assert!(parser.poll().unwrap().is_none());
+        parser.push(b">text</b></a");
+        let b = parser.poll().unwrap().unwrap();
+        assert_eq!((b.name(), b.span.start.offset, b.span.end.offset), ("StartElement<b>".to_string(), 3, 6));
+        assert!(matches!(parser.poll().unwrap().unwrap().event, XmlEvent::Characters(t) if t == "text"));
+        assert!(matches!(parser.poll().unwrap().unwrap().event, XmlEvent::EndElement { .. }));
+        assert!(parser.poll().unwrap().is_none());
+
+        // Errors are where they are in the whole input
+        parser.push(b">\n<");
+        parser.close();
+        assert!(matches!(parser.poll().unwrap().unwrap().event, XmlEvent::EndElement { .. }));
+        match parser.poll() {
+            Err(XmlDocumentError::XmlError(pos, _)) => assert_eq!((pos.line, pos.column), (2, 2)),
+            other => panic!("Expected XmlError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_push_doctype() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = concat!(
+            "<!DOCTYPE a [\n",
+            "  <!ENTITY e \"ent\">\n",
+            "]>\n",
+            "<a>&e;<b>&e; &e;</b></a>\n");
+
+        // Entities declared in the internal subset are still known after
+        // parsing is suspended part way through
+        let mut parser = Parser::new(BufReader::new(Cursor::new(INPUT.as_bytes().to_vec())));
+        let mut expected = vec!();
+        loop {
+            let element = parser.next().unwrap();
+            let done = matches!(element.event, XmlEvent::EndDocument);
+            expected.push((format!("{:?}", element.event), element.span));
+            if done {
+                break;
+            }
+        }
+        assert!(expected.iter().any(|(event, _)| event.contains("ent ent")));
+
+        for chunk_size in [1, 2, 3, 7, INPUT.len()] {
+            println!("chunk size {}", chunk_size);
+            assert_eq!(push_chunks(INPUT.as_bytes(), chunk_size), expected);
+        }
+
+        // Errors after the internal subset are still where they are in the
+        // whole input
+        let mut parser = Parser::new_push();
+        parser.push(&INPUT.as_bytes()[..INPUT.find("<b>").unwrap()]);
+        while parser.poll().unwrap().is_some() {}
+        parser.push(b"<b>&f;</b></a>");
+        parser.close();
+        let error = loop {
+            match parser.poll() {
+                Ok(_) => {},
+                Err(e) => break e,
+            }
+        };
+        let mut whole = String::from(INPUT);
+        whole.replace_range(INPUT.find("<b>").unwrap().., "<b>&f;</b></a>");
+        let mut parser = Parser::new(BufReader::new(Cursor::new(whole.into_bytes())));
+        let expected = loop {
+            if let Err(e) = parser.next() {
+                break e;
+            }
+        };
+        assert_eq!(error.position(), expected.position());
+        assert_eq!(error.position().unwrap().line, 4);
+    }
+
+    #[test]
+    fn test_recovery_doctype() {
+        println!("\nRunning test {}", function_name!());
+        const INPUT: &str = concat!(
+            "<!DOCTYPE a [\n",
+            "  <!ENTITY e \"ent\">\n",
+            "]>\n",
+            "<a><b></c>&e;</b>\n",
+            "<c =/><d/></a>");
+
+        let input_bytes = INPUT.as_bytes().to_vec();
+        let mut parser = Parser::with_recovery(BufReader::new(Cursor::new(input_bytes)));
+        start_document(&mut parser);
+        start_element(&mut parser, &"a".to_string());
+        start_element(&mut parser, &"b".to_string());
+
+        // The entity can still be used after the EventReader is restarted
+        let text = parser.next().unwrap();
+        assert!(matches!(&text.event, XmlEvent::Characters(t) if t == "ent"), "{:?}", text);
+        end_element(&mut parser, &"b".to_string());
+        whitespace(&mut parser);
+        start_element(&mut parser, &"d".to_string());
+        end_element(&mut parser, &"d".to_string());
+        end_element(&mut parser, &"a".to_string());
+        end_document(&mut parser);
+
+        let lines: Vec<usize> = parser.diagnostics().unwrap().iter()
+            .map(|diagnostic| diagnostic.span.unwrap().start.line)
+            .collect();
+        assert_eq!(lines, [4, 5]);
+    }
+
     fn start_element(parser: &mut Parser<BufReader<Cursor<Vec<u8>>>>, element_name: &String) {
         let element = parser.next();
         if let xml::reader::XmlEvent::StartElement { name, .. } = &element.unwrap().event {
@@ -542,6 +2192,15 @@ mod tests {
         }
     }
 
+    fn comment(parser: &mut Parser<BufReader<Cursor<Vec<u8>>>>) {
+        let element = parser.next();
+        if let xml::reader::XmlEvent::Comment(cmnt) = &element.unwrap().event {
+            print!("<!--{}-->", cmnt);
+        } else {
+            panic!("Failed to get Comment");
+        }
+    }
+
     fn start_element_lookahead(parser: &mut Parser<BufReader<Cursor<Vec<u8>>>>, element_name: &String) {
         let element = parser.lookahead();
         if let xml::reader::XmlEvent::StartElement { name, .. } = &element.unwrap().event {
@@ -0,0 +1,321 @@
+/*
+ * Parse XML text input and write it out as JSON as it is parsed, without
+ * building a tree. See json_tree for the mapping. The JSON is written
+ * without any whitespace.
+ */
+
+use serde_json::{Map, Value};
+use std::io::{BufReader, Read, Write};
+use std::marker::PhantomData;
+use xml::namespace::Namespace;
+use xml::reader::XmlEvent;
+
+use crate::element::ElementInfo;
+use crate::json_tree::{element_head, Items, JsonOptions};
+use crate::parse_doc::{Accumulator, DocumentResult, LevelInfo, ParseDoc, SharedOutput};
+use crate::parse_item::LineNumber;
+use crate::qname::QName;
+use crate::xml_document_error::XmlDocumentError;
+
+pub struct ParseJson<W: Write> {
+    writer: PhantomData<W>,
+}
+
+impl<W: Write> ParseJson<W> {
+    pub fn parse_path(
+        path: &str,
+        element_level_info: &<ParseJson<W> as ParseDoc>::LI,
+    ) -> DocumentResult<<ParseJson<W> as ParseDoc>::LI>
+    {
+        let result = Self::parse_path_base(path, element_level_info);
+        element_level_info.output.check(result)
+    }
+
+    pub fn parse<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &<ParseJson<W> as ParseDoc>::LI,
+    ) -> DocumentResult<<ParseJson<W> as ParseDoc>::LI>
+    where
+        R: Read,
+    {
+        let result = Self::parse_base(buf_reader, element_level_info);
+        element_level_info.output.check(result)
+    }
+}
+
+impl<W: Write> ParseDoc for ParseJson<W> {
+    type LI = JsonLevelInfo<W>;
+    type AC = JsonAccumulator<W>;
+}
+
+/**
+ * LevelInfo that shares the output between the levels
+ */
+pub struct JsonLevelInfo<W: Write> {
+    output: SharedOutput<W, JsonOptions>,
+}
+
+impl<W: Write> JsonLevelInfo<W> {
+    pub fn new(writer: W, options: JsonOptions) -> Self {
+        JsonLevelInfo {
+            output: SharedOutput::new(writer, options, "JSON output"),
+        }
+    }
+}
+
+impl<W: Write> LevelInfo for JsonLevelInfo<W> {
+    type AccumulatorType = JsonAccumulator<W>;
+
+    fn next_level(&self) -> Self {
+        JsonLevelInfo {
+            output: self.output.clone(),
+        }
+    }
+
+    fn create_accumulator(&self, element_info: ElementInfo) ->
+        Result<JsonAccumulator<W>, XmlDocumentError>
+    {
+        // Stop the parse at the first write error
+        self.output.error()?;
+        Ok(JsonAccumulator::new(element_info, self.output.clone()))
+    }
+}
+
+/**
+ * Accumulator that writes the element as JSON as it is parsed. The object
+ * for the element is left open while what it holds is parsed. Until
+ * something other than text turns up, it isn't known whether that goes in
+ * "$text" or "$value", so text is held back until then.
+ * element_name:            Name of the element
+ * element_lineno:          Line number of the start of the element
+ * head:                    Start of the object, with the name, namespace
+ *                          declarations and attributes, until it has been
+ *                          written
+ * items:                   What the element holds that hasn't been written
+ * started:                 True once "$value" has been started
+ * current_subelement_name: Name of the subelement being parsed, if any
+ * output:                  Where the JSON is written
+ */
+pub struct JsonAccumulator<W: Write> {
+    element_name: QName,
+    element_lineno: LineNumber,
+    head: Option<String>,
+    items: Items,
+    started: bool,
+    current_subelement_name: Option<QName>,
+    output: SharedOutput<W, JsonOptions>,
+}
+
+impl<W: Write> JsonAccumulator<W> {
+    fn new(element_info: ElementInfo, output: SharedOutput<W, JsonOptions>) -> Self {
+        let (head, items) = {
+            let mut output = output.borrow_mut();
+            let parent_namespace = output.namespaces.last().cloned()
+                .unwrap_or_else(Namespace::empty);
+            let head = Value::Object(element_head(&element_info, &parent_namespace)).to_string();
+            output.namespaces.push(element_info.namespace.clone());
+            (head, Items::new(&output.options))
+        };
+
+        JsonAccumulator {
+            element_name: element_info.qname(),
+            element_lineno: element_info.lineno(),
+            // Leave the object open
+            head: Some(head[..head.len() - 1].to_string()),
+            items,
+            started: false,
+            current_subelement_name: None,
+            output,
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if let Some(head) = self.head.take() {
+            self.output.borrow_mut().write(|writer, _| writer.write_all(head.as_bytes()));
+        }
+        self.output.borrow_mut().write(|writer, _| writer.write_all(text.as_bytes()));
+    }
+
+    /*
+     * Start the next item in "$value", starting "$value" itself if need be
+     */
+    fn next_item(&mut self) {
+        match self.started {
+            false => self.write(",\"$value\":["),
+            true => self.write(","),
+        }
+        self.started = true;
+    }
+
+    /*
+     * Write out the items that are ready
+     */
+    fn write_items(&mut self, items: Vec<Value>) {
+        for item in items {
+            self.next_item();
+            self.write(&item.to_string());
+        }
+    }
+
+    fn event(&mut self, event: XmlEvent) {
+        self.items.event(&event);
+        if let Some(items) = self.items.take() {
+            self.write_items(items);
+        }
+    }
+}
+
+impl<W: Write> Accumulator for JsonAccumulator<W> {
+    type Value = ();
+
+    fn start_subelement(&mut self, element_info: &ElementInfo) {
+        self.items.subelement();
+        let items = self.items.take().unwrap_or_default();
+        self.write_items(items);
+        self.next_item();
+        self.current_subelement_name = Some(element_info.qname());
+    }
+
+    fn add_subelement(&mut self, _subelement: ()) {
+        // Subelements have already been written
+    }
+
+    fn end_subelement(&mut self) {
+        self.current_subelement_name = None;
+    }
+
+    fn has_open_subelement(&self) -> bool {
+        self.current_subelement_name.is_some()
+    }
+
+    fn current_subelement_name(&self) -> Option<&QName> {
+        self.current_subelement_name.as_ref()
+    }
+
+    fn finish(mut self) {
+        let mut rest = Map::new();
+        self.items.finish(&mut rest);
+
+        match (rest.remove("$value"), rest.remove("$text")) {
+            (Some(Value::Array(items)), _) => {
+                self.write_items(items);
+                if !self.started {
+                    self.write(",\"$value\":[");
+                }
+                self.write("]}");
+            },
+            (_, Some(text)) => self.write(&format!(",\"$text\":{}}}", text)),
+            _ => self.write("}"),
+        }
+        self.output.borrow_mut().namespaces.pop();
+    }
+
+    fn element_name(&self) -> &QName {
+        &self.element_name
+    }
+
+    fn element_lineno(&self) -> LineNumber {
+        self.element_lineno
+    }
+
+    fn characters(&mut self, characters: &str) {
+        self.event(XmlEvent::Characters(characters.to_string()));
+    }
+
+    fn whitespace(&mut self, whitespace: &str) {
+        self.event(XmlEvent::Whitespace(whitespace.to_string()));
+    }
+
+    fn cdata(&mut self, cdata: &str) {
+        self.event(XmlEvent::CData(cdata.to_string()));
+    }
+
+    fn comment(&mut self, comment: &str) {
+        self.event(XmlEvent::Comment(comment.to_string()));
+    }
+
+    fn processing_instruction(&mut self, name: &str, data: Option<&str>) {
+        self.event(XmlEvent::ProcessingInstruction {
+            name: name.to_string(),
+            data: data.map(|d| d.to_string()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use serde_json::{json, Value};
+    use std::fs::File;
+    use std::io::{BufReader, Cursor, Read};
+
+    use crate::json_tree::{to_json, JsonOptions};
+    use crate::parse_tree::parse_document;
+
+    use super::{JsonLevelInfo, ParseJson};
+
+    /*
+     * Convert while parsing and by building a tree, checking that both
+     * give the same result
+     */
+    fn convert(input: &[u8], options: &JsonOptions) -> Value {
+        let mut streamed = Vec::new();
+        let level_info = JsonLevelInfo::new(&mut streamed, options.clone());
+        ParseJson::parse(BufReader::new(Cursor::new(input)), &level_info)
+            .expect("conversion failed");
+        drop(level_info);
+        let streamed: Value = serde_json::from_slice(&streamed).expect("output not JSON");
+
+        let (_, root) = parse_document(input).expect("parse failed");
+        assert_eq!(streamed, to_json(root.as_ref(), options));
+        streamed
+    }
+
+    #[test]
+    fn test_stream() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<!-- before -->\n",
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\" p:y=\"2\">\n",
+            "  <p:b>one<![CDATA[<x>]]><!-- inside -->two</p:b>\n",
+            "  <c xmlns=\"\"><p:d/> \"quoted\" </c>\n",
+            "  <e>  </e><e/>\n",
+            "</a>\n");
+
+        assert_eq!(convert(input.as_bytes(), &JsonOptions::default()), json!({
+            "$name": "a", "@xmlns": "urn:a", "@xmlns:p": "urn:p", "@p:y": "2",
+            "$value": [
+                {"$name": "p:b", "$text": "one<x>two"},
+                {"$name": "c", "@xmlns": "", "$value": [{"$name": "p:d"}, " \"quoted\" "]},
+                {"$name": "e"},
+                {"$name": "e"},
+            ],
+        }));
+
+        let options = JsonOptions { whitespace: true, comments: true };
+        let json = convert(input.as_bytes(), &options);
+        assert_eq!(json["$value"][1], json!({"$name": "p:b",
+            "$value": ["one<x>", {"$comment": " inside "}, "two"]}));
+        assert_eq!(json["$value"][5], json!({"$name": "e", "$text": "  "}));
+    }
+
+    #[test]
+    fn test_schema() {
+        println!("Running test {}", function_name!());
+        let mut input = Vec::new();
+        File::open("schema/SpaceSystem-patched.xsd")
+            .expect("Unable to open SpaceSystem-patched.xsd")
+            .read_to_end(&mut input)
+            .unwrap();
+        let json = convert(&input, &JsonOptions::default());
+        assert_eq!(json["$name"], "schema");
+        assert_eq!(json["@targetNamespace"], "http://www.omg.org/spec/XTCE/20180204");
+        let complex_types = json["$value"].as_array().unwrap().iter()
+            .filter(|item| item["$name"] == "complexType")
+            .count();
+        assert_eq!(complex_types, 281);
+
+        convert(&input, &JsonOptions { whitespace: true, comments: true });
+    }
+}
@@ -3,7 +3,6 @@
  */
 use std::fmt;
 use std::io::{BufReader, Read};
-use std::ops::{ControlFlow, FromResidual, Try};
 use xml::name::OwnedName;
 use xml::reader::XmlEvent;
 
@@ -12,7 +11,8 @@ use crate::element::{Element, ElementInfo, element_info_display};
 use crate::misc::{nl_indent, owned_name_display, vec_display, XmlDisplay};
 use crate::parse_item::LineNumber;
 pub use crate::xml_document_error::XmlDocumentError;
-use crate::parse_doc::{Accumulator, LevelInfo, ParseDoc};
+use crate::parse_doc::{Accumulator, DocumentResult, LevelInfo, ParseDoc};
+use crate::qname::QName;
 use crate::document::DocumentInfo;
 
 const TREE_DEPTH: usize = 2;
@@ -32,7 +32,7 @@ pub struct ParseSchemaParams<'a> {
     pub schema_name:    &'a str,
 }
 
-impl<'a> ParseSchema {
+impl ParseSchema {
     pub fn new(document_info: DocumentInfo, root: Box<dyn Element>) -> Self {
         ParseSchema {
             document_info,
@@ -40,14 +40,14 @@ impl<'a> ParseSchema {
         }
     }
 
-    pub fn parse_path<'b> (
+    pub fn parse_path(
         params:             &ParseSchemaParams,
-        path:               &'b str,
+        path:               &str,
         element_level_info: &<ParseSchema as ParseDoc>::LI,
-    ) -> Result<(DocumentInfo, <<<ParseSchema as ParseDoc>::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    ) -> DocumentResult<<ParseSchema as ParseDoc>::LI>
     {
         // FIXME: check for error
-        let _ = Self::display_start(&params);
+        let _ = Self::display_start(params);
         let res = Self::parse_path_base(path, element_level_info)?;
         Self::display_end();
         Ok(res)
@@ -57,12 +57,12 @@ impl<'a> ParseSchema {
         params:             &ParseSchemaParams,
         buf_reader:         BufReader<R>,
         element_level_info: &<ParseSchema as ParseDoc>::LI,
-    ) -> Result<(DocumentInfo, <<<ParseSchema as ParseDoc>::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    ) -> DocumentResult<<ParseSchema as ParseDoc>::LI>
     where
         R: Read,
     {
         // FIXME: check for error
-        let _ = Self::display_start(&params);
+        let _ = Self::display_start(params);
         let res = Self::parse_base(buf_reader, element_level_info)?;
         Self::display_end();
         Ok(res)
@@ -140,7 +140,7 @@ impl<'a> ParseSchema {
     }
 }
 
-impl<'a> ParseDoc for ParseSchema {
+impl ParseDoc for ParseSchema {
     type LI = SchemaLevelInfo;
     type AC = SchemaAccumulator;
 }
@@ -227,20 +227,6 @@ fn back_matter_display(f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
 
 */
 
-impl<'a> Try for ParseSchema 
-{
-    type Output = <<ParseSchema as ParseDoc>::AC as Accumulator>::Value;
-    type Residual = XmlDocumentError;
-    fn from_output(_: <Self as Try>::Output) -> Self
-    { todo!() }
-    fn branch(self) -> ControlFlow<<Self as Try>::Residual, <Self as Try>::Output>
-    { todo!() }
-}
-
-impl<'a> FromResidual for ParseSchema {
-    fn from_residual(_: <ParseSchema as Try>::Residual) -> Self
-    { todo!() }
-}
 
 /// LevelInfo that tracks depth for indented output
 #[derive(Debug, Clone)]
@@ -249,7 +235,7 @@ pub struct SchemaLevelInfo {
 }
 
 impl SchemaLevelInfo {
-    pub fn new(_schema: &Box<dyn Element>) -> Self {
+    pub fn new(_schema: &dyn Element) -> Self {
         SchemaLevelInfo { depth: 0 }
     }
 }
@@ -271,10 +257,10 @@ impl LevelInfo for SchemaLevelInfo {
 /// Accumulator that just echoes structure (doesn't build elements)
 pub struct SchemaAccumulator {
     element: SchemaElement,
-    element_name: String,
+    element_name: QName,
     element_lineno: LineNumber,
     depth: usize,
-    current_subelement_name: Option<String>,
+    current_subelement_name: Option<QName>,
 }
 
 impl SchemaAccumulator {
@@ -286,9 +272,9 @@ impl SchemaAccumulator {
         SchemaAccumulator {
             element,
             // FIXME: should use element.name()
-            element_name: element_info.owned_name.local_name.clone(),
-            element_lineno: element_info.lineno,
-            depth: depth,
+            element_name: element_info.qname(),
+            element_lineno: element_info.lineno(),
+            depth,
             current_subelement_name: None,
         }
     }
@@ -301,9 +287,7 @@ impl Accumulator for SchemaAccumulator {
      * Note that we have started a sublement
      */
     fn start_subelement(&mut self, element_info: &ElementInfo) {
-        // FIXME: probably needs to be fully qualified
-        // FIXME: propagate to other parse_.*() code
-        self.current_subelement_name = Some(element_info.owned_name.local_name.clone());
+        self.current_subelement_name = Some(element_info.qname());
     }
     
     fn add_subelement(&mut self, _subelement: ()) {
@@ -323,18 +307,16 @@ impl Accumulator for SchemaAccumulator {
         self.current_subelement_name.is_some()
     }
     
-    fn current_subelement_name(&self) -> &str {
+    fn current_subelement_name(&self) -> Option<&QName> {
         self.current_subelement_name.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("")
     }
     
-    fn finish(self) -> () {
+    fn finish(self) {
         // FIXME: return error
         let _ = self.element.display_end(self.depth);
     }
     
-    fn element_name(&self) -> &str {
+    fn element_name(&self) -> &QName {
         &self.element_name
     }
     
@@ -343,7 +325,7 @@ impl Accumulator for SchemaAccumulator {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct SchemaElement {
     pub element_info:   ElementInfo,
     pub depth:          usize,
@@ -386,8 +368,8 @@ impl SchemaElement {
         owned_name_display(f, depth1, &owned_name)?;
 
         let element_info = ElementInfo {
-            lineno:     0,
-            owned_name: owned_name,
+            owned_name,
+            ..Default::default()
         };
         element_info_display(f, depth1, &element_info)?;
         write!(f, "{}", nl_indent(depth1))?;
@@ -413,26 +395,6 @@ impl SchemaElement {
     }
 }
 
-impl Default for SchemaElement {
-    fn default() -> SchemaElement {
-        SchemaElement {
-            element_info: ElementInfo {
-                owned_name: OwnedName {
-                    local_name: "".to_string(),
-                    namespace:  None,
-                    prefix:     None
-                },
-                lineno:     0,
-            },
-            depth: 0,
-            subelements: vec!(),
-            before_element: vec!(),
-            content: vec!(),
-            after_element: vec!(),
-        }
-    }
-}
-
 impl fmt::Display for SchemaElement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.display(f, self.depth)
@@ -458,17 +420,9 @@ impl Element for SchemaElement {
      * Find a subelement (one level deeper) with the given name
      */
     fn get(&self, name: &str) -> Option<&dyn Element> {
-println!("get: looking for {} in {}", name, self.name());
-println!("...");
-for x in self.subelements() {
-    println!(" {}", x);
-}
         self.subelements()
             .iter()
-            .find(|&x| {
-                println!("get: is {} == {}", x.name(), name);
-                x.name() == name
-            })
+            .find(|&x| x.name() == name)
             .map(|v| &**v)
     }
 
@@ -481,7 +435,15 @@ for x in self.subelements() {
     }
 
     fn lineno(&self) -> LineNumber {
-        self.element_info.lineno
+        self.element_info.lineno()
+    }
+
+    fn element_info(&self) -> &ElementInfo {
+        &self.element_info
+    }
+
+    fn element_info_mut(&mut self) -> &mut ElementInfo {
+        &mut self.element_info
     }
 
     /**
@@ -497,6 +459,30 @@ for x in self.subelements() {
     fn subelements_mut<'b>(&'b mut self) -> &'b mut Vec<Box<dyn Element + 'static>> {
         &mut self.subelements
     }
+
+    fn before_element(&self) -> &Vec<XmlEvent> {
+        &self.before_element
+    }
+
+    fn before_element_mut(&mut self) -> &mut Vec<XmlEvent> {
+        &mut self.before_element
+    }
+
+    fn content(&self) -> &Vec<XmlEvent> {
+        &self.content
+    }
+
+    fn content_mut(&mut self) -> &mut Vec<XmlEvent> {
+        &mut self.content
+    }
+
+    fn after_element(&self) -> &Vec<XmlEvent> {
+        &self.after_element
+    }
+
+    fn after_element_mut(&mut self) -> &mut Vec<XmlEvent> {
+        &mut self.after_element
+    }
 }
 
 impl XmlDisplay for SchemaElement {
@@ -506,12 +492,12 @@ impl XmlDisplay for SchemaElement {
             .expect("Unable to write Box::new");
 
         let element_info = ElementInfo {
-            lineno: 0,
             owned_name: OwnedName {
                         local_name: self.name().to_string(),
                         namespace:  None,
                         prefix:     None,
             },
+            ..Default::default()
         };
 
         owned_name_display(f, depth + 1, &element_info.owned_name)?;
@@ -1,10 +1,9 @@
-/**
+/*
  * Parse XML text input and produce an XML tree
  */
 
 use std::fmt;
-use std::io::{BufReader, Read};
-use std::ops::{ControlFlow, FromResidual, Try};
+use std::io::{self, BufReader, Read, Write};
 use xml::name::OwnedName;
 use xml::reader::XmlEvent;
 
@@ -12,8 +11,11 @@ use crate::element::{element_info_display, Element, ElementInfo};
 use crate::misc::{nl_indent, owned_name_display, vec_display, XmlDisplay};
 use crate::parse_item::LineNumber;
 pub use crate::xml_document_error::XmlDocumentError;
-use crate::parse_doc::{Accumulator, LevelInfo, ParseDoc};
+use crate::parse_doc::{Accumulator, DocumentResult, DocumentStream, Fragment, LevelInfo,
+    ParseDoc, PushDocument, RecoveredDocument};
+use crate::qname::QName;
 use crate::document::DocumentInfo;
+use crate::write_tree::write_document;
 
 pub struct ParseTree {
     pub document_info:  DocumentInfo,
@@ -28,10 +30,17 @@ impl ParseTree {
         }
     }
 
-    pub fn parse_path<'b>(
-        path: &'b str,
+    /*
+     * Write the document out as XML
+     */
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_document(writer, &self.document_info, self.root.as_ref())
+    }
+
+    pub fn parse_path(
+        path: &str,
         element_level_info: &<ParseTree as ParseDoc>::LI,
-    ) -> Result<(DocumentInfo, <<<ParseTree as ParseDoc>::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    ) -> DocumentResult<<ParseTree as ParseDoc>::LI>
     {
         Self::parse_path_base(path, element_level_info)
     }
@@ -39,12 +48,87 @@ impl ParseTree {
     pub fn parse<R>(
         buf_reader: BufReader<R>,
         element_level_info: &<ParseTree as ParseDoc>::LI,
-    ) -> Result<(DocumentInfo, <<<ParseTree as ParseDoc>::LI as LevelInfo>::AccumulatorType as Accumulator>::Value), XmlDocumentError>
+    ) -> DocumentResult<<ParseTree as ParseDoc>::LI>
     where
         R: Read,
     {
         Self::parse_base(buf_reader, element_level_info)
     }
+
+    /*
+     * Parse a file, carrying on after errors to build as much of the tree
+     * as possible
+     */
+    pub fn parse_path_recovering(
+        path: &str,
+        element_level_info: &<ParseTree as ParseDoc>::LI,
+    ) -> RecoveredDocument<<ParseTree as ParseDoc>::LI>
+    {
+        Self::parse_path_recovering_base(path, element_level_info)
+    }
+
+    pub fn parse_recovering<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &<ParseTree as ParseDoc>::LI,
+    ) -> RecoveredDocument<<ParseTree as ParseDoc>::LI>
+    where
+        R: Read,
+    {
+        Self::parse_recovering_base(buf_reader, element_level_info)
+    }
+
+    /*
+     * Parse an XML fragment, returning a tree for each top level element
+     */
+    pub fn parse_fragment<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &<ParseTree as ParseDoc>::LI,
+    ) -> Fragment<'_, ParseTree, R>
+    where
+        R: Read,
+    {
+        Self::parse_fragment_base(buf_reader, element_level_info)
+    }
+
+    /*
+     * Parse a document from input pushed in as it arrives
+     */
+    pub fn parse_push(
+        element_level_info: &<ParseTree as ParseDoc>::LI,
+    ) -> PushDocument<'_, ParseTree>
+    {
+        Self::parse_push_base(element_level_info)
+    }
+
+    /*
+     * Parse a series of XML documents, returning a tree for each
+     */
+    pub fn parse_stream<R>(
+        buf_reader: BufReader<R>,
+        element_level_info: &<ParseTree as ParseDoc>::LI,
+    ) -> DocumentStream<'_, ParseTree, R>
+    where
+        R: Read,
+    {
+        Self::parse_stream_base(buf_reader, element_level_info)
+    }
+}
+
+/*
+ * Parse a string into a document, for tests
+ */
+#[cfg(test)]
+pub(crate) fn parse_document(input: impl AsRef<[u8]>) -> DocumentResult<TreeLevelInfo> {
+    ParseTree::parse(BufReader::new(io::Cursor::new(input.as_ref())), &TreeLevelInfo::new())
+}
+
+/*
+ * Parse a string into a tree, for tests
+ */
+#[cfg(test)]
+pub(crate) fn parse(input: &str) -> Box<dyn Element> {
+    let (_, root) = parse_document(input).expect("parse failed");
+    root
 }
 
 impl ParseDoc for ParseTree {
@@ -82,20 +166,6 @@ impl fmt::Debug for ParseTree {
     }
 }
 
-impl Try for ParseTree
-{
-    type Output = <<ParseTree as ParseDoc>::AC as Accumulator>::Value;
-    type Residual = XmlDocumentError;
-    fn from_output(_: <Self as Try>::Output) -> Self
-    { todo!() }
-    fn branch(self) -> ControlFlow<<Self as Try>::Residual, <Self as Try>::Output>
-    { todo!() }
-}
-
-impl FromResidual for ParseTree {
-    fn from_residual(_: <ParseTree as Try>::Residual) -> Self
-    { todo!() }
-}
 
 /// LevelInfo that doesn't track depth - just creates tree nodes
 #[derive(Debug, Clone)]
@@ -107,18 +177,32 @@ impl TreeLevelInfo {
     }
 }
 
+impl Default for TreeLevelInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Accumulator that builds actual element tree
+/// pending:    Text, comments and processing instructions seen since the
+///             last subelement. These become the before_element items of the
+///             next subelement or, at the end, the content of this element.
 pub struct TreeAccumulator {
     element: TreeElement,
-    current_subelement_name: Option<String>,
+    element_name: QName,
+    current_subelement_name: Option<QName>,
+    pending: Vec<XmlEvent>,
 }
 
 impl TreeAccumulator {
     pub fn new(element_info: ElementInfo) -> Self {
+        let element_name = element_info.qname();
         let element = TreeElement::new(element_info, vec![], vec![], vec![], vec![]);
         TreeAccumulator {
             element,
+            element_name,
             current_subelement_name: None,
+            pending: vec![],
         }
     }
 }
@@ -128,12 +212,13 @@ impl Accumulator for TreeAccumulator {
 
     fn start_subelement(&mut self, element_info: &ElementInfo) {
         // We'll set the name when we get the actual subelement
-        self.current_subelement_name = Some(element_info.owned_name.local_name.clone());
+        self.current_subelement_name = Some(element_info.qname());
 
     }
     
-    fn add_subelement(&mut self, subelement: Box<dyn Element>) {
-        self.current_subelement_name = Some(subelement.name().to_string());
+    fn add_subelement(&mut self, mut subelement: Box<dyn Element>) {
+        self.current_subelement_name = Some(subelement.qname());
+        subelement.before_element_mut().append(&mut self.pending);
         self.element.subelements_mut().push(subelement);
     }
     
@@ -145,26 +230,56 @@ impl Accumulator for TreeAccumulator {
         self.current_subelement_name.is_some()
     }
     
-    fn current_subelement_name(&self) -> &str {
+    fn current_subelement_name(&self) -> Option<&QName> {
         self.current_subelement_name.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("")
     }
     
-    fn finish(self) -> Box<dyn Element> {
+    fn finish(mut self) -> Box<dyn Element> {
+        self.element.content.append(&mut self.pending);
         Box::new(self.element)
     }
     
-    fn element_name(&self) -> &str {
-        self.element.name()
+    fn element_name(&self) -> &QName {
+        &self.element_name
     }
     
     fn element_lineno(&self) -> LineNumber {
         self.element.lineno()
     }
+
+    fn characters(&mut self, characters: &str) {
+        self.pending.push(XmlEvent::Characters(characters.to_string()));
+    }
+
+    fn whitespace(&mut self, whitespace: &str) {
+        self.pending.push(XmlEvent::Whitespace(whitespace.to_string()));
+    }
+
+    fn cdata(&mut self, cdata: &str) {
+        self.pending.push(XmlEvent::CData(cdata.to_string()));
+    }
+
+    fn comment(&mut self, comment: &str) {
+        self.pending.push(XmlEvent::Comment(comment.to_string()));
+    }
+
+    fn processing_instruction(&mut self, name: &str, data: Option<&str>) {
+        self.pending.push(XmlEvent::ProcessingInstruction {
+            name: name.to_string(),
+            data: data.map(|d| d.to_string()),
+        });
+    }
+
+    fn prolog(&mut self, event: &XmlEvent) {
+        self.element.before_element.push(event.clone());
+    }
+
+    fn epilog(&mut self, event: &XmlEvent) {
+        self.element.after_element.push(event.clone());
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct TreeElement {
     pub element_info: ElementInfo,
     pub before_element: Vec<XmlEvent>,
@@ -189,25 +304,6 @@ impl TreeElement {
     }
 }
 
-impl Default for TreeElement {
-    fn default() -> TreeElement {
-        TreeElement {
-            element_info: ElementInfo {
-                owned_name: OwnedName {
-                    local_name: "".to_string(),
-                    namespace:  None,
-                    prefix:     None
-                },
-                lineno:     0,
-            },
-            subelements: vec!(),
-            before_element: vec!(),
-            content: vec!(),
-            after_element: vec!(),
-        }
-    }
-}
-
 impl fmt::Display for TreeElement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.display(f, 0)
@@ -234,8 +330,8 @@ impl Element for TreeElement {
         owned_name_display(f, depth + 1, &owned_name)?;
 
         let element_info = ElementInfo {
-            lineno:     0,
-            owned_name: owned_name,
+            owned_name,
+            ..Default::default()
         };
         element_info_display(f, depth + 1, &element_info)?;
         write!(f, "{}", nl_indent(depth + 1))?;
@@ -256,17 +352,9 @@ impl Element for TreeElement {
      * Find a subelement (one level deeper) with the given name
      */
     fn get(&self, name: &str) -> Option<&dyn Element> {
-println!("get: looking for {} in {}", name, self.name());
-println!("...");
-for x in self.subelements() {
-    println!(" {}", x);
-}
         self.subelements()
             .iter()
-            .find(|&x| {
-                println!("get: is {} == {}", x.name(), name);
-                x.name() == name
-            })
+            .find(|&x| x.name() == name)
             .map(|v| &**v)
     }
 
@@ -279,7 +367,15 @@ for x in self.subelements() {
     }
 
     fn lineno(&self) -> LineNumber {
-        self.element_info.lineno
+        self.element_info.lineno()
+    }
+
+    fn element_info(&self) -> &ElementInfo {
+        &self.element_info
+    }
+
+    fn element_info_mut(&mut self) -> &mut ElementInfo {
+        &mut self.element_info
     }
 
     /**
@@ -295,6 +391,30 @@ for x in self.subelements() {
     fn subelements_mut<'b>(&'b mut self) -> &'b mut Vec<Box<dyn Element + 'static>> {
         &mut self.subelements
     }
+
+    fn before_element(&self) -> &Vec<XmlEvent> {
+        &self.before_element
+    }
+
+    fn before_element_mut(&mut self) -> &mut Vec<XmlEvent> {
+        &mut self.before_element
+    }
+
+    fn content(&self) -> &Vec<XmlEvent> {
+        &self.content
+    }
+
+    fn content_mut(&mut self) -> &mut Vec<XmlEvent> {
+        &mut self.content
+    }
+
+    fn after_element(&self) -> &Vec<XmlEvent> {
+        &self.after_element
+    }
+
+    fn after_element_mut(&mut self) -> &mut Vec<XmlEvent> {
+        &mut self.after_element
+    }
 }
 
 impl XmlDisplay for TreeElement {
@@ -304,12 +424,12 @@ impl XmlDisplay for TreeElement {
             .expect("Unable to write Box::new");
 
         let element_info = ElementInfo {
-            lineno: 0,
             owned_name: OwnedName {
                         local_name: self.name().to_string(),
                         namespace:  None,
                         prefix:     None,
             },
+            ..Default::default()
         };
 
         owned_name_display(f, depth + 1, &element_info.owned_name)?;
@@ -1,14 +1,14 @@
-/**
+/*
  * Parse XML text input and produce an XML echo
  */
 
 use std::fmt;
-use std::ops::{ControlFlow, FromResidual, Try};
 
 use crate::element::{Element, ElementInfo};
 use crate::parse_item::LineNumber;
 pub use crate::xml_document_error::XmlDocumentError;
 use crate::parse_doc::{Accumulator, LevelInfo, ParseDoc};
+use crate::qname::QName;
 use crate::document::DocumentInfo;
 
 pub struct ParseXsd {
@@ -47,20 +47,6 @@ impl fmt::Debug for ParseXsd {
     }
 }
 
-impl Try for ParseXsd
-{
-    type Output = <<ParseXsd as ParseDoc>::AC as Accumulator>::Value;
-    type Residual = XmlDocumentError;
-    fn from_output(_: <Self as Try>::Output) -> Self
-    { todo!() }
-    fn branch(self) -> ControlFlow<<Self as Try>::Residual, <Self as Try>::Output>
-    { todo!() }
-}
-
-impl FromResidual for ParseXsd {
-    fn from_residual(_: <ParseXsd as Try>::Residual) -> Self
-    { todo!() }
-}
 
 /// LevelInfo that tracks depth for indented output
 #[derive(Debug, Clone)]
@@ -70,10 +56,10 @@ pub struct XsdLevelInfo {
 }
 
 impl XsdLevelInfo {
-    pub fn new(root: &Box<dyn Element>) -> Self {
+    pub fn new(root: &(dyn Element + 'static)) -> Self {
         XsdLevelInfo {
             depth:      0,
-            element:    root.clone(),
+            element:    dyn_clone::clone_box(root),
         }
     }
 }
@@ -98,17 +84,17 @@ impl LevelInfo for XsdLevelInfo {
 
 /// Accumulator that just echoes structure (doesn't build elements)
 pub struct XsdAccumulator {
-    element_name: String,
+    element_name: QName,
     element_lineno: LineNumber,
     depth: usize,
-    current_subelement_name: Option<String>,
+    current_subelement_name: Option<QName>,
 }
 
 impl XsdAccumulator {
     pub fn new(element_info: ElementInfo, depth: usize) -> Self {
         XsdAccumulator {
-            element_name: element_info.owned_name.local_name.clone(),
-            element_lineno: element_info.lineno,
+            element_name: element_info.qname(),
+            element_lineno: element_info.lineno(),
             depth,
             current_subelement_name: None,
         }
@@ -129,7 +115,7 @@ impl Accumulator for XsdAccumulator {
     
     fn end_subelement(&mut self) {
         if let Some(name) = &self.current_subelement_name {
-            println!("{}</{}>", "  ".repeat(self.depth + 1), name);
+            println!("{}</{}>", "  ".repeat(self.depth + 1), name.local_name);
         }
         self.current_subelement_name = None;
     }
@@ -138,18 +124,15 @@ impl Accumulator for XsdAccumulator {
         self.current_subelement_name.is_some()
     }
     
-    fn current_subelement_name(&self) -> &str {
+    fn current_subelement_name(&self) -> Option<&QName> {
         self.current_subelement_name.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("")
     }
     
-    fn finish(self) -> () {
-        println!("{}</{}>", "  ".repeat(self.depth), self.element_name);
-        ()
+    fn finish(self) {
+        println!("{}</{}>", "  ".repeat(self.depth), self.element_name.local_name);
     }
     
-    fn element_name(&self) -> &str {
+    fn element_name(&self) -> &QName {
         &self.element_name
     }
     
@@ -0,0 +1,117 @@
+/*
+ * Expanded XML names. A prefix is only shorthand for a namespace URI, so
+ * two names are the same if they have the same namespace URI and local name,
+ * whatever prefixes were used to write them.
+ */
+
+use serde::Serialize;
+use std::fmt;
+use xml::name::OwnedName;
+
+/*
+ * Namespaces for the vocabularies this crate works with
+ */
+pub const XSD_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema";
+pub const XTCE_NAMESPACE: &str = "http://www.omg.org/spec/XTCE/20180204";
+
+/**
+ * Namespace-qualified name
+ * namespace:   Namespace URI, or None if the name is in no namespace
+ * local_name:  Name without any prefix
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct QName {
+    pub namespace:  Option<String>,
+    pub local_name: String,
+}
+
+impl QName {
+    pub fn new(namespace: Option<&str>, local_name: &str) -> QName {
+        QName {
+            namespace:  namespace.filter(|uri| !uri.is_empty()).map(|uri| uri.to_string()),
+            local_name: local_name.to_string(),
+        }
+    }
+
+    /*
+     * Name in no namespace
+     */
+    pub fn local(local_name: &str) -> QName {
+        QName::new(None, local_name)
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub fn local_name(&self) -> &str {
+        &self.local_name
+    }
+
+    /*
+     * Check for a name in the given namespace
+     */
+    pub fn is(&self, namespace: &str, local_name: &str) -> bool {
+        self.namespace() == Some(namespace) && self.local_name == local_name
+    }
+}
+
+impl From<&OwnedName> for QName {
+    fn from(owned_name: &OwnedName) -> QName {
+        QName::new(owned_name.namespace.as_deref(), &owned_name.local_name)
+    }
+}
+
+impl From<OwnedName> for QName {
+    fn from(owned_name: OwnedName) -> QName {
+        QName::from(&owned_name)
+    }
+}
+
+/*
+ * Names in a namespace are printed in Clark notation, e.g.
+ * {http://www.w3.org/2001/XMLSchema}element
+ */
+impl fmt::Display for QName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.namespace {
+            None => write!(f, "{}", self.local_name),
+            Some(uri) => write!(f, "{{{}}}{}", uri, self.local_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use xml::name::OwnedName;
+
+    use super::{QName, XSD_NAMESPACE};
+
+    #[test]
+    fn test_qname() {
+        println!("Running test {}", function_name!());
+        let xs: OwnedName = OwnedName {
+            local_name: "element".to_string(),
+            namespace:  Some(XSD_NAMESPACE.to_string()),
+            prefix:     Some("xs".to_string()),
+        };
+        let default: OwnedName = OwnedName {
+            local_name: "element".to_string(),
+            namespace:  Some(XSD_NAMESPACE.to_string()),
+            prefix:     None,
+        };
+
+        // The prefix doesn't matter, only the namespace
+        assert_eq!(QName::from(&xs), QName::from(&default));
+        assert_ne!(QName::from(&xs), QName::local("element"));
+        assert!(QName::from(&xs).is(XSD_NAMESPACE, "element"));
+
+        // An empty namespace URI is no namespace
+        assert_eq!(QName::new(Some(""), "a"), QName::local("a"));
+
+        assert_eq!(QName::from(&xs).to_string(),
+            "{http://www.w3.org/2001/XMLSchema}element");
+        assert_eq!(QName::local("a").to_string(), "a");
+    }
+}
@@ -0,0 +1,545 @@
+/*
+ * Graph of the references between the components of XML schemas. Each
+ * top-level type, element, attribute, group and attribute group is a
+ * node, and each type, base, ref or substitutionGroup attribute is an
+ * edge from the top-level component it appears in to the component it
+ * names. Components that are named but not defined, such as the built-in
+ * XSD types or those from imported schemas, are nodes with no definition.
+ *
+ * Extension and Restriction edges are only used for the derivation of a
+ * named type itself. A base within an anonymous type, such as the type of
+ * a local element, is a Type edge, so that the derivation hierarchy only
+ * holds named types.
+ */
+
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::{Dfs, EdgeRef};
+use petgraph::Direction;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::element::{Element, ElementInfo};
+use crate::parse_item::LineNumber;
+use crate::parse_tree::{ParseTree, TreeLevelInfo};
+use crate::qname::{QName, XSD_NAMESPACE};
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * Symbol spaces of schema components. Simple and complex types share a
+ * symbol space, so both are Type.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ComponentKind {
+    Type,
+    Element,
+    Attribute,
+    Group,
+    AttributeGroup,
+}
+
+impl ComponentKind {
+    /*
+     * Kind of component defined by a top-level schema element, if any
+     */
+    fn defined_by(local_name: &str) -> Option<ComponentKind> {
+        match local_name {
+            "complexType" | "simpleType" => Some(ComponentKind::Type),
+            "element" => Some(ComponentKind::Element),
+            "attribute" => Some(ComponentKind::Attribute),
+            "group" => Some(ComponentKind::Group),
+            "attributeGroup" => Some(ComponentKind::AttributeGroup),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Where a component is defined
+ * element:     Local name of the schema element defining it, e.g.
+ *              "complexType"
+ * lineno:      Line number of the definition
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Definition {
+    pub element:    String,
+    pub lineno:     LineNumber,
+}
+
+/**
+ * A node in the graph
+ * kind:        Symbol space of the component
+ * name:        Name of the component
+ * definition:  Where it is defined, or None if it is only referred to
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaComponent {
+    pub kind:       ComponentKind,
+    pub name:       QName,
+    pub definition: Option<Definition>,
+}
+
+impl SchemaComponent {
+    pub fn is_complex_type(&self) -> bool {
+        self.definition.as_ref().is_some_and(|definition| definition.element == "complexType")
+    }
+}
+
+impl fmt::Display for SchemaComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {}", self.kind, self.name)
+    }
+}
+
+/**
+ * How one component refers to another
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ReferenceKind {
+    /// type attribute, or base within an anonymous type
+    Type,
+    /// base of an extension deriving a named type
+    Extension,
+    /// base of a restriction deriving a named type
+    Restriction,
+    /// ref attribute
+    Ref,
+    /// substitutionGroup attribute
+    SubstitutionGroup,
+}
+
+impl ReferenceKind {
+    pub fn is_derivation(&self) -> bool {
+        matches!(self, ReferenceKind::Extension | ReferenceKind::Restriction)
+    }
+}
+
+/**
+ * An edge in the graph
+ * kind:        How the component is referred to
+ * lineno:      Line number of the element with the reference
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    pub kind:   ReferenceKind,
+    pub lineno: LineNumber,
+}
+
+/**
+ * References between schema components
+ * graph:       The components and references
+ * nodes:       Node for each component, by kind and name
+ */
+#[derive(Debug, Default)]
+pub struct SchemaGraph {
+    graph:  DiGraph<SchemaComponent, Reference>,
+    nodes:  HashMap<(ComponentKind, QName), NodeIndex>,
+}
+
+impl SchemaGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Build the graph for a schema file
+     */
+    pub fn from_path(path: &str) -> Result<Self, XmlDocumentError> {
+        let (_, root) = ParseTree::parse_path(path, &TreeLevelInfo::new())?;
+        let mut schema_graph = SchemaGraph::new();
+        schema_graph.add_schema(root.as_ref())?;
+        Ok(schema_graph)
+    }
+
+    /**
+     * Add the components of a schema, given its root <schema> element.
+     * Several schemas can be added, e.g. for imports and includes.
+     */
+    pub fn add_schema(&mut self, schema: &dyn Element) -> Result<(), XmlDocumentError> {
+        let schema_info = schema.element_info();
+        if !schema_info.is(XSD_NAMESPACE, "schema") {
+            return Err(XmlDocumentError::UnknownElement(schema_info.span.start,
+                schema_info.qname().to_string(), "document".to_string()));
+        }
+        let target_namespace = schema_info.attribute("targetNamespace");
+
+        // Define everything first, so that the definitions don't depend on
+        // the order of references
+        let mut owners = vec![];
+        for subelement in schema.subelements() {
+            let element_info = subelement.element_info();
+            let kind = match ComponentKind::defined_by(&element_info.owned_name.local_name) {
+                Some(kind) if element_info.owned_name.namespace.as_deref() ==
+                    Some(XSD_NAMESPACE) => kind,
+                _ => continue,
+            };
+            let name = match element_info.attribute("name") {
+                Some(name) => QName::new(target_namespace, name),
+                None => continue,
+            };
+            let node = self.node(kind, name);
+            self.graph[node].definition = Some(Definition {
+                element:    element_info.owned_name.local_name.clone(),
+                lineno:     element_info.lineno(),
+            });
+            owners.push((node, subelement.as_ref()));
+        }
+
+        for (node, element) in owners {
+            self.add_references(node, element, true)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Add the references in an element and its subelements. own_type is
+     * true while within the top-level component itself, rather than an
+     * anonymous type within it.
+     */
+    fn add_references(&mut self, owner: NodeIndex, element: &dyn Element, own_type: bool) ->
+        Result<(), XmlDocumentError> {
+        let element_info = element.element_info();
+        if element_info.owned_name.namespace.as_deref() != Some(XSD_NAMESPACE) {
+            return Ok(());
+        }
+
+        let local_name = element_info.owned_name.local_name.as_str();
+        let base_kind = match (local_name, own_type) {
+            ("extension", true) => ReferenceKind::Extension,
+            ("restriction", true) => ReferenceKind::Restriction,
+            _ => ReferenceKind::Type,
+        };
+        let references = [
+            ("type", ComponentKind::Type, ReferenceKind::Type),
+            ("base", ComponentKind::Type, base_kind),
+            ("ref", ComponentKind::defined_by(local_name).unwrap_or(ComponentKind::Element),
+                ReferenceKind::Ref),
+            ("substitutionGroup", ComponentKind::Element, ReferenceKind::SubstitutionGroup),
+        ];
+        for (attribute, kind, reference_kind) in references {
+            // substitutionGroup can be a list in XSD 1.1
+            for value in element_info.attribute(attribute).unwrap_or_default().split_whitespace() {
+                let name = resolve(element_info, value)?;
+                let target = self.node(kind, name);
+                self.graph.add_edge(owner, target, Reference {
+                    kind:   reference_kind,
+                    lineno: element_info.lineno(),
+                });
+            }
+        }
+
+        for subelement in element.subelements() {
+            let anonymous = subelement.element_info().owned_name.local_name.ends_with("Type");
+            self.add_references(owner, subelement.as_ref(), own_type && !anonymous)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Node for a component, adding it if it isn't there yet
+     */
+    fn node(&mut self, kind: ComponentKind, name: QName) -> NodeIndex {
+        let graph = &mut self.graph;
+        *self.nodes.entry((kind, name.clone())).or_insert_with(|| {
+            graph.add_node(SchemaComponent { kind, name, definition: None })
+        })
+    }
+
+    /**
+     * The underlying petgraph graph, for anything not covered here
+     */
+    pub fn graph(&self) -> &DiGraph<SchemaComponent, Reference> {
+        &self.graph
+    }
+
+    pub fn component(&self, node: NodeIndex) -> &SchemaComponent {
+        &self.graph[node]
+    }
+
+    /**
+     * Node for a component, if it is defined or referred to
+     */
+    pub fn find(&self, kind: ComponentKind, name: &QName) -> Option<NodeIndex> {
+        self.nodes.get(&(kind, name.clone())).copied()
+    }
+
+    /**
+     * Nodes for the components defined in the schemas, in the order they
+     * were added
+     */
+    pub fn defined(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.graph.node_indices().filter(|node| self.graph[*node].definition.is_some())
+    }
+
+    /**
+     * Components that refer to the given one, each once, in node order
+     */
+    pub fn users(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        self.neighbors(node, Direction::Incoming)
+    }
+
+    /**
+     * Components the given one refers to, each once, in node order
+     */
+    pub fn uses(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        self.neighbors(node, Direction::Outgoing)
+    }
+
+    fn neighbors(&self, node: NodeIndex, direction: Direction) -> Vec<NodeIndex> {
+        let mut neighbors: Vec<NodeIndex> = self.graph.neighbors_directed(node, direction)
+            .collect();
+        neighbors.sort();
+        neighbors.dedup();
+        neighbors
+    }
+
+    /**
+     * Defined components that nothing else refers to. Global elements are
+     * included, as are the roots of documents.
+     */
+    pub fn unused(&self) -> Vec<NodeIndex> {
+        self.defined()
+            .filter(|node| self.users(*node).iter().all(|user| user == node))
+            .collect()
+    }
+
+    /**
+     * Defined components that can't be reached by following references
+     * from the given roots, usually the global elements that can be the
+     * root of a document
+     */
+    pub fn unreachable(&self, roots: &[NodeIndex]) -> Vec<NodeIndex> {
+        let mut reached = vec![false; self.graph.node_count()];
+        for root in roots {
+            let mut dfs = Dfs::new(&self.graph, *root);
+            while let Some(node) = dfs.next(&self.graph) {
+                reached[node.index()] = true;
+            }
+        }
+        self.defined().filter(|node| !reached[node.index()]).collect()
+    }
+
+    /**
+     * Groups of components that refer to each other, directly or
+     * indirectly, including components that refer to themselves
+     */
+    pub fn cycles(&self) -> Vec<Vec<NodeIndex>> {
+        tarjan_scc(&self.graph).into_iter()
+            .filter(|scc| scc.len() > 1 || self.graph.contains_edge(scc[0], scc[0]))
+            .map(|mut scc| {
+                scc.sort();
+                scc
+            })
+            .collect()
+    }
+
+    /**
+     * Components in an order where everything a component refers to comes
+     * before it, as needed to generate code. Components in a cycle can't
+     * be ordered like that, so they are returned together as a group;
+     * every other group holds a single component.
+     */
+    pub fn topological_order(&self) -> Vec<Vec<NodeIndex>> {
+        // tarjan_scc() gives the groups in reverse topological order, i.e.
+        // with the components referred to first
+        tarjan_scc(&self.graph).into_iter()
+            .map(|mut scc| {
+                scc.sort();
+                scc
+            })
+            .collect()
+    }
+
+    /**
+     * The type a named type is derived from, and how
+     */
+    pub fn base_type(&self, node: NodeIndex) -> Option<(NodeIndex, ReferenceKind)> {
+        self.graph.edges_directed(node, Direction::Outgoing)
+            .find(|edge| edge.weight().kind.is_derivation())
+            .map(|edge| (edge.target(), edge.weight().kind))
+    }
+
+    /**
+     * The type a named type is derived from, the type that one is derived
+     * from, and so on. A derivation cycle, which is an error in the
+     * schema, ends the chain.
+     */
+    pub fn base_types(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut base_types = vec![];
+        let mut current = node;
+        while let Some((base, _)) = self.base_type(current) {
+            if base == node || base_types.contains(&base) {
+                break;
+            }
+            base_types.push(base);
+            current = base;
+        }
+        base_types
+    }
+
+    /**
+     * Types directly derived from the given one, in node order
+     */
+    pub fn derived_types(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut derived: Vec<NodeIndex> = self.graph.edges_directed(node, Direction::Incoming)
+            .filter(|edge| edge.weight().kind.is_derivation())
+            .map(|edge| edge.source())
+            .collect();
+        derived.sort();
+        derived.dedup();
+        derived
+    }
+}
+
+/*
+ * Resolve a qualified name in an attribute value
+ */
+fn resolve(element_info: &ElementInfo, value: &str) -> Result<QName, XmlDocumentError> {
+    element_info.resolve_qname(value).ok_or_else(|| {
+        let prefix = value.split_once(':').map_or("", |(prefix, _)| prefix);
+        XmlDocumentError::UnboundPrefix(prefix.to_string(),
+            element_info.owned_name.local_name.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use petgraph::graph::NodeIndex;
+
+    use crate::parse_tree::parse;
+    use crate::qname::{QName, XSD_NAMESPACE, XTCE_NAMESPACE};
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::{ComponentKind, ReferenceKind, SchemaGraph};
+
+    fn graph(input: &str) -> Result<SchemaGraph, XmlDocumentError> {
+        let root = parse(input);
+        let mut schema_graph = SchemaGraph::new();
+        schema_graph.add_schema(root.as_ref())?;
+        Ok(schema_graph)
+    }
+
+    fn names(schema_graph: &SchemaGraph, nodes: &[NodeIndex]) -> Vec<String> {
+        nodes.iter().map(|node| schema_graph.component(*node).name.local_name.clone()).collect()
+    }
+
+    #[test]
+    fn test_references() {
+        println!("Running test {}", function_name!());
+        let schema_graph = graph(concat!(
+            "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" ",
+            "    xmlns:t=\"urn:t\" targetNamespace=\"urn:t\">\n",
+            "  <xs:element name=\"Root\" type=\"t:DerivedType\"/>\n",
+            "  <xs:element name=\"Head\" type=\"t:BaseType\"/>\n",
+            "  <xs:element name=\"Member\" substitutionGroup=\"t:Head\"/>\n",
+            "  <xs:complexType name=\"BaseType\">\n",
+            "    <xs:sequence><xs:element ref=\"t:Head\" minOccurs=\"0\"/></xs:sequence>\n",
+            "  </xs:complexType>\n",
+            "  <xs:complexType name=\"DerivedType\">\n",
+            "    <xs:complexContent><xs:extension base=\"t:BaseType\">\n",
+            "      <xs:attribute name=\"size\" type=\"t:SizeType\"/>\n",
+            "    </xs:extension></xs:complexContent>\n",
+            "  </xs:complexType>\n",
+            "  <xs:simpleType name=\"SizeType\">\n",
+            "    <xs:restriction base=\"xs:unsignedInt\"/>\n",
+            "  </xs:simpleType>\n",
+            "  <xs:simpleType name=\"UnusedType\">\n",
+            "    <xs:restriction><xs:simpleType><xs:restriction base=\"t:SizeType\"/>",
+            "</xs:simpleType></xs:restriction>\n",
+            "  </xs:simpleType>\n",
+            "</xs:schema>\n")).unwrap();
+        let find = |kind, name| schema_graph.find(kind, &QName::new(Some("urn:t"), name))
+            .unwrap();
+        let root = find(ComponentKind::Element, "Root");
+        let head = find(ComponentKind::Element, "Head");
+        let base_type = find(ComponentKind::Type, "BaseType");
+        let derived_type = find(ComponentKind::Type, "DerivedType");
+        let size_type = find(ComponentKind::Type, "SizeType");
+        let unused_type = find(ComponentKind::Type, "UnusedType");
+        let unsigned_int = schema_graph.find(ComponentKind::Type,
+            &QName::new(Some(XSD_NAMESPACE), "unsignedInt")).unwrap();
+
+        assert_eq!(schema_graph.defined().count(), 7);
+        assert!(schema_graph.component(unsigned_int).definition.is_none());
+        assert_eq!(schema_graph.component(derived_type).definition.as_ref().unwrap().lineno, 8);
+        assert_eq!(names(&schema_graph, &schema_graph.users(head)),
+            ["Member", "BaseType"]);
+        assert_eq!(names(&schema_graph, &schema_graph.uses(derived_type)),
+            ["BaseType", "SizeType"]);
+
+        // Derivation
+        assert_eq!(schema_graph.base_type(derived_type),
+            Some((base_type, ReferenceKind::Extension)));
+        assert_eq!(schema_graph.base_types(size_type), [unsigned_int]);
+        assert_eq!(schema_graph.derived_types(base_type), [derived_type]);
+        // The restriction in the anonymous type isn't UnusedType's own
+        assert_eq!(schema_graph.base_type(unused_type), None);
+        assert_eq!(schema_graph.uses(unused_type), [size_type]);
+
+        assert_eq!(names(&schema_graph, &schema_graph.unused()), ["Root", "Member", "UnusedType"]);
+        assert_eq!(names(&schema_graph, &schema_graph.unreachable(&[root])),
+            ["Member", "UnusedType"]);
+        assert_eq!(names(&schema_graph, &schema_graph.cycles()[0]), ["Head", "BaseType"]);
+        assert_eq!(schema_graph.cycles().len(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        println!("Running test {}", function_name!());
+        match graph("<schema xmlns=\"urn:not-xsd\"/>") {
+            Err(XmlDocumentError::UnknownElement(..)) => {},
+            result => panic!("expected UnknownElement, got {:?}", result),
+        }
+        match graph(concat!(
+            "<schema xmlns=\"http://www.w3.org/2001/XMLSchema\">",
+            "<element name=\"a\" type=\"q:b\"/></schema>")) {
+            Err(XmlDocumentError::UnboundPrefix(prefix, _)) => assert_eq!(prefix, "q"),
+            result => panic!("expected UnboundPrefix, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        println!("Running test {}", function_name!());
+        let schema_graph = SchemaGraph::from_path("schema/SpaceSystem-patched.xsd").unwrap();
+        let find = |kind, name| schema_graph.find(kind, &QName::new(Some(XTCE_NAMESPACE), name))
+            .unwrap();
+        let complex_types = schema_graph.defined()
+            .filter(|node| schema_graph.component(*node).is_complex_type())
+            .count();
+        assert_eq!(complex_types, 281);
+
+        // Two of the 155 extensions are in anonymous types
+        let count = |kind| schema_graph.graph().edge_weights()
+            .filter(|reference| reference.kind == kind)
+            .count();
+        assert_eq!(count(ReferenceKind::Extension), 153);
+
+        let parameter_type = find(ComponentKind::Type, "IntegerParameterType");
+        assert_eq!(names(&schema_graph, &schema_graph.base_types(parameter_type)),
+            ["IntegerDataType", "BaseDataType", "NameDescriptionType", "DescriptionType"]);
+        assert_eq!(names(&schema_graph, &schema_graph.users(parameter_type)),
+            ["ParameterTypeSetType"]);
+
+        let space_system = find(ComponentKind::Element, "SpaceSystem");
+        let space_system_type = find(ComponentKind::Type, "SpaceSystemType");
+        assert!(schema_graph.cycles().contains(&vec![space_system, space_system_type]));
+        assert_eq!(schema_graph.unreachable(&[space_system]), schema_graph.unused()
+            .into_iter()
+            .filter(|node| *node != space_system)
+            .collect::<Vec<_>>());
+
+        // Everything a component refers to comes before it, unless they
+        // are in the same cycle
+        let mut group = vec![0; schema_graph.graph().node_count()];
+        for (i, nodes) in schema_graph.topological_order().iter().enumerate() {
+            for node in nodes {
+                group[node.index()] = i;
+            }
+        }
+        for edge in schema_graph.graph().raw_edges() {
+            assert!(group[edge.target().index()] <= group[edge.source().index()]);
+        }
+    }
+}
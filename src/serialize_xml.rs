@@ -0,0 +1,1141 @@
+/*
+ * serde Serializer that writes XML, using the same mapping as
+ * deserialize_xml so that what is written can be read back:
+ *
+ * o    A struct is an element. A field named "@name" is the attribute
+ *      "name", a field named "$text" is text within the element, and any
+ *      other field is a subelement with that name. Fields are written in
+ *      order, so text goes between the subelements around it.
+ * o    Names can have a prefix, e.g. "xtce:Parameter" or "@xsi:type". The
+ *      prefix has to be one of the namespaces in the options, all of which
+ *      are declared on the root element.
+ * o    A field named "$value" is written as elements named by its enum
+ *      variants.
+ * o    A Vec field is written as one subelement for each item, and an
+ *      Option field that is None isn't written at all. A Vec field that
+ *      may be empty needs #[serde(default)] to be read back. The items
+ *      can't be sequences themselves, since they would run together, so
+ *      a Vec of Vecs needs the inner Vec wrapped in a struct.
+ * o    Numbers, booleans and strings are written as the text of an element
+ *      or an attribute value. A Vec of them as an attribute or "$text" is
+ *      written as a whitespace-separated list. Floating point numbers use
+ *      the shortest form that reads back the same, with an exponent if
+ *      they are very large or small.
+ * o    "$text" that is only whitespace in an element with subelements is
+ *      marked with xml:space="preserve" so that it isn't taken for
+ *      indentation.
+ * o    A unit variant is written as its name, as text or as an attribute
+ *      value. Other variants are written as an element named by the
+ *      variant, inside the element for the field.
+ * o    A map is written like a struct, with its keys as the field names.
+ *
+ * The root element is named by SerializeOptions::root or, if that isn't
+ * given, by the name of the Rust type.
+ */
+
+use serde::ser::{self, Impossible, Serialize};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+use xml::common::XmlVersion;
+use xml::name::OwnedName;
+use xml::namespace::{Namespace, NS_EMPTY_URI, NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XMLNS_URI,
+    NS_XML_PREFIX, NS_XML_URI};
+use xml::reader::XmlEvent;
+
+use crate::document::DocumentInfo;
+use crate::element::{Element, ElementInfo};
+use crate::format_tree::{format_document, FormatOptions};
+use crate::parse_item::SourceSpan;
+use crate::parse_tree::TreeElement;
+use crate::write_tree::write_document;
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * How to write a value as XML
+ * root:        Name of the root element. If None, the name of the type
+ *              being serialized is used.
+ * namespaces:  Namespace prefixes and URIs to declare on the root element.
+ *              The prefix "" is the default namespace.
+ * indent:      If Some, the number of spaces to indent each level by.
+ *              Otherwise, no whitespace is added.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SerializeOptions {
+    pub root:       Option<String>,
+    pub namespaces: Vec<(String, String)>,
+    pub indent:     Option<usize>,
+}
+
+/**
+ * Serialize a value as an Element tree
+ */
+pub fn to_element<T: Serialize + ?Sized>(value: &T, options: &SerializeOptions) ->
+    Result<Box<dyn Element>, XmlDocumentError> {
+    let mut declarations = new_element(&Namespace::empty());
+    for (prefix, uri) in &options.namespaces {
+        declarations.declare_namespace(prefix, uri)?;
+    }
+    let namespace = declarations.element_info().namespace.clone();
+
+    let mut elements = value.serialize(ElementSerializer {
+        namespace:  &namespace,
+        name:       options.root.clone(),
+        variant:    false,
+        item:       false,
+    })?;
+    match elements.len() {
+        1 => Ok(elements.remove(0)),
+        n => Err(XmlDocumentError::Custom(format!("expected one root element, got {}", n))),
+    }
+}
+
+/**
+ * Serialize a value as an XML document
+ */
+pub fn to_writer<W: Write, T: Serialize + ?Sized>(writer: &mut W, value: &T,
+    options: &SerializeOptions) -> Result<(), XmlDocumentError> {
+    let root = to_element(value, options)?;
+    let document_info = DocumentInfo::new(XmlVersion::Version10, "UTF-8".to_string(), None);
+
+    let written = match options.indent {
+        None => write_document(writer, &document_info, root.as_ref()),
+        Some(indent) => format_document(writer, &document_info, root.as_ref(),
+            &FormatOptions { indent, ..Default::default() }),
+    };
+    written.map_err(|e: io::Error| XmlDocumentError::IoError("output".to_string(), Arc::new(e)))
+}
+
+/**
+ * Serialize a value as an XML document in a string
+ */
+pub fn to_string<T: Serialize + ?Sized>(value: &T, options: &SerializeOptions) ->
+    Result<String, XmlDocumentError> {
+    let mut output = Vec::new();
+    to_writer(&mut output, value, options)?;
+    Ok(String::from_utf8(output).expect("XML output is always UTF-8"))
+}
+
+/*
+ * Element with no name yet, with the given namespace mappings
+ */
+pub(crate) fn new_element(namespace: &Namespace) -> Box<dyn Element> {
+    let mut namespace = namespace.clone();
+    namespace.put(NS_NO_PREFIX, NS_EMPTY_URI);
+    namespace.put(NS_XML_PREFIX, NS_XML_URI);
+    namespace.put(NS_XMLNS_PREFIX, NS_XMLNS_URI);
+    let element_info = ElementInfo::new(OwnedName::local(""), SourceSpan::default(), vec![],
+        namespace);
+    Box::new(TreeElement::new(element_info, vec![], vec![], vec![], vec![]))
+}
+
+/*
+ * Element with a name and nothing in it yet
+ */
+fn named_element(namespace: &Namespace, name: &str) -> Result<Box<dyn Element>, XmlDocumentError> {
+    let mut element = new_element(namespace);
+    element.rename(name)?;
+    Ok(element)
+}
+
+/*
+ * Element holding just text
+ */
+fn text_element(namespace: &Namespace, name: &str, text: &str) ->
+    Result<Box<dyn Element>, XmlDocumentError> {
+    let mut element = named_element(namespace, name)?;
+    element.set_text(text);
+    Ok(element)
+}
+
+/*
+ * Text for a floating point number. Display never uses an exponent, so
+ * 1.5e300 would be 301 digits long; {:e} gives the shortest digits that
+ * read back the same. Infinities and NaN are written as in xs:double.
+ */
+fn float_text<T: Copy + Into<f64> + fmt::Display + fmt::LowerExp>(v: T) -> String {
+    let x: f64 = v.into();
+    if x.is_nan() {
+        "NaN".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "INF" } else { "-INF" }.to_string()
+    } else if x != 0.0 && !(1e-6..1e16).contains(&x.abs()) {
+        format!("{:e}", v)
+    } else {
+        v.to_string()
+    }
+}
+
+/*
+ * Serializer for a value as elements. Most values are a single element,
+ * but a sequence is an element for each item and None is no element.
+ * namespace:   Namespace mappings for all the elements
+ * name:        Name of the elements, if known
+ * variant:     True if enum variants name the elements, as for "$value"
+ *              fields
+ * item:        True for the items of a sequence. An item can't be a
+ *              sequence itself, since its elements would run together with
+ *              those of the other items.
+ */
+struct ElementSerializer<'a> {
+    namespace:  &'a Namespace,
+    name:       Option<String>,
+    variant:    bool,
+    item:       bool,
+}
+
+impl<'a> ElementSerializer<'a> {
+    fn name(&self, kind: &str) -> Result<&str, XmlDocumentError> {
+        self.name.as_deref()
+            .ok_or_else(|| XmlDocumentError::Custom(format!("no element name for {}", kind)))
+    }
+
+    fn text(self, text: &str) -> Result<Vec<Box<dyn Element>>, XmlDocumentError> {
+        Ok(vec![text_element(self.namespace, self.name("text")?, text)?])
+    }
+
+    /*
+     * Serializer for the items of a sequence, which are elements with the
+     * same name
+     */
+    fn items(&self) -> ElementSerializer<'a> {
+        ElementSerializer {
+            namespace:  self.namespace,
+            name:       self.name.clone(),
+            variant:    self.variant,
+            item:       true,
+        }
+    }
+
+    /*
+     * The element that holds a variant, and its name. When the variant
+     * names the element, there isn't one.
+     */
+    fn variant_holder(&self, name: &'static str, variant: &'static str) ->
+        Result<(Option<Box<dyn Element>>, String), XmlDocumentError> {
+        match self.variant {
+            true => Ok((None, variant.to_string())),
+            false => Ok((Some(named_element(self.namespace,
+                self.name.as_deref().unwrap_or(name))?), variant.to_string())),
+        }
+    }
+}
+
+/*
+ * Serialize a value that is written as its text
+ */
+macro_rules! serialize_text {
+    ($($method:ident: $type:ty,)*) => {$(
+        fn $method(self, v: $type) -> Result<Self::Ok, XmlDocumentError> {
+            self.text(&v.to_string())
+        }
+    )*};
+}
+
+impl<'a> ser::Serializer for ElementSerializer<'a> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+    type SerializeSeq = SeqSerializer<'a>;
+    type SerializeTuple = SeqSerializer<'a>;
+    type SerializeTupleStruct = SeqSerializer<'a>;
+    type SerializeTupleVariant = SeqSerializer<'a>;
+    type SerializeMap = StructSerializer<'a>;
+    type SerializeStruct = StructSerializer<'a>;
+    type SerializeStructVariant = StructSerializer<'a>;
+
+    serialize_text! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_char: char,
+        serialize_str: &str,
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, XmlDocumentError> {
+        self.text(&float_text(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, XmlDocumentError> {
+        self.text(&float_text(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, XmlDocumentError> {
+        match std::str::from_utf8(v) {
+            Ok(text) => self.text(text),
+            Err(e) => Err(XmlDocumentError::Custom(format!("bytes aren't UTF-8: {}", e))),
+        }
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(vec![])
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) ->
+        Result<Self::Ok, XmlDocumentError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(vec![named_element(self.namespace, self.name("()")?)?])
+    }
+
+    fn serialize_unit_struct(mut self, name: &'static str) -> Result<Self::Ok, XmlDocumentError> {
+        self.name.get_or_insert_with(|| name.to_string());
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, name: &'static str, _variant_index: u32,
+        variant: &'static str) -> Result<Self::Ok, XmlDocumentError> {
+        match self.variant {
+            true => Ok(vec![named_element(self.namespace, variant)?]),
+            false => Ok(vec![text_element(self.namespace,
+                self.name.as_deref().unwrap_or(name), variant)?]),
+        }
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(mut self, name: &'static str,
+        value: &T) -> Result<Self::Ok, XmlDocumentError> {
+        self.name.get_or_insert_with(|| name.to_string());
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, name: &'static str,
+        _variant_index: u32, variant: &'static str, value: &T) ->
+        Result<Self::Ok, XmlDocumentError> {
+        let (holder, variant) = self.variant_holder(name, variant)?;
+        let elements = value.serialize(ElementSerializer {
+            namespace:  self.namespace,
+            name:       Some(variant),
+            variant:    false,
+            item:       false,
+        })?;
+        Ok(hold(holder, elements))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqSerializer<'a>, XmlDocumentError> {
+        if self.item {
+            return Err(XmlDocumentError::Custom(format!(
+                "can't write a sequence within a sequence as <{}> elements",
+                self.name("a sequence")?)));
+        }
+        Ok(SeqSerializer {
+            items:      self.items(),
+            holder:     None,
+            element:    None,
+            elements:   vec![],
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer<'a>, XmlDocumentError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(mut self, name: &'static str, len: usize) ->
+        Result<SeqSerializer<'a>, XmlDocumentError> {
+        self.name.get_or_insert_with(|| name.to_string());
+        self.serialize_seq(Some(len))
+    }
+
+    // The items are elements named by the variant, within the element
+    // for the variant
+    fn serialize_tuple_variant(self, name: &'static str, _variant_index: u32,
+        variant: &'static str, _len: usize) -> Result<SeqSerializer<'a>, XmlDocumentError> {
+        let (holder, variant) = self.variant_holder(name, variant)?;
+        let element = named_element(self.namespace, &variant)?;
+        Ok(SeqSerializer {
+            items:      ElementSerializer {
+                namespace:  self.namespace,
+                name:       Some(variant),
+                variant:    false,
+                item:       true,
+            },
+            holder,
+            element:    Some(element),
+            elements:   vec![],
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<StructSerializer<'a>, XmlDocumentError> {
+        let element = named_element(self.namespace, self.name("a map")?)?;
+        Ok(StructSerializer::new(self.namespace, None, element))
+    }
+
+    fn serialize_struct(mut self, name: &'static str, len: usize) ->
+        Result<StructSerializer<'a>, XmlDocumentError> {
+        self.name.get_or_insert_with(|| name.to_string());
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, name: &'static str, _variant_index: u32,
+        variant: &'static str, _len: usize) -> Result<StructSerializer<'a>, XmlDocumentError> {
+        let (holder, variant) = self.variant_holder(name, variant)?;
+        let element = named_element(self.namespace, &variant)?;
+        Ok(StructSerializer::new(self.namespace, holder, element))
+    }
+}
+
+/*
+ * Put elements in the element that holds them, if there is one
+ */
+fn hold(holder: Option<Box<dyn Element>>, elements: Vec<Box<dyn Element>>) ->
+    Vec<Box<dyn Element>> {
+    match holder {
+        None => elements,
+        Some(mut holder) => {
+            for element in elements {
+                holder.append_child(element);
+            }
+            vec![holder]
+        },
+    }
+}
+
+/*
+ * Serializer for the items of a sequence
+ * items:       Serializer for each item
+ * holder:      For a tuple variant, the element holding the variant, if
+ *              there is one
+ * element:     For a tuple variant, the element for the variant
+ * elements:    Elements for the items so far
+ */
+struct SeqSerializer<'a> {
+    items:      ElementSerializer<'a>,
+    holder:     Option<Box<dyn Element>>,
+    element:    Option<Box<dyn Element>>,
+    elements:   Vec<Box<dyn Element>>,
+}
+
+impl SeqSerializer<'_> {
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlDocumentError> {
+        self.elements.extend(value.serialize(self.items.items())?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<Box<dyn Element>>, XmlDocumentError> {
+        Ok(hold(self.holder, hold(self.element, self.elements)))
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer<'_> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, XmlDocumentError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer<'_> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, XmlDocumentError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer<'_> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, XmlDocumentError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer<'_> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, XmlDocumentError> {
+        self.finish()
+    }
+}
+
+/*
+ * Serializer for the fields of a struct or the entries of a map
+ * namespace:   Namespace mappings for all the elements
+ * holder:      For a struct variant, the element holding the variant, if
+ *              there is one
+ * element:     Element being filled in
+ * pending:     Text that goes before the next subelement
+ * blank:       True if the text is only whitespace, which needs
+ *              xml:space="preserve" if there are subelements, too
+ * key:         Key of the map entry whose value is next
+ */
+struct StructSerializer<'a> {
+    namespace:  &'a Namespace,
+    holder:     Option<Box<dyn Element>>,
+    element:    Box<dyn Element>,
+    pending:    Vec<XmlEvent>,
+    blank:      bool,
+    key:        Option<String>,
+}
+
+impl<'a> StructSerializer<'a> {
+    fn new(namespace: &'a Namespace, holder: Option<Box<dyn Element>>,
+        element: Box<dyn Element>) -> Self {
+        StructSerializer {
+            namespace,
+            holder,
+            element,
+            pending:    vec![],
+            blank:      false,
+            key:        None,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) ->
+        Result<(), XmlDocumentError> {
+        if let Some(name) = key.strip_prefix('@') {
+            if let Some(text) = value.serialize(TextSerializer { item: false })? {
+                self.element.set_attribute(name, &text)?;
+            }
+            return Ok(());
+        }
+        if key == "$text" {
+            if let Some(text) = value.serialize(TextSerializer { item: false })?.filter(|text| !text.is_empty()) {
+                self.blank = text.trim().is_empty();
+                self.pending.push(XmlEvent::Characters(text));
+            }
+            return Ok(());
+        }
+
+        let elements = value.serialize(ElementSerializer {
+            namespace:  self.namespace,
+            name:       Some(key.to_string()).filter(|key| key != "$value"),
+            variant:    key == "$value",
+            item:       false,
+        })?;
+        for mut subelement in elements {
+            *subelement.before_element_mut() = std::mem::take(&mut self.pending);
+            self.element.append_child(subelement);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<Box<dyn Element>>, XmlDocumentError> {
+        if self.blank && !self.element.subelements().is_empty() {
+            self.element.set_attribute("xml:space", "preserve")?;
+        }
+        self.element.content_mut().append(&mut self.pending);
+        Ok(hold(self.holder, vec![self.element]))
+    }
+}
+
+impl ser::SerializeStruct for StructSerializer<'_> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, XmlDocumentError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for StructSerializer<'_> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, XmlDocumentError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for StructSerializer<'_> {
+    type Ok = Vec<Box<dyn Element>>;
+    type Error = XmlDocumentError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), XmlDocumentError> {
+        match key.serialize(TextSerializer { item: false })? {
+            None => Err(XmlDocumentError::Custom("map key is None".to_string())),
+            Some(key) => {
+                self.key = Some(key);
+                Ok(())
+            },
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        match self.key.take() {
+            None => Err(XmlDocumentError::Custom("map value before key".to_string())),
+            Some(key) => self.field(&key, value),
+        }
+    }
+
+    fn end(self) -> Result<Self::Ok, XmlDocumentError> {
+        self.finish()
+    }
+}
+
+/*
+ * Serializer for an attribute value or text. None is nothing at all.
+ * item:    True for the items of a list, which can't be lists themselves
+ */
+struct TextSerializer {
+    item:   bool,
+}
+
+impl TextSerializer {
+    fn unsupported<T>(kind: &str) -> Result<T, XmlDocumentError> {
+        Err(XmlDocumentError::Custom(format!("can't write {} as text", kind)))
+    }
+
+    fn list(self) -> Result<ListSerializer, XmlDocumentError> {
+        match self.item {
+            false => Ok(ListSerializer(vec![])),
+            true => Self::unsupported("a list within a list"),
+        }
+    }
+}
+
+/*
+ * Serialize a value as its string form
+ */
+macro_rules! text_to_string {
+    ($($method:ident: $type:ty,)*) => {$(
+        fn $method(self, v: $type) -> Result<Option<String>, XmlDocumentError> {
+            Ok(Some(v.to_string()))
+        }
+    )*};
+}
+
+impl ser::Serializer for TextSerializer {
+    type Ok = Option<String>;
+    type Error = XmlDocumentError;
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = Impossible<Option<String>, XmlDocumentError>;
+    type SerializeMap = Impossible<Option<String>, XmlDocumentError>;
+    type SerializeStruct = Impossible<Option<String>, XmlDocumentError>;
+    type SerializeStructVariant = Impossible<Option<String>, XmlDocumentError>;
+
+    text_to_string! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_char: char,
+        serialize_str: &str,
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(Some(float_text(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(Some(float_text(v)))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, XmlDocumentError> {
+        match std::str::from_utf8(v) {
+            Ok(text) => Ok(Some(text.to_string())),
+            Err(e) => Err(XmlDocumentError::Custom(format!("bytes aren't UTF-8: {}", e))),
+        }
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) ->
+        Result<Self::Ok, XmlDocumentError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(Some(String::new()))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(Some(String::new()))
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str) -> Result<Self::Ok, XmlDocumentError> {
+        Ok(Some(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str,
+        value: &T) -> Result<Self::Ok, XmlDocumentError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str,
+        _variant_index: u32, variant: &'static str, _value: &T) ->
+        Result<Self::Ok, XmlDocumentError> {
+        Self::unsupported(&format!("variant {}", variant))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<ListSerializer, XmlDocumentError> {
+        self.list()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<ListSerializer, XmlDocumentError> {
+        self.list()
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) ->
+        Result<ListSerializer, XmlDocumentError> {
+        self.list()
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant,
+        XmlDocumentError> {
+        Self::unsupported(&format!("variant {}", variant))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, XmlDocumentError> {
+        Self::unsupported("a map")
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) ->
+        Result<Self::SerializeStruct, XmlDocumentError> {
+        Self::unsupported(name)
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant,
+        XmlDocumentError> {
+        Self::unsupported(&format!("variant {}", variant))
+    }
+}
+
+/*
+ * Serializer for a whitespace-separated list, as for xs:list
+ */
+struct ListSerializer(Vec<String>);
+
+impl ListSerializer {
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlDocumentError> {
+        self.0.extend(value.serialize(TextSerializer { item: true })?);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for ListSerializer {
+    type Ok = Option<String>;
+    type Error = XmlDocumentError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Option<String>, XmlDocumentError> {
+        Ok(Some(self.0.join(" ")))
+    }
+}
+
+impl ser::SerializeTuple for ListSerializer {
+    type Ok = Option<String>;
+    type Error = XmlDocumentError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Option<String>, XmlDocumentError> {
+        Ok(Some(self.0.join(" ")))
+    }
+}
+
+impl ser::SerializeTupleStruct for ListSerializer {
+    type Ok = Option<String>;
+    type Error = XmlDocumentError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) ->
+        Result<(), XmlDocumentError> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Option<String>, XmlDocumentError> {
+        Ok(Some(self.0.join(" ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use serde::{Deserialize, Serialize};
+
+    use crate::deserialize_xml::from_str;
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::{to_string, SerializeOptions};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Encoding {
+        #[serde(rename = "unsigned")]
+        Unsigned,
+        #[serde(rename = "twosComplement")]
+        TwosComplement,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Parameter {
+        #[serde(rename = "@name")]
+        name: String,
+        #[serde(rename = "@encoding", skip_serializing_if = "Option::is_none")]
+        encoding: Option<Encoding>,
+        #[serde(rename = "@sizeInBits")]
+        size_in_bits: u32,
+        #[serde(rename = "$text")]
+        description: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Size {
+        Fixed(u32),
+        Dynamic {
+            #[serde(rename = "@ref")]
+            reference: String,
+        },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Entry {
+        ParameterRefEntry {
+            #[serde(rename = "@parameterRef")]
+            parameter_ref: String,
+        },
+        ContainerRefEntry(String),
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Container {
+        #[serde(rename = "@name")]
+        name: String,
+        #[serde(rename = "@offsets", default)]
+        offsets: Vec<i64>,
+        #[serde(rename = "Parameter", default)]
+        parameters: Vec<Parameter>,
+        #[serde(rename = "Size")]
+        size: Option<Size>,
+        #[serde(rename = "$value", default)]
+        entries: Vec<Entry>,
+    }
+
+    /*
+     * Serialize a value, check that reading it back gives the same value,
+     * and return the XML
+     */
+    fn round_trip<T>(value: &T, options: &SerializeOptions) -> String
+    where
+        T: std::fmt::Debug + for<'de> Deserialize<'de> + PartialEq + Serialize,
+    {
+        let xml = to_string(value, options).expect("serialize failed");
+        let read: T = from_str(&xml).expect("deserialize failed");
+        assert_eq!(&read, value);
+        xml
+    }
+
+    #[test]
+    fn test_round_trip() {
+        println!("Running test {}", function_name!());
+        let container = Container {
+            name:       "Header".to_string(),
+            offsets:    vec![0, 8, -16],
+            parameters: vec![
+                Parameter {
+                    name:           "A".to_string(),
+                    encoding:       Some(Encoding::TwosComplement),
+                    size_in_bits:   8,
+                    description:    None,
+                },
+                Parameter {
+                    name:           "B & C".to_string(),
+                    encoding:       None,
+                    size_in_bits:   16,
+                    description:    Some("second <one>".to_string()),
+                },
+            ],
+            size:       Some(Size::Dynamic { reference: "Length".to_string() }),
+            entries:    vec![
+                Entry::ParameterRefEntry { parameter_ref: "A".to_string() },
+                Entry::ContainerRefEntry("Body".to_string()),
+            ],
+        };
+        assert_eq!(round_trip(&container, &SerializeOptions::default()), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Container name=\"Header\" offsets=\"0 8 -16\">",
+            "<Parameter name=\"A\" encoding=\"twosComplement\" sizeInBits=\"8\"/>",
+            "<Parameter name=\"B &amp; C\" sizeInBits=\"16\">second &lt;one&gt;</Parameter>",
+            "<Size><Dynamic ref=\"Length\"/></Size>",
+            "<ParameterRefEntry parameterRef=\"A\"/>",
+            "<ContainerRefEntry>Body</ContainerRefEntry>",
+            "</Container>\n"));
+
+        // The root can be renamed, and empty Vecs and None aren't written
+        let fixed = Container { size: Some(Size::Fixed(32)), parameters: vec![], entries: vec![],
+            ..container };
+        let options = SerializeOptions { root: Some("SequenceContainer".to_string()),
+            ..Default::default() };
+        assert_eq!(round_trip(&fixed, &options), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<SequenceContainer name=\"Header\" offsets=\"0 8 -16\">",
+            "<Size><Fixed>32</Fixed></Size>",
+            "</SequenceContainer>\n"));
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Note {
+        #[serde(rename = "@a")]
+        a: String,
+        #[serde(rename = "$text")]
+        text: String,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Row {
+        #[serde(rename = "Cell", default)]
+        cells: Vec<i32>,
+        #[serde(rename = "@list")]
+        list: Vec<i32>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Table {
+        #[serde(rename = "Row", default)]
+        rows: Vec<Row>,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Nested {
+        #[serde(rename = "Row")]
+        rows: Vec<Vec<i32>>,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct NestedList {
+        #[serde(rename = "@list")]
+        list: Vec<Vec<i32>>,
+    }
+
+    #[test]
+    fn test_empty_and_nested() {
+        println!("Running test {}", function_name!());
+        // Empty text and attributes
+        let note = Note { a: String::new(), text: String::new() };
+        assert_eq!(round_trip(&note, &SerializeOptions::default()), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Note a=\"\"/>\n"));
+
+        // Sequences within sequences are wrapped in an element
+        let table = Table { rows: vec![
+            Row { cells: vec![1, 2], list: vec![3, 4] },
+            Row { cells: vec![], list: vec![] },
+            Row { cells: vec![5], list: vec![6] },
+        ] };
+        assert_eq!(round_trip(&table, &SerializeOptions::default()), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Table>",
+            "<Row list=\"3 4\"><Cell>1</Cell><Cell>2</Cell></Row>",
+            "<Row list=\"\"/>",
+            "<Row list=\"6\"><Cell>5</Cell></Row>",
+            "</Table>\n"));
+
+        // or else they're an error, rather than running together
+        let nested = Nested { rows: vec![vec![1, 2], vec![3]] };
+        match to_string(&nested, &SerializeOptions::default()) {
+            Err(XmlDocumentError::Custom(message)) => assert_eq!(message,
+                "can't write a sequence within a sequence as <Row> elements"),
+            result => panic!("expected an error, got {:?}", result),
+        }
+        let nested = NestedList { list: vec![vec![1, 2], vec![3]] };
+        match to_string(&nested, &SerializeOptions::default()) {
+            Err(XmlDocumentError::Custom(message)) =>
+                assert_eq!(message, "can't write a list within a list as text"),
+            result => panic!("expected an error, got {:?}", result),
+        }
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Mixed {
+        #[serde(rename = "b", default)]
+        b: Vec<String>,
+        #[serde(rename = "$text")]
+        text: String,
+    }
+
+    #[test]
+    fn test_blank_text() {
+        println!("Running test {}", function_name!());
+        // Whitespace beside subelements is kept with xml:space="preserve",
+        // even when indenting
+        let mixed = Mixed { b: vec!["x".to_string()], text: " ".to_string() };
+        assert_eq!(round_trip(&mixed, &SerializeOptions::default()), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Mixed xml:space=\"preserve\"><b>x</b> </Mixed>\n"));
+        let options = SerializeOptions { indent: Some(2), ..Default::default() };
+        assert_eq!(round_trip(&mixed, &options), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Mixed xml:space=\"preserve\"><b>x</b> </Mixed>\n"));
+
+        // but isn't needed without them
+        let mixed = Mixed { b: vec![], text: "\t".to_string() };
+        assert_eq!(round_trip(&mixed, &options), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Mixed>\t</Mixed>\n"));
+        let mixed = Mixed { b: vec!["x".to_string()], text: String::new() };
+        assert_eq!(round_trip(&mixed, &options), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Mixed>\n",
+            "  <b>x</b>\n",
+            "</Mixed>\n"));
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Floats {
+        #[serde(rename = "@single")]
+        single: f32,
+        #[serde(rename = "Double")]
+        doubles: Vec<f64>,
+    }
+
+    #[test]
+    fn test_floats() {
+        println!("Running test {}", function_name!());
+        let floats = Floats {
+            single:     3.4e38,
+            doubles:    vec![1.5e300, -2.5e-300, 0.1, 1e15, 123.25, 0.0, 1e-7, f64::INFINITY,
+                f64::NEG_INFINITY, f64::MAX, f64::MIN_POSITIVE],
+        };
+        assert_eq!(round_trip(&floats, &SerializeOptions::default()), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Floats single=\"3.4e38\">",
+            "<Double>1.5e300</Double><Double>-2.5e-300</Double><Double>0.1</Double>",
+            "<Double>1000000000000000</Double><Double>123.25</Double><Double>0</Double>",
+            "<Double>1e-7</Double><Double>INF</Double><Double>-INF</Double>",
+            "<Double>1.7976931348623157e308</Double><Double>2.2250738585072014e-308</Double>",
+            "</Floats>\n"));
+
+        let nan = to_string(&Floats { single: f32::NAN, doubles: vec![] },
+            &SerializeOptions::default()).unwrap();
+        assert!(nan.contains("single=\"NaN\""));
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Typed {
+        #[serde(rename = "@xsi:type")]
+        xsi_type: String,
+        #[serde(rename = "xtce:LongDescription")]
+        description: String,
+        #[serde(rename = "xtce:Alias")]
+        aliases: Vec<String>,
+    }
+
+    #[test]
+    fn test_namespaces() {
+        println!("Running test {}", function_name!());
+        let typed = Typed {
+            xsi_type:       "xtce:IntegerParameterType".to_string(),
+            description:    "Counts".to_string(),
+            aliases:        vec!["n".to_string(), "count".to_string()],
+        };
+        let options = SerializeOptions {
+            root:       Some("xtce:ParameterType".to_string()),
+            namespaces: vec![
+                ("xtce".to_string(), "http://www.omg.org/spec/XTCE/20180204".to_string()),
+                ("xsi".to_string(), "http://www.w3.org/2001/XMLSchema-instance".to_string()),
+            ],
+            indent:     None,
+        };
+        assert_eq!(round_trip(&typed, &options), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<xtce:ParameterType xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" ",
+            "xmlns:xtce=\"http://www.omg.org/spec/XTCE/20180204\" ",
+            "xsi:type=\"xtce:IntegerParameterType\">",
+            "<xtce:LongDescription>Counts</xtce:LongDescription>",
+            "<xtce:Alias>n</xtce:Alias><xtce:Alias>count</xtce:Alias>",
+            "</xtce:ParameterType>\n"));
+
+        // Every prefix has to be declared
+        let options = SerializeOptions { namespaces: options.namespaces[..1].to_vec(),
+            ..options };
+        match to_string(&typed, &options) {
+            Err(XmlDocumentError::UnboundPrefix(..)) => {},
+            result => panic!("expected UnboundPrefix, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_indent() {
+        println!("Running test {}", function_name!());
+        let parameter = Parameter {
+            name:           "A".to_string(),
+            encoding:       None,
+            size_in_bits:   8,
+            description:    None,
+        };
+        let container = Container {
+            name:       "Header".to_string(),
+            offsets:    vec![],
+            parameters: vec![parameter],
+            size:       None,
+            entries:    vec![Entry::ContainerRefEntry("Body".to_string())],
+        };
+        let options = SerializeOptions { indent: Some(2), ..Default::default() };
+        assert_eq!(round_trip(&container, &options), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Container name=\"Header\" offsets=\"\">\n",
+            "  <Parameter name=\"A\" sizeInBits=\"8\"/>\n",
+            "  <ContainerRefEntry>Body</ContainerRefEntry>\n",
+            "</Container>\n"));
+
+        // Values that aren't an element
+        match to_string(&vec![1, 2], &SerializeOptions::default()) {
+            Err(XmlDocumentError::Custom(message)) =>
+                assert_eq!(message, "no element name for text"),
+            result => panic!("expected an error, got {:?}", result),
+        }
+    }
+}
@@ -1,22 +1,14 @@
-/* 
- * FIXME: This should probably go away
- */
-
 /*
-/*
- * Trait for building recursive walk types
+ * Walk an XML tree. Each element gets an Accumulator, which is given the
+ * result of walking each of its subelements and then produces the result
+ * for the element. Errors stop the walk and are returned from walk().
  */
 
-//use std::borrow::Borrow;
-//use std::cell::RefCell;
-use std::ops::{FromResidual, Try};
-
-// FIXME: delete xml_tree::Element
-use crate::xml_tree::XmlTree;
-use crate::parse_tree::Element;
+use crate::element::Element;
 
 /**
- * Data for the Element being worked on by walk_down().
+ * Data for the Element being worked on by walk(), passed down from its
+ * parent
  */
 pub trait ElemData<AC, ED>
 {
@@ -24,232 +16,137 @@ pub trait ElemData<AC, ED>
 }
 
 /**
- * Data returned by Accumulator functions.
- */
-pub trait WalkData {}
-
-/*
-/**
- * Data stored at the root level of the Walkable and a reference to which is
- * returned by the Walkable base_level_cell() function.
- */
-pub trait BaseLevel {}
-*/
-
-/**
- * Data stored for the peers of the Element a given invocation of walk_down()
+ * Accumulates the results for the subelements of an Element
+ * BL:  Data for the walk as a whole
+ * ED:  ElemData for the Element
+ * WD:  Data produced for each Element
+ * E:   Error that stops the walk
  */
-pub trait Accumulator<'a, BL, ED, WD, WR> {
+pub trait Accumulator<BL, ED, WD, E> {
     fn new(bl: &mut BL, e: &dyn Element, ed: &ED) -> Self
     where
         Self: Sized;
-    fn add(&mut self, wd: &WD, ed: &ED) -> WR;
-    fn summary(&self, bl: &mut BL) -> WR;
+    fn add(&mut self, bl: &mut BL, wd: WD, ed: &ED) -> Result<(), E>;
+    fn summary(self, bl: &mut BL) -> Result<WD, E>;
 }
 
 /**
- * This is the core code for walking an XmlTree. Use it to implement
- * a struct. The structs that need to be implemented are:
- * Accumulator
- * BaseLevel
- * ElemData
- * WalkData
- * WalkResult
+ * This is the core code for walking an XML tree, depth first.
+ *
+ * bl:      Data for the walk as a whole
+ * element: Element at the top of the tree to be walked
+ * ed:      ElemData for element
+ *
+ * Returns:
+ * Ok(WD)   The result of AC::summary() for element
+ * Err(E)   The first error returned by an Accumulator
  */
-pub fn walk<'a, AC, BL, ED, WD, WR>(bl: &mut BL, xml_doc: &XmlTree, ed: &ED) -> WR
-where
-    AC: Accumulator<'a, BL, ED, WD, WR>,
-    ED: ElemData<AC, ED>,
-    WR: Try<Output = WD>,
-    WR: FromResidual,
-{
-    // Seems inelegant
-    walk_down::<AC, BL, ED, WD, WR>(bl, &vec!(xml_doc.root.clone()), ed)
-}
-
-// FIXME: this assumes the root has only one element
-fn walk_down<'a, AC, BL, ED, WD, WR>(bl: &mut BL, elements: &Vec<Box<dyn Element>>, ed: &ED) -> WR
+pub fn walk<AC, BL, ED, WD, E>(bl: &mut BL, element: &dyn Element, ed: &ED) -> Result<WD, E>
 where
-    AC: Accumulator<'a, BL, ED, WD, WR>,
+    AC: Accumulator<BL, ED, WD, E>,
     ED: ElemData<AC, ED>,
-    WR: Try<Output = WD>,
-    WR: FromResidual,
 {
-    let mut acc = AC::new(bl, &*(elements[0]), ed);
-
-    // Process subelements and collect WalkData results in a vector
-//    let next_ed = ed.next_level(&acc, &(*elements)[0]);
-    let next_ed = ed.next_level(&acc, &*(elements)[0]);
-
-    let mut wd_vec = Vec::<WD>::new();
+    let mut acc = AC::new(bl, element, ed);
+    let next_ed = ed.next_level(&acc, element);
 
-    for elem in (*elements)[0].subelements() {
-        let wd = walk_down::<AC, BL, ED, WD, WR>(bl, elem.subelements(), &next_ed)?;
-        wd_vec.push(wd);
+    for subelement in element.subelements() {
+        let wd = walk::<AC, BL, ED, WD, E>(bl, subelement.as_ref(), &next_ed)?;
+        acc.add(bl, wd, &next_ed)?;
     }
 
-    // Accumulate results
-    for wd in &wd_vec {
-        acc.add(wd, &next_ed)?;
-    }
     acc.summary(bl)
 }
-/*
-}
-*/
 
 #[cfg(test)]
-mod test_tests {
-    use std::cell::RefCell;
-    use std::fmt;
+mod tests {
+    use stdext::function_name;
 
-    use crate::xml_document::{create_test_doc, TreeElement, Element, XmlTree};
-    use crate::parse_tree::{DocumentInfo};
-    use crate::walkable::{Accumulator, BaseLevel, ElemData, WalkData, Walkable};
+    use crate::element::Element;
+    use crate::parse_tree::parse;
 
-    const INDENT: &str = "    ";
-
-    #[derive(Debug)]
-    pub enum TestWalkError {
-    }
-
-    impl std::error::Error for TestWalkError {}
-
-    impl fmt::Display for TestWalkError {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "{:?}", self)
-        }
-    }
+    use super::{walk, Accumulator, ElemData};
 
-    type TestWalkResult = Result<TestWalkData, TestWalkError>;
+    const INDENT: &str = "    ";
 
     /**
-     * We don't have any data at the base level as it's all returned
-     * directly
+     * Count of the elements visited
      */
-    pub struct TestBaseLevel {}
-
-    impl TestBaseLevel {
-        pub fn new() -> TestBaseLevel {
-            TestBaseLevel {}
-        }
+    pub struct TestBaseLevel {
+        visited: usize,
     }
 
-    impl BaseLevel for TestBaseLevel {}
-
     /**
-     * Keep track of the depth of nexting
+     * Keep track of the depth of nesting
      */
     pub struct TestElemData {
         depth:  usize,
     }
 
-    impl TestElemData {
-        pub fn new(depth: usize) -> TestElemData {
-            TestElemData {
-                depth:  depth,
-            }
-        }
-    }
-
-    impl ElemData<TestElemData> for TestElemData {
-        fn next_level(&self, &acc: Accumulator, _element: &Box<dyn Element>) -> TestElemData {
-            TestElemData::new(self.depth + 1)
+    impl ElemData<TestAccumulator, TestElemData> for TestElemData {
+        fn next_level(&self, _acc: &TestAccumulator, _element: &dyn Element) -> TestElemData {
+            TestElemData { depth: self.depth + 1 }
         }
     }
 
-    #[derive(Debug)]
-    pub struct TestWalkData {
-        pub data: String,
-    }
-    impl TestWalkData {
-        fn new(data: String) -> TestWalkData {
-            TestWalkData {
-                data:   data,
-            }
-        }
-    }
-    impl WalkData for TestWalkData {}
-
+    /**
+     * Builds an indented list of element names. Elements named "bad" are
+     * errors.
+     */
     pub struct TestAccumulator {
         result: String,
+        bad:    bool,
     }
 
-    impl<'a> Accumulator<'a, TestBaseLevel, TestElemData, TestWalkData, TestWalkResult>
-    for TestAccumulator
-    {
-        fn new(bl: &mut TestBaseLevel, e: &'a Box<dyn Element>, ed: &TestElemData) -> Self {
+    impl Accumulator<TestBaseLevel, TestElemData, String, String> for TestAccumulator {
+        fn new(bl: &mut TestBaseLevel, e: &dyn Element, ed: &TestElemData) -> Self {
+            bl.visited += 1;
             TestAccumulator {
-                result: nl_indent(ed.depth) +  e.name.local_name.as_str() + "\n",
+                result: INDENT.repeat(ed.depth) + e.name() + "\n",
+                bad:    e.name() == "bad",
             }
         }
 
-        fn add(&mut self, wd: &TestWalkData) -> TestWalkResult {
-            self.result += &wd.data;
-            Ok(TestWalkData::new(self.result.clone()))
-        }
-
-        fn summary(&self, bl: &mut TestBaseLevel) -> TestWalkResult {
-            Ok(TestWalkData::new(self.result.clone()))
+        fn add(&mut self, _bl: &mut TestBaseLevel, wd: String, _ed: &TestElemData) ->
+            Result<(), String> {
+            self.result += &wd;
+            Ok(())
         }
-    }
 
-    pub struct TestWalkable<'a> {
-        xml_doc:    &'a XmlTree,
-        base:       RefCell<TestBaseLevel>,
-    }
-
-    impl<'a> TestWalkable<'a> {
-        pub fn new(xml_doc: &'a XmlTree, base: TestBaseLevel) -> TestWalkable<'a> {
-            TestWalkable{
-                xml_doc:    xml_doc,
-                base:       RefCell::new(base),
+        fn summary(self, _bl: &mut TestBaseLevel) -> Result<String, String> {
+            match self.bad {
+                false => Ok(self.result),
+                true => Err("bad element".to_string()),
             }
         }
     }
 
-    impl<'a> Walkable<'_, TestAccumulator, TestBaseLevel, TestElemData, TestWalkData, TestWalkResult> for TestWalkable<'_> {
-        fn xml_document(&self) -> &XmlTree {
-            self.xml_doc
-        }
-        /*
-        fn base_level_cell(&self) -> &RefCell<TestBaseLevel> {
-            &self.base
-        }
-        */
-    }
-
-/*
-    pub fn nl_indent(n: usize) -> String {
-        INDENT.repeat(n)
+    fn walk_input(input: &str) -> (Result<String, String>, usize) {
+        let root = parse(input);
+        let mut bl = TestBaseLevel { visited: 0 };
+        let result = walk::<TestAccumulator, _, _, _, _>(&mut bl, root.as_ref(),
+            &TestElemData { depth: 0 });
+        (result, bl.visited)
     }
-*/
 
     #[test]
     fn test_result_return() {
-        let xml_document = create_test_doc();
-
-        println!("Try with a TestWalkResult");
-        println!("-------------------------");
-        let bl1 = TestBaseLevel::new();
-        let ed1 = TestElemData::new(0);
-        let w1 = TestWalkable::new(&xml_document);
-        println!("Display w1:");
-        let res = w1.walk(&bl1, &xml_document, &ed1);
-        match res {
-            Err(e) => println!("Error: {:?}", e),
-            Ok(twd) => {
-                println!("Actual: {:?}", twd.data);
-                let expected = "n1\n".to_owned() +
-                    &nl_indent(1) + "n2\n" +
-                    &nl_indent(1) + "n3\n" +
-                    &nl_indent(2) + "n4\n";
-                println!("Expected: {:?}", expected);
-
-                assert_eq!(twd.data, expected);
-            },
-        };
+        println!("Running test {}", function_name!());
+        let (result, visited) = walk_input("<n1><n2/><n3><n4/></n3></n1>");
+        let expected = "n1\n".to_owned() +
+            INDENT + "n2\n" +
+            INDENT + "n3\n" +
+            INDENT + INDENT + "n4\n";
+        assert_eq!(result, Ok(expected));
+        assert_eq!(visited, 4);
     }
 
+    #[test]
+    fn test_error_return() {
+        println!("Running test {}", function_name!());
+        let (result, visited) = walk_input("<n1><n2><bad/></n2><n3/></n1>");
+        assert_eq!(result, Err("bad element".to_string()));
+
+        // The walk stops at the error, so n3 is never visited
+        assert_eq!(visited, 3);
+    }
 }
-*/
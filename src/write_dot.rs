@@ -0,0 +1,549 @@
+/*
+ * Write Graphviz DOT for element trees, schema graphs and XTCE container
+ * inheritance, e.g. to be rendered with "dot -Tsvg". Large documents and
+ * schemas make unreadable pictures, so each can be limited to a subtree
+ * and a depth with DotOptions:
+ *
+ * o    For an element tree, the subtree is given by a path from the root,
+ *      as for ElementRef::walk_path(), and the depth counts levels of
+ *      subelements. Elements with subelements that aren't drawn get a
+ *      note of how many there are.
+ * o    For a schema derivation graph, the subtree is a type and the types
+ *      derived from it, and for a schema containment graph, it is a
+ *      component and everything it uses. The depth counts the references
+ *      followed.
+ * o    For XTCE containers, the subtree is a container and the containers
+ *      that inherit from it, with the depth counting generations.
+ *
+ * In the graphs, the references from everything drawn are drawn too, so
+ * that, e.g., the base of the root type of a derivation subtree appears.
+ */
+
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::element::Element;
+use crate::navigate::{ElementRef, TreeIndex};
+use crate::parse_item::LineNumber;
+use crate::qname::XTCE_NAMESPACE;
+use crate::schema_graph::{ComponentKind, ReferenceKind, SchemaGraph};
+use crate::write_tree::qualified_name;
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * What to draw
+ * max_depth:   If Some, how far below the root of the subtree to go
+ * attributes:  For element trees, include the attributes in the labels
+ * root:        If Some, draw only the subtree starting here
+ */
+#[derive(Clone, Debug, Default)]
+pub struct DotOptions {
+    pub max_depth:  Option<usize>,
+    pub attributes: bool,
+    pub root:       Option<String>,
+}
+
+impl DotOptions {
+    fn within_depth(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+    }
+}
+
+/**
+ * Which references of a schema graph to draw
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaView {
+    /// Extensions and restrictions between named types
+    Derivation,
+    /// Everything else: types of elements and attributes, refs and
+    /// substitution groups
+    Containment,
+}
+
+/**
+ * Write an element tree
+ */
+pub fn write_tree_dot<W: Write>(writer: &mut W, root: &dyn Element, options: &DotOptions) ->
+    Result<(), XmlDocumentError> {
+    let index = TreeIndex::new(root);
+    let top = match &options.root {
+        None => index.root(),
+        Some(path) => index.root().walk_path(path)
+            .ok_or_else(|| XmlDocumentError::NotFound(path.clone()))?,
+    };
+
+    let mut dot = Dot::new(writer, "tree");
+    dot.line("node [shape=box];");
+    tree_node(&mut dot, top, 0, options);
+    let mut descendants = top.descendants();
+    while let Some(element_ref) = descendants.next() {
+        let depth = element_ref.depth() - top.depth();
+        if !options.within_depth(depth) {
+            descendants.skip_subtree();
+            continue;
+        }
+        tree_node(&mut dot, element_ref, depth, options);
+        let parent = element_ref.parent().expect("only the root has no parent");
+        dot.edge(&format!("e{}", parent.position()), &format!("e{}", element_ref.position()),
+            "");
+    }
+    dot.finish()
+}
+
+/*
+ * Write the node for an element, and a note of how many subelements it
+ * has if they are too deep to be drawn
+ */
+fn tree_node<W: Write>(dot: &mut Dot<W>, element_ref: ElementRef, depth: usize,
+    options: &DotOptions) {
+    let element = element_ref.element();
+    let id = format!("e{}", element_ref.position());
+    let mut label = qualified_name(&element.element_info().owned_name).to_string();
+    if options.attributes {
+        for attribute in &element.element_info().attributes {
+            label.push_str(&format!("\n{}={}", qualified_name(&attribute.name),
+                attribute.value));
+        }
+    }
+    dot.node(&id, &label, "");
+
+    let hidden = element.subelements().len();
+    if hidden > 0 && !options.within_depth(depth + 1) {
+        let more = format!("{}_more", id);
+        dot.node(&more, &format!("{} more", hidden), "shape=plaintext");
+        dot.edge(&id, &more, "style=dotted");
+    }
+}
+
+/**
+ * Write the derivation or containment graph of schemas. The root of the
+ * subtree is the local name of a component, types being preferred.
+ */
+pub fn write_schema_dot<W: Write>(writer: &mut W, schema_graph: &SchemaGraph,
+    view: SchemaView, options: &DotOptions) -> Result<(), XmlDocumentError> {
+    let graph = schema_graph.graph();
+    let in_view = |kind: ReferenceKind|
+        kind.is_derivation() == (view == SchemaView::Derivation);
+
+    let roots = match &options.root {
+        None => graph.node_indices()
+            .filter(|node| graph.edges_directed(*node, Direction::Outgoing)
+                .any(|edge| in_view(edge.weight().kind)))
+            .collect(),
+        Some(root) => {
+            let node = [ComponentKind::Type, ComponentKind::Element, ComponentKind::Group,
+                ComponentKind::AttributeGroup, ComponentKind::Attribute].iter()
+                .find_map(|kind| graph.node_indices().find(|node| {
+                    let component = &graph[*node];
+                    component.kind == *kind && component.name.local_name == *root
+                }))
+                .ok_or_else(|| XmlDocumentError::NotFound(root.clone()))?;
+            vec![node]
+        },
+    };
+    // Derived types point at their bases, so the derivation subtree is
+    // found by going against the edges
+    let direction = match view {
+        SchemaView::Derivation => Direction::Incoming,
+        SchemaView::Containment => Direction::Outgoing,
+    };
+    let selected = select(graph, &roots, direction, |reference| in_view(reference.kind),
+        options);
+
+    let name = match view {
+        SchemaView::Derivation => "derivation",
+        SchemaView::Containment => "containment",
+    };
+    let mut dot = Dot::new(writer, name);
+    if view == SchemaView::Derivation {
+        dot.line("rankdir=BT;");
+    }
+    let mut drawn = vec![false; graph.node_count()];
+    let mut draw = |dot: &mut Dot<W>, node: NodeIndex| {
+        if !std::mem::replace(&mut drawn[node.index()], true) {
+            let component = &graph[node];
+            let (shape, mut styles) = match (component.kind, component.is_complex_type()) {
+                (ComponentKind::Type, true) => ("box", vec![]),
+                (ComponentKind::Type, false) => ("ellipse", vec![]),
+                (ComponentKind::Element, _) => ("box", vec!["rounded"]),
+                _ => ("hexagon", vec![]),
+            };
+            if component.definition.is_none() {
+                styles.push("dashed");
+            }
+            let style = match styles.is_empty() {
+                true => format!("shape={}", shape),
+                false => format!("shape={}, style=\"{}\"", shape, styles.join(",")),
+            };
+            dot.node(&format!("n{}", node.index()), &component.name.local_name, &style);
+        }
+    };
+
+    for node in graph.node_indices().filter(|node| selected[node.index()]) {
+        draw(&mut dot, node);
+        let mut targets: Vec<(NodeIndex, ReferenceKind)> =
+            graph.edges_directed(node, Direction::Outgoing)
+                .filter(|edge| in_view(edge.weight().kind))
+                .map(|edge| (edge.target(), edge.weight().kind))
+                .collect();
+        targets.sort_by_key(|(target, _)| *target);
+        targets.dedup_by_key(|(target, _)| *target);
+        for (target, kind) in targets {
+            draw(&mut dot, target);
+            let style = match kind {
+                ReferenceKind::Extension => "arrowhead=empty",
+                ReferenceKind::Restriction => "arrowhead=empty, style=dashed",
+                ReferenceKind::Ref => "arrowhead=vee",
+                ReferenceKind::SubstitutionGroup => "arrowhead=vee, style=dashed",
+                ReferenceKind::Type => "",
+            };
+            dot.edge(&format!("n{}", node.index()), &format!("n{}", target.index()), style);
+        }
+    }
+    dot.finish()
+}
+
+/*
+ * How one XTCE container refers to another
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ContainerLink {
+    Base,
+    Entry,
+}
+
+/*
+ * An XTCE container
+ * name:    Name of the container
+ * lineno:  Line number of its definition, or None if it is only referred to
+ */
+struct Container {
+    name:   String,
+    lineno: Option<LineNumber>,
+}
+
+/**
+ * Write the inheritance of the XTCE SequenceContainers in a document,
+ * along with the containers each one includes with a ContainerRefEntry.
+ * Containers are matched by name, ignoring any SpaceSystem path in a
+ * containerRef. The root of the subtree is the name of a container.
+ */
+pub fn write_container_dot<W: Write>(writer: &mut W, root: &dyn Element,
+    options: &DotOptions) -> Result<(), XmlDocumentError> {
+    let mut graph = DiGraph::<Container, ContainerLink>::new();
+    let mut nodes = HashMap::new();
+    let mut node = |graph: &mut DiGraph<Container, ContainerLink>, name: &str| {
+        let name = name.rsplit('/').next().unwrap_or(name);
+        *nodes.entry(name.to_string()).or_insert_with(|| graph.add_node(Container {
+            name:   name.to_string(),
+            lineno: None,
+        }))
+    };
+
+    let index = TreeIndex::new(root);
+    for element_ref in std::iter::once(index.root()).chain(index.root().descendants()) {
+        let element_info = element_ref.element().element_info();
+        if !element_info.is(XTCE_NAMESPACE, "SequenceContainer") {
+            continue;
+        }
+        let name = element_info.required_attribute("name")?;
+        let container = node(&mut graph, name);
+        graph[container].lineno = Some(element_info.lineno());
+
+        for descendant in element_ref.descendants() {
+            let descendant_info = descendant.element().element_info();
+            let link = match descendant_info.owned_name.local_name.as_str() {
+                "BaseContainer" => ContainerLink::Base,
+                "ContainerRefEntry" => ContainerLink::Entry,
+                _ => continue,
+            };
+            if descendant_info.owned_name.namespace.as_deref() != Some(XTCE_NAMESPACE) {
+                continue;
+            }
+            let target = node(&mut graph, descendant_info.required_attribute("containerRef")?);
+            graph.add_edge(container, target, link);
+        }
+    }
+
+    let roots = match &options.root {
+        None => graph.node_indices().collect(),
+        Some(root) => vec![*nodes.get(root.as_str())
+            .ok_or_else(|| XmlDocumentError::NotFound(root.clone()))?],
+    };
+    let selected = select(&graph, &roots, Direction::Incoming,
+        |link| *link == ContainerLink::Base, options);
+
+    let mut dot = Dot::new(writer, "containers");
+    dot.line("rankdir=BT;");
+    dot.line("node [shape=box];");
+    let mut drawn = vec![false; graph.node_count()];
+    for node in graph.node_indices().filter(|node| selected[node.index()]) {
+        let targets: Vec<_> = graph.edges_directed(node, Direction::Outgoing)
+            .map(|edge| (edge.target(), *edge.weight()))
+            .collect();
+        for node in std::iter::once(node).chain(targets.iter().map(|(target, _)| *target)) {
+            if !std::mem::replace(&mut drawn[node.index()], true) {
+                let style = match graph[node].lineno {
+                    Some(_) => "",
+                    None => "style=dashed",
+                };
+                dot.node(&format!("c{}", node.index()), &graph[node].name, style);
+            }
+        }
+        for (target, link) in targets {
+            let style = match link {
+                ContainerLink::Base => "arrowhead=empty",
+                ContainerLink::Entry => "arrowhead=diamond, style=dashed",
+            };
+            dot.edge(&format!("c{}", node.index()), &format!("c{}", target.index()), style);
+        }
+    }
+    dot.finish()
+}
+
+/*
+ * Nodes in the subtrees starting at the roots, found by following the
+ * edges that pass the filter in the given direction, as far as the
+ * maximum depth
+ */
+fn select<N, E, F>(graph: &DiGraph<N, E>, roots: &[NodeIndex], direction: Direction,
+    follow: F, options: &DotOptions) -> Vec<bool>
+where
+    F: Fn(&E) -> bool,
+{
+    let mut selected = vec![false; graph.node_count()];
+    let mut queue: VecDeque<(NodeIndex, usize)> = roots.iter().map(|root| (*root, 0)).collect();
+
+    while let Some((node, depth)) = queue.pop_front() {
+        if std::mem::replace(&mut selected[node.index()], true) {
+            continue;
+        }
+        if !options.within_depth(depth + 1) {
+            continue;
+        }
+        for edge in graph.edges_directed(node, direction).filter(|edge| follow(edge.weight())) {
+            let next = match direction {
+                Direction::Incoming => edge.source(),
+                Direction::Outgoing => edge.target(),
+            };
+            queue.push_back((next, depth + 1));
+        }
+    }
+    selected
+}
+
+/*
+ * DOT output, keeping the first error
+ */
+struct Dot<'a, W: Write> {
+    writer: &'a mut W,
+    error:  Option<io::Error>,
+}
+
+impl<'a, W: Write> Dot<'a, W> {
+    fn new(writer: &'a mut W, name: &str) -> Self {
+        let mut dot = Dot { writer, error: None };
+        dot.write(format_args!("digraph {} {{\n", name));
+        dot
+    }
+
+    fn write(&mut self, args: std::fmt::Arguments) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.write_fmt(args) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        self.write(format_args!("    {}\n", line));
+    }
+
+    fn node(&mut self, id: &str, label: &str, style: &str) {
+        match style {
+            "" => self.write(format_args!("    {} [label={}];\n", id, quote(label))),
+            style => self.write(format_args!("    {} [label={}, {}];\n", id, quote(label),
+                style)),
+        }
+    }
+
+    fn edge(&mut self, from: &str, to: &str, style: &str) {
+        match style {
+            "" => self.write(format_args!("    {} -> {};\n", from, to)),
+            style => self.write(format_args!("    {} -> {} [{}];\n", from, to, style)),
+        }
+    }
+
+    fn finish(mut self) -> Result<(), XmlDocumentError> {
+        self.write(format_args!("}}\n"));
+        match self.error {
+            None => Ok(()),
+            Some(e) => Err(XmlDocumentError::IoError("DOT output".to_string(), Arc::new(e))),
+        }
+    }
+}
+
+/*
+ * Quote a string for DOT, with each line left-justified
+ */
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\l"),
+            '\r' => {},
+            c => quoted.push(c),
+        }
+    }
+    if text.contains('\n') {
+        quoted.push_str("\\l");
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use crate::element::Element;
+    use crate::parse_tree::parse;
+    use crate::schema_graph::SchemaGraph;
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::{write_container_dot, write_schema_dot, write_tree_dot, DotOptions, SchemaView};
+
+    fn tree_dot(root: &dyn Element, options: &DotOptions) -> Result<String, XmlDocumentError> {
+        let mut output = Vec::new();
+        write_tree_dot(&mut output, root, options)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_tree() {
+        println!("Running test {}", function_name!());
+        let root = parse(concat!(
+            "<SpaceSystem name=\"Top\">",
+            "<TelemetryMetaData><ParameterSet><Parameter name=\"A\"/><Parameter name=\"B\"/>",
+            "</ParameterSet></TelemetryMetaData>",
+            "<LongDescription lang=\"en\">Say \"hi\"</LongDescription>",
+            "</SpaceSystem>"));
+
+        let options = DotOptions { max_depth: Some(1), attributes: true, root: None };
+        assert_eq!(tree_dot(root.as_ref(), &options).unwrap(), concat!(
+            "digraph tree {\n",
+            "    node [shape=box];\n",
+            "    e0 [label=\"SpaceSystem\\lname=Top\\l\"];\n",
+            "    e1 [label=\"TelemetryMetaData\"];\n",
+            "    e1_more [label=\"1 more\", shape=plaintext];\n",
+            "    e1 -> e1_more [style=dotted];\n",
+            "    e0 -> e1;\n",
+            "    e5 [label=\"LongDescription\\llang=en\\l\"];\n",
+            "    e0 -> e5;\n",
+            "}\n"));
+
+        let options = DotOptions { root: Some("TelemetryMetaData/ParameterSet".to_string()),
+            ..Default::default() };
+        assert_eq!(tree_dot(root.as_ref(), &options).unwrap(), concat!(
+            "digraph tree {\n",
+            "    node [shape=box];\n",
+            "    e2 [label=\"ParameterSet\"];\n",
+            "    e3 [label=\"Parameter\"];\n",
+            "    e2 -> e3;\n",
+            "    e4 [label=\"Parameter\"];\n",
+            "    e2 -> e4;\n",
+            "}\n"));
+
+        let options = DotOptions { root: Some("CommandMetaData".to_string()),
+            ..Default::default() };
+        match tree_dot(root.as_ref(), &options) {
+            Err(XmlDocumentError::NotFound(root)) => assert_eq!(root, "CommandMetaData"),
+            result => panic!("expected NotFound, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        println!("Running test {}", function_name!());
+        let schema_graph = SchemaGraph::from_path("schema/SpaceSystem-patched.xsd").unwrap();
+        let schema_dot = |view, options: &DotOptions| {
+            let mut output = Vec::new();
+            write_schema_dot(&mut output, &schema_graph, view, options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // The subtree of types derived from ContainerType, along with its
+        // own base
+        let options = DotOptions { root: Some("ContainerType".to_string()),
+            ..Default::default() };
+        assert_eq!(schema_dot(SchemaView::Derivation, &options), concat!(
+            "digraph derivation {\n",
+            "    rankdir=BT;\n",
+            "    n17 [label=\"ContainerType\", shape=box];\n",
+            "    n267 [label=\"NameDescriptionType\", shape=box];\n",
+            "    n17 -> n267 [arrowhead=empty];\n",
+            "    n30 [label=\"SequenceContainerType\", shape=box];\n",
+            "    n30 -> n17 [arrowhead=empty];\n",
+            "    n94 [label=\"CommandContainerType\", shape=box];\n",
+            "    n94 -> n17 [arrowhead=empty];\n",
+            "}\n"));
+
+        // Containment, one reference deep
+        let options = DotOptions { root: Some("SequenceContainerType".to_string()),
+            max_depth: Some(1), ..Default::default() };
+        let dot = schema_dot(SchemaView::Containment, &options);
+        assert!(dot.contains("[label=\"BaseContainerType\", shape=box]"), "{}", dot);
+        assert!(!dot.contains("arrowhead=empty"));
+    }
+
+    #[test]
+    fn test_containers() {
+        println!("Running test {}", function_name!());
+        let root = parse(concat!(
+            "<SpaceSystem xmlns=\"http://www.omg.org/spec/XTCE/20180204\" name=\"S\">\n",
+            "  <TelemetryMetaData><ContainerSet>\n",
+            "    <SequenceContainer name=\"Header\" abstract=\"true\"><EntryList/>",
+            "</SequenceContainer>\n",
+            "    <SequenceContainer name=\"Packet\">\n",
+            "      <EntryList><ContainerRefEntry containerRef=\"Trailer\"/></EntryList>\n",
+            "      <BaseContainer containerRef=\"/S/Header\"/>\n",
+            "    </SequenceContainer>\n",
+            "    <SequenceContainer name=\"Housekeeping\">\n",
+            "      <EntryList/><BaseContainer containerRef=\"Packet\"/>\n",
+            "    </SequenceContainer>\n",
+            "    <SequenceContainer name=\"Other\"><EntryList/>",
+            "<BaseContainer containerRef=\"Header\"/></SequenceContainer>\n",
+            "  </ContainerSet></TelemetryMetaData>\n",
+            "</SpaceSystem>\n"));
+        let container_dot = |options: &DotOptions| {
+            let mut output = Vec::new();
+            write_container_dot(&mut output, root.as_ref(), options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let options = DotOptions { root: Some("Packet".to_string()), ..Default::default() };
+        assert_eq!(container_dot(&options), concat!(
+            "digraph containers {\n",
+            "    rankdir=BT;\n",
+            "    node [shape=box];\n",
+            "    c1 [label=\"Packet\"];\n",
+            "    c0 [label=\"Header\"];\n",
+            "    c2 [label=\"Trailer\", style=dashed];\n",
+            "    c1 -> c0 [arrowhead=empty];\n",
+            "    c1 -> c2 [arrowhead=diamond, style=dashed];\n",
+            "    c3 [label=\"Housekeeping\"];\n",
+            "    c3 -> c1 [arrowhead=empty];\n",
+            "}\n"));
+
+        let dot = container_dot(&DotOptions::default());
+        assert_eq!(dot.matches("[label=").count(), 5);
+        assert_eq!(dot.matches("->").count(), 4);
+    }
+}
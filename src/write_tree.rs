@@ -0,0 +1,338 @@
+/*
+ * Write an XML tree back out as XML text. Everything the parser keeps is
+ * written back: the XML declaration, if there was one, comments and
+ * processing instructions around the root element, namespace declarations
+ * and attributes, in the order they were written, text, CDATA sections,
+ * comments and processing instructions. Parsing the output gives the same
+ * tree as parsing the original input.
+ *
+ * Things xml-rs doesn't report, such as the whitespace outside the root
+ * element and within tags, the quotes used around attribute values, and
+ * entity and character references, can't be reproduced, so the output
+ * may differ from the input in those ways.
+ */
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use xml::common::XmlVersion;
+use xml::escape::{escape_str_attribute, escape_str_pcdata};
+use xml::name::OwnedName;
+use xml::namespace::{Namespace, NS_EMPTY_URI, NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XML_PREFIX};
+use xml::reader::XmlEvent;
+
+use crate::document::DocumentInfo;
+use crate::element::{Element, ElementInfo};
+
+/**
+ * Write a document, starting with the XML declaration if the document
+ * info says it has one. The output is always UTF-8, so any other encoding
+ * is declared as UTF-8.
+ * writer:          Where to write the document
+ * document_info:   Information for the XML declaration
+ * root:            Root element of the document
+ */
+pub fn write_document<W: Write>(writer: &mut W, document_info: &DocumentInfo,
+    root: &dyn Element) -> io::Result<()> {
+    write_declaration(writer, document_info)?;
+
+    // Whitespace outside the root element isn't kept, so each item goes on
+    // a line of its own
+    for event in root.before_element() {
+        write_event(writer, event)?;
+        writeln!(writer)?;
+    }
+
+    write_tag(writer, root, &Namespace::empty())?;
+
+    for event in root.after_element() {
+        writeln!(writer)?;
+        write_event(writer, event)?;
+    }
+    writeln!(writer)
+}
+
+/**
+ * Write an element and everything in it, without the items before and
+ * after it. Namespace declarations are written for all the namespaces in
+ * scope, so the result stands on its own.
+ */
+pub fn write_element<W: Write>(writer: &mut W, element: &dyn Element) -> io::Result<()> {
+    write_tag(writer, element, &Namespace::empty())
+}
+
+pub(crate) fn write_declaration<W: Write>(writer: &mut W, document_info: &DocumentInfo) -> io::Result<()> {
+    if !document_info.declared {
+        return Ok(());
+    }
+    let version = match document_info.version {
+        XmlVersion::Version10 => "1.0",
+        XmlVersion::Version11 => "1.1",
+    };
+    let encoding = match document_info.encoding.to_ascii_uppercase().as_str() {
+        "UTF-8" | "UTF8" | "US-ASCII" | "ASCII" => document_info.encoding.as_str(),
+        _ => "UTF-8",
+    };
+
+    write!(writer, "<?xml version=\"{}\" encoding=\"{}\"", version, encoding)?;
+    if let Some(standalone) = document_info.standalone {
+        write!(writer, " standalone=\"{}\"", if standalone { "yes" } else { "no" })?;
+    }
+    writeln!(writer, "?>")
+}
+
+/*
+ * Write an element, given the namespaces in scope for its parent
+ */
+pub(crate) fn write_tag<W: Write>(writer: &mut W, element: &dyn Element,
+    parent_namespace: &Namespace) -> io::Result<()> {
+    let element_info = element.element_info();
+
+    write!(writer, "<{}", qualified_name(&element_info.owned_name))?;
+    for attribute in tag_attributes(element_info, parent_namespace) {
+        write!(writer, " {}", attribute)?;
+    }
+
+    if element.subelements().is_empty() && element.content().is_empty() {
+        return write!(writer, "/>");
+    }
+    write!(writer, ">")?;
+
+    for subelement in element.subelements() {
+        for event in subelement.before_element() {
+            write_event(writer, event)?;
+        }
+        write_tag(writer, subelement.as_ref(), &element_info.namespace)?;
+    }
+    for event in element.content() {
+        write_event(writer, event)?;
+    }
+
+    write!(writer, "</{}>", qualified_name(&element_info.owned_name))
+}
+
+/*
+ * Attributes for the start tag of an element, as name="value". These are
+ * the declarations for the namespaces that are in scope for the element but
+ * weren't for its parent and the element's own attributes, in the order
+ * they were written. Declarations added since then go first and
+ * attributes added since then go last. The xml and xmlns prefixes are
+ * always in scope, so they're never declared.
+ */
+pub(crate) fn tag_attributes(element_info: &ElementInfo, parent_namespace: &Namespace) ->
+    Vec<String> {
+    let mut attributes = Vec::new();
+
+    for (prefix, uri) in namespace_declarations(&element_info.namespace, parent_namespace) {
+        let name = match prefix {
+            NS_NO_PREFIX => Cow::Borrowed("xmlns"),
+            prefix => Cow::Owned(format!("xmlns:{}", prefix)),
+        };
+        let attribute = format!("{}=\"{}\"", name, escape_attribute(uri));
+        attributes.push((name, attribute, 0));
+    }
+
+    for attribute in &element_info.attributes {
+        let name = qualified_name(&attribute.name);
+        let attribute = format!("{}=\"{}\"", name, escape_attribute(&attribute.value));
+        attributes.push((name, attribute, usize::MAX));
+    }
+
+    let order = &element_info.attribute_order;
+    attributes.sort_by_key(|(name, _, added)| order.iter()
+        .position(|written| written == name)
+        .map_or(*added, |position| position + 1));
+    attributes.into_iter().map(|(_, attribute, _)| attribute).collect()
+}
+
+/*
+ * Prefixes and URIs an element with the given namespace has to declare,
+ * given its parent's namespace. The prefix "" is the default namespace.
+ */
+pub(crate) fn namespace_declarations<'a>(namespace: &'a Namespace,
+    parent_namespace: &Namespace) -> Vec<(&'a str, &'a str)> {
+    let mut declarations = Vec::new();
+
+    for (prefix, uri) in namespace {
+        if prefix == NS_XML_PREFIX || prefix == NS_XMLNS_PREFIX ||
+            parent_namespace.get(prefix) == Some(uri) {
+            continue;
+        }
+        // An empty default namespace only needs declaring if it undoes a
+        // default namespace from the parent
+        if prefix == NS_NO_PREFIX && uri == NS_EMPTY_URI && parent_namespace.get(NS_NO_PREFIX)
+            .is_none_or(|uri| uri == NS_EMPTY_URI) {
+            continue;
+        }
+        declarations.push((prefix, uri));
+    }
+
+    declarations
+}
+
+/*
+ * Write text, a CDATA section, a comment or a processing instruction
+ */
+pub(crate) fn write_event<W: Write>(writer: &mut W, event: &XmlEvent) -> io::Result<()> {
+    match event {
+        XmlEvent::Characters(characters) => write!(writer, "{}", escape_text(characters)),
+        XmlEvent::Whitespace(whitespace) => write!(writer, "{}", escape_text(whitespace)),
+        XmlEvent::CData(cdata) => write!(writer, "<![CDATA[{}]]>", cdata),
+        XmlEvent::Comment(comment) => write!(writer, "<!--{}-->", comment),
+        XmlEvent::ProcessingInstruction { name, data } => match data {
+            None => write!(writer, "<?{}?>", name),
+            Some(data) => write!(writer, "<?{} {}?>", name, data),
+        },
+        _ => Ok(()),
+    }
+}
+
+/*
+ * Name as it was written, with its prefix
+ */
+pub(crate) fn qualified_name(owned_name: &OwnedName) -> Cow<'_, str> {
+    match &owned_name.prefix {
+        None => Cow::Borrowed(&owned_name.local_name),
+        Some(prefix) => Cow::Owned(format!("{}:{}", prefix, owned_name.local_name)),
+    }
+}
+
+/*
+ * Escape an attribute value. Tabs are escaped as well as newlines so that
+ * attribute value normalization doesn't turn them into spaces.
+ */
+fn escape_attribute(value: &str) -> Cow<'_, str> {
+    match escape_str_attribute(value) {
+        Cow::Owned(escaped) => Cow::Owned(escaped.replace('\t', "&#x9;")),
+        Cow::Borrowed(escaped) if escaped.contains('\t') =>
+            Cow::Owned(escaped.replace('\t', "&#x9;")),
+        escaped => escaped,
+    }
+}
+
+/*
+ * Escape text. Carriage returns are escaped so that line end
+ * normalization doesn't turn them into newlines.
+ */
+fn escape_text(text: &str) -> Cow<'_, str> {
+    match escape_str_pcdata(text) {
+        Cow::Borrowed(escaped) if escaped.contains('\r') =>
+            Cow::Owned(escaped.replace('\r', "&#xD;")),
+        Cow::Owned(escaped) => Cow::Owned(escaped.replace('\r', "&#xD;")),
+        escaped => escaped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+    use std::fs::File;
+    use std::io::BufReader;
+    use xml::reader::XmlEvent;
+
+    use crate::element::Element;
+    use crate::parse_tree::{parse, parse_document, ParseTree, TreeLevelInfo};
+
+    use super::{write_document, write_element};
+
+    fn serialize(input: &str) -> String {
+        let (document_info, root) = parse_document(input).expect("parse failed");
+        let mut output = Vec::new();
+        write_document(&mut output, &document_info, root.as_ref()).expect("write failed");
+        String::from_utf8(output).expect("output not UTF-8")
+    }
+
+    /*
+     * Check that two trees are the same apart from how they were written
+     */
+    fn assert_same(a: &dyn Element, b: &dyn Element) {
+        assert_eq!(a.qname(), b.qname());
+        let mut a_attributes: Vec<_> = a.attributes()
+            .map(|attribute| (attribute.name.namespace.clone(), attribute.name.local_name.clone(),
+                attribute.value.clone()))
+            .collect();
+        let mut b_attributes: Vec<_> = b.attributes()
+            .map(|attribute| (attribute.name.namespace.clone(), attribute.name.local_name.clone(),
+                attribute.value.clone()))
+            .collect();
+        a_attributes.sort();
+        b_attributes.sort();
+        assert_eq!(a_attributes, b_attributes, "attributes of <{}>", a.name());
+        assert_eq!(a.namespace(), b.namespace(), "namespaces of <{}>", a.name());
+        assert_eq!(a.before_element(), b.before_element(), "before <{}>", a.name());
+        assert_eq!(a.content(), b.content(), "content of <{}>", a.name());
+        assert_eq!(a.after_element(), b.after_element(), "after <{}>", a.name());
+        assert_eq!(a.subelements().len(), b.subelements().len());
+        for (a_sub, b_sub) in a.subelements().iter().zip(b.subelements()) {
+            assert_same(a_sub.as_ref(), b_sub.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_write_document() {
+        println!("Running test {}", function_name!());
+        let input = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n",
+            "<!-- before -->\n",
+            "<?pi data?>\n",
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\" p:x=\"1 &lt; 2 &amp; &quot;3&quot;\">\n",
+            "  <p:b>caf\u{e9} &amp; <![CDATA[<raw>]]></p:b>\n",
+            "  <c xmlns=\"\"><d xmlns:q=\"urn:q\" q:y=\"tab&#9;here\"/></c>\n",
+            "  <!-- inside --><e></e>\n",
+            "</a>\n",
+            "<!-- after -->\n");
+        let expected = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n",
+            "<!-- before -->\n",
+            "<?pi data?>\n",
+            "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\" p:x=\"1 &lt; 2 &amp; &quot;3&quot;\">\n",
+            "  <p:b>caf\u{e9} &amp; <![CDATA[<raw>]]></p:b>\n",
+            "  <c xmlns=\"\"><d xmlns:q=\"urn:q\" q:y=\"tab&#x9;here\"/></c>\n",
+            "  <!-- inside --><e/>\n",
+            "</a>\n",
+            "<!-- after -->\n");
+        assert_eq!(serialize(input), expected);
+
+        // Without a declaration in the input, none is written
+        assert_eq!(serialize("<a>x</a>"), "<a>x</a>\n");
+        assert_eq!(serialize("<?xml version=\"1.0\"?><a>x</a>"),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a>x</a>\n");
+
+        // Namespace declarations and attributes keep their order
+        let input = concat!(
+            "<a id=\"1\" xmlns:z=\"urn:z\" z:b=\"2\" xmlns=\"urn:a\" a=\"3\">",
+            "<b xmlns:q=\"urn:q\" q:y=\"1\" xmlns:p=\"urn:p\" x=\"2\"/></a>\n");
+        assert_eq!(serialize(input), input);
+
+    }
+
+    #[test]
+    fn test_write_element() {
+        println!("Running test {}", function_name!());
+        let root = parse("<a xmlns:p=\"urn:p\"><p:b><c/></p:b></a>");
+        let mut output = Vec::new();
+        write_element(&mut output, root.subelements()[0].as_ref()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "<p:b xmlns:p=\"urn:p\"><c/></p:b>");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        println!("Running test {}", function_name!());
+        // SpaceSystem.xsd has a bare '<' in an attribute value, fixed in
+        // the patched version
+        let path = "schema/SpaceSystem-patched.xsd";
+        let file = File::open(path).expect("Unable to open SpaceSystem-patched.xsd");
+        let (document_info, root) = ParseTree::parse(BufReader::new(file),
+            &TreeLevelInfo::new()).expect("parse failed");
+
+        let mut output = Vec::new();
+        write_document(&mut output, &document_info, root.as_ref()).expect("write failed");
+        let (_, reparsed) = parse_document(&output).expect("reparse failed");
+        assert_same(root.as_ref(), reparsed.as_ref());
+        assert!(matches!(&root.before_element()[..], [XmlEvent::Comment(_)]));
+
+        // Writing the result again gives exactly the same output
+        let mut again = Vec::new();
+        write_document(&mut again, &document_info, reparsed.as_ref()).unwrap();
+        assert_eq!(output, again);
+    }
+}
@@ -1,15 +1,33 @@
 // FIXME: Maybe XmlTreeError
+use std::fmt;
 use std::sync::Arc;
 use thiserror::Error;
 use xml::reader::XmlEvent;
 
-use crate::parse_item::LineNumber;
+use crate::parse_item::SourcePosition;
 
+/*
+ * Errors for XML documents. Errors in the input carry the SourcePosition
+ * of the problem, which is available through position().
+ */
 #[derive(Clone, Debug, Error)]
 pub enum XmlDocumentError {
+    #[error("{0}: Bad value \"{2}\" for attribute \"{1}\": {3}")]
+    BadAttributeValue(SourcePosition, String, String, String),
+
+    #[error("Bad XPath expression \"{0}\": {1}")]
+    BadXPath(String, String),
+
     #[error("Can't insert element \"{0}\", is it a duplication?")]
     CantInsertElement(String),
 
+    // A message from serde, before it is known which element it is about
+    #[error("{0}")]
+    Custom(String),
+
+    #[error("{0}: Can't deserialize <{1}>: {2}")]
+    Deserialize(SourcePosition, String, String),
+
     #[error("Element name \"{0}\" is duplicated in ElementDefs")]
     DuplicateElementDefsName(String),
 
@@ -23,37 +41,56 @@ pub enum XmlDocumentError {
     #[error("XML parse_item error: {0}")]
     Error(Arc<dyn std::error::Error>),
 
-    #[error("Line {0}: Internal error: {1}")]
-    InternalError(LineNumber, String),
+    #[error("{0}: Internal error: {1}")]
+    InternalError(SourcePosition, String),
+
+    #[error("\"{0}\" is not a valid name here")]
+    InvalidName(String),
 
-    #[error("line {0}: Misplaced element end: {1}, found {2}")]
-    MisplacedElementEnd(LineNumber, String, String),
+    #[error("Can't convert JSON to XML at \"{0}\": {1}")]
+    InvalidJson(String, String),
 
-    // FIXME: need to fix this
-    #[error("No end element in input")]
-    NoEndDocument(),
+    #[error("{0}: {1}")]
+    IoError(String, Arc<std::io::Error>),
 
-    #[error("No document found in input")]
-    NoDocumentFound(),
+    #[error("{0}: Missing attribute \"{1}\" on <{2}>")]
+    MissingAttribute(SourcePosition, String, String),
+
+    #[error("{0}: Misplaced element end: {1}, found {2}")]
+    MisplacedElementEnd(SourcePosition, String, String),
+
+    #[error("{0}: No end element in input")]
+    NoEndDocument(SourcePosition),
+
+    #[error("{0}: No document found in input, found {1:?}")]
+    NoDocumentFound(SourcePosition, Box<XmlEvent>),
 
     #[error("No element \"{0}\" as referenced in element description for \"{1}\"")]
     NoSuchElement(String, String),
 
-    // FIXME: need to fix this
-    #[error("No XML elements in input")]
-    NoXTCE(),
+    #[error("No subelement {0} in <{1}>")]
+    NoSuchSubelement(usize, String),
+
+    #[error("\"{0}\" not found")]
+    NotFound(String),
+
+    #[error("{0}: No XML elements in input")]
+    NoXTCE(SourcePosition),
 
     #[error("Allowable key \"{0}\" for element definition \"{1}\" not found in elements")]
     AllowableKeyNotAnElement(String, String),
 
-    #[error("Must have exactly one root element")]
-    OnlyOneRootElementAllowed(),
+    #[error("{0}: Must have exactly one root element")]
+    OnlyOneRootElementAllowed(SourcePosition),
+
+    #[error("<{0}> is read-only")]
+    ReadOnly(String),
 
     #[error("ElementRef not resolved for \"{0}\"")]
     UnresolvedRef(String),
 
-    #[error("line {0}: StartDocument after StartDocument")]
-    StartAfterStart(LineNumber),
+    #[error("{0}: StartDocument after StartDocument")]
+    StartAfterStart(SourcePosition),
 
     #[error("ElementDef name \"{0}\" not in ElementDescs")]
     ElementDefNotInElementDescs(String),
@@ -64,25 +101,125 @@ pub enum XmlDocumentError {
     #[error("Root is unexpectedly None")]
     RootIsNone(),
 
-    #[error("Unexpected XML error: {0:?}")]
-    UnexpectedXml(XmlEvent),
+    #[error("{0}: Unexpected XML: {1:?}")]
+    UnexpectedXml(SourcePosition, Box<XmlEvent>),
+
+    #[error("Namespace prefix \"{0}\" is not bound in <{1}>")]
+    UnboundPrefix(String, String),
+
+    #[error("{0}: Document ended with unclosed element <{1}>")]
+    UnclosedElement(SourcePosition, String),
 
     // FIXME: this is temporary and should eventually be deleted
-    #[error("Line {0}: Unknown XTCE parsing error")]
-    Unknown(LineNumber),
+    #[error("{0}: Unknown XTCE parsing error")]
+    Unknown(SourcePosition),
 
-    #[error("line {0}: Unknown or misplaced element: <{1}> in <{2}>")]
-    UnknownElement(LineNumber, String, String),
+    #[error("{0}: Unknown or misplaced element: <{1}> in <{2}>")]
+    UnknownElement(SourcePosition, String, String),
 
-    // FIXME: get line number from the XmlEvent
-    #[error("Line {0}: XML error: {1}")]
-    XmlError(LineNumber, xml::reader::Error),
+    // The message is used rather than the Display output of the error to
+    // avoid giving the position twice
+    #[error("{pos}: XML error: {msg}", pos = .0, msg = .1.msg())]
+    XmlError(SourcePosition, xml::reader::Error),
 
     #[error("No elements defined")]
     XmlNoElementDefined(),
 }
 
-// FIXME: don't use debug format
-pub fn warning(err: &XmlDocumentError) {
-    eprintln!("Warning: {:?}", err);
+impl XmlDocumentError {
+    /*
+     * Position in the input of the problem, if the error came from the
+     * input
+     */
+    pub fn position(&self) -> Option<SourcePosition> {
+        match self {
+            XmlDocumentError::BadAttributeValue(pos, ..) |
+            XmlDocumentError::Deserialize(pos, ..) |
+            XmlDocumentError::InternalError(pos, ..) |
+            XmlDocumentError::MissingAttribute(pos, ..) |
+            XmlDocumentError::MisplacedElementEnd(pos, ..) |
+            XmlDocumentError::NoEndDocument(pos) |
+            XmlDocumentError::NoDocumentFound(pos, ..) |
+            XmlDocumentError::NoXTCE(pos) |
+            XmlDocumentError::OnlyOneRootElementAllowed(pos) |
+            XmlDocumentError::StartAfterStart(pos) |
+            XmlDocumentError::UnexpectedXml(pos, ..) |
+            XmlDocumentError::UnclosedElement(pos, ..) |
+            XmlDocumentError::Unknown(pos) |
+            XmlDocumentError::UnknownElement(pos, ..) |
+            XmlDocumentError::XmlError(pos, ..) => Some(*pos),
+            _ => None,
+        }
+    }
+
+    /*
+     * Stable code identifying the kind of error. Codes are never changed or
+     * reused, so they can be looked up and matched by tools.
+     */
+    pub fn code(&self) -> &'static str {
+        match self {
+            XmlDocumentError::BadAttributeValue(..) => "XT0001",
+            XmlDocumentError::CantInsertElement(..) => "XT0002",
+            XmlDocumentError::DuplicateElementDefsName(..) => "XT0003",
+            XmlDocumentError::DuplicateAllowableElement(..) => "XT0004",
+            XmlDocumentError::DuplicateKey(..) => "XT0005",
+            XmlDocumentError::Error(..) => "XT0006",
+            XmlDocumentError::InternalError(..) => "XT0007",
+            XmlDocumentError::IoError(..) => "XT0008",
+            XmlDocumentError::MissingAttribute(..) => "XT0009",
+            XmlDocumentError::MisplacedElementEnd(..) => "XT0010",
+            XmlDocumentError::NoEndDocument(..) => "XT0011",
+            XmlDocumentError::NoDocumentFound(..) => "XT0012",
+            XmlDocumentError::NoSuchElement(..) => "XT0013",
+            XmlDocumentError::NoXTCE(..) => "XT0014",
+            XmlDocumentError::AllowableKeyNotAnElement(..) => "XT0015",
+            XmlDocumentError::OnlyOneRootElementAllowed(..) => "XT0016",
+            XmlDocumentError::UnresolvedRef(..) => "XT0017",
+            XmlDocumentError::StartAfterStart(..) => "XT0018",
+            XmlDocumentError::ElementDefNotInElementDescs(..) => "XT0019",
+            XmlDocumentError::RootKeyNotFound(..) => "XT0020",
+            XmlDocumentError::RootIsNone() => "XT0021",
+            XmlDocumentError::UnexpectedXml(..) => "XT0022",
+            XmlDocumentError::UnclosedElement(..) => "XT0023",
+            XmlDocumentError::Unknown(..) => "XT0024",
+            XmlDocumentError::UnknownElement(..) => "XT0025",
+            XmlDocumentError::XmlError(..) => "XT0026",
+            XmlDocumentError::XmlNoElementDefined() => "XT0027",
+            XmlDocumentError::BadXPath(..) => "XT0028",
+            XmlDocumentError::InvalidName(..) => "XT0029",
+            XmlDocumentError::NoSuchSubelement(..) => "XT0030",
+            XmlDocumentError::UnboundPrefix(..) => "XT0031",
+            XmlDocumentError::Custom(..) => "XT0032",
+            XmlDocumentError::Deserialize(..) => "XT0033",
+            XmlDocumentError::InvalidJson(..) => "XT0034",
+            XmlDocumentError::NotFound(..) => "XT0035",
+            XmlDocumentError::ReadOnly(..) => "XT0036",
+        }
+    }
+
+    /*
+     * Description of the error without the position
+     */
+    pub fn message(&self) -> String {
+        let text = self.to_string();
+        match self.position() {
+            None => text,
+            Some(pos) => match text.strip_prefix(&format!("{}: ", pos)) {
+                None => text,
+                Some(message) => message.to_string(),
+            },
+        }
+    }
+}
+
+impl serde::de::Error for XmlDocumentError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        XmlDocumentError::Custom(msg.to_string())
+    }
+}
+
+impl serde::ser::Error for XmlDocumentError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        XmlDocumentError::Custom(msg.to_string())
+    }
 }
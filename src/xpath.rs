@@ -0,0 +1,1683 @@
+/*
+ * Queries on Element trees using a subset of XPath 1.0. Supported are:
+ *
+ * - Location paths, absolute and relative, with the abbreviations /, //,
+ *   ., .. and @
+ * - The self, child, descendant, descendant-or-self, parent, ancestor,
+ *   ancestor-or-self, following-sibling, preceding-sibling and attribute
+ *   axes
+ * - Name tests, including * and prefix:*, and the text() and node() node
+ *   type tests
+ * - Predicates, both positional, e.g. [2] and [last()], and boolean, e.g.
+ *   [@name='TempType']
+ * - The or, and, =, !=, <, <=, >, >=, +, -, *, div, mod and | operators
+ * - The node set functions last(), position(), count(), name(),
+ *   local-name() and namespace-uri(), the string functions string(),
+ *   concat(), starts-with(), contains(), substring-before(),
+ *   substring-after(), substring(), string-length(), normalize-space() and
+ *   translate(), the boolean functions boolean(), not(), true() and false(),
+ *   and the number functions number(), sum(), floor(), ceiling() and
+ *   round()
+ *
+ * Documents are mostly written using a default namespace, so a name
+ * without a prefix matches elements with that local name in any namespace.
+ * A name with a prefix only matches elements in the namespace for the
+ * prefix, which is looked up in the namespaces given with
+ * XPath::namespace() and then in the namespaces in scope for the root
+ * element. Attribute names without a prefix only match attributes that
+ * aren't in a namespace, as usual, though @* matches all attributes.
+ *
+ * The tree is queried as if the element it starts at were the root element
+ * of a document, so "/" selects a document node holding that element and
+ * absolute paths start from there. Relative paths start at the element
+ * itself.
+ */
+
+use std::collections::BTreeMap;
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent;
+
+use crate::element::Element;
+use crate::navigate::{Entry, TreeIndex};
+use crate::parse_item::LineNumber;
+use crate::write_tree::qualified_name;
+use crate::xml_document_error::XmlDocumentError;
+
+/**
+ * A compiled XPath expression
+ * source:      Text of the expression
+ * expr:        Parsed expression
+ * namespaces:  Namespaces for the prefixes used in the expression
+ */
+#[derive(Clone, Debug)]
+pub struct XPath {
+    source:     String,
+    expr:       Expr,
+    namespaces: BTreeMap<String, String>,
+}
+
+impl XPath {
+    pub fn new(source: &str) -> Result<XPath, XmlDocumentError> {
+        let error = |msg: String| XmlDocumentError::BadXPath(source.to_string(), msg);
+        let tokens = tokenize(source).map_err(error)?;
+        let expr = ExprParser { tokens: &tokens, pos: 0, end: source.chars().count() }
+            .parse().map_err(error)?;
+
+        Ok(XPath {
+            source:     source.to_string(),
+            expr,
+            namespaces: BTreeMap::new(),
+        })
+    }
+
+    /**
+     * Give the namespace URI for a prefix used in the expression
+     */
+    pub fn namespace(mut self, prefix: &str, uri: &str) -> XPath {
+        self.namespaces.insert(prefix.to_string(), uri.to_string());
+        self
+    }
+
+    /**
+     * Evaluate the expression with the given element as the context node
+     */
+    pub fn evaluate<'a>(&self, root: &'a dyn Element) -> Result<XPathValue<'a>, XmlDocumentError> {
+        let evaluator = Evaluator {
+            tree:       Tree::new(root),
+            namespaces: &self.namespaces,
+        };
+        let context = Context { node: Node::Element(0), position: 1, size: 1 };
+        let value = evaluator.eval(&self.expr, &context)
+            .map_err(|msg| XmlDocumentError::BadXPath(self.source.clone(), msg))?;
+
+        Ok(match value {
+            Value::Nodes(nodes) => XPathValue::Nodes(nodes.into_iter()
+                .map(|node| evaluator.tree.node(node))
+                .collect()),
+            Value::String(s) => XPathValue::String(s),
+            Value::Number(n) => XPathValue::Number(n),
+            Value::Boolean(b) => XPathValue::Boolean(b),
+        })
+    }
+
+    /**
+     * Evaluate an expression that gives a node set, returning the nodes in
+     * document order
+     */
+    pub fn select<'a>(&self, root: &'a dyn Element) ->
+        Result<Vec<XPathNode<'a>>, XmlDocumentError> {
+        match self.evaluate(root)? {
+            XPathValue::Nodes(nodes) => Ok(nodes),
+            _ => Err(XmlDocumentError::BadXPath(self.source.clone(),
+                "Expression doesn't give a node set".to_string())),
+        }
+    }
+}
+
+/**
+ * Result of evaluating an XPath expression
+ */
+#[derive(Clone, Debug)]
+pub enum XPathValue<'a> {
+    Nodes(Vec<XPathNode<'a>>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+/**
+ * Node selected by an XPath expression. The document node, selected by
+ * "/", holds the root element.
+ */
+#[derive(Clone)]
+pub enum XPathNode<'a> {
+    Document(&'a dyn Element),
+    Element(&'a dyn Element),
+    Attribute(&'a dyn Element, &'a OwnedAttribute),
+    Text(&'a dyn Element, String),
+}
+
+impl<'a> XPathNode<'a> {
+    /**
+     * The element, or the element the attribute or text is in. For the
+     * document node, this is the root element.
+     */
+    pub fn element(&self) -> &'a dyn Element {
+        match self {
+            XPathNode::Document(element) |
+                XPathNode::Element(element) |
+                XPathNode::Attribute(element, _) |
+                XPathNode::Text(element, _) => *element,
+        }
+    }
+
+    /**
+     * Line number of the start of the element, or the element the attribute
+     * or text is in
+     */
+    pub fn lineno(&self) -> LineNumber {
+        self.element().lineno()
+    }
+
+    /**
+     * XPath string value of the node
+     */
+    pub fn string_value(&self) -> String {
+        match self {
+            XPathNode::Document(element) | XPathNode::Element(element) =>
+                element_string(*element),
+            XPathNode::Attribute(_, attribute) => attribute.value.clone(),
+            XPathNode::Text(_, text) => text.clone(),
+        }
+    }
+}
+
+impl std::fmt::Debug for XPathNode<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XPathNode::Document(element) => write!(f, "Document({})", element.qname()),
+            XPathNode::Element(element) =>
+                write!(f, "Element({}, line {})", element.qname(), element.lineno()),
+            XPathNode::Attribute(element, attribute) =>
+                write!(f, "Attribute({}=\"{}\", line {})", attribute.name, attribute.value,
+                    element.lineno()),
+            XPathNode::Text(element, text) =>
+                write!(f, "Text({:?}, line {})", text, element.lineno()),
+        }
+    }
+}
+
+/*
+ * All the text within an element, including that of its subelements
+ */
+fn element_string(element: &dyn Element) -> String {
+    let mut text = String::new();
+    for subelement in element.subelements() {
+        text += &events_text(subelement.before_element());
+        text += &element_string(subelement.as_ref());
+    }
+    text + &events_text(element.content())
+}
+
+fn events_text(events: &[XmlEvent]) -> String {
+    events.iter()
+        .filter_map(|event| match event {
+            XmlEvent::Characters(s) | XmlEvent::Whitespace(s) | XmlEvent::CData(s) =>
+                Some(s.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/*
+ * Tokens of an XPath expression
+ */
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Slash,
+    DoubleSlash,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Pipe,
+    Plus,
+    Minus,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+    // Prefix, if any, and local name, with None for *
+    NameTest(Option<String>, Option<String>),
+    NodeType(String),
+    FunctionName(String),
+    AxisName(String),
+    Literal(String),
+    Number(f64),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::Slash => "/",
+            Token::DoubleSlash => "//",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Dot => ".",
+            Token::DotDot => "..",
+            Token::At => "@",
+            Token::Comma => ",",
+            Token::ColonColon => "::",
+            Token::Pipe => "|",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Equal => "=",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Multiply => "*",
+            Token::And => "and",
+            Token::Or => "or",
+            Token::Mod => "mod",
+            Token::Div => "div",
+            Token::NameTest(prefix, local) => {
+                if let Some(prefix) = prefix {
+                    write!(f, "'{}:", prefix)?;
+                } else {
+                    write!(f, "'")?;
+                }
+                return write!(f, "{}'", local.as_deref().unwrap_or("*"));
+            },
+            Token::NodeType(name) | Token::FunctionName(name) | Token::AxisName(name) =>
+                return write!(f, "'{}'", name),
+            Token::Literal(literal) => return write!(f, "\"{}\"", literal),
+            Token::Number(number) => return write!(f, "{}", number),
+        };
+        write!(f, "'{}'", text)
+    }
+}
+
+impl Token {
+    /*
+     * Check whether a * or name following this token is an operator
+     */
+    fn precedes_operator(&self) -> bool {
+        !matches!(self, Token::At | Token::ColonColon | Token::LParen | Token::LBracket |
+            Token::Comma | Token::Slash | Token::DoubleSlash | Token::Pipe | Token::Plus |
+            Token::Minus | Token::Equal | Token::NotEqual | Token::Less | Token::LessEqual |
+            Token::Greater | Token::GreaterEqual | Token::Multiply | Token::And | Token::Or |
+            Token::Mod | Token::Div)
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+/*
+ * Split an expression into tokens, each with the offset it starts at,
+ * using the rules in section 3.7 of the XPath 1.0 recommendation to tell
+ * operators from names
+ */
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<(Token, usize)> = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let operator_context = tokens.last().is_some_and(|(token, _)| token.precedes_operator());
+        let offset = i;
+
+        let token = match c {
+            ' ' | '\t' | '\r' | '\n' => {
+                i += 1;
+                continue;
+            },
+            '/' if next == Some('/') => { i += 2; Token::DoubleSlash },
+            '/' => { i += 1; Token::Slash },
+            '(' => { i += 1; Token::LParen },
+            ')' => { i += 1; Token::RParen },
+            '[' => { i += 1; Token::LBracket },
+            ']' => { i += 1; Token::RBracket },
+            '.' if next == Some('.') => { i += 2; Token::DotDot },
+            '.' if !next.is_some_and(|c| c.is_ascii_digit()) => { i += 1; Token::Dot },
+            '@' => { i += 1; Token::At },
+            ',' => { i += 1; Token::Comma },
+            ':' if next == Some(':') => { i += 2; Token::ColonColon },
+            '|' => { i += 1; Token::Pipe },
+            '+' => { i += 1; Token::Plus },
+            '-' => { i += 1; Token::Minus },
+            '=' => { i += 1; Token::Equal },
+            '!' if next == Some('=') => { i += 2; Token::NotEqual },
+            '<' if next == Some('=') => { i += 2; Token::LessEqual },
+            '<' => { i += 1; Token::Less },
+            '>' if next == Some('=') => { i += 2; Token::GreaterEqual },
+            '>' => { i += 1; Token::Greater },
+            '*' if operator_context => { i += 1; Token::Multiply },
+            '*' => { i += 1; Token::NameTest(None, None) },
+            '"' | '\'' => {
+                let end = chars[i + 1..].iter().position(|&q| q == c)
+                    .ok_or_else(|| format!("Unterminated string at offset {}", i))?;
+                let literal: String = chars[i + 1..i + 1 + end].iter().collect();
+                i += end + 2;
+                Token::Literal(literal)
+            },
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                Token::Number(number.parse()
+                    .map_err(|_| format!("Bad number \"{}\" at offset {}", number, start))?)
+            },
+            c if is_name_start(c) => {
+                let start = i;
+                while i < chars.len() && is_name_char(chars[i]) {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+
+                if operator_context {
+                    match name.as_str() {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "mod" => Token::Mod,
+                        "div" => Token::Div,
+                        _ => return Err(format!("Expected an operator at offset {}, found \"{}\"",
+                            start, name)),
+                    }
+                } else if chars.get(i) == Some(&':') && chars.get(i + 1) != Some(&':') {
+                    // Prefixed name
+                    i += 1;
+                    if chars.get(i) == Some(&'*') {
+                        i += 1;
+                        Token::NameTest(Some(name), None)
+                    } else {
+                        let local_start = i;
+                        while i < chars.len() && is_name_char(chars[i]) {
+                            i += 1;
+                        }
+                        if local_start == i {
+                            return Err(format!("Bad name at offset {}", start));
+                        }
+                        let local: String = chars[local_start..i].iter().collect();
+                        match following(&chars, i) {
+                            Some('(') => Token::FunctionName(format!("{}:{}", name, local)),
+                            _ => Token::NameTest(Some(name), Some(local)),
+                        }
+                    }
+                } else {
+                    let after = following(&chars, i);
+                    let after_next = chars.iter().skip(i)
+                        .skip_while(|c| c.is_whitespace())
+                        .nth(1).copied();
+                    match (after, after_next) {
+                        (Some('('), _) if matches!(name.as_str(),
+                            "node" | "text" | "comment" | "processing-instruction") =>
+                            Token::NodeType(name),
+                        (Some('('), _) => Token::FunctionName(name),
+                        (Some(':'), Some(':')) => Token::AxisName(name),
+                        _ => Token::NameTest(None, Some(name)),
+                    }
+                }
+            },
+            c => return Err(format!("Unexpected character '{}' at offset {}", c, i)),
+        };
+
+        tokens.push((token, offset));
+    }
+
+    Ok(tokens)
+}
+
+/*
+ * Next character that isn't whitespace
+ */
+fn following(chars: &[char], i: usize) -> Option<char> {
+    chars[i..].iter().find(|c| !c.is_whitespace()).copied()
+}
+
+/*
+ * Parsed XPath expressions
+ */
+#[derive(Clone, Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(Compare, Box<Expr>, Box<Expr>),
+    Arithmetic(Arithmetic, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Path(PathStart, Vec<Step>),
+    Filter(Box<Expr>, Vec<Expr>),
+    Literal(String),
+    Number(f64),
+    Function(String, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+/*
+ * Where a location path starts
+ */
+#[derive(Clone, Debug)]
+enum PathStart {
+    Root,
+    Context,
+    Filter(Box<Expr>),
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    axis:       Axis,
+    test:       NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    FollowingSibling,
+    Parent,
+    PrecedingSibling,
+    Itself,
+}
+
+#[derive(Clone, Debug)]
+enum NodeTest {
+    // Prefix, if any, and local name, with None for *
+    Name(Option<String>, Option<String>),
+    Text,
+    Node,
+}
+
+/*
+ * Recursive descent parser for the XPath 1.0 grammar
+ * tokens:  Tokens of the expression, with their offsets
+ * pos:     Index of the next token
+ * end:     Offset of the end of the expression
+ */
+struct ExprParser<'t> {
+    tokens: &'t [(Token, usize)],
+    pos:    usize,
+    end:    usize,
+}
+
+impl ExprParser<'_> {
+    fn parse(mut self) -> Result<Expr, String> {
+        let expr = self.or_expr()?;
+        match self.peek() {
+            None => Ok(expr),
+            Some(_) => Err(self.expected("an operator")),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    /*
+     * Error for something other than what was expected at the next token
+     */
+    fn expected(&self, what: &str) -> String {
+        match self.tokens.get(self.pos) {
+            None => format!("Expected {} at offset {}, found the end of the expression",
+                what, self.end),
+            Some((token, offset)) => format!("Expected {} at offset {}, found {}",
+                what, offset, token),
+        }
+    }
+
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), String> {
+        match self.accept(token) {
+            true => Ok(()),
+            false => Err(self.expected(&token.to_string())),
+        }
+    }
+
+    fn or_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and_expr()?;
+        while self.accept(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.equality_expr()?;
+        while self.accept(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.equality_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn equality_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.relational_expr()?;
+        loop {
+            let compare = match self.peek() {
+                Some(Token::Equal) => Compare::Equal,
+                Some(Token::NotEqual) => Compare::NotEqual,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Compare(compare, Box::new(expr), Box::new(self.relational_expr()?));
+        }
+    }
+
+    fn relational_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.additive_expr()?;
+        loop {
+            let compare = match self.peek() {
+                Some(Token::Less) => Compare::Less,
+                Some(Token::LessEqual) => Compare::LessEqual,
+                Some(Token::Greater) => Compare::Greater,
+                Some(Token::GreaterEqual) => Compare::GreaterEqual,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Compare(compare, Box::new(expr), Box::new(self.additive_expr()?));
+        }
+    }
+
+    fn additive_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.multiplicative_expr()?;
+        loop {
+            let arithmetic = match self.peek() {
+                Some(Token::Plus) => Arithmetic::Add,
+                Some(Token::Minus) => Arithmetic::Subtract,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Arithmetic(arithmetic, Box::new(expr),
+                Box::new(self.multiplicative_expr()?));
+        }
+    }
+
+    fn multiplicative_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary_expr()?;
+        loop {
+            let arithmetic = match self.peek() {
+                Some(Token::Multiply) => Arithmetic::Multiply,
+                Some(Token::Div) => Arithmetic::Divide,
+                Some(Token::Mod) => Arithmetic::Modulo,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Arithmetic(arithmetic, Box::new(expr), Box::new(self.unary_expr()?));
+        }
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, String> {
+        match self.accept(&Token::Minus) {
+            true => Ok(Expr::Negate(Box::new(self.unary_expr()?))),
+            false => self.union_expr(),
+        }
+    }
+
+    fn union_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.path_expr()?;
+        while self.accept(&Token::Pipe) {
+            expr = Expr::Union(Box::new(expr), Box::new(self.path_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn starts_step(&self) -> bool {
+        matches!(self.peek(), Some(Token::NameTest(..) | Token::NodeType(_) | Token::At |
+            Token::Dot | Token::DotDot | Token::AxisName(_)))
+    }
+
+    fn path_expr(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Slash) => {
+                self.pos += 1;
+                let steps = match self.starts_step() {
+                    true => self.relative_path()?,
+                    false => vec![],
+                };
+                Ok(Expr::Path(PathStart::Root, steps))
+            },
+            Some(Token::DoubleSlash) => {
+                self.pos += 1;
+                let mut steps = vec![descendant_or_self()];
+                steps.extend(self.relative_path()?);
+                Ok(Expr::Path(PathStart::Root, steps))
+            },
+            _ if self.starts_step() => Ok(Expr::Path(PathStart::Context, self.relative_path()?)),
+            _ => {
+                let primary = self.primary_expr()?;
+                let predicates = self.predicates()?;
+                let filter = match predicates.is_empty() {
+                    true => primary,
+                    false => Expr::Filter(Box::new(primary), predicates),
+                };
+
+                let mut steps = vec![];
+                if self.accept(&Token::DoubleSlash) {
+                    steps.push(descendant_or_self());
+                } else if !self.accept(&Token::Slash) {
+                    return Ok(filter);
+                }
+                steps.extend(self.relative_path()?);
+                Ok(Expr::Path(PathStart::Filter(Box::new(filter)), steps))
+            },
+        }
+    }
+
+    fn relative_path(&mut self) -> Result<Vec<Step>, String> {
+        let mut steps = vec![self.step()?];
+        loop {
+            if self.accept(&Token::DoubleSlash) {
+                steps.push(descendant_or_self());
+            } else if !self.accept(&Token::Slash) {
+                return Ok(steps);
+            }
+            steps.push(self.step()?);
+        }
+    }
+
+    fn step(&mut self) -> Result<Step, String> {
+        if self.accept(&Token::Dot) {
+            return Ok(Step { axis: Axis::Itself, test: NodeTest::Node, predicates: vec![] });
+        }
+        if self.accept(&Token::DotDot) {
+            return Ok(Step { axis: Axis::Parent, test: NodeTest::Node, predicates: vec![] });
+        }
+
+        let axis = match self.peek() {
+            Some(Token::At) => {
+                self.pos += 1;
+                Axis::Attribute
+            },
+            Some(Token::AxisName(name)) => {
+                let axis = match name.as_str() {
+                    "ancestor" => Axis::Ancestor,
+                    "ancestor-or-self" => Axis::AncestorOrSelf,
+                    "attribute" => Axis::Attribute,
+                    "child" => Axis::Child,
+                    "descendant" => Axis::Descendant,
+                    "descendant-or-self" => Axis::DescendantOrSelf,
+                    "following-sibling" => Axis::FollowingSibling,
+                    "parent" => Axis::Parent,
+                    "preceding-sibling" => Axis::PrecedingSibling,
+                    "self" => Axis::Itself,
+                    _ => return Err(format!("Unsupported axis \"{}\"", name)),
+                };
+                self.pos += 1;
+                self.expect(&Token::ColonColon)?;
+                axis
+            },
+            _ => Axis::Child,
+        };
+
+        let test = match self.peek().cloned() {
+            Some(Token::NameTest(prefix, local)) => {
+                self.pos += 1;
+                NodeTest::Name(prefix, local)
+            },
+            Some(Token::NodeType(node_type)) => {
+                self.pos += 1;
+                self.expect(&Token::LParen)?;
+                self.expect(&Token::RParen)?;
+                match node_type.as_str() {
+                    "text" => NodeTest::Text,
+                    "node" => NodeTest::Node,
+                    _ => return Err(format!("Unsupported node type test \"{}()\"", node_type)),
+                }
+            },
+            _ => return Err(self.expected("a node test")),
+        };
+
+        Ok(Step { axis, test, predicates: self.predicates()? })
+    }
+
+    fn predicates(&mut self) -> Result<Vec<Expr>, String> {
+        let mut predicates = vec![];
+        while self.accept(&Token::LBracket) {
+            if self.peek().is_none() {
+                return Err(self.expected("']'"));
+            }
+            predicates.push(self.or_expr()?);
+            self.expect(&Token::RBracket)?;
+        }
+        Ok(predicates)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, String> {
+        if self.peek().is_none() {
+            return Err(self.expected("an expression"));
+        }
+        let start = self.pos;
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or_expr()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            },
+            Some(Token::Literal(literal)) => Ok(Expr::Literal(literal)),
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::FunctionName(name)) => {
+                self.expect(&Token::LParen)?;
+                let mut args = vec![];
+                if !self.accept(&Token::RParen) {
+                    loop {
+                        args.push(self.or_expr()?);
+                        if self.accept(&Token::RParen) {
+                            break;
+                        }
+                        if !self.accept(&Token::Comma) {
+                            return Err(self.expected("',' or ')'"));
+                        }
+                    }
+                }
+                Ok(Expr::Function(name, args))
+            },
+            _ => {
+                self.pos = start;
+                Err(self.expected("an expression"))
+            },
+        }
+    }
+}
+
+fn descendant_or_self() -> Step {
+    Step { axis: Axis::DescendantOrSelf, test: NodeTest::Node, predicates: vec![] }
+}
+
+/*
+ * Nodes in the tree being queried
+ * Root:        The document node, whose only child is the root element
+ * Element:     Index of the element in Tree::entries
+ * Attribute:   Element index and index of the attribute
+ * Text:        Element index and index of the text. Text k is the text
+ *              before subelement k, or the content after the last subelement
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Node {
+    Root,
+    Element(usize),
+    Attribute(usize, usize),
+    Text(usize, usize),
+}
+
+/*
+ * Elements of the tree being queried, in document order
+ */
+struct Tree<'a> {
+    entries: Vec<Entry<'a>>,
+}
+
+impl<'a> Tree<'a> {
+    fn new(root: &'a dyn Element) -> Tree<'a> {
+        Tree { entries: TreeIndex::new(root).entries }
+    }
+
+    /*
+     * Items that hold text k of an element
+     */
+    fn text_events(&self, index: usize, k: usize) -> &'a [XmlEvent] {
+        let element = self.entries[index].element;
+        match element.subelements().get(k) {
+            Some(subelement) => subelement.before_element(),
+            None => element.content(),
+        }
+    }
+
+    fn has_text(&self, index: usize, k: usize) -> bool {
+        self.text_events(index, k).iter()
+            .any(|event| matches!(event, XmlEvent::Characters(_) | XmlEvent::Whitespace(_) |
+                XmlEvent::CData(_)))
+    }
+
+    /*
+     * Key giving the document order of nodes. Text comes just before the
+     * element that follows it, or the element that would come next if it's
+     * at the end of its parent. Several texts can come before the same
+     * element, the ones at the end of elements that close there and then
+     * the one in the element's parent, so those go in order of depth, the
+     * deepest first.
+     */
+    fn order(&self, node: Node) -> (usize, usize, usize) {
+        match node {
+            Node::Root => (0, 0, 0),
+            Node::Element(i) => (i + 1, 1, 0),
+            Node::Attribute(i, a) => (i + 1, 2, a),
+            Node::Text(i, k) => {
+                let next = self.entries[i].children.get(k).copied()
+                    .unwrap_or(self.entries[i].end);
+                (next + 1, 0, usize::MAX - self.entries[i].depth)
+            },
+        }
+    }
+
+    fn sort(&self, nodes: &mut Vec<Node>) {
+        nodes.sort_by_key(|&node| self.order(node));
+        nodes.dedup();
+    }
+
+    fn children(&self, node: Node) -> Vec<Node> {
+        let i = match node {
+            Node::Root => return vec![Node::Element(0)],
+            Node::Element(i) => i,
+            _ => return vec![],
+        };
+
+        let mut children = vec![];
+        for (k, &child) in self.entries[i].children.iter().enumerate() {
+            if self.has_text(i, k) {
+                children.push(Node::Text(i, k));
+            }
+            children.push(Node::Element(child));
+        }
+        let k = self.entries[i].children.len();
+        if self.has_text(i, k) {
+            children.push(Node::Text(i, k));
+        }
+        children
+    }
+
+    fn descendants(&self, node: Node, nodes: &mut Vec<Node>) {
+        for child in self.children(node) {
+            nodes.push(child);
+            self.descendants(child, nodes);
+        }
+    }
+
+    fn parent(&self, node: Node) -> Option<Node> {
+        match node {
+            Node::Root => None,
+            Node::Element(i) => Some(self.entries[i].parent.map_or(Node::Root, Node::Element)),
+            Node::Attribute(i, _) | Node::Text(i, _) => Some(Node::Element(i)),
+        }
+    }
+
+    /*
+     * Nodes on an axis, in the order of the axis. This is document order,
+     * except for the ancestor, parent and preceding-sibling axes, which are
+     * in reverse document order.
+     */
+    fn axis(&self, axis: Axis, node: Node) -> Vec<Node> {
+        let mut nodes = vec![];
+
+        match axis {
+            Axis::Itself => nodes.push(node),
+            Axis::Child => nodes = self.children(node),
+            Axis::Descendant => self.descendants(node, &mut nodes),
+            Axis::DescendantOrSelf => {
+                nodes.push(node);
+                self.descendants(node, &mut nodes);
+            },
+            Axis::Parent => nodes.extend(self.parent(node)),
+            Axis::Ancestor | Axis::AncestorOrSelf => {
+                if axis == Axis::AncestorOrSelf {
+                    nodes.push(node);
+                }
+                let mut current = node;
+                while let Some(parent) = self.parent(current) {
+                    nodes.push(parent);
+                    current = parent;
+                }
+            },
+            Axis::FollowingSibling | Axis::PrecedingSibling => {
+                if matches!(node, Node::Root | Node::Attribute(..)) {
+                    return nodes;
+                }
+                if let Some(parent) = self.parent(node) {
+                    let siblings = self.children(parent);
+                    if let Some(position) = siblings.iter().position(|&n| n == node) {
+                        nodes = match axis {
+                            Axis::FollowingSibling => siblings[position + 1..].to_vec(),
+                            _ => siblings[..position].iter().rev().copied().collect(),
+                        };
+                    }
+                }
+            },
+            Axis::Attribute => if let Node::Element(i) = node {
+                let count = self.entries[i].element.element_info().attributes.len();
+                nodes.extend((0..count).map(|a| Node::Attribute(i, a)));
+            },
+        }
+
+        nodes
+    }
+
+    fn string_value(&self, node: Node) -> String {
+        match node {
+            Node::Root => element_string(self.entries[0].element),
+            Node::Element(i) => element_string(self.entries[i].element),
+            Node::Attribute(i, a) =>
+                self.entries[i].element.element_info().attributes[a].value.clone(),
+            Node::Text(i, k) => events_text(self.text_events(i, k)),
+        }
+    }
+
+    fn node(&self, node: Node) -> XPathNode<'a> {
+        match node {
+            Node::Root => XPathNode::Document(self.entries[0].element),
+            Node::Element(i) => XPathNode::Element(self.entries[i].element),
+            Node::Attribute(i, a) => {
+                let element = self.entries[i].element;
+                XPathNode::Attribute(element, &element.element_info().attributes[a])
+            },
+            Node::Text(i, k) =>
+                XPathNode::Text(self.entries[i].element, events_text(self.text_events(i, k))),
+        }
+    }
+}
+
+/*
+ * Values of XPath expressions while they are being evaluated
+ */
+#[derive(Clone, Debug)]
+enum Value {
+    Nodes(Vec<Node>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+/*
+ * Evaluation context
+ * node:        Context node
+ * position:    Context position, starting from 1
+ * size:        Context size
+ */
+struct Context {
+    node:       Node,
+    position:   usize,
+    size:       usize,
+}
+
+struct Evaluator<'a, 'x> {
+    tree:       Tree<'a>,
+    namespaces: &'x BTreeMap<String, String>,
+}
+
+impl Evaluator<'_, '_> {
+    fn eval(&self, expr: &Expr, context: &Context) -> Result<Value, String> {
+        Ok(match expr {
+            Expr::Or(a, b) => Value::Boolean(self.boolean(&self.eval(a, context)?) ||
+                self.boolean(&self.eval(b, context)?)),
+            Expr::And(a, b) => Value::Boolean(self.boolean(&self.eval(a, context)?) &&
+                self.boolean(&self.eval(b, context)?)),
+            Expr::Compare(compare, a, b) => Value::Boolean(self.compare(*compare,
+                &self.eval(a, context)?, &self.eval(b, context)?)),
+            Expr::Arithmetic(arithmetic, a, b) => {
+                let a = self.number(&self.eval(a, context)?);
+                let b = self.number(&self.eval(b, context)?);
+                Value::Number(match arithmetic {
+                    Arithmetic::Add => a + b,
+                    Arithmetic::Subtract => a - b,
+                    Arithmetic::Multiply => a * b,
+                    Arithmetic::Divide => a / b,
+                    Arithmetic::Modulo => a % b,
+                })
+            },
+            Expr::Negate(a) => Value::Number(-self.number(&self.eval(a, context)?)),
+            Expr::Union(a, b) => {
+                let mut nodes = self.nodes(self.eval(a, context)?)?;
+                nodes.extend(self.nodes(self.eval(b, context)?)?);
+                self.tree.sort(&mut nodes);
+                Value::Nodes(nodes)
+            },
+            Expr::Path(start, steps) => {
+                let mut nodes = match start {
+                    PathStart::Root => vec![Node::Root],
+                    PathStart::Context => vec![context.node],
+                    PathStart::Filter(filter) => self.nodes(self.eval(filter, context)?)?,
+                };
+                for step in steps {
+                    nodes = self.step(step, &nodes)?;
+                }
+                Value::Nodes(nodes)
+            },
+            Expr::Filter(primary, predicates) => {
+                let mut nodes = self.nodes(self.eval(primary, context)?)?;
+                for predicate in predicates {
+                    nodes = self.filter(predicate, nodes)?;
+                }
+                Value::Nodes(nodes)
+            },
+            Expr::Literal(literal) => Value::String(literal.clone()),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Function(name, args) => self.function(name, args, context)?,
+        })
+    }
+
+    fn nodes(&self, value: Value) -> Result<Vec<Node>, String> {
+        match value {
+            Value::Nodes(nodes) => Ok(nodes),
+            value => Err(format!("Expected a node set, found {:?}", value)),
+        }
+    }
+
+    /*
+     * Apply a location step to each of a set of nodes
+     */
+    fn step(&self, step: &Step, nodes: &[Node]) -> Result<Vec<Node>, String> {
+        let mut result = vec![];
+
+        for &node in nodes {
+            let mut selected: Vec<Node> = self.tree.axis(step.axis, node).into_iter()
+                .filter(|&node| self.test(&step.test, step.axis, node))
+                .collect();
+            for predicate in &step.predicates {
+                selected = self.filter(predicate, selected)?;
+            }
+            result.extend(selected);
+        }
+
+        // Reverse axes are in reverse document order, so always sort
+        self.tree.sort(&mut result);
+        Ok(result)
+    }
+
+    /*
+     * Keep the nodes for which a predicate is true. The nodes are in the
+     * order used for context positions.
+     */
+    fn filter(&self, predicate: &Expr, nodes: Vec<Node>) -> Result<Vec<Node>, String> {
+        let size = nodes.len();
+        let mut result = vec![];
+
+        for (i, node) in nodes.into_iter().enumerate() {
+            let context = Context { node, position: i + 1, size };
+            let keep = match self.eval(predicate, &context)? {
+                Value::Number(n) => n == (i + 1) as f64,
+                value => self.boolean(&value),
+            };
+            if keep {
+                result.push(node);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn test(&self, test: &NodeTest, axis: Axis, node: Node) -> bool {
+        match (test, node) {
+            (NodeTest::Node, _) => true,
+            (NodeTest::Text, Node::Text(..)) => true,
+            (NodeTest::Name(prefix, local), Node::Element(i)) if axis != Axis::Attribute => {
+                let element = self.tree.entries[i].element;
+                let qname = element.qname();
+                if local.as_ref().is_some_and(|local| *local != qname.local_name) {
+                    return false;
+                }
+                match prefix {
+                    None => true,
+                    Some(prefix) => self.resolve(prefix).is_some_and(|uri|
+                        qname.namespace() == Some(uri)),
+                }
+            },
+            (NodeTest::Name(prefix, local), Node::Attribute(i, a)) => {
+                let attribute = &self.tree.entries[i].element.element_info().attributes[a];
+                if local.as_ref().is_some_and(|local| *local != attribute.name.local_name) {
+                    return false;
+                }
+                let namespace = attribute.name.namespace.as_deref()
+                    .filter(|uri| !uri.is_empty());
+                match prefix {
+                    None => local.is_none() || namespace.is_none(),
+                    Some(prefix) => self.resolve(prefix).is_some_and(|uri|
+                        namespace == Some(uri)),
+                }
+            },
+            _ => false,
+        }
+    }
+
+    fn resolve(&self, prefix: &str) -> Option<&str> {
+        match self.namespaces.get(prefix) {
+            Some(uri) => Some(uri),
+            None => self.tree.entries[0].element.element_info().resolve_prefix(prefix),
+        }
+    }
+
+    fn boolean(&self, value: &Value) -> bool {
+        match value {
+            Value::Nodes(nodes) => !nodes.is_empty(),
+            Value::String(s) => !s.is_empty(),
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::Boolean(b) => *b,
+        }
+    }
+
+    fn number(&self, value: &Value) -> f64 {
+        match value {
+            Value::Boolean(b) => if *b { 1.0 } else { 0.0 },
+            Value::Number(n) => *n,
+            value => string_to_number(&self.string(value)),
+        }
+    }
+
+    fn string(&self, value: &Value) -> String {
+        match value {
+            Value::Nodes(nodes) => nodes.first()
+                .map_or(String::new(), |&node| self.tree.string_value(node)),
+            Value::String(s) => s.clone(),
+            Value::Number(n) => number_to_string(*n),
+            Value::Boolean(b) => b.to_string(),
+        }
+    }
+
+    /*
+     * Compare two values as described in section 3.4 of the XPath 1.0
+     * recommendation
+     */
+    fn compare(&self, compare: Compare, a: &Value, b: &Value) -> bool {
+        let strings = |nodes: &Vec<Node>| -> Vec<String> {
+            nodes.iter().map(|&node| self.tree.string_value(node)).collect()
+        };
+
+        match (a, b) {
+            (Value::Nodes(a), Value::Nodes(b)) => {
+                let b = strings(b);
+                strings(a).iter().any(|a| b.iter().any(|b|
+                    self.compare_atoms(compare, &Value::String(a.clone()),
+                        &Value::String(b.clone()))))
+            },
+            (Value::Nodes(nodes), Value::Boolean(_)) =>
+                self.compare_atoms(compare, &Value::Boolean(!nodes.is_empty()), b),
+            (Value::Boolean(_), Value::Nodes(nodes)) =>
+                self.compare_atoms(compare, a, &Value::Boolean(!nodes.is_empty())),
+            (Value::Nodes(nodes), other) => strings(nodes).into_iter()
+                .any(|s| self.compare_atoms(compare, &Value::String(s), other)),
+            (other, Value::Nodes(nodes)) => strings(nodes).into_iter()
+                .any(|s| self.compare_atoms(compare, other, &Value::String(s))),
+            (a, b) => self.compare_atoms(compare, a, b),
+        }
+    }
+
+    fn compare_atoms(&self, compare: Compare, a: &Value, b: &Value) -> bool {
+        match compare {
+            Compare::Equal | Compare::NotEqual => {
+                let equal = match (a, b) {
+                    (Value::Boolean(_), _) | (_, Value::Boolean(_)) =>
+                        self.boolean(a) == self.boolean(b),
+                    (Value::Number(_), _) | (_, Value::Number(_)) =>
+                        self.number(a) == self.number(b),
+                    _ => self.string(a) == self.string(b),
+                };
+                equal == matches!(compare, Compare::Equal)
+            },
+            Compare::Less => self.number(a) < self.number(b),
+            Compare::LessEqual => self.number(a) <= self.number(b),
+            Compare::Greater => self.number(a) > self.number(b),
+            Compare::GreaterEqual => self.number(a) >= self.number(b),
+        }
+    }
+
+    fn function(&self, name: &str, args: &[Expr], context: &Context) -> Result<Value, String> {
+        let arity = |min: usize, max: usize| -> Result<(), String> {
+            match args.len() >= min && args.len() <= max {
+                true => Ok(()),
+                false => Err(format!("Wrong number of arguments for {}()", name)),
+            }
+        };
+        let arg = |i: usize| self.eval(&args[i], context);
+
+        // The argument as a string, or the context node if there isn't one
+        let string_arg = |i: usize| -> Result<String, String> {
+            match args.get(i) {
+                None => Ok(self.tree.string_value(context.node)),
+                Some(_) => Ok(self.string(&arg(i)?)),
+            }
+        };
+        // The first node of the argument, or the context node if there
+        // isn't one
+        let node_arg = || -> Result<Option<Node>, String> {
+            match args.first() {
+                None => Ok(Some(context.node)),
+                Some(_) => Ok(self.nodes(arg(0)?)?.first().copied()),
+            }
+        };
+
+        Ok(match name {
+            "last" => { arity(0, 0)?; Value::Number(context.size as f64) },
+            "position" => { arity(0, 0)?; Value::Number(context.position as f64) },
+            "count" => { arity(1, 1)?; Value::Number(self.nodes(arg(0)?)?.len() as f64) },
+            "name" | "local-name" | "namespace-uri" => {
+                arity(0, 1)?;
+                let owned_name = match node_arg()? {
+                    Some(Node::Element(i)) => Some(&self.tree.entries[i].element
+                        .element_info().owned_name),
+                    Some(Node::Attribute(i, a)) => Some(&self.tree.entries[i].element
+                        .element_info().attributes[a].name),
+                    _ => None,
+                };
+                Value::String(match owned_name {
+                    None => String::new(),
+                    Some(owned_name) => match name {
+                        "name" => qualified_name(owned_name).into_owned(),
+                        "local-name" => owned_name.local_name.clone(),
+                        _ => owned_name.namespace.clone().unwrap_or_default(),
+                    },
+                })
+            },
+
+            "string" => { arity(0, 1)?; Value::String(string_arg(0)?) },
+            "concat" => {
+                if args.len() < 2 {
+                    return Err("Wrong number of arguments for concat()".to_string());
+                }
+                let mut s = String::new();
+                for i in 0..args.len() {
+                    s += &string_arg(i)?;
+                }
+                Value::String(s)
+            },
+            "starts-with" => {
+                arity(2, 2)?;
+                Value::Boolean(string_arg(0)?.starts_with(&string_arg(1)?))
+            },
+            "contains" => {
+                arity(2, 2)?;
+                Value::Boolean(string_arg(0)?.contains(&string_arg(1)?))
+            },
+            "substring-before" => {
+                arity(2, 2)?;
+                let s = string_arg(0)?;
+                let pattern = string_arg(1)?;
+                Value::String(s.find(&pattern).map_or(String::new(), |i| s[..i].to_string()))
+            },
+            "substring-after" => {
+                arity(2, 2)?;
+                let s = string_arg(0)?;
+                let pattern = string_arg(1)?;
+                Value::String(s.find(&pattern)
+                    .map_or(String::new(), |i| s[i + pattern.len()..].to_string()))
+            },
+            "substring" => {
+                arity(2, 3)?;
+                let s = string_arg(0)?;
+                let start = round(self.number(&arg(1)?));
+                let end = match args.len() {
+                    3 => start + round(self.number(&arg(2)?)),
+                    _ => f64::INFINITY,
+                };
+                Value::String(s.chars().enumerate()
+                    .filter(|&(i, _)| {
+                        let position = (i + 1) as f64;
+                        position >= start && position < end
+                    })
+                    .map(|(_, c)| c)
+                    .collect())
+            },
+            "string-length" => {
+                arity(0, 1)?;
+                Value::Number(string_arg(0)?.chars().count() as f64)
+            },
+            "normalize-space" => {
+                arity(0, 1)?;
+                Value::String(string_arg(0)?.split_whitespace().collect::<Vec<_>>().join(" "))
+            },
+            "translate" => {
+                arity(3, 3)?;
+                let from: Vec<char> = string_arg(1)?.chars().collect();
+                let to: Vec<char> = string_arg(2)?.chars().collect();
+                Value::String(string_arg(0)?.chars()
+                    .filter_map(|c| match from.iter().position(|&f| f == c) {
+                        None => Some(c),
+                        Some(i) => to.get(i).copied(),
+                    })
+                    .collect())
+            },
+
+            "boolean" => { arity(1, 1)?; Value::Boolean(self.boolean(&arg(0)?)) },
+            "not" => { arity(1, 1)?; Value::Boolean(!self.boolean(&arg(0)?)) },
+            "true" => { arity(0, 0)?; Value::Boolean(true) },
+            "false" => { arity(0, 0)?; Value::Boolean(false) },
+
+            "number" => {
+                arity(0, 1)?;
+                Value::Number(match args.is_empty() {
+                    true => string_to_number(&self.tree.string_value(context.node)),
+                    false => self.number(&arg(0)?),
+                })
+            },
+            "sum" => {
+                arity(1, 1)?;
+                Value::Number(self.nodes(arg(0)?)?.into_iter()
+                    .map(|node| string_to_number(&self.tree.string_value(node)))
+                    .sum())
+            },
+            "floor" => { arity(1, 1)?; Value::Number(self.number(&arg(0)?).floor()) },
+            "ceiling" => { arity(1, 1)?; Value::Number(self.number(&arg(0)?).ceil()) },
+            "round" => { arity(1, 1)?; Value::Number(round(self.number(&arg(0)?))) },
+
+            _ => return Err(format!("Unknown function {}()", name)),
+        })
+    }
+}
+
+/*
+ * XPath rounding, which rounds halves up rather than away from zero
+ */
+fn round(n: f64) -> f64 {
+    match n.is_finite() {
+        true => (n + 0.5).floor(),
+        false => n,
+    }
+}
+
+/*
+ * Convert a string to a number. Only optional whitespace, an optional
+ * minus sign and decimal digits with an optional decimal point are
+ * allowed; anything else is NaN.
+ */
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim();
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let valid = !digits.is_empty() &&
+        digits.chars().all(|c| c.is_ascii_digit() || c == '.') &&
+        digits.matches('.').count() <= 1 && digits != ".";
+
+    match valid {
+        true => s.parse().unwrap_or(f64::NAN),
+        false => f64::NAN,
+    }
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == 0.0 {
+        "0".to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stdext::function_name;
+
+    use crate::element::Element;
+    use crate::parse_tree::parse;
+    use crate::xml_document_error::XmlDocumentError;
+
+    use super::{XPath, XPathValue};
+
+    const INPUT: &str = concat!(
+        "<SpaceSystem xmlns=\"http://www.omg.org/spec/XTCE/20180204\" name=\"Sat\">\n",
+        "  <TelemetryMetaData>\n",
+        "    <ParameterTypeSet>\n",
+        "      <ParameterType name=\"VoltType\" size=\"8\">\n",
+        "        <UnitSet><Unit>V</Unit></UnitSet>\n",
+        "      </ParameterType>\n",
+        "      <ParameterType name=\"TempType\" size=\"16\">\n",
+        "        <UnitSet><Unit>C</Unit></UnitSet>\n",
+        "      </ParameterType>\n",
+        "      <ParameterType name=\"CountType\" size=\"32\"/>\n",
+        "    </ParameterTypeSet>\n",
+        "  </TelemetryMetaData>\n",
+        "</SpaceSystem>\n");
+
+    /*
+     * Select nodes, giving a description and line number for each
+     */
+    fn select(root: &dyn Element, xpath: &str) -> Vec<(String, usize)> {
+        XPath::new(xpath).expect("bad XPath").select(root).expect("select failed")
+            .iter()
+            .map(|node| (node.string_value().trim().to_string(), node.lineno()))
+            .collect()
+    }
+
+    fn names(root: &dyn Element, xpath: &str) -> Vec<String> {
+        XPath::new(xpath).unwrap().select(root).unwrap().iter()
+            .map(|node| node.element().attribute("name").unwrap_or(node.element().name())
+                .to_string())
+            .collect()
+    }
+
+    fn evaluate(root: &dyn Element, xpath: &str) -> String {
+        match XPath::new(xpath).unwrap().evaluate(root).unwrap() {
+            XPathValue::Nodes(nodes) => format!("{:?}", nodes),
+            XPathValue::String(s) => s,
+            XPathValue::Number(n) => super::number_to_string(n),
+            XPathValue::Boolean(b) => b.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_paths() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+        let root = root.as_ref();
+
+        assert_eq!(select(root, "//ParameterType[@name='TempType']/UnitSet"),
+            vec![("C".to_string(), 8)]);
+        assert_eq!(names(root, "/SpaceSystem/TelemetryMetaData/ParameterTypeSet/*"),
+            vec!["VoltType", "TempType", "CountType"]);
+        assert_eq!(names(root, "TelemetryMetaData//ParameterType[2]"), vec!["TempType"]);
+        assert_eq!(names(root, "//ParameterType[last()]"), vec!["CountType"]);
+        assert_eq!(names(root, "//ParameterType[@size > 8 and @size < 32]"),
+            vec!["TempType"]);
+        assert_eq!(names(root, "//Unit[text()='V']/../.."), vec!["VoltType"]);
+        assert_eq!(names(root, "//Unit/ancestor::ParameterType[1]"),
+            vec!["VoltType", "TempType"]);
+        assert_eq!(names(root, "//ParameterType[@name='VoltType']/following-sibling::*[1]"),
+            vec!["TempType"]);
+        assert_eq!(names(root, "//ParameterType[not(UnitSet)] | /SpaceSystem"),
+            vec!["Sat", "CountType"]);
+        assert_eq!(select(root, "//ParameterType/@name"), vec![
+            ("VoltType".to_string(), 4), ("TempType".to_string(), 7),
+            ("CountType".to_string(), 10)]);
+        assert_eq!(select(root, "//Unit/text()"),
+            vec![("V".to_string(), 5), ("C".to_string(), 8)]);
+        assert!(select(root, "//ParameterType[@name='NoType']").is_empty());
+        assert_eq!(root.select("TelemetryMetaData/ParameterTypeSet").unwrap()[0].lineno(), 3);
+
+        // Prefixes must match the namespace
+        let xtce = XPath::new("//x:Unit").unwrap()
+            .namespace("x", "http://www.omg.org/spec/XTCE/20180204");
+        assert_eq!(xtce.select(root).unwrap().len(), 2);
+        let other = XPath::new("//x:Unit").unwrap().namespace("x", "urn:other");
+        assert!(other.select(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_functions() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+        let root = root.as_ref();
+
+        assert_eq!(evaluate(root, "count(//ParameterType)"), "3");
+        assert_eq!(evaluate(root, "sum(//ParameterType/@size) div 8"), "7");
+        assert_eq!(evaluate(root, "string(//ParameterType[2]/@name)"), "TempType");
+        assert_eq!(evaluate(root, "concat(@name, '-', local-name())"), "Sat-SpaceSystem");
+        assert_eq!(evaluate(root, "substring-before('TempType', 'Type')"), "Temp");
+        assert_eq!(evaluate(root, "substring-after('TempType', 'Temp')"), "Type");
+        assert_eq!(evaluate(root, "substring('12345', 1.5, 2.6)"), "234");
+        assert_eq!(evaluate(root, "string-length(normalize-space('  a   b '))"), "3");
+        assert_eq!(evaluate(root, "translate('bar', 'abc', 'ABC')"), "BAr");
+        assert_eq!(evaluate(root, "starts-with(//ParameterType/@name, 'Volt')"), "true");
+        assert_eq!(evaluate(root, "contains(//Unit, 'X')"), "false");
+        assert_eq!(evaluate(root, "floor(2.5) + ceiling(2.5) + round(2.5) + round(-2.5)"),
+            "6");
+        assert_eq!(evaluate(root, "number('x') = number('x')"), "false");
+        assert_eq!(evaluate(root, "1 div 0"), "Infinity");
+        assert_eq!(evaluate(root, "7 mod 3 * -1"), "-1");
+        assert_eq!(evaluate(root, "namespace-uri()"), "http://www.omg.org/spec/XTCE/20180204");
+    }
+
+    /*
+     * String values of the nodes selected
+     */
+    fn strings(root: &dyn Element, xpath: &str) -> Vec<String> {
+        XPath::new(xpath).unwrap().select(root).unwrap().iter()
+            .map(|node| node.string_value())
+            .collect()
+    }
+
+    #[test]
+    fn test_document_order() {
+        println!("Running test {}", function_name!());
+        let root = parse("<r>a<c> 1 </c><c>2<e>3</e>4</c>text<d/>end</r>");
+        let root = root.as_ref();
+
+        // Text at the end of an element comes before text in its parent
+        assert_eq!(strings(root, "//text()"), ["a", " 1 ", "2", "3", "4", "text", "end"]);
+        assert_eq!(strings(root, "//c[2]//text() | /r/text()"),
+            ["a", "2", "3", "4", "text", "end"]);
+        assert_eq!(strings(root, "/r/node()"), ["a", " 1 ", "234", "text", "", "end"]);
+        assert_eq!(strings(root, "//e/following-sibling::node()"), ["4"]);
+        assert_eq!(strings(root, "/r/d/preceding-sibling::node()[1]"), ["text"]);
+
+        // Unions don't repeat nodes
+        assert_eq!(evaluate(root, "count(//c[2]/text() | /r/text() | //c[2]/text())"), "5");
+        let root = parse("<r><c> 1 </c><c>2</c>text<d/></r>");
+        let root = root.as_ref();
+        assert_eq!(evaluate(root, "count(//c[2]/text() | /r/text() | //c[2]/text())"), "2");
+        assert_eq!(strings(root, "//text()"), [" 1 ", "2", "text"]);
+        assert_eq!(evaluate(root, "count(//node() | //* | //text())"), "7");
+    }
+
+    #[test]
+    fn test_document_node() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+        let root = root.as_ref();
+
+        assert_eq!(evaluate(root, "/"), "[Document({http://www.omg.org/spec/XTCE/20180204}SpaceSystem)]");
+        assert_eq!(evaluate(root, "count(/..)"), "0");
+        assert_eq!(evaluate(root, "count(/*)"), "1");
+        assert_eq!(evaluate(root, "name(/*)"), "SpaceSystem");
+        assert_eq!(evaluate(root, "name(/)"), "");
+        assert_eq!(evaluate(root, "count(..)"), "1");
+        assert_eq!(evaluate(root, "count(../..)"), "0");
+        assert_eq!(evaluate(root, "count((//Unit)[1]/ancestor::node())"), "6");
+        assert_eq!(evaluate(root, "/ = /SpaceSystem"), "true");
+        assert_eq!(evaluate(root, "normalize-space(/)"), "V C");
+    }
+
+    #[test]
+    fn test_names() {
+        println!("Running test {}", function_name!());
+        let root = parse(concat!(
+            "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" ",
+            "xmlns:x=\"urn:x\" x:version=\"1\" id=\"s\">",
+            "<xs:element name=\"a\"/><b xmlns=\"urn:b\"/></xs:schema>"));
+        let root = root.as_ref();
+
+        assert_eq!(evaluate(root, "name()"), "xs:schema");
+        assert_eq!(evaluate(root, "local-name()"), "schema");
+        assert_eq!(evaluate(root, "namespace-uri()"), "http://www.w3.org/2001/XMLSchema");
+        assert_eq!(evaluate(root, "name(xs:element)"), "xs:element");
+        assert_eq!(evaluate(root, "name(*[2])"), "b");
+        assert_eq!(evaluate(root, "namespace-uri(*[2])"), "urn:b");
+        assert_eq!(evaluate(root, "name(@x:version)"), "x:version");
+        assert_eq!(evaluate(root, "name(@*[2])"), "id");
+        assert_eq!(evaluate(root, "count(@*)"), "2");
+        assert_eq!(evaluate(root, "count(@x:*)"), "1");
+        assert_eq!(evaluate(root, "name(//*[local-name() = 'element'])"), "xs:element");
+    }
+
+    #[test]
+    fn test_axes() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+        let root = root.as_ref();
+
+        assert_eq!(names(root, "//ParameterType[2]/preceding-sibling::*"), ["VoltType"]);
+        assert_eq!(names(root, "//ParameterType[3]/preceding-sibling::*[1]"), ["TempType"]);
+        assert_eq!(names(root, "//ParameterType[1]/following-sibling::ParameterType"),
+            ["TempType", "CountType"]);
+        assert_eq!(names(root, "(//Unit)[1]/ancestor-or-self::*[position() <= 2]"),
+            ["UnitSet", "Unit"]);
+        assert_eq!(names(root, "//ParameterTypeSet/descendant::Unit"), ["Unit", "Unit"]);
+        assert_eq!(names(root, "//ParameterTypeSet/child::*/self::*[@size = 32]"),
+            ["CountType"]);
+        assert_eq!(names(root, "//UnitSet/parent::*"), ["VoltType", "TempType"]);
+        assert_eq!(names(root, "(//ParameterType)[last() - 1]"), ["TempType"]);
+        assert_eq!(names(root, "//ParameterType[UnitSet/Unit = 'C']"), ["TempType"]);
+        assert_eq!(names(root, "//*[@size][not(@size = 8)]"), ["TempType", "CountType"]);
+        assert_eq!(names(root, "//ParameterType[@size != 16][2]"), ["CountType"]);
+        assert_eq!(names(root, "//ParameterType[position() mod 2 = 1]"),
+            ["VoltType", "CountType"]);
+        assert!(names(root, "//ParameterType/@size/..").len() == 3);
+        assert!(names(root, "//ParameterType/@name/following-sibling::node()").is_empty());
+        assert_eq!(evaluate(root, "count(descendant-or-self::node())"),
+            evaluate(root, "count(//node())"));
+    }
+
+    #[test]
+    fn test_errors() {
+        println!("Running test {}", function_name!());
+        let root = parse(INPUT);
+
+        for bad in ["//", "a[", "'unterminated", "a b", "foo::a", "1 +", "a/comment()"] {
+            match XPath::new(bad) {
+                Err(XmlDocumentError::BadXPath(source, _)) => assert_eq!(source, bad),
+                other => panic!("Expected BadXPath for \"{}\", got {:?}", bad, other),
+            }
+        }
+
+        for (bad, message) in [
+            ("//b[", "Expected ']' at offset 4, found the end of the expression"),
+            ("//b[@x", "Expected ']' at offset 6, found the end of the expression"),
+            ("a[]", "Expected an expression at offset 2, found ']'"),
+            ("count(a", "Expected ',' or ')' at offset 7, found the end of the expression"),
+            ("a/", "Expected a node test at offset 2, found the end of the expression"),
+            ("a/@", "Expected a node test at offset 3, found the end of the expression"),
+            ("a/)", "Expected a node test at offset 2, found ')'"),
+            ("child::", "Expected a node test at offset 7, found the end of the expression"),
+            ("1 +", "Expected an expression at offset 3, found the end of the expression"),
+            ("(1))", "Expected an operator at offset 3, found ')'"),
+            ("text(", "Expected ')' at offset 5, found the end of the expression"),
+        ] {
+            match XPath::new(bad) {
+                Err(XmlDocumentError::BadXPath(_, msg)) => assert_eq!(msg, message, "{}", bad),
+                other => panic!("Expected BadXPath for \"{}\", got {:?}", bad, other),
+            }
+        }
+
+        for bad in ["unknown()", "count(1)", "count()"] {
+            let xpath = XPath::new(bad).unwrap();
+            assert!(xpath.evaluate(root.as_ref()).is_err(), "{}", bad);
+        }
+        assert!(XPath::new("1 + 1").unwrap().select(root.as_ref()).is_err());
+    }
+}
@@ -19,3 +19,9 @@ impl<'a> XsdSchema<'a> {
         }
     }
 }
+
+impl Default for XsdSchema<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}